@@ -0,0 +1,61 @@
+//! Measures `Clipboard::get_text` with large texts, so that regressions in how the text is
+//! converted show up. Run with `cargo bench --bench get_text`.
+//!
+//! This uses the clipboard of the system, whose contents are replaced. The crate doesn't depend
+//! on a benchmark framework, so the timing is done here: each size is read a number of times after
+//! a warm-up, and the median and fastest read are reported.
+
+extern crate arboard;
+
+use std::time::{Duration, Instant};
+
+use arboard::Clipboard;
+
+const SIZES: [usize; 2] = [1 << 20, 10 << 20];
+const WARM_UP: usize = 2;
+const SAMPLES: usize = 10;
+
+/// Text of `len` bytes that mixes ASCII with multi-byte characters, like real documents do.
+fn text_of_len(len: usize) -> String {
+	let line = "The quick brown fox jumps over the lazy dog. Größe, café, 東京. 🦀\n";
+	let mut text = line.repeat(len / line.len() + 1);
+	let mut end = len;
+	while !text.is_char_boundary(end) {
+		end -= 1;
+	}
+	text.truncate(end);
+	text
+}
+
+fn main() {
+	let mut clipboard = match Clipboard::new() {
+		Ok(clipboard) => clipboard,
+		Err(e) => {
+			eprintln!("Skipping the benchmark, the clipboard isn't available: {}", e);
+			return;
+		}
+	};
+	for &len in &SIZES {
+		let text = text_of_len(len);
+		clipboard.set_text(text.as_str()).unwrap();
+		for _ in 0..WARM_UP {
+			assert_eq!(clipboard.get_text().unwrap().len(), text.len());
+		}
+		let mut samples: Vec<Duration> = (0..SAMPLES)
+			.map(|_| {
+				let start = Instant::now();
+				let read = clipboard.get_text().unwrap();
+				let elapsed = start.elapsed();
+				assert_eq!(read.len(), text.len());
+				elapsed
+			})
+			.collect();
+		samples.sort();
+		println!(
+			"get_text {:>5} KiB: median {:?}, fastest {:?}",
+			len >> 10,
+			samples[SAMPLES / 2],
+			samples[0]
+		);
+	}
+}
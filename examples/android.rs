@@ -0,0 +1,26 @@
+// Build and start on a connected device or emulator with `cargo apk run --example android`.
+//
+// The output appears in `adb logcat`. Reading the clipboard only succeeds while the activity has
+// the input focus, so the text is read whenever the window gains the focus.
+
+#[cfg(target_os = "android")]
+#[ndk_glue::main(backtrace = "on")]
+fn main() {
+	use arboard::Clipboard;
+	use ndk_glue::Event;
+
+	let mut clipboard = Clipboard::new().unwrap();
+	let the_string = "Hello from Android!";
+	clipboard.set_text(the_string).unwrap();
+	println!("The clipboard text should now be: \"{}\"", the_string);
+
+	loop {
+		match ndk_glue::poll_events() {
+			Some(Event::WindowHasFocus) => {
+				println!("Clipboard text is: {:?}", clipboard.get_text());
+			}
+			Some(Event::Destroy) => break,
+			_ => {}
+		}
+	}
+}
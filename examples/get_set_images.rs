@@ -1,4 +1,4 @@
-use arboard::{Clipboard, ImageData};
+use arboard::Clipboard;
 
 pub fn main() {
 	let mut clip_board = Clipboard::new().unwrap();
@@ -11,5 +11,5 @@ pub fn main() {
 	);
 
 	let mut clip_board = Clipboard::new().unwrap();
-	clip_board.set_image(img).unwrap();
+	clip_board.set_image_ref(&img).unwrap();
 }
@@ -0,0 +1,23 @@
+#![no_main]
+
+use arboard::fuzzing::{html_from_utf8, unwrap_cf_html, wrap_html};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	// Whatever the header claims, the HTML is a part of the data, up to the first null.
+	let html = unwrap_cf_html(data);
+	let (data_range, html_range) = (data.as_ptr_range(), html.as_ptr_range());
+	assert!(data_range.start <= html_range.start && html_range.end <= data_range.end);
+	assert!(!html.contains(&0));
+	let _ = html_from_utf8(html.to_vec());
+
+	// The HTML that arboard writes is found again.
+	if let Ok(fragment) = std::str::from_utf8(data) {
+		if !fragment.contains('\0') {
+			let wrapped = wrap_html(fragment);
+			let html = std::str::from_utf8(unwrap_cf_html(wrapped.as_bytes())).unwrap();
+			assert!(html.starts_with("<html><body>") && html.ends_with("</body></html>"));
+			assert!(html.contains(fragment));
+		}
+	}
+});
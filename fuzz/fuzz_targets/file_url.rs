@@ -0,0 +1,12 @@
+#![no_main]
+
+use arboard::fuzzing::path_from_file_url;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|url: &str| {
+	if let Some(path) = path_from_file_url(url) {
+		assert!(path.starts_with('/'), "{:?} isn't absolute", path);
+		// Decoding only ever shortens the path.
+		assert!(path.len() + "file://".len() <= url.len());
+	}
+});
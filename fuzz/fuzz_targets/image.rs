@@ -0,0 +1,25 @@
+#![no_main]
+
+use arboard::{
+	fuzzing::{decode_image, probe_dimensions},
+	ImageFormatTag,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	// The first byte chooses the format that the rest is read as.
+	let (format, data) = match data.split_first() {
+		Some((format, data)) => (
+			[ImageFormatTag::Png, ImageFormatTag::Tiff, ImageFormatTag::Dib][*format as usize % 3],
+			data,
+		),
+		None => return,
+	};
+	let dimensions = probe_dimensions(format, data);
+	if let Some((width, height)) = dimensions {
+		assert!(width > 0 && height > 0);
+	}
+	if let Ok(image) = decode_image(format, data) {
+		assert_eq!(image.bytes.len(), image.width * image.height * 4);
+	}
+});
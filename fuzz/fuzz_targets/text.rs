@@ -0,0 +1,30 @@
+#![no_main]
+
+use arboard::{
+	fuzzing::{apply_line_endings, text_from_utf16},
+	Error, LineEndings,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	// `CF_UNICODETEXT` is read as native endian units, which don't have to be valid UTF-16.
+	let units: Vec<u16> =
+		data.chunks_exact(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]])).collect();
+	match text_from_utf16(&units) {
+		Ok(text) => assert!(!text.contains('\0')),
+		Err(Error::InvalidUtf8 { bytes }) => {
+			let text = String::from_utf8(bytes).expect("the lossy text isn't UTF-8");
+			assert!(!text.contains('\0'));
+		}
+		Err(e) => panic!("unexpected error: {}", e),
+	}
+
+	let text = String::from_utf8_lossy(data);
+	let unix = apply_line_endings(LineEndings::Unix, &text);
+	let dos = apply_line_endings(LineEndings::Dos, &text);
+	assert!(!unix.contains('\r'));
+	assert_eq!(apply_line_endings(LineEndings::Unix, &unix), unix);
+	assert_eq!(apply_line_endings(LineEndings::Dos, &dos), dos);
+	assert_eq!(apply_line_endings(LineEndings::Unix, &dos), unix);
+	assert_eq!(dos.replace("\r\n", "\n"), unix);
+});
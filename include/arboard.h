@@ -0,0 +1,153 @@
+#ifndef ARBOARD_H
+#define ARBOARD_H
+
+/* Generated by cbindgen from src/ffi.rs, don't edit this file by hand. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+/*
+ * The outcome of a call, see `arboard_last_error_message` for the details of a failure.
+ */
+typedef enum ArboardStatus {
+  /*
+   * The call succeeded.
+   */
+  ARBOARD_STATUS_OK = 0,
+  /*
+   * The clipboard doesn't hold content in the requested format, or it's empty.
+   */
+  ARBOARD_STATUS_CONTENT_NOT_AVAILABLE = 1,
+  /*
+   * The clipboard can't be used on this system, or doesn't support the operation.
+   */
+  ARBOARD_STATUS_CLIPBOARD_NOT_SUPPORTED = 2,
+  /*
+   * Another application is holding the clipboard, so trying again later may succeed.
+   */
+  ARBOARD_STATUS_CLIPBOARD_OCCUPIED = 3,
+  /*
+   * The content couldn't be converted, for example text that isn't valid UTF-8 or contains a
+   * null character.
+   */
+  ARBOARD_STATUS_CONVERSION_FAILURE = 4,
+  /*
+   * The buffer of `arboard_get_image` is too small for the image. The dimensions were written
+   * anyway.
+   */
+  ARBOARD_STATUS_BUFFER_TOO_SMALL = 5,
+  /*
+   * A pointer that must not be `NULL` was `NULL`, or a text argument isn't valid UTF-8.
+   */
+  ARBOARD_STATUS_INVALID_ARGUMENT = 6,
+  /*
+   * arboard panicked. This is a bug, which should be reported.
+   */
+  ARBOARD_STATUS_PANIC = 7,
+  /*
+   * Any other error.
+   */
+  ARBOARD_STATUS_UNKNOWN = 8,
+} ArboardStatus;
+
+/*
+ * The clipboard that the functions operate on. Its contents are opaque to C.
+ */
+typedef struct ArboardClipboard ArboardClipboard;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ * Creates a clipboard, like `Clipboard::new`.
+ *
+ * Returns `NULL` if the clipboard can't be created, in which case `arboard_last_error_message`
+ * describes why. The clipboard must be released with `arboard_clipboard_free`.
+ */
+ArboardClipboard *arboard_clipboard_new(void);
+
+/*
+ * Releases a clipboard created by `arboard_clipboard_new`. Does nothing if `clipboard` is `NULL`.
+ *
+ * # Safety
+ *
+ * `clipboard` must be `NULL` or a pointer returned by `arboard_clipboard_new` that wasn't freed
+ * yet. It must not be used afterwards.
+ */
+void arboard_clipboard_free(ArboardClipboard *clipboard);
+
+/*
+ * Fetches the text on the clipboard, like `Clipboard::get_text`.
+ *
+ * On success, `*text` is set to a null-terminated UTF-8 string, which the caller owns and must
+ * release with `arboard_string_free`. On failure, `*text` is set to `NULL`. Text that contains a
+ * null character is reported as `ARBOARD_STATUS_CONVERSION_FAILURE`, since C would cut it off.
+ *
+ * # Safety
+ *
+ * `clipboard` must be a valid clipboard, and `text` must be `NULL` or point to writable memory
+ * for a pointer.
+ */
+ArboardStatus arboard_get_text(ArboardClipboard *clipboard, char **text);
+
+/*
+ * Places the text onto the clipboard, like `Clipboard::set_text`.
+ *
+ * `text` must be a null-terminated UTF-8 string, which is copied, so the caller keeps owning it.
+ * Text that isn't valid UTF-8 is rejected with `ARBOARD_STATUS_INVALID_ARGUMENT`.
+ *
+ * # Safety
+ *
+ * `clipboard` must be a valid clipboard, and `text` must be `NULL` or point to a null-terminated
+ * string.
+ */
+ArboardStatus arboard_set_text(ArboardClipboard *clipboard, const char *text);
+
+/*
+ * Fetches the image on the clipboard, like `Clipboard::get_image`, into a buffer of the caller.
+ *
+ * The pixels are written as rows of 8 bit RGBA values, starting with the top row, and `*stride`
+ * is set to the number of bytes of each row. `*width`, `*height` and `*stride` are set whenever
+ * an image was read, even if `buffer_len` is too small for it, in which case
+ * `ARBOARD_STATUS_BUFFER_TOO_SMALL` is returned and nothing is written into `buffer`. So the size
+ * of the buffer can be found by passing `NULL` and 0 first, and then calling the function again
+ * with a buffer of `*stride * *height` bytes. The clipboard is read again by the second call, so
+ * another application may have placed a larger image there in the meantime.
+ *
+ * # Safety
+ *
+ * `clipboard` must be a valid clipboard, `buffer` must be `NULL` or point to `buffer_len`
+ * writable bytes, and `width`, `height` and `stride` must each be `NULL` or point to writable
+ * memory for a `size_t`.
+ */
+ArboardStatus arboard_get_image(ArboardClipboard *clipboard,
+                                uint8_t *buffer,
+                                size_t buffer_len,
+                                size_t *width,
+                                size_t *height,
+                                size_t *stride);
+
+/*
+ * Releases text returned by `arboard_get_text`. Does nothing if `text` is `NULL`.
+ *
+ * # Safety
+ *
+ * `text` must be `NULL` or a string returned by `arboard_get_text` that wasn't freed yet.
+ */
+void arboard_string_free(char *text);
+
+/*
+ * Returns a description of why the last call of an arboard function on this thread failed, or
+ * `NULL` if it succeeded.
+ *
+ * The message is a null-terminated UTF-8 string owned by arboard. It stays valid until the next
+ * call of an arboard function on the same thread, and must not be freed.
+ */
+const char *arboard_last_error_message(void);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* ARBOARD_H */
@@ -0,0 +1,315 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2020 The arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+// The clipboard on Android is only reachable through the `ClipboardManager` system service of the
+// Java framework, so every operation is a series of JNI calls. The Java VM and the `Context` of
+// the application are taken from `ndk-context`, which is initialized by the glue crate that starts
+// the native activity (for example `ndk-glue` or `android-activity`).
+
+use std::borrow::Cow;
+
+use jni::{
+	errors::Error as JniError,
+	objects::{GlobalRef, JObject, JString, JValue},
+	JNIEnv, JavaVM,
+};
+
+use crate::common::{ClipboardSnapshot, Error, PlatformError, Representation};
+#[cfg(feature = "image-data")]
+use crate::common::{ImageData, ImageFormatTag};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The number of local references that a single clipboard operation creates at most.
+const LOCAL_FRAME_CAPACITY: i32 = 16;
+
+/// A value of a `ClipData.Item`, which is identified by its MIME type in snapshots.
+#[derive(Copy, Clone)]
+struct Format {
+	mime_type: &'static str,
+	getter: &'static str,
+	signature: &'static str,
+}
+
+const TEXT: Format =
+	Format { mime_type: "text/plain", getter: "getText", signature: "()Ljava/lang/CharSequence;" };
+const HTML: Format =
+	Format { mime_type: "text/html", getter: "getHtmlText", signature: "()Ljava/lang/String;" };
+
+pub struct AndroidClipboardContext {
+	vm: JavaVM,
+	/// The `android.content.ClipboardManager`.
+	manager: GlobalRef,
+}
+
+impl AndroidClipboardContext {
+	/// The async methods run the operations on the calling thread, and `clear_after` isn't
+	/// supported.
+	pub(crate) fn reopen(&self) -> Option<crate::common::Reopen<Self>> {
+		None
+	}
+
+	/// Returns `Error::ClipboardNotSupported` if `ndk-context` wasn't initialized.
+	pub(crate) fn new() -> Result<Self> {
+		// `android_context` panics instead of returning an error if there's no context.
+		let context = std::panic::catch_unwind(ndk_context::android_context)
+			.map_err(|_| Error::ClipboardNotSupported)?;
+		let vm = unsafe { JavaVM::from_raw(context.vm().cast()) }.map_err(jni_error)?;
+		let activity = JObject::from(context.context() as jni::sys::jobject);
+		let manager = with_local_frame(&vm, |env| {
+			let service = env
+				.get_static_field(
+					"android/content/Context",
+					"CLIPBOARD_SERVICE",
+					"Ljava/lang/String;",
+				)?
+				.l()?;
+			let manager = env
+				.call_method(
+					activity,
+					"getSystemService",
+					"(Ljava/lang/String;)Ljava/lang/Object;",
+					&[JValue::Object(service)],
+				)?
+				.l()?;
+			env.new_global_ref(manager)
+		})?;
+		Ok(Self { vm, manager })
+	}
+
+	/// Returns `Error::ContentNotAvailable` if the clipboard doesn't hold text. Since Android 10,
+	/// this is also the case while the application doesn't have the input focus, because the
+	/// system reports an empty clipboard to applications in the background.
+	pub(crate) fn get_text(&mut self) -> Result<String> {
+		self.read_item(TEXT)?.ok_or(Error::ContentNotAvailable)
+	}
+
+	/// Calls the getter of `format` on the first item of the clip on the clipboard, returning
+	/// `None` if the clipboard is empty or the item doesn't have a value of that format.
+	fn read_item(&mut self, format: Format) -> Result<Option<String>> {
+		let manager = self.manager.as_obj();
+		with_local_frame(&self.vm, |env| {
+			let clip = env
+				.call_method(manager, "getPrimaryClip", "()Landroid/content/ClipData;", &[])?
+				.l()?;
+			if clip.is_null() || env.call_method(clip, "getItemCount", "()I", &[])?.i()? == 0 {
+				return Ok(None);
+			}
+			let item = env
+				.call_method(clip, "getItemAt", "(I)Landroid/content/ClipData$Item;", &[0.into()])?
+				.l()?;
+			let value = env.call_method(item, format.getter, format.signature, &[])?.l()?;
+			if value.is_null() {
+				return Ok(None);
+			}
+			let value = env.call_method(value, "toString", "()Ljava/lang/String;", &[])?.l()?;
+			Ok(Some(String::from(env.get_string(JString::from(value))?)))
+		})
+	}
+
+	pub(crate) fn get_text_bytes(&mut self, _format: &str) -> Result<Vec<u8>> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub(crate) fn set_text(&mut self, text: Cow<'_, str>) -> Result<()> {
+		self.set_clip(&text, None)
+	}
+
+	/// Places a `ClipData` with the text, and the HTML if it's given, onto the clipboard.
+	fn set_clip(&mut self, text: &str, html: Option<&str>) -> Result<()> {
+		let manager = self.manager.as_obj();
+		with_local_frame(&self.vm, |env| {
+			let label = JValue::Object(*env.new_string("")?);
+			let text = JValue::Object(*env.new_string(text)?);
+			let clip = match html {
+				Some(html) => env.call_static_method(
+					"android/content/ClipData",
+					"newHtmlText",
+					"(Ljava/lang/CharSequence;Ljava/lang/CharSequence;Ljava/lang/String;)Landroid/content/ClipData;",
+					&[label, text, JValue::Object(*env.new_string(html)?)],
+				)?,
+				None => env.call_static_method(
+					"android/content/ClipData",
+					"newPlainText",
+					"(Ljava/lang/CharSequence;Ljava/lang/CharSequence;)Landroid/content/ClipData;",
+					&[label, text],
+				)?,
+			};
+			env.call_method(manager, "setPrimaryClip", "(Landroid/content/ClipData;)V", &[clip])?;
+			Ok(())
+		})
+	}
+
+	pub(crate) fn swap_text(&mut self, text: String) -> Result<Option<String>> {
+		let previous = match self.get_text() {
+			Ok(text) => Some(text),
+			Err(Error::ContentNotAvailable) => None,
+			Err(e) => return Err(e),
+		};
+		self.set_text(text.into())?;
+		Ok(previous)
+	}
+
+	pub(crate) fn generation(&mut self) -> Result<u64> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	/// Captures the text and the HTML of the first item of the clip, which are stored under the
+	/// `text/plain` and `text/html` MIME types.
+	pub(crate) fn snapshot(&mut self, max_bytes: Option<usize>) -> Result<ClipboardSnapshot> {
+		let mut snapshot = ClipboardSnapshot::default();
+		snapshot.new_item();
+		for format in [TEXT, HTML] {
+			if let Some(value) = self.read_item(format)? {
+				snapshot.push(format.mime_type.to_owned(), value.into_bytes(), max_bytes);
+			}
+		}
+		self.skip_other_types(&mut snapshot)?;
+		Ok(snapshot)
+	}
+
+	/// The clipboard service of the system keeps the contents.
+	pub(crate) fn persist(&mut self) -> Result<()> {
+		Ok(())
+	}
+
+	pub(crate) fn restore(&mut self, snapshot: &ClipboardSnapshot) -> Result<()> {
+		let representations: Vec<_> = (snapshot.items.iter().take(1).flatten())
+			.map(|(format, bytes)| Representation::Custom(format.clone(), bytes.clone()))
+			.collect();
+		self.write_all(&representations)
+	}
+
+	/// Only the text and the HTML can be transferred, the other representations are left out.
+	/// Custom data is accepted under the `text/plain` and `text/html` MIME types.
+	pub(crate) fn write_all(&mut self, representations: &[Representation]) -> Result<()> {
+		let mut text = None;
+		let mut html = None;
+		for representation in representations {
+			let (slot, value) = match representation {
+				Representation::Text(value) => (&mut text, value.as_str()),
+				Representation::Html(value) => (&mut html, value.as_str()),
+				Representation::Custom(format, bytes) => {
+					let slot = match format.as_str() {
+						format if format == TEXT.mime_type => &mut text,
+						format if format == HTML.mime_type => &mut html,
+						_ => continue,
+					};
+					(slot, std::str::from_utf8(bytes).map_err(|_| Error::ConversionFailure)?)
+				}
+			};
+			slot.get_or_insert(value);
+		}
+		match text {
+			Some(text) => self.set_clip(text, html),
+			None => Err(Error::ClipboardNotSupported),
+		}
+	}
+
+	/// Records the other MIME types of the clip, which can't be captured, in the warnings of the
+	/// snapshot.
+	fn skip_other_types(&mut self, snapshot: &mut ClipboardSnapshot) -> Result<()> {
+		for mime_type in self.available_formats()? {
+			if mime_type != TEXT.mime_type && mime_type != HTML.mime_type {
+				snapshot.skip(&mime_type, "only text and HTML can be read on Android");
+			}
+		}
+		Ok(())
+	}
+
+	/// Returns the MIME types from the description of the clip.
+	pub(crate) fn available_formats(&mut self) -> Result<Vec<String>> {
+		let manager = self.manager.as_obj();
+		with_local_frame(&self.vm, |env| {
+			let description = env
+				.call_method(
+					manager,
+					"getPrimaryClipDescription",
+					"()Landroid/content/ClipDescription;",
+					&[],
+				)?
+				.l()?;
+			if description.is_null() {
+				return Ok(Vec::new());
+			}
+			let count = env.call_method(description, "getMimeTypeCount", "()I", &[])?.i()?;
+			(0..count)
+				.map(|i| {
+					let mime_type = env
+						.call_method(
+							description,
+							"getMimeType",
+							"(I)Ljava/lang/String;",
+							&[i.into()],
+						)?
+						.l()?;
+					Ok(String::from(env.get_string(JString::from(mime_type))?))
+				})
+				.collect()
+		})
+	}
+
+	#[cfg(feature = "image-data")]
+	pub(crate) fn get_image(&mut self) -> Result<ImageData<'static>> {
+		Err(Error::UnsupportedFormat { format: "image".into() })
+	}
+
+	#[cfg(feature = "image-data")]
+	pub(crate) fn get_image_with_format(&mut self) -> Result<(ImageFormatTag, ImageData<'static>)> {
+		Err(Error::UnsupportedFormat { format: "image".into() })
+	}
+
+	#[cfg(feature = "image-data")]
+	pub(crate) fn set_image(&mut self, _image: ImageData) -> Result<()> {
+		Err(Error::UnsupportedFormat { format: "image".into() })
+	}
+}
+
+/// Attaches the current thread to the Java VM for the duration of `f`, unless it's already
+/// attached, and frees the local references created by `f` afterwards.
+///
+/// The local references have to be freed explicitly, because threads that are already attached
+/// (like the main thread of a native activity) may never return to Java, which would free them.
+fn with_local_frame<T>(
+	vm: &JavaVM,
+	f: impl FnOnce(&JNIEnv) -> jni::errors::Result<T>,
+) -> Result<T> {
+	let env = vm.attach_current_thread().map_err(jni_error)?;
+	env.push_local_frame(LOCAL_FRAME_CAPACITY).map_err(jni_error)?;
+	let result = f(&env).map_err(|error| java_error(&env, error));
+	// Popping the frame is allowed while an exception is pending, but `java_error` clears it anyway.
+	let _ = env.pop_local_frame(JObject::null());
+	result
+}
+
+/// Converts the error of a JNI call, clearing the Java exception that caused it if there's one, so
+/// that it doesn't propagate to the Java code that called into native code.
+fn java_error(env: &JNIEnv, error: JniError) -> Error {
+	if let JniError::JavaException = error {
+		if let Ok(exception) = env.exception_occurred() {
+			let _ = env.exception_clear();
+			let description = env
+				.call_method(exception, "toString", "()Ljava/lang/String;", &[])
+				.and_then(|description| description.l())
+				.and_then(|description| {
+					env.get_string(JString::from(description)).map(String::from)
+				});
+			if let Ok(description) = description {
+				return Error::Platform(PlatformError::new("JNI").with_detail(description));
+			}
+			let _ = env.exception_clear();
+		}
+	}
+	jni_error(error)
+}
+
+fn jni_error(error: JniError) -> Error {
+	Error::Platform(PlatformError::new("JNI").with_source(error))
+}
@@ -0,0 +1,276 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2020 The arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+//! A command-line interface to the clipboard, for scripts and for comparing the clipboard across
+//! platforms. It's built with the `cli` feature, for example with
+//! `cargo install arboard --features cli`, and only uses the public API of arboard.
+
+use std::{
+	convert::TryFrom,
+	env, fs,
+	io::{self, Read, Write},
+	process, thread,
+	time::Duration,
+};
+
+use arboard::{Clipboard, ClipboardWatcher, Error, ImageData};
+
+const USAGE: &str = "\
+Usage: arboard <command> [options]
+
+Commands:
+  copy [--format <format>]                 Places standard input onto the clipboard
+  paste [--format <format>] [--out <file>] Writes the clipboard to standard output, or a file
+  formats                                  Lists the formats on the clipboard, one per line
+  watch                                    Lists the formats whenever the clipboard changes
+
+A format is `text` (the default), `html`, `png`, or the name of any other format as the
+platform calls it: a UTI on macOS, a clipboard format name on Windows, and a target or MIME
+type on Linux, like `arboard formats` prints them.
+
+Exit status: 0 on success, 1 if the clipboard is empty or doesn't hold the format, and 2 for
+any other error.";
+
+/// The exit status for a clipboard that's empty or doesn't hold the requested format.
+const EXIT_EMPTY: i32 = 1;
+/// The exit status for every other failure, including invalid arguments.
+const EXIT_ERROR: i32 = 2;
+
+/// How often `watch` checks the clipboard on the platforms that don't notify about changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+enum Failure {
+	Empty,
+	Usage(String),
+	Error(String),
+}
+
+impl From<Error> for Failure {
+	fn from(error: Error) -> Self {
+		match error {
+			Error::ContentNotAvailable => Failure::Empty,
+			error => Failure::Error(error.to_string()),
+		}
+	}
+}
+
+impl From<io::Error> for Failure {
+	fn from(error: io::Error) -> Self {
+		Failure::Error(error.to_string())
+	}
+}
+
+enum Format {
+	Text,
+	Html,
+	Png,
+	Other(String),
+}
+
+impl Format {
+	fn parse(name: &str) -> Self {
+		match name {
+			"text" => Format::Text,
+			"html" => Format::Html,
+			"png" => Format::Png,
+			name => Format::Other(name.to_owned()),
+		}
+	}
+}
+
+struct Options {
+	format: Format,
+	out: Option<String>,
+}
+
+fn main() {
+	let args: Vec<String> = env::args().skip(1).collect();
+	let status = match run(&args) {
+		Ok(()) => 0,
+		Err(Failure::Empty) => {
+			eprintln!("arboard: the clipboard is empty, or doesn't hold the format");
+			EXIT_EMPTY
+		}
+		Err(Failure::Usage(message)) => {
+			eprintln!("arboard: {}\n\n{}", message, USAGE);
+			EXIT_ERROR
+		}
+		Err(Failure::Error(message)) => {
+			eprintln!("arboard: {}", message);
+			EXIT_ERROR
+		}
+	};
+	process::exit(status);
+}
+
+fn run(args: &[String]) -> Result<(), Failure> {
+	let (command, args) = match args.split_first() {
+		Some((command, args)) => (command.as_str(), args),
+		None => return Err(Failure::Usage("no command given".into())),
+	};
+	// The arguments are checked before the clipboard is opened, so that mistakes are reported
+	// even where there's no clipboard.
+	let options = match command {
+		"copy" => parse_options(args, false)?,
+		"paste" => parse_options(args, true)?,
+		"formats" | "watch" if args.is_empty() => Options { format: Format::Text, out: None },
+		"formats" | "watch" => {
+			return Err(Failure::Usage(format!("`{}` doesn't take arguments", command)))
+		}
+		"help" | "--help" | "-h" => {
+			println!("{}", USAGE);
+			return Ok(());
+		}
+		command => return Err(Failure::Usage(format!("unknown command `{}`", command))),
+	};
+	let mut clipboard = Clipboard::new()?;
+	match command {
+		"copy" => copy(&mut clipboard, &options.format),
+		"paste" => paste(&mut clipboard, &options),
+		"formats" => formats(&mut clipboard),
+		_ => watch(&mut clipboard),
+	}
+}
+
+fn parse_options(args: &[String], accepts_out: bool) -> Result<Options, Failure> {
+	let mut options = Options { format: Format::Text, out: None };
+	let mut args = args.iter();
+	while let Some(arg) = args.next() {
+		let mut value =
+			|| args.next().ok_or_else(|| Failure::Usage(format!("`{}` needs a value", arg)));
+		match arg.as_str() {
+			"--format" | "-f" => options.format = Format::parse(value()?),
+			"--out" | "-o" if accepts_out => options.out = Some(value()?.clone()),
+			arg => return Err(Failure::Usage(format!("unexpected argument `{}`", arg))),
+		}
+	}
+	Ok(options)
+}
+
+fn copy(clipboard: &mut Clipboard, format: &Format) -> Result<(), Failure> {
+	let mut input = Vec::new();
+	io::stdin().read_to_end(&mut input)?;
+	let text = || {
+		String::from_utf8(input.clone()).map_err(|_| {
+			Failure::Error("the input isn't UTF-8 text, use `--format` for other data".into())
+		})
+	};
+	match format {
+		Format::Text => clipboard.write().text(text()?).commit()?,
+		Format::Html => clipboard.write().html(text()?).commit()?,
+		Format::Png => {
+			let image = image::load_from_memory_with_format(&input, image::ImageFormat::Png)
+				.map_err(|e| Failure::Error(format!("the input isn't a PNG image: {}", e)))?
+				.into_rgba8();
+			let (width, height) = image.dimensions();
+			let bytes = image.into_raw().into();
+			clipboard.set_image(ImageData {
+				width: width as usize,
+				height: height as usize,
+				bytes,
+			})?
+		}
+		Format::Other(name) => clipboard.write().data(name.as_str(), input.clone()).commit()?,
+	}
+
+	// On X11 the contents are gone once arboard exits, unless a clipboard manager takes them over.
+	match clipboard.persist() {
+		Ok(()) => Ok(()),
+		Err(Error::NoClipboardManager) => {
+			eprintln!(
+				"arboard: no clipboard manager is running, waiting until the contents are pasted"
+			);
+			let write = clipboard.write().wait();
+			let write = match format {
+				Format::Text => write.text(text()?),
+				Format::Html => write.html(text()?),
+				// X11 is the only platform without a clipboard manager, where PNG is `image/png`.
+				Format::Png => write.data("image/png", input),
+				Format::Other(name) => write.data(name.as_str(), input),
+			};
+			Ok(write.commit()?)
+		}
+		Err(Error::ClipboardNotSupported) => {
+			eprintln!("arboard: the contents are only available until arboard exits here");
+			Ok(())
+		}
+		Err(e) => Err(e.into()),
+	}
+}
+
+fn paste(clipboard: &mut Clipboard, options: &Options) -> Result<(), Failure> {
+	let bytes = match &options.format {
+		Format::Text => clipboard.get_text()?.into_bytes(),
+		Format::Html => clipboard.get_html()?.into_bytes(),
+		Format::Png => {
+			let image = image::DynamicImage::try_from(clipboard.get_image()?)?;
+			let mut png = Vec::new();
+			image
+				.write_to(&mut png, image::ImageFormat::Png)
+				.map_err(|e| Failure::Error(format!("could not encode the image: {}", e)))?;
+			png
+		}
+		Format::Other(name) => clipboard
+			.items()?
+			.iter()
+			.find_map(|item| item.data_for_type(name))
+			.ok_or(Failure::Empty)?
+			.to_vec(),
+	};
+	match &options.out {
+		Some(path) => fs::write(path, bytes)?,
+		None => io::stdout().write_all(&bytes)?,
+	}
+	Ok(())
+}
+
+fn formats(clipboard: &mut Clipboard) -> Result<(), Failure> {
+	let formats = clipboard.available_formats()?;
+	if formats.is_empty() {
+		return Err(Failure::Empty);
+	}
+	for format in formats {
+		println!("{}", format);
+	}
+	Ok(())
+}
+
+/// Prints the formats whenever the clipboard changes, until arboard is interrupted.
+fn watch(clipboard: &mut Clipboard) -> Result<(), Failure> {
+	match ClipboardWatcher::new() {
+		Ok(watcher) => {
+			for _ in watcher.iter() {
+				print_change(clipboard);
+			}
+			Err(Failure::Error("the clipboard watcher stopped".into()))
+		}
+		// Without notifications, the clipboard is polled for a new generation.
+		Err(Error::ClipboardNotSupported) => {
+			let mut last = clipboard.generation()?;
+			loop {
+				thread::sleep(POLL_INTERVAL);
+				let generation = clipboard.generation()?;
+				if generation != last {
+					last = generation;
+					print_change(clipboard);
+				}
+			}
+		}
+		Err(e) => Err(e.into()),
+	}
+}
+
+fn print_change(clipboard: &mut Clipboard) {
+	match clipboard.available_formats() {
+		Ok(formats) if formats.is_empty() => println!("(empty)"),
+		Ok(formats) => println!("{}", formats.join(", ")),
+		Err(e) => eprintln!("arboard: could not read the formats: {}", e),
+	}
+}
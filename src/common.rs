@@ -9,27 +9,44 @@ and conditions of the chosen license apply to this file.
 */
 
 #[cfg(feature = "image-data")]
-use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::{borrow::Cow, time::Instant};
 use thiserror::Error;
 
 /// An error that might happen during a clipboard operation.
 ///
-/// Note that both the `Display` and the `Debug` trait is implemented for this type in such a way
-/// that they give a short human-readable description of the error; however the documentation
-/// gives a more detailed explanation for each error kind.
+/// The `Display` implementation gives a short human-readable description of the error, which
+/// includes the `description` of `Unknown` errors, so it's suitable for showing to users. The
+/// `Debug` implementation additionally names the kind of the error. The documentation gives a more
+/// detailed explanation for each error kind.
+///
+/// New kinds of errors may be added in the future, so matching on it requires a wildcard arm.
 #[derive(Error)]
+#[non_exhaustive]
 pub enum Error {
 	/// The clipboard contents were not available in the requested format.
 	/// This could either be due to the clipboard being empty or the clipboard contents having
 	/// an incompatible format to the requested one (eg when calling `get_image` on text)
+	///
+	/// On Android 10 and later this is also returned when reading the clipboard while the
+	/// application doesn't have the input focus.
 	#[error("The clipboard contents were not available in the requested format or the clipboard is empty.")]
 	ContentNotAvailable,
 
 	/// The selected clipboard is not supported by the current configuration (system and/or environment).
 	///
 	/// This can be caused by a few conditions:
+	/// - Creating a clipboard on Linux when no display server (or, in WSL, no Windows interop)
+	///   is available
 	/// - Using the Primary clipboard with an older Wayland compositor (that doesn't support version 2)
 	/// - Using the Secondary clipboard on Wayland
+	/// - Using the OSC 52 clipboard without a controlling terminal, reading from it with a terminal
+	///   that doesn't allow that, or transferring formats other than text (except for images,
+	///   which return `UnsupportedFormat`) through it
+	/// - Using the clipboard on Android before `ndk-context` was initialized, or transferring
+	///   formats other than text and HTML there (except for images, as above)
+	/// - Using the synchronous methods in a web browser, or the asynchronous ones in a page that
+	///   isn't a secure context (served over HTTPS or from localhost)
 	#[error("The selected clipboard is not supported with the current system configuration.")]
 	ClipboardNotSupported,
 
@@ -42,6 +59,9 @@ pub enum Error {
 	/// Note that it's OK to have multiple `Clipboard` instances. The underlying
 	/// implementation will make sure that the native clipboard is only
 	/// opened for transferring data and then closed as soon as possible.
+	///
+	/// On Windows, opening the clipboard is retried for a short while before this is returned,
+	/// see `ClipboardExtWindows::set_open_retry`.
 	#[error("The native clipboard is not accessible due to being held by an other party.")]
 	ClipboardOccupied,
 
@@ -49,10 +69,94 @@ pub enum Error {
 	///
 	/// - When returned from `set_image`: the image going to the clipboard cannot be converted to the appropriate format.
 	/// - When returned from `get_image`: the image coming from the clipboard could not be converted into the `ImageData` struct.
-	/// - When returned from `get_text`: the text coming from the clipboard is not valid utf-8 or cannot be converted to utf-8.
-	#[error("The image or the text that was about the be transferred to/from the clipboard could not be converted to the appropriate format.")]
+	#[error("The image or the text that was about to be transferred to/from the clipboard could not be converted to the appropriate format.")]
 	ConversionFailure,
 
+	/// The text on the clipboard isn't valid UTF-8, or, where the platform stores text as UTF-16
+	/// (Windows, macOS and iOS), it contains unpaired surrogates.
+	///
+	/// Returned by `get_text`, which doesn't alter the text it returns. `Clipboard::get_text_lossy`
+	/// returns the text with the invalid parts replaced by U+FFFD REPLACEMENT CHARACTER instead.
+	#[error("The text on the clipboard is not valid UTF-8.")]
+	InvalidUtf8 {
+		/// The text as it was read from the clipboard. Where the platform stores text as UTF-16,
+		/// it's converted to UTF-8 with the unpaired surrogates already replaced by U+FFFD.
+		bytes: Vec<u8>,
+	},
+
+	/// The requested format isn't supported by the platform, the backend or the configuration of
+	/// the crate, as opposed to data that failed to convert (see `ConversionFailure`).
+	///
+	/// This is returned by the image methods of the backends that only transfer text (OSC 52,
+	/// WSL and Android), and of all backends when the `image-data` feature is disabled. It's also
+	/// returned when the image on the clipboard is stored in a variant of its format that can't be
+	/// decoded, like a compressed `CF_DIB` on Windows.
+	#[error("The clipboard doesn't support the {format} format.")]
+	UnsupportedFormat {
+		/// The format that was requested, for example `image` or a platform specific name.
+		format: String,
+	},
+
+	/// The user or the platform denied the access to the clipboard.
+	///
+	/// Returned by the asynchronous methods in web browsers, which reject clipboard access that
+	/// wasn't granted by the user or that didn't happen in response to a user gesture, and by the
+	/// native Wayland backend when writing while none of the windows of the application has
+	/// keyboard focus.
+	#[error("The access to the clipboard was denied.")]
+	PermissionDenied,
+
+	/// The clipboard contents couldn't be handed over to a clipboard manager, because none is
+	/// running.
+	///
+	/// Only returned by `Clipboard::persist` on X11. The contents stay available as long as the
+	/// process keeps running, so the caller may choose to wait instead of exiting.
+	#[error("No clipboard manager is running that could take the clipboard contents over.")]
+	NoClipboardManager,
+
+	/// The pixels of an image take up more than the limit that was set with
+	/// `ClipboardBuilder::max_image_bytes`.
+	///
+	/// Returned by `get_image`, which then doesn't return the image, and by `set_image`, which
+	/// doesn't place the image onto the clipboard. `get_image` also returns it for encoded images
+	/// whose header claims more than 1 GiB of pixels, without a limit being set.
+	#[error("The image takes up {size} bytes, which exceeds the limit of {limit} bytes.")]
+	ImageTooLarge {
+		/// The size of the pixels of the image in bytes.
+		size: usize,
+		/// The limit that was exceeded.
+		limit: usize,
+	},
+
+	/// The operation didn't finish within the time set with `ClipboardBuilder::timeout`.
+	///
+	/// On X11, this is returned when the application that owns the selection doesn't answer a
+	/// read, or stops sending the data part way through. On Windows, it's returned when another
+	/// process keeps the clipboard open for longer than that.
+	#[error("The clipboard operation timed out.")]
+	Timeout,
+
+	/// The clipboard service of the platform can't be reached from this process, so no operation
+	/// can succeed.
+	///
+	/// Returned on macOS when the pasteboard server can't be reached, like in sandboxed helpers
+	/// that lack the entitlements, in daemons without a GUI session, on headless CI machines or
+	/// over SSH. Creating a clipboard returns it, or, with `Clipboard::new_for_process`, the first
+	/// operation. This points to the environment of the process rather than to a bug.
+	#[error("The clipboard of the platform is unavailable: {reason}")]
+	PlatformUnavailable {
+		/// Why the clipboard is unavailable.
+		reason: String,
+	},
+
+	/// A call to the native clipboard API failed.
+	///
+	/// The `PlatformError` names the function or request that failed, together with the error
+	/// code and the context that the platform reported. It's also the `source` of this error, so
+	/// error reporters that walk the chain of sources show the details.
+	#[error("A call to the native clipboard API failed.")]
+	Platform(#[source] PlatformError),
+
 	/// Any error that doesn't fit the other error types.
 	///
 	/// The `description` field is only meant to help the developer and should not be relied on as a
@@ -78,12 +182,642 @@ impl std::fmt::Debug for Error {
 			ClipboardNotSupported,
 			ClipboardOccupied,
 			ConversionFailure,
+			InvalidUtf8 { .. },
+			UnsupportedFormat { .. },
+			PermissionDenied,
+			NoClipboardManager,
+			ImageTooLarge { .. },
+			Timeout,
+			PlatformUnavailable { .. },
+			Platform(..),
 			Unknown { .. }
 		);
-		f.write_fmt(format_args!("{} - \"{}\"", name, self))
+		f.write_fmt(format_args!("{} - \"{}\"", name, self))?;
+		if let Platform(error) = self {
+			f.write_fmt(format_args!(" ({:?})", error))?;
+		}
+		Ok(())
+	}
+}
+
+/// The details of a failed call to the native clipboard API, see `Error::Platform`.
+///
+/// The `Display` implementation names the failed call and the error code, while the `Debug`
+/// implementation includes everything. The underlying error, like an `std::io::Error` made from
+/// the code that `GetLastError` returned on Windows, or the error of the X11 connection, is
+/// available as the `source`.
+#[derive(Debug)]
+pub struct PlatformError {
+	api: &'static str,
+	code: Option<i64>,
+	detail: Option<String>,
+	source: Option<Box<dyn std::error::Error + Send + Sync + 'static>>,
+}
+
+// Not every platform reports error codes and underlying errors.
+#[cfg_attr(
+	any(target_os = "macos", target_os = "ios", target_os = "android", target_arch = "wasm32"),
+	allow(dead_code)
+)]
+impl PlatformError {
+	pub(crate) fn new(api: &'static str) -> Self {
+		Self { api, code: None, detail: None, source: None }
+	}
+
+	pub(crate) fn with_code(mut self, code: i64) -> Self {
+		self.code = Some(code);
+		self
+	}
+
+	pub(crate) fn with_detail(mut self, detail: impl Into<String>) -> Self {
+		self.detail = Some(detail.into());
+		self
+	}
+
+	pub(crate) fn with_source(
+		mut self,
+		source: impl std::error::Error + Send + Sync + 'static,
+	) -> Self {
+		self.source = Some(Box::new(source));
+		self
+	}
+
+	/// The name of the function or request that failed, like `OpenClipboard` on Windows or
+	/// `convert_selection` on X11.
+	pub fn api(&self) -> &str {
+		self.api
+	}
+
+	/// The error code reported by the platform, like the value of `GetLastError` on Windows or
+	/// the code of the error the X server sent.
+	pub fn code(&self) -> Option<i64> {
+		self.code
+	}
+
+	/// Further context about the failure, like the atom or format that was involved.
+	pub fn detail(&self) -> Option<&str> {
+		self.detail.as_deref()
+	}
+}
+
+impl std::fmt::Display for PlatformError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "`{}` failed", self.api)?;
+		if let Some(code) = self.code {
+			write!(f, " with error code {}", code)?;
+		}
+		if let Some(detail) = &self.detail {
+			write!(f, " ({})", detail)?;
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for PlatformError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		self.source.as_ref().map(|source| source.as_ref() as &(dyn std::error::Error + 'static))
+	}
+}
+
+/// A notification delivered by a `ClipboardWatcher` when the contents of the clipboard change.
+///
+/// On Linux, more details about the change are available through `ClipboardEventExtLinux`.
+#[derive(Debug, Clone)]
+pub struct ClipboardEvent {
+	#[cfg(all(
+		unix,
+		not(any(
+			target_os = "macos",
+			target_os = "ios",
+			target_os = "android",
+			target_os = "emscripten"
+		))
+	))]
+	pub(crate) selection: crate::LinuxClipboardKind,
+	#[cfg(all(
+		unix,
+		not(any(
+			target_os = "macos",
+			target_os = "ios",
+			target_os = "android",
+			target_os = "emscripten"
+		))
+	))]
+	pub(crate) owner: Option<u32>,
+	/// The contents of the selection at the time of the event. Only available on Wayland.
+	#[cfg(all(
+		unix,
+		not(any(
+			target_os = "macos",
+			target_os = "ios",
+			target_os = "android",
+			target_os = "emscripten"
+		)),
+		feature = "wayland-data-control"
+	))]
+	pub(crate) offer: Option<std::sync::Arc<crate::wayland_data_control_clipboard::WaylandOffer>>,
+}
+
+/// The watcher used on platforms that don't support watching the clipboard yet.
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "android", target_arch = "wasm32"))]
+pub(crate) struct UnsupportedWatcher;
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "android", target_arch = "wasm32"))]
+impl UnsupportedWatcher {
+	pub(crate) fn new(_events: std::sync::mpsc::Sender<ClipboardEvent>) -> Result<Self, Error> {
+		Err(Error::ClipboardNotSupported)
 	}
 }
 
+/// Opens the same clipboard again on another thread, for running a blocking operation there.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) type Reopen<T> = Box<dyn FnOnce() -> Result<T, Error> + Send>;
+
+/// Runs `job` on a new thread, and returns a future that resolves to its result.
+///
+/// The future doesn't depend on any particular async runtime, as the thread wakes it up directly.
+#[cfg(all(feature = "async", not(target_arch = "wasm32")))]
+pub(crate) fn offload<T: Send + 'static>(
+	job: impl FnOnce() -> Result<T, Error> + Send + 'static,
+) -> Offload<T> {
+	use std::sync::{Arc, Mutex};
+
+	let shared = Arc::new(Mutex::new(OffloadState { result: None, waker: None }));
+	let sender = OffloadSender(Arc::clone(&shared));
+	let spawned = std::thread::Builder::new().name("arboard-async".into()).spawn(move || {
+		let result = job();
+		sender.send(result);
+	});
+	if let Err(e) = spawned {
+		// Replaces the error that the sender stored when it was dropped along with the closure.
+		let description = format!("couldn't start a thread for the clipboard: {}", e);
+		let mut state = shared.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+		state.result = Some(Err(Error::Unknown { description }));
+		drop(state);
+	}
+	Offload { shared }
+}
+
+#[cfg(all(feature = "async", not(target_arch = "wasm32")))]
+struct OffloadState<T> {
+	result: Option<Result<T, Error>>,
+	waker: Option<std::task::Waker>,
+}
+
+/// Stores the result of the thread, or an error if the thread panicked before producing one.
+#[cfg(all(feature = "async", not(target_arch = "wasm32")))]
+struct OffloadSender<T>(std::sync::Arc<std::sync::Mutex<OffloadState<T>>>);
+
+#[cfg(all(feature = "async", not(target_arch = "wasm32")))]
+impl<T> OffloadSender<T> {
+	fn send(&self, result: Result<T, Error>) {
+		let mut state = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+		if state.result.is_none() {
+			state.result = Some(result);
+		}
+		if let Some(waker) = state.waker.take() {
+			waker.wake();
+		}
+	}
+}
+
+#[cfg(all(feature = "async", not(target_arch = "wasm32")))]
+impl<T> Drop for OffloadSender<T> {
+	fn drop(&mut self) {
+		let description = "the clipboard thread panicked".into();
+		self.send(Err(Error::Unknown { description }));
+	}
+}
+
+/// The future returned by `offload`.
+#[cfg(all(feature = "async", not(target_arch = "wasm32")))]
+pub(crate) struct Offload<T> {
+	shared: std::sync::Arc<std::sync::Mutex<OffloadState<T>>>,
+}
+
+#[cfg(all(feature = "async", not(target_arch = "wasm32")))]
+impl<T> std::future::Future for Offload<T> {
+	type Output = Result<T, Error>;
+
+	fn poll(
+		self: std::pin::Pin<&mut Self>,
+		cx: &mut std::task::Context<'_>,
+	) -> std::task::Poll<Self::Output> {
+		let mut state = self.shared.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+		match state.result.take() {
+			Some(result) => std::task::Poll::Ready(result),
+			None => {
+				state.waker = Some(cx.waker().clone());
+				std::task::Poll::Pending
+			}
+		}
+	}
+}
+
+/// The line endings that text is converted to when it's read or written, see
+/// [`ClipboardGet::line_endings`](crate::ClipboardGet::line_endings) and
+/// [`ClipboardWrite::line_endings`](crate::ClipboardWrite::line_endings).
+///
+/// `\r\n`, a lone `\r` and a lone `\n` are each treated as a single line break, so text with
+/// mixed line endings is converted consistently, and converting text again doesn't change it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEndings {
+	/// `\n`, as used on Linux and macOS.
+	Unix,
+	/// `\r\n`, as used on Windows.
+	Dos,
+	/// The line endings of the platform the application runs on: `Dos` on Windows, and `Unix`
+	/// everywhere else.
+	Native,
+}
+
+impl LineEndings {
+	fn separator(self) -> &'static str {
+		match self {
+			LineEndings::Unix => "\n",
+			LineEndings::Dos => "\r\n",
+			LineEndings::Native if cfg!(windows) => "\r\n",
+			LineEndings::Native => "\n",
+		}
+	}
+
+	/// Returns the text with all of its line breaks replaced by these line endings.
+	pub(crate) fn apply(self, text: &str) -> String {
+		let separator = self.separator();
+		let mut result = String::with_capacity(text.len());
+		let mut chars = text.chars().peekable();
+		while let Some(c) = chars.next() {
+			match c {
+				'\r' => {
+					chars.next_if_eq(&'\n');
+					result.push_str(separator);
+				}
+				'\n' => result.push_str(separator),
+				c => result.push(c),
+			}
+		}
+		result
+	}
+}
+
+/// How long a `ClipboardWrite` keeps serving the contents after writing them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WaitConfig {
+	/// Returns right after writing.
+	None,
+	/// Until the contents were pasted, or another application placed its contents onto the
+	/// clipboard.
+	Forever,
+	/// The same as `Forever`, but no longer than until the deadline.
+	Until(Instant),
+}
+
+/// A single representation of the contents placed onto the clipboard by a `ClipboardWrite`.
+// The web backend can't write synchronously, so it never reads the representations.
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+#[derive(Debug, Clone)]
+pub(crate) enum Representation {
+	/// Plain text, stored in the platform's preferred text format.
+	Text(String),
+	/// An HTML fragment, stored in the platform's HTML format.
+	Html(String),
+	/// Arbitrary data, stored in the format with the given platform specific name.
+	Custom(String, Vec<u8>),
+}
+
+impl Representation {
+	/// Returns the number of bytes of the data.
+	pub(crate) fn len(&self) -> usize {
+		match self {
+			Representation::Text(text) | Representation::Html(text) => text.len(),
+			Representation::Custom(_, bytes) => bytes.len(),
+		}
+	}
+
+	/// Returns the name of the platform specific format, or `text` and `html` for the formats that
+	/// the backends choose.
+	#[cfg(feature = "tracing")]
+	pub(crate) fn name(&self) -> String {
+		match self {
+			Representation::Text(_) => "text".to_owned(),
+			Representation::Html(_) => "html".to_owned(),
+			Representation::Custom(format, _) => format.clone(),
+		}
+	}
+}
+
+/// Converts text that was read from the clipboard, keeping the bytes in the error if they aren't
+/// valid UTF-8.
+pub(crate) fn text_from_utf8(bytes: Vec<u8>) -> Result<String, Error> {
+	String::from_utf8(bytes).map_err(|e| Error::InvalidUtf8 { bytes: e.into_bytes() })
+}
+
+/// Converts the UTF-16 text of `CF_UNICODETEXT` on Windows.
+///
+/// The text ends at the first null, since the memory that holds it is often larger than the text.
+/// A leading byte order mark, which some applications like Excel add, is removed. Unpaired
+/// surrogates are replaced with U+FFFD, and the text is returned in an `Error::InvalidUtf8` then,
+/// which `Clipboard::get_text_lossy` accepts. Line endings are kept as they are.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) fn text_from_utf16(units: &[u16]) -> Result<String, Error> {
+	let end = units.iter().position(|&unit| unit == 0).unwrap_or(units.len());
+	let units = &units[..end];
+	let units = units.strip_prefix(&[0xFEFF]).unwrap_or(units);
+
+	let mut text = String::with_capacity(units.len());
+	let mut valid = true;
+	for c in std::char::decode_utf16(units.iter().copied()) {
+		match c {
+			Ok(c) => text.push(c),
+			Err(_) => {
+				valid = false;
+				text.push(std::char::REPLACEMENT_CHARACTER);
+			}
+		}
+	}
+	if valid {
+		Ok(text)
+	} else {
+		Err(Error::InvalidUtf8 { bytes: text.into_bytes() })
+	}
+}
+
+/// Converts HTML that was read from the clipboard, removing a leading byte order mark and
+/// converting the line endings to `\n`.
+pub(crate) fn html_from_utf8(bytes: Vec<u8>) -> Result<String, Error> {
+	let html = text_from_utf8(bytes)?;
+	let html = html.strip_prefix('\u{FEFF}').unwrap_or(&html);
+	Ok(LineEndings::Unix.apply(html))
+}
+
+/// Wraps an HTML fragment into the header required by the `HTML Format` clipboard format.
+///
+/// See: https://docs.microsoft.com/en-us/windows/win32/dataxchg/html-clipboard-format
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) fn wrap_html(fragment: &str) -> String {
+	const PREFIX: &str = "<html><body>\r\n<!--StartFragment-->";
+	const SUFFIX: &str = "<!--EndFragment-->\r\n</body></html>";
+	fn header(start_html: usize, end_html: usize, start_frag: usize, end_frag: usize) -> String {
+		format!(
+			"Version:0.9\r\nStartHTML:{:010}\r\nEndHTML:{:010}\r\nStartFragment:{:010}\r\nEndFragment:{:010}\r\n",
+			start_html, end_html, start_frag, end_frag
+		)
+	}
+	// The offsets are zero-padded, so the length of the header doesn't depend on them.
+	let start_html = header(0, 0, 0, 0).len();
+	let start_fragment = start_html + PREFIX.len();
+	let end_fragment = start_fragment + fragment.len();
+	let end_html = end_fragment + SUFFIX.len();
+	let mut result = header(start_html, end_html, start_fragment, end_fragment);
+	result.push_str(PREFIX);
+	result.push_str(fragment);
+	result.push_str(SUFFIX);
+	result
+}
+
+/// Returns the HTML in the `HTML Format` on Windows, without the header that precedes it and the
+/// null that follows it.
+///
+/// The header holds the byte offsets of the HTML (`StartHTML` and `EndHTML`), and of the copied
+/// fragment within it. Some applications don't give the offsets of the HTML (`StartHTML:-1`), the
+/// fragment is returned then. Data without a header is returned as it is.
+///
+/// See: https://docs.microsoft.com/en-us/windows/win32/dataxchg/html-clipboard-format
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) fn unwrap_cf_html(data: &[u8]) -> &[u8] {
+	let data = &data[..data.iter().position(|&b| b == 0).unwrap_or(data.len())];
+	// The header only consists of `Key:Value` lines, so it ends where the markup starts.
+	let header_end = data.iter().position(|&b| b == b'<').unwrap_or(data.len());
+	let header = String::from_utf8_lossy(&data[..header_end]);
+	if !header.starts_with("Version:") {
+		return data;
+	}
+	let offset = |key: &str| {
+		header.lines().find_map(|line| {
+			let value = line.strip_prefix(key)?.strip_prefix(':')?;
+			value.trim().parse::<usize>().ok()
+		})
+	};
+	let range = |start, end| match (offset(start), offset(end)) {
+		(Some(start), Some(end)) if start <= end && end <= data.len() => Some(&data[start..end]),
+		_ => None,
+	};
+	range("StartHTML", "EndHTML")
+		.or_else(|| range("StartFragment", "EndFragment"))
+		.unwrap_or(&data[header_end..])
+}
+
+/// Replaces the invalid parts of the text in an `Error::InvalidUtf8` with U+FFFD.
+pub(crate) fn lossy_text(result: Result<String, Error>) -> Result<String, Error> {
+	match result {
+		Err(Error::InvalidUtf8 { bytes }) => Ok(String::from_utf8_lossy(&bytes).into_owned()),
+		result => result,
+	}
+}
+
+/// Returns the decoded path of a `file://` URL, as macOS stores them in `public.file-url`. The
+/// authority has to be empty or `localhost`, as in `file:///path` or `file://localhost/path`, since
+/// other hosts can't be opened as a path.
+///
+/// Escapes that aren't followed by two hexadecimal digits are kept as they are, and `None` is
+/// returned if the decoded path isn't valid UTF-8.
+#[cfg(feature = "image-data")]
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+pub(crate) fn path_from_file_url(url: &str) -> Option<String> {
+	let rest = url.strip_prefix("file://")?;
+	let path = match rest.get(..9) {
+		Some(host) if host.eq_ignore_ascii_case("localhost") => &rest[9..],
+		_ => rest,
+	};
+	if !path.starts_with('/') {
+		return None;
+	}
+	let hex = |digit: u8| char::from(digit).to_digit(16);
+	let mut bytes = path.as_bytes().iter();
+	let mut decoded = Vec::with_capacity(path.len());
+	while let Some(&byte) = bytes.next() {
+		let escaped = match bytes.as_slice() {
+			[high, low, ..] if byte == b'%' => hex(*high).zip(hex(*low)),
+			_ => None,
+		};
+		match escaped {
+			Some((high, low)) => {
+				decoded.push((high * 16 + low) as u8);
+				bytes.nth(1);
+			}
+			None => decoded.push(byte),
+		}
+	}
+	String::from_utf8(decoded).ok()
+}
+
+/// One of the items on the clipboard, as returned by [`Clipboard::items`](crate::Clipboard::items).
+///
+/// The macOS pasteboard can hold several items, for example one for each of the files that were
+/// dragged, and each of them offers its own formats. The other platforms only have a single item.
+///
+/// With the `serde` feature, items can be serialized like [`ClipboardSnapshot`]s.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClipboardItem {
+	#[cfg_attr(
+		feature = "serde",
+		serde(
+			serialize_with = "byte_string::serialize_item",
+			deserialize_with = "byte_string::deserialize_item"
+		)
+	)]
+	formats: Vec<(String, Vec<u8>)>,
+}
+
+impl ClipboardItem {
+	/// Returns the names of the formats of the item, using the same naming as
+	/// [`ClipboardSnapshot::formats`].
+	pub fn types(&self) -> impl Iterator<Item = &str> {
+		self.formats.iter().map(|(format, _)| format.as_str())
+	}
+
+	/// Returns the data of the item in the given format, or `None` if the item doesn't offer it.
+	pub fn data_for_type(&self, format: &str) -> Option<&[u8]> {
+		self.formats.iter().find(|(f, _)| f == format).map(|(_, bytes)| bytes.as_slice())
+	}
+}
+
+/// A copy of all the formats that were available on the clipboard at a given moment.
+///
+/// A snapshot is created by [`Clipboard::snapshot`](crate::Clipboard::snapshot) and can be placed
+/// back onto the clipboard by [`Clipboard::restore`](crate::Clipboard::restore). It's plain data,
+/// so it can be stored for an arbitrary amount of time and sent between threads.
+///
+/// Formats are identified by the names the platform uses for them: UTIs on macOS, clipboard
+/// format names on Windows, target atom names on X11 and MIME types on Wayland.
+///
+/// With the `serde` feature, snapshots can be serialized, for example to keep a clipboard history
+/// on disk. The data of each format is serialized as a byte string, like the pixels of
+/// [`ImageData`].
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClipboardSnapshot {
+	/// Each item is a list of `(format, data)` pairs. Only macOS supports more than one item.
+	#[cfg_attr(
+		feature = "serde",
+		serde(
+			serialize_with = "byte_string::serialize_items",
+			deserialize_with = "byte_string::deserialize_items"
+		)
+	)]
+	pub(crate) items: Vec<Vec<(String, Vec<u8>)>>,
+	pub(crate) warnings: Vec<String>,
+}
+
+impl ClipboardSnapshot {
+	/// Returns the names of all the formats stored in the snapshot.
+	pub fn formats(&self) -> impl Iterator<Item = &str> {
+		self.items.iter().flatten().map(|(format, _)| format.as_str())
+	}
+
+	/// Returns the total number of data bytes stored in the snapshot.
+	pub fn size(&self) -> usize {
+		self.items.iter().flatten().map(|(_, bytes)| bytes.len()).sum()
+	}
+
+	/// Returns true if no formats were captured, either because the clipboard was empty or
+	/// because none of its formats could be read.
+	pub fn is_empty(&self) -> bool {
+		self.items.iter().all(|item| item.is_empty())
+	}
+
+	/// Returns a description for each format that was available on the clipboard but was left out
+	/// of the snapshot, because it couldn't be read or because it didn't fit into the size limit.
+	pub fn warnings(&self) -> &[String] {
+		&self.warnings
+	}
+
+	/// Starts a new item, to which the subsequent `push` calls add the formats.
+	#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+	pub(crate) fn new_item(&mut self) {
+		self.items.push(Vec::new());
+	}
+
+	/// Adds a format to the last item, unless doing so would make the snapshot larger than
+	/// `max_bytes`. In that case a warning is recorded instead.
+	#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+	pub(crate) fn push(&mut self, format: String, bytes: Vec<u8>, max_bytes: Option<usize>) {
+		if let Some(max_bytes) = max_bytes {
+			if self.size() + bytes.len() > max_bytes {
+				self.warnings.push(format!(
+					"Skipped `{}` ({} bytes) because the snapshot would exceed {} bytes",
+					format,
+					bytes.len(),
+					max_bytes
+				));
+				return;
+			}
+		}
+		if self.items.is_empty() {
+			self.new_item();
+		}
+		self.items.last_mut().unwrap().push((format, bytes));
+	}
+
+	/// Splits the snapshot into its items.
+	pub(crate) fn into_items(self) -> Vec<ClipboardItem> {
+		self.items.into_iter().map(|formats| ClipboardItem { formats }).collect()
+	}
+
+	/// Records that a format was skipped.
+	#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+	pub(crate) fn skip(&mut self, format: &str, reason: impl std::fmt::Display) {
+		self.warnings.push(format!("Skipped `{}`: {}", format, reason));
+	}
+}
+
+/// The format that an image was decoded from, see `Clipboard::get_image_with_format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ImageFormatTag {
+	/// PNG data, like `image/png` on Linux, `public.png` on iOS or the `PNG` format on Windows.
+	Png,
+	/// TIFF data, which is how images are stored on the macOS pasteboard.
+	Tiff,
+	/// A device independent bitmap, the `CF_DIB` format on Windows.
+	Dib,
+	/// An image file that the `public.file-url` on the macOS pasteboard points to.
+	FileUrl,
+	/// A format that the backend doesn't report, like an image that UIKit converted from a
+	/// `UIImage` on iOS, or one that a custom provider or the mock returned.
+	Other,
+}
+
+/// The order of the channels of the pixels that are passed to
+/// [`Clipboard::set_image_with_order`](crate::Clipboard::set_image_with_order).
+///
+/// [`ImageData`] always holds RGBA pixels, this describes pixels that come from elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PixelOrder {
+	/// Red, green, blue and alpha, like `ImageData`.
+	#[default]
+	Rgba,
+	/// Blue, green, red and alpha, which is how Windows bitmaps and many GPU framebuffers store
+	/// their pixels.
+	Bgra,
+}
+
+/// The format of the pixels that
+/// [`Clipboard::get_image_as`](crate::Clipboard::get_image_as) returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[non_exhaustive]
+pub enum PixelFormat {
+	/// Red, green, blue and alpha with 8 bits each, like `get_image` returns.
+	#[default]
+	Rgba8,
+	/// Blue, green, red and alpha with 8 bits each.
+	Bgra8,
+	/// Red, green, blue and alpha with 8 bits each, where the colors are multiplied by the alpha,
+	/// as many renderers expect them.
+	PremultipliedRgba8,
+}
+
 /// Stores pixel data of an image.
 ///
 /// Each element in `bytes` stores the value of a channel of a single pixel.
@@ -111,15 +845,168 @@ impl std::fmt::Debug for Error {
 ///     bytes: Cow::from(bytes.as_ref())
 /// };
 /// ```
-#[cfg(feature = "image-data")]
-#[derive(Debug, Clone)]
+///
+/// With the `serde` feature, the image can be serialized, for example to keep a clipboard history
+/// on disk. The bytes are serialized as a byte string, which formats like bincode store as they
+/// are. Deserializing always copies them, so the result can be an `ImageData<'static>`, and it
+/// fails if the number of bytes doesn't match the dimensions.
+///
+/// The type is available without the `image-data` feature too, so that code which passes images
+/// around compiles either way. The clipboard methods then return [`Error::UnsupportedFormat`].
+///
+/// Two images are equal if they have the same dimensions and the same pixels, no matter whether
+/// they own or borrow them, and `Hash` is consistent with that. This allows using images as keys,
+/// for example to remove duplicates from a clipboard history, but keep in mind that comparing and
+/// hashing go through all the bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "byte_string::RawImageData"))]
 pub struct ImageData<'a> {
 	pub width: usize,
 	pub height: usize,
+	#[cfg_attr(feature = "serde", serde(with = "byte_string"))]
 	pub bytes: Cow<'a, [u8]>,
 }
 
-#[cfg(feature = "image-data")]
+/// Serializes the pixels of an `ImageData` and the data of the clipboard formats as byte strings.
+/// They can be deserialized from a byte string or a sequence of numbers, which is how JSON
+/// represents them.
+#[cfg(feature = "serde")]
+mod byte_string {
+	use super::ImageData;
+	use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+	use std::{borrow::Cow, convert::TryFrom, fmt};
+
+	/// The `(format, data)` pairs of an item.
+	type Formats = Vec<(String, Vec<u8>)>;
+
+	pub(super) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_bytes(bytes)
+	}
+
+	pub(super) fn deserialize<'de, 'a, D: Deserializer<'de>>(
+		deserializer: D,
+	) -> Result<Cow<'a, [u8]>, D::Error> {
+		deserializer.deserialize_byte_buf(BytesVisitor).map(Cow::Owned)
+	}
+
+	/// An `ImageData` as it was deserialized, before checking the number of bytes.
+	#[derive(Deserialize)]
+	#[serde(rename = "ImageData")]
+	pub(super) struct RawImageData {
+		width: usize,
+		height: usize,
+		#[serde(with = "self")]
+		bytes: Cow<'static, [u8]>,
+	}
+
+	impl<'a> TryFrom<RawImageData> for ImageData<'a> {
+		type Error = String;
+
+		fn try_from(raw: RawImageData) -> Result<Self, Self::Error> {
+			let expected_len =
+				raw.width.checked_mul(raw.height).and_then(|pixels| pixels.checked_mul(4));
+			if expected_len != Some(raw.bytes.len()) {
+				return Err(format!(
+					"a {}x{} image can't have {} bytes",
+					raw.width,
+					raw.height,
+					raw.bytes.len()
+				));
+			}
+			Ok(ImageData { width: raw.width, height: raw.height, bytes: raw.bytes })
+		}
+	}
+
+	/// Serializes the formats of a `ClipboardItem`.
+	pub(super) fn serialize_item<S: Serializer>(
+		formats: &[(String, Vec<u8>)],
+		serializer: S,
+	) -> Result<S::Ok, S::Error> {
+		ItemRef(formats).serialize(serializer)
+	}
+
+	pub(super) fn deserialize_item<'de, D: Deserializer<'de>>(
+		deserializer: D,
+	) -> Result<Formats, D::Error> {
+		ItemBuf::deserialize(deserializer).map(|item| item.0)
+	}
+
+	/// Serializes the items of a `ClipboardSnapshot`.
+	pub(super) fn serialize_items<S: Serializer>(
+		items: &[Formats],
+		serializer: S,
+	) -> Result<S::Ok, S::Error> {
+		serializer.collect_seq(items.iter().map(|formats| ItemRef(formats)))
+	}
+
+	pub(super) fn deserialize_items<'de, D: Deserializer<'de>>(
+		deserializer: D,
+	) -> Result<Vec<Formats>, D::Error> {
+		let items = Vec::<ItemBuf>::deserialize(deserializer)?;
+		Ok(items.into_iter().map(|item| item.0).collect())
+	}
+
+	struct ItemRef<'a>(&'a [(String, Vec<u8>)]);
+
+	impl Serialize for ItemRef<'_> {
+		fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+			serializer.collect_seq(self.0.iter().map(|(format, bytes)| (format, BytesRef(bytes))))
+		}
+	}
+
+	struct ItemBuf(Formats);
+
+	impl<'de> Deserialize<'de> for ItemBuf {
+		fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+			let formats = Vec::<(String, ByteBuf)>::deserialize(deserializer)?;
+			Ok(ItemBuf(formats.into_iter().map(|(format, bytes)| (format, bytes.0)).collect()))
+		}
+	}
+
+	struct BytesRef<'a>(&'a [u8]);
+
+	impl Serialize for BytesRef<'_> {
+		fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+			serializer.serialize_bytes(self.0)
+		}
+	}
+
+	struct ByteBuf(Vec<u8>);
+
+	impl<'de> Deserialize<'de> for ByteBuf {
+		fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+			deserializer.deserialize_byte_buf(BytesVisitor).map(ByteBuf)
+		}
+	}
+
+	struct BytesVisitor;
+
+	impl<'de> de::Visitor<'de> for BytesVisitor {
+		type Value = Vec<u8>;
+
+		fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+			f.write_str("the bytes of the pixels")
+		}
+
+		fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Self::Value, E> {
+			Ok(bytes.to_vec())
+		}
+
+		fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Self::Value, E> {
+			Ok(bytes)
+		}
+
+		fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+			let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+			while let Some(byte) = seq.next_element()? {
+				bytes.push(byte);
+			}
+			Ok(bytes)
+		}
+	}
+}
+
 impl<'a> ImageData<'a> {
 	/// Returns a the bytes field in a way that it's guaranteed to be owned.
 	/// It moves the bytes if they are already owned and clones them if they are borrowed.
@@ -136,4 +1023,660 @@ impl<'a> ImageData<'a> {
 			bytes: self.bytes.clone().into_owned().into(),
 		}
 	}
+
+	/// Returns an image data that borrows the bytes of `self`. This never copies the pixels.
+	pub fn borrowed(&self) -> ImageData<'_> {
+		ImageData { width: self.width, height: self.height, bytes: Cow::Borrowed(&self.bytes) }
+	}
+
+	/// Resamples the image to `width` by `height` pixels, without preserving the aspect ratio.
+	///
+	/// Returns `Error::ConversionFailure` if either of the new dimensions is zero, or if the number
+	/// of bytes doesn't match the dimensions of `self`.
+	#[cfg(feature = "image-data")]
+	pub fn resize(&self, width: usize, height: usize) -> Result<ImageData<'static>, Error> {
+		let new_width = u32::try_from(width).map_err(|_| Error::ConversionFailure)?;
+		let new_height = u32::try_from(height).map_err(|_| Error::ConversionFailure)?;
+		if new_width == 0 || new_height == 0 {
+			return Err(Error::ConversionFailure);
+		}
+		let pixels = into_rgba_image(self.borrowed())?;
+		let resized = image::imageops::resize(
+			&pixels,
+			new_width,
+			new_height,
+			image::imageops::FilterType::Lanczos3,
+		);
+		Ok(ImageData { width, height, bytes: resized.into_raw().into() })
+	}
+}
+
+/// Shrinks the image so that neither of its dimensions exceeds `max_dimension`, preserving the
+/// aspect ratio. Images that are small enough are returned as they are.
+#[cfg(feature = "image-data")]
+pub(crate) fn downscale_image(
+	image: ImageData<'_>,
+	max_dimension: usize,
+) -> Result<ImageData<'_>, Error> {
+	let max_dimension = max_dimension.max(1);
+	let larger = image.width.max(image.height);
+	if larger <= max_dimension {
+		return Ok(image);
+	}
+	let scale = |dimension: usize| ((dimension * max_dimension + larger / 2) / larger).max(1);
+	image.resize(scale(image.width), scale(image.height))
+}
+
+/// Converts the pixels of `image`, which are in `order`, to RGBA. RGBA images are returned as
+/// they are, the others are copied once, unless they already own their pixels.
+#[cfg(feature = "image-data")]
+pub(crate) fn reorder_pixels(image: ImageData<'_>, order: PixelOrder) -> ImageData<'_> {
+	match order {
+		PixelOrder::Rgba => image,
+		PixelOrder::Bgra => {
+			let mut bytes = image.bytes.into_owned();
+			for pixel in bytes.chunks_exact_mut(4) {
+				pixel.swap(0, 2);
+			}
+			ImageData { width: image.width, height: image.height, bytes: bytes.into() }
+		}
+	}
+}
+
+/// Converts the RGBA pixels of `image` to `format`, in place if the image owns its pixels.
+#[cfg(feature = "image-data")]
+pub(crate) fn convert_pixels(image: ImageData<'_>, format: PixelFormat) -> ImageData<'_> {
+	let mut bytes = match format {
+		PixelFormat::Rgba8 => return image,
+		PixelFormat::Bgra8 | PixelFormat::PremultipliedRgba8 => image.bytes.into_owned(),
+	};
+	match format {
+		PixelFormat::Bgra8 => bytes.chunks_exact_mut(4).for_each(|pixel| pixel.swap(0, 2)),
+		_ => premultiply_rgba(&mut bytes),
+	}
+	ImageData { width: image.width, height: image.height, bytes: bytes.into() }
+}
+
+/// Multiplies the colors of the RGBA pixels in `pixels` by their alpha, converting straight alpha
+/// to the premultiplied alpha that, for example, CoreGraphics prefers.
+///
+/// The pixels are 8 bit RGBA values, like the `bytes` of [`ImageData`]. An incomplete pixel at the
+/// end is left as it is.
+///
+/// # Examples
+///
+/// ```
+/// let mut pixels = [200, 100, 50, 128];
+/// arboard::premultiply_rgba(&mut pixels);
+/// assert_eq!(pixels, [100, 50, 25, 128]);
+/// ```
+#[cfg(feature = "image-data")]
+pub fn premultiply_rgba(pixels: &mut [u8]) {
+	for pixel in pixels.chunks_exact_mut(4) {
+		let alpha = u16::from(pixel[3]);
+		for channel in &mut pixel[..3] {
+			*channel = ((u16::from(*channel) * alpha + 127) / 255) as u8;
+		}
+	}
+}
+
+/// Divides the colors of the RGBA pixels in `pixels` by their alpha, converting premultiplied
+/// alpha back to the straight alpha that, for example, the web expects. This is the inverse of
+/// [`premultiply_rgba`], as far as the rounding allows.
+///
+/// Fully transparent pixels keep their colors, which premultiplying set to zero. Colors that
+/// exceed their alpha, which isn't valid premultiplied alpha, become 255.
+///
+/// # Examples
+///
+/// ```
+/// let mut pixels = [100, 50, 25, 128];
+/// arboard::unpremultiply_rgba(&mut pixels);
+/// assert_eq!(pixels, [199, 100, 50, 128]);
+/// ```
+#[cfg(feature = "image-data")]
+pub fn unpremultiply_rgba(pixels: &mut [u8]) {
+	for pixel in pixels.chunks_exact_mut(4) {
+		let alpha = u32::from(pixel[3]);
+		if alpha == 0 {
+			continue;
+		}
+		for channel in &mut pixel[..3] {
+			*channel = ((u32::from(*channel) * 255 + alpha / 2) / alpha).min(255) as u8;
+		}
+	}
+}
+
+/// The largest image that `decode_image` decodes, in bytes of RGBA pixels. That's a square of
+/// 16384 pixels, or about eight times an 8K (7680x4320) screenshot.
+#[cfg(feature = "image-data")]
+pub(crate) const MAX_DECODED_IMAGE_BYTES: usize = 1 << 30;
+
+/// Decodes image data in `format`, as a platform stores it, into RGBA pixels.
+///
+/// The data comes from whichever application owns the clipboard, so anything that can't be
+/// decoded is reported as `Error::ConversionFailure`, as are the formats whose decoder isn't
+/// built on this platform. The decoders allocate the pixels that the header claims before reading
+/// them (the PNG decoder even while reading the header), so the header is checked first, and
+/// images larger than `MAX_DECODED_IMAGE_BYTES` return `Error::ImageTooLarge`.
+#[cfg(feature = "image-data")]
+pub(crate) fn decode_image(
+	format: ImageFormatTag,
+	data: &[u8],
+) -> Result<ImageData<'static>, Error> {
+	let (width, height) =
+		crate::lazy_image::probe_dimensions(format, data).ok_or(Error::ConversionFailure)?;
+	let size = width.saturating_mul(height).saturating_mul(4);
+	if size > MAX_DECODED_IMAGE_BYTES {
+		return Err(Error::ImageTooLarge { size, limit: MAX_DECODED_IMAGE_BYTES });
+	}
+	let format = match format {
+		ImageFormatTag::Png => image::ImageFormat::Png,
+		ImageFormatTag::Tiff => image::ImageFormat::Tiff,
+		#[cfg(windows)]
+		ImageFormatTag::Dib => return crate::windows_clipboard::decode_dib(data.to_vec()),
+		_ => return Err(Error::ConversionFailure),
+	};
+	let image = image::load_from_memory_with_format(data, format)
+		.map_err(|_| Error::ConversionFailure)?
+		.into_rgba8();
+	let (width, height) = image.dimensions();
+	Ok(ImageData { width: width as usize, height: height as usize, bytes: image.into_raw().into() })
+}
+
+/// Returns `Error::ConversionFailure` if the number of bytes doesn't match the dimensions.
+#[cfg(feature = "image-data")]
+fn into_rgba_image(image: ImageData<'_>) -> Result<image::RgbaImage, Error> {
+	let expected_len =
+		image.width.checked_mul(image.height).and_then(|pixels| pixels.checked_mul(4));
+	if expected_len != Some(image.bytes.len()) {
+		return Err(Error::ConversionFailure);
+	}
+	let width = u32::try_from(image.width).map_err(|_| Error::ConversionFailure)?;
+	let height = u32::try_from(image.height).map_err(|_| Error::ConversionFailure)?;
+	image::RgbaImage::from_raw(width, height, image.bytes.into_owned())
+		.ok_or(Error::ConversionFailure)
+}
+
+/// Converts the pixels into an RGBA8 image.
+///
+/// Returns `Error::ConversionFailure` if the number of bytes doesn't match the dimensions.
+#[cfg(feature = "image-data")]
+impl TryFrom<ImageData<'_>> for image::DynamicImage {
+	type Error = Error;
+
+	fn try_from(image: ImageData<'_>) -> Result<Self, Self::Error> {
+		Ok(image::DynamicImage::ImageRgba8(into_rgba_image(image)?))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn error_display() {
+		let error = Error::Unknown { description: "the display server went away".into() };
+		assert_eq!(
+			error.to_string(),
+			"Unknown error while interacting with the clipboard: the display server went away"
+		);
+		assert_eq!(format!("{:?}", error), format!("Unknown {{ .. }} - \"{}\"", error));
+		assert!(Error::ConversionFailure.to_string().contains("could not be converted"));
+		let error = Error::UnsupportedFormat { format: "image".into() };
+		assert_eq!(error.to_string(), "The clipboard doesn't support the image format.");
+		assert!(format!("{:?}", error).starts_with("UnsupportedFormat { .. }"));
+		assert_eq!(
+			format!("{:?}", Error::Timeout),
+			"Timeout - \"The clipboard operation timed out.\""
+		);
+		let error = Error::PlatformUnavailable { reason: "no GUI session".into() };
+		assert_eq!(
+			error.to_string(),
+			"The clipboard of the platform is unavailable: no GUI session"
+		);
+		assert!(format!("{:?}", error).starts_with("PlatformUnavailable { .. }"));
+		let error = Error::ImageTooLarge { size: 24, limit: 16 };
+		assert_eq!(
+			error.to_string(),
+			"The image takes up 24 bytes, which exceeds the limit of 16 bytes."
+		);
+
+		use std::error::Error as _;
+		let io_error = std::io::Error::other("access denied");
+		let error = Error::Platform(
+			PlatformError::new("OpenClipboard")
+				.with_code(5)
+				.with_detail("CF_DIB")
+				.with_source(io_error),
+		);
+		assert_eq!(error.to_string(), "A call to the native clipboard API failed.");
+		let platform = error.source().unwrap();
+		assert_eq!(platform.to_string(), "`OpenClipboard` failed with error code 5 (CF_DIB)");
+		assert_eq!(platform.source().unwrap().to_string(), "access denied");
+		let debug = format!("{:?}", error);
+		assert!(debug.starts_with("Platform(..) - "));
+		assert!(debug.contains("code: Some(5)") && debug.contains("access denied"));
+		if let Error::Platform(platform) = &error {
+			assert_eq!(
+				(platform.api(), platform.code(), platform.detail()),
+				("OpenClipboard", Some(5), Some("CF_DIB"))
+			);
+		}
+	}
+
+	#[test]
+	fn invalid_utf8_text() {
+		use super::{lossy_text, text_from_utf8};
+
+		// Latin-1, as sent by X11 applications that label it as UTF-8.
+		let latin1 = b"caf\xE9".to_vec();
+		assert!(
+			matches!(text_from_utf8(latin1.clone()), Err(Error::InvalidUtf8 { ref bytes }) if *bytes == latin1)
+		);
+		assert_eq!(lossy_text(text_from_utf8(latin1)).unwrap(), "caf\u{FFFD}");
+		// A truncated sequence and a lone continuation byte.
+		assert_eq!(
+			lossy_text(text_from_utf8(vec![0xE2, 0x82, b'x', 0x80])).unwrap(),
+			"\u{FFFD}x\u{FFFD}"
+		);
+		assert_eq!(lossy_text(text_from_utf8(b"valid".to_vec())).unwrap(), "valid");
+		assert!(matches!(
+			lossy_text(Err(Error::ContentNotAvailable)),
+			Err(Error::ContentNotAvailable)
+		));
+	}
+
+	#[test]
+	fn utf16_text() {
+		use super::{lossy_text, text_from_utf16};
+
+		let wide = |text: &str| text.encode_utf16().collect::<Vec<u16>>();
+
+		// A byte order mark is only removed at the start.
+		let mut bom = vec![0xFEFF];
+		bom.extend(wide("a\u{FEFF}b"));
+		assert_eq!(text_from_utf16(&bom).unwrap(), "a\u{FEFF}b");
+		// The memory block is larger than the text, and holds garbage after the null.
+		let mut padded = wide("line 1\r\nline 2\r\n");
+		padded.extend([0, b'x' as u16, 0xD800, 0]);
+		assert_eq!(text_from_utf16(&padded).unwrap(), "line 1\r\nline 2\r\n");
+		assert_eq!(text_from_utf16(&[]).unwrap(), "");
+		assert_eq!(text_from_utf16(&[0xFEFF, 0]).unwrap(), "");
+		assert_eq!(text_from_utf16(&wide("🐔 🤓")).unwrap(), "🐔 🤓");
+
+		// A lone high surrogate, followed by a character and at the end of the text.
+		let lone = [b'a' as u16, 0xD83D, b'b' as u16, 0xD83D, 0];
+		assert!(matches!(
+			text_from_utf16(&lone),
+			Err(Error::InvalidUtf8 { ref bytes }) if bytes == "a\u{FFFD}b\u{FFFD}".as_bytes()
+		));
+		assert_eq!(lossy_text(text_from_utf16(&lone)).unwrap(), "a\u{FFFD}b\u{FFFD}");
+		// A lone low surrogate.
+		assert_eq!(lossy_text(text_from_utf16(&[0xDC00, b'c' as u16])).unwrap(), "\u{FFFD}c");
+	}
+
+	#[test]
+	fn html_cleanup() {
+		use super::{html_from_utf8, unwrap_cf_html};
+
+		let html = b"\xEF\xBB\xBF<p>one\r\ntwo\rthree</p>\n".to_vec();
+		assert_eq!(html_from_utf8(html).unwrap(), "<p>one\ntwo\nthree</p>\n");
+		assert_eq!(html_from_utf8(b"<b>\xEF\xBB\xBF</b>".to_vec()).unwrap(), "<b>\u{FEFF}</b>");
+		assert!(matches!(html_from_utf8(b"<b>\xE9</b>".to_vec()), Err(Error::InvalidUtf8 { .. })));
+
+		let header = "Version:0.9\r\nStartHTML:0000000105\r\nEndHTML:0000000144\r\n\
+		              StartFragment:0000000117\r\nEndFragment:0000000130\r\n";
+		assert_eq!(header.len(), 105);
+		let cf_html = format!("{}<html><body>{}</body></html>\0\0", header, "<b>copied</b>");
+		assert_eq!(unwrap_cf_html(cf_html.as_bytes()), b"<html><body><b>copied</b></body></html>");
+		let fragment_only = cf_html.replace("StartHTML:0000000105", "StartHTML:-000000001");
+		assert_eq!(unwrap_cf_html(fragment_only.as_bytes()), b"<b>copied</b>");
+		let out_of_bounds = cf_html.replace("EndHTML:0000000144", "EndHTML:0000009999");
+		assert_eq!(unwrap_cf_html(out_of_bounds.as_bytes()), b"<b>copied</b>");
+		assert_eq!(unwrap_cf_html(b"<i>no header</i>\0garbage"), b"<i>no header</i>");
+	}
+
+	#[test]
+	fn line_endings_conversion() {
+		assert_eq!(LineEndings::Unix.apply("a\r\nb\rc\nd"), "a\nb\nc\nd");
+		assert_eq!(LineEndings::Dos.apply("a\r\nb\rc\nd"), "a\r\nb\r\nc\r\nd");
+		assert_eq!(LineEndings::Dos.apply("\r\r\n\n"), "\r\n\r\n\r\n");
+		assert_eq!(LineEndings::Unix.apply("\n\r"), "\n\n");
+		assert_eq!(LineEndings::Unix.apply("no breaks"), "no breaks");
+	}
+
+	#[test]
+	fn image_data_hash() {
+		use std::collections::HashSet;
+
+		let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8];
+		let owned = ImageData { width: 2, height: 1, bytes: bytes.clone().into() };
+		let borrowed = ImageData { width: 2, height: 1, bytes: bytes.as_slice().into() };
+		let transposed = ImageData { width: 1, height: 2, bytes: bytes.as_slice().into() };
+		assert_eq!(owned, borrowed);
+		assert_ne!(owned, transposed);
+
+		let history =
+			[owned.clone(), borrowed, transposed, owned].iter().cloned().collect::<HashSet<_>>();
+		assert_eq!(history.len(), 2);
+	}
+
+	#[cfg(feature = "serde")]
+	#[test]
+	fn image_data_serde() {
+		let bytes = [255, 0, 0, 255, 0, 255, 0, 255];
+		let image = ImageData { width: 2, height: 1, bytes: bytes.as_ref().into() };
+		let json = serde_json::to_string(&image).unwrap();
+		assert_eq!(json, r#"{"width":2,"height":1,"bytes":[255,0,0,255,0,255,0,255]}"#);
+
+		let deserialized: ImageData<'static> = serde_json::from_str(&json).unwrap();
+		assert_eq!((deserialized.width, deserialized.height), (2, 1));
+		assert_eq!(deserialized.bytes, bytes.as_ref());
+		assert!(matches!(deserialized.bytes, std::borrow::Cow::Owned(_)));
+
+		let encoded = bincode::serialize(&image).unwrap();
+		// The two dimensions, the length and then the bytes as they are.
+		assert_eq!(encoded.len(), 3 * 8 + bytes.len());
+		let decoded: ImageData<'static> = bincode::deserialize(&encoded).unwrap();
+		assert_eq!((decoded.width, decoded.height), (2, 1));
+		assert_eq!(decoded.bytes, bytes.as_ref());
+
+		let inconsistent = r#"{"width":2,"height":2,"bytes":[255,0,0,255,0,255,0,255]}"#;
+		assert!(serde_json::from_str::<ImageData>(inconsistent).is_err());
+		let overflowing = ImageData { width: usize::MAX, height: 2, bytes: bytes.as_ref().into() };
+		let encoded = bincode::serialize(&overflowing).unwrap();
+		assert!(bincode::deserialize::<ImageData>(&encoded).is_err());
+	}
+
+	#[cfg(feature = "serde")]
+	#[test]
+	fn snapshot_serde() {
+		let mut snapshot = ClipboardSnapshot::default();
+		snapshot.push("text/plain".to_owned(), b"text".to_vec(), None);
+		snapshot.new_item();
+		snapshot.push("public.png".to_owned(), vec![0x89, b'P'], None);
+		snapshot.push("too large".to_owned(), vec![0; 16], Some(8));
+
+		let json = serde_json::to_string(&snapshot).unwrap();
+		assert_eq!(
+			json,
+			r#"{"items":[[["text/plain",[116,101,120,116]]],[["public.png",[137,80]]]],"warnings":["Skipped `too large` (16 bytes) because the snapshot would exceed 8 bytes"]}"#
+		);
+		let encoded = bincode::serialize(&snapshot).unwrap();
+		for decoded in [
+			serde_json::from_str::<ClipboardSnapshot>(&json).unwrap(),
+			bincode::deserialize::<ClipboardSnapshot>(&encoded).unwrap(),
+		] {
+			assert_eq!(decoded.items, snapshot.items);
+			assert_eq!(decoded.warnings(), snapshot.warnings());
+		}
+
+		let item = snapshot.into_items().remove(1);
+		let decoded: ClipboardItem =
+			bincode::deserialize(&bincode::serialize(&item).unwrap()).unwrap();
+		assert_eq!(decoded.data_for_type("public.png"), Some([0x89, b'P'].as_ref()));
+	}
+
+	#[cfg(feature = "image-data")]
+	#[test]
+	fn file_url_paths() {
+		use super::path_from_file_url;
+
+		assert_eq!(path_from_file_url("file:///tmp/a%20b.png").as_deref(), Some("/tmp/a b.png"));
+		assert_eq!(path_from_file_url("file://localhost/tmp/a.png").as_deref(), Some("/tmp/a.png"));
+		assert_eq!(path_from_file_url("file://LOCALHOST/tmp/a.png").as_deref(), Some("/tmp/a.png"));
+		assert_eq!(path_from_file_url("file://localhost").as_deref(), None);
+		assert_eq!(path_from_file_url("file://server/tmp/a.png").as_deref(), None);
+		assert_eq!(path_from_file_url("/tmp/a.png").as_deref(), None);
+		assert_eq!(path_from_file_url("file:///caf%C3%a9").as_deref(), Some("/café"));
+		assert_eq!(path_from_file_url("file:///100%25%").as_deref(), Some("/100%%"));
+		assert_eq!(path_from_file_url("file:///a%zzb%4").as_deref(), Some("/a%zzb%4"));
+		assert_eq!(path_from_file_url("file:///%FF").as_deref(), None);
+	}
+
+	#[cfg(feature = "image-data")]
+	#[test]
+	fn image_resize() {
+		let red = [255, 0, 0, 255];
+		let image = ImageData { width: 4, height: 2, bytes: red.repeat(8).into() };
+		let resized = image.resize(2, 3).unwrap();
+		assert_eq!((resized.width, resized.height), (2, 3));
+		assert_eq!(resized.bytes.len(), 2 * 3 * 4);
+		assert!(resized.bytes.chunks(4).all(|pixel| pixel == red));
+		assert!(matches!(resized.bytes, std::borrow::Cow::Owned(_)));
+
+		assert!(matches!(image.resize(0, 1), Err(Error::ConversionFailure)));
+		let truncated = ImageData { width: 4, height: 2, bytes: red.as_ref().into() };
+		assert!(matches!(truncated.resize(1, 1), Err(Error::ConversionFailure)));
+	}
+
+	#[cfg(feature = "image-data")]
+	#[test]
+	fn premultiplied_alpha() {
+		let mut pixels = [255, 128, 0, 255, 255, 128, 0, 0, 200, 100, 50, 128, 7];
+		premultiply_rgba(&mut pixels);
+		assert_eq!(pixels, [255, 128, 0, 255, 0, 0, 0, 0, 100, 50, 25, 128, 7]);
+		unpremultiply_rgba(&mut pixels);
+		assert_eq!(pixels, [255, 128, 0, 255, 0, 0, 0, 0, 199, 100, 50, 128, 7]);
+		let mut invalid = [200, 0, 0, 100];
+		unpremultiply_rgba(&mut invalid);
+		assert_eq!(invalid, [255, 0, 0, 100]);
+
+		// Every valid premultiplied color survives the round trip, and every straight one is off by
+		// no more than the rounding of premultiplying it allows.
+		for alpha in 1..=255u8 {
+			for color in 0..=255u8 {
+				let mut pixel = [color, 0, 0, alpha];
+				premultiply_rgba(&mut pixel);
+				unpremultiply_rgba(&mut pixel);
+				let error = (i32::from(pixel[0]) - i32::from(color)).abs();
+				assert!(error <= 255 / (2 * i32::from(alpha)) + 1, "{} at alpha {}", color, alpha);
+				if color <= alpha {
+					let mut pixel = [color, 0, 0, alpha];
+					unpremultiply_rgba(&mut pixel);
+					premultiply_rgba(&mut pixel);
+					assert_eq!(pixel[0], color, "at alpha {}", alpha);
+				}
+			}
+		}
+	}
+
+	#[cfg(feature = "image-data")]
+	#[test]
+	fn fuzz_regressions() {
+		use super::{decode_image, MAX_DECODED_IMAGE_BYTES};
+
+		// Inputs that crashed the `image` fuzz target, whose first byte chooses the format.
+		let formats = [ImageFormatTag::Png, ImageFormatTag::Tiff, ImageFormatTag::Dib];
+		let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/regressions/image");
+		for entry in std::fs::read_dir(dir).unwrap() {
+			let path = entry.unwrap().path();
+			let data = std::fs::read(&path).unwrap();
+			let format = formats[usize::from(data[0]) % formats.len()];
+			assert!(decode_image(format, &data[1..]).is_err(), "{} was decoded", path.display());
+		}
+
+		// The decoder allocated the pixels, or a row of them, that the header claimed before reading
+		// them. The repeated header has small dimensions in the first one, and huge ones in the next.
+		let fixtures: [&[u8]; 4] = [
+			include_bytes!("../fuzz/regressions/image/png-huge-dimensions"),
+			include_bytes!("../fuzz/regressions/image/png-huge-row"),
+			include_bytes!("../fuzz/regressions/image/png-repeated-header"),
+			include_bytes!("../fuzz/regressions/image/png-width-overflow"),
+		];
+		for data in fixtures {
+			assert!(matches!(
+				decode_image(ImageFormatTag::Png, &data[1..]),
+				Err(Error::ImageTooLarge { limit: MAX_DECODED_IMAGE_BYTES, .. })
+			));
+		}
+	}
+}
+
+/// Properties of the parsers of the data that other applications place onto the clipboard. The
+/// fuzz targets in `fuzz/` run the same functions with arbitrary data.
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod parser_properties {
+	use super::{html_from_utf8, text_from_utf16, unwrap_cf_html, wrap_html, Error, LineEndings};
+	use proptest::prelude::*;
+
+	/// Percent-encodes every byte of `path` except for letters, digits and slashes.
+	#[cfg(feature = "image-data")]
+	fn percent_encode(path: &str, upper: bool) -> String {
+		path.bytes()
+			.map(|byte| match byte {
+				b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' => char::from(byte).to_string(),
+				_ if upper => format!("%{:02X}", byte),
+				_ => format!("%{:02x}", byte),
+			})
+			.collect()
+	}
+
+	proptest! {
+		#[test]
+		fn line_endings(text in "[a🦀 \r\n]{0,32}") {
+			let unix = LineEndings::Unix.apply(&text);
+			let dos = LineEndings::Dos.apply(&text);
+			prop_assert!(!unix.contains('\r'));
+			prop_assert_eq!(LineEndings::Unix.apply(&unix), unix.clone());
+			prop_assert_eq!(LineEndings::Dos.apply(&dos), dos.clone());
+			prop_assert_eq!(LineEndings::Unix.apply(&dos), unix.clone());
+			prop_assert_eq!(dos.replace("\r\n", "\n"), unix);
+			let native = LineEndings::Native.apply(&text);
+			prop_assert_eq!(LineEndings::Native.apply(&native), native);
+		}
+
+		#[test]
+		fn utf16_round_trip(text in "\\PC*") {
+			prop_assume!(!text.contains('\0') && !text.starts_with('\u{FEFF}'));
+			let units: Vec<u16> = text.encode_utf16().collect();
+			prop_assert_eq!(text_from_utf16(&units).unwrap(), text);
+		}
+
+		#[test]
+		fn utf16_arbitrary(units in prop::collection::vec(any::<u16>(), 0..64)) {
+			let end = units.iter().position(|&unit| unit == 0).unwrap_or(units.len());
+			let text = units[..end].strip_prefix(&[0xFEFF]).unwrap_or(&units[..end]);
+			let lossy = String::from_utf16_lossy(text);
+			match text_from_utf16(&units) {
+				Ok(converted) => prop_assert_eq!(Some(converted), String::from_utf16(text).ok()),
+				Err(Error::InvalidUtf8 { bytes }) => {
+					prop_assert!(String::from_utf16(text).is_err());
+					prop_assert_eq!(bytes, lossy.into_bytes());
+				}
+				Err(e) => prop_assert!(false, "unexpected error: {}", e),
+			}
+		}
+
+		#[test]
+		fn cf_html_round_trip(fragment in "[^\0]{0,64}") {
+			let wrapped = wrap_html(&fragment);
+			let html = format!(
+				"<html><body>\r\n<!--StartFragment-->{}<!--EndFragment-->\r\n</body></html>",
+				fragment
+			);
+			prop_assert_eq!(unwrap_cf_html(wrapped.as_bytes()), html.as_bytes());
+			let read = html_from_utf8(unwrap_cf_html(wrapped.as_bytes()).to_vec()).unwrap();
+			prop_assert_eq!(read, LineEndings::Unix.apply(html.trim_start_matches('\u{FEFF}')));
+		}
+
+		#[test]
+		fn cf_html_headers(
+			offsets in prop::collection::vec((0..4usize, -2i64..128), 0..6),
+			markup in "[<a-z>\0\r\n]{0,64}",
+		) {
+			let keys = ["StartHTML", "EndHTML", "StartFragment", "EndFragment"];
+			let mut data = String::from("Version:0.9\r\n");
+			for (key, offset) in offsets {
+				data.push_str(&format!("{}:{}\r\n", keys[key], offset));
+			}
+			data.push_str(&markup);
+			let html = unwrap_cf_html(data.as_bytes());
+			let (all, part) = (data.as_bytes().as_ptr_range(), html.as_ptr_range());
+			prop_assert!(all.start <= part.start && part.end <= all.end);
+			prop_assert!(!html.contains(&0));
+		}
+	}
+
+	#[cfg(feature = "image-data")]
+	proptest! {
+		#[test]
+		fn file_url_round_trip(
+			segments in prop::collection::vec("[^/]{0,8}", 1..4),
+			localhost in any::<bool>(),
+			upper in any::<bool>(),
+		) {
+			use super::path_from_file_url;
+
+			let path = format!("/{}", segments.join("/"));
+			let host = if localhost { "localhost" } else { "" };
+			let url = format!("file://{}{}", host, percent_encode(&path, upper));
+			prop_assert_eq!(path_from_file_url(&url), Some(path));
+		}
+
+		#[test]
+		fn file_url_arbitrary(url in "(file://(localhost)?)?[a-z/%0-9A-F\\PC]{0,16}") {
+			if let Some(path) = super::path_from_file_url(&url) {
+				prop_assert!(path.starts_with('/'));
+			}
+		}
+
+		#[test]
+		fn dib_header(width in any::<i32>(), height in any::<i32>(), size in 40u32..=124) {
+			use super::ImageFormatTag;
+			use crate::lazy_image::probe_dimensions;
+			use std::convert::TryFrom;
+
+			let mut dib = size.to_le_bytes().to_vec();
+			dib.extend_from_slice(&width.to_le_bytes());
+			dib.extend_from_slice(&height.to_le_bytes());
+			let expected = match (usize::try_from(width), height.checked_abs()) {
+				(Ok(width), Some(height)) if width > 0 && height > 0 => {
+					Some((width, height as usize))
+				}
+				_ => None,
+			};
+			prop_assert_eq!(probe_dimensions(ImageFormatTag::Dib, &dib), expected);
+		}
+
+		#[test]
+		fn png_round_trip(width in 1u32..16, height in 1u32..16, seed in any::<u32>()) {
+			use super::{decode_image, ImageFormatTag};
+			use crate::lazy_image::probe_dimensions;
+
+			let pixels: Vec<u8> = (0..width * height * 4)
+				.map(|i| (i.wrapping_mul(2_654_435_761) ^ seed) as u8)
+				.collect();
+			let image = image::RgbaImage::from_raw(width, height, pixels.clone()).unwrap();
+			let mut png = Vec::new();
+			image::DynamicImage::ImageRgba8(image)
+				.write_to(&mut png, image::ImageFormat::Png)
+				.unwrap();
+			let dimensions = (width as usize, height as usize);
+			prop_assert_eq!(probe_dimensions(ImageFormatTag::Png, &png), Some(dimensions));
+			let decoded = decode_image(ImageFormatTag::Png, &png).unwrap();
+			prop_assert_eq!((decoded.width, decoded.height), dimensions);
+			prop_assert_eq!(decoded.bytes.into_owned(), pixels);
+		}
+
+		#[test]
+		fn image_arbitrary(format in 0..3usize, data in prop::collection::vec(any::<u8>(), 0..64)) {
+			use super::{decode_image, ImageFormatTag};
+			use crate::lazy_image::probe_dimensions;
+
+			let format = [ImageFormatTag::Png, ImageFormatTag::Tiff, ImageFormatTag::Dib][format];
+			// A PNG signature, so that the decoder gets further than that.
+			let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+			png.extend_from_slice(&data);
+			for data in [&data, &png] {
+				if let Some((width, height)) = probe_dimensions(format, data) {
+					prop_assert!(width > 0 && height > 0);
+				}
+				if let Ok(image) = decode_image(format, data) {
+					prop_assert_eq!(image.bytes.len(), image.width * image.height * 4);
+				}
+			}
+		}
+	}
 }
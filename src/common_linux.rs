@@ -1,14 +1,25 @@
+use std::{borrow::Cow, ffi::OsStr, sync::mpsc::Sender, time::Duration};
 #[cfg(feature = "image-data")]
 use std::{cell::RefCell, rc::Rc};
 
+#[cfg(feature = "osc52")]
+use crate::osc52_clipboard::Osc52ClipboardContext;
+#[cfg(feature = "wayland-native")]
+use crate::wayland_clipboard::WaylandClipboardContext;
 #[cfg(feature = "wayland-data-control")]
-use crate::wayland_data_control_clipboard::WaylandDataControlClipboardContext;
-#[cfg(feature = "wayland-data-control")]
-use log::{info, warn};
+use crate::wayland_data_control_clipboard::{
+	WaylandDataControlClipboardContext, WaylandDataControlWatcher,
+};
+#[cfg(feature = "wsl")]
+use crate::wsl_clipboard::WslClipboardContext;
 
+use crate::{
+	common::{Representation, WaitConfig},
+	x11_clipboard::{X11ClipboardContext, X11ClipboardWatcher},
+	Backend, ClipboardEvent, ClipboardSnapshot, Error,
+};
 #[cfg(feature = "image-data")]
-use crate::ImageData;
-use crate::{x11_clipboard::X11ClipboardContext, Error};
+use crate::{ImageData, ImageFormatTag};
 
 pub fn into_unknown<E: std::fmt::Display>(error: E) -> Error {
 	Error::Unknown { description: format!("{}", error) }
@@ -57,7 +68,7 @@ pub fn encode_as_png(image: &ImageData) -> Result<Vec<u8>, Error> {
 }
 
 /// Clipboard selection
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum LinuxClipboardKind {
 	/// Typically used selection for explicit cut/copy/paste actions (ie. windows/macos like
 	/// clipboard behavior)
@@ -67,7 +78,9 @@ pub enum LinuxClipboardKind {
 	/// mouse click.
 	///
 	/// *On Wayland, this may not be available for all systems (requires a compositor supporting
-	/// version 2 or above) and operations using this will return an error if unsupported.*
+	/// version 2 or above of the data control protocol, or the primary selection protocol with
+	/// the core protocol backend) and operations using this will return an error if
+	/// unsupported.*
 	Primary,
 
 	/// The secondary clipboard is rarely used but theoretically available on X11.
@@ -77,6 +90,154 @@ pub enum LinuxClipboardKind {
 	Secondary,
 }
 
+/// The protocol that a [`Clipboard`](crate::Clipboard) uses to talk to the display server.
+///
+/// More backends may be added, and `LinuxBackend::Mock` only exists with the `test-util`
+/// feature, so matches on this enum need a wildcard arm.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LinuxBackend {
+	/// The X11 selections. Also used for X11 applications running under XWayland.
+	X11,
+
+	/// The Wayland `wlr-data-control` protocol, which doesn't require a focused window.
+	WaylandDataControl,
+
+	/// The core Wayland data device protocol, either on the connection of the application through
+	/// [`Clipboard::from_wayland_display`](crate::Clipboard::from_wayland_display), or on a
+	/// connection of its own.
+	WaylandNative,
+
+	/// The OSC 52 escape sequence, which is used by `Clipboard::new_osc52`.
+	Osc52,
+
+	/// The clipboard of the Windows host, when running inside the Windows Subsystem for Linux.
+	Wsl,
+
+	/// A [`ClipboardProvider`](crate::ClipboardProvider) of the application, which is used by
+	/// [`Clipboard::from_provider`](crate::Clipboard::from_provider).
+	Custom,
+
+	/// The in-memory [`MockClipboard`](crate::MockClipboard), which is used by
+	/// [`Clipboard::new_mock`](crate::Clipboard::new_mock).
+	#[cfg(feature = "test-util")]
+	Mock,
+}
+
+impl LinuxBackend {
+	/// The backends that can be selected, with the names that `ARBOARD_BACKEND` accepts for them.
+	const SELECTABLE: &'static [(&'static str, LinuxBackend)] = &[
+		("x11", LinuxBackend::X11),
+		#[cfg(feature = "wayland-data-control")]
+		("wayland", LinuxBackend::WaylandDataControl),
+		#[cfg(feature = "wayland-native")]
+		("wayland-native", LinuxBackend::WaylandNative),
+		#[cfg(feature = "osc52")]
+		("osc52", LinuxBackend::Osc52),
+		#[cfg(feature = "wsl")]
+		("wsl", LinuxBackend::Wsl),
+		#[cfg(feature = "test-util")]
+		("mock", LinuxBackend::Mock),
+	];
+
+	/// Returns a human readable name, as returned by
+	/// [`Clipboard::backend_name`](crate::Clipboard::backend_name).
+	pub(crate) fn name(self) -> &'static str {
+		match self {
+			LinuxBackend::X11 => "X11",
+			LinuxBackend::WaylandDataControl => "Wayland (data control)",
+			LinuxBackend::WaylandNative => "Wayland (core protocol)",
+			LinuxBackend::Osc52 => "OSC 52",
+			LinuxBackend::Wsl => "WSL (Windows host)",
+			LinuxBackend::Custom => "Custom",
+			#[cfg(feature = "test-util")]
+			LinuxBackend::Mock => "Mock",
+		}
+	}
+
+	/// Returns the error for a backend that was requested but isn't part of this build.
+	fn unavailable(requested: &str) -> Error {
+		let available =
+			Self::SELECTABLE.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ");
+		Error::Unknown {
+			description: format!(
+				"the clipboard backend {} isn't available in this build, the available backends are: {}",
+				requested, available
+			),
+		}
+	}
+}
+
+impl std::fmt::Display for LinuxBackend {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.name())
+	}
+}
+
+/// The MIME type that KDE's clipboard history (Klipper) and some other clipboard managers check
+/// for, with the value `secret`, to leave out passwords.
+pub(crate) const PASSWORD_MANAGER_HINT: &str = "x-kde-passwordManagerHint";
+
+/// The environment variable that forces a backend, for example `ARBOARD_BACKEND=x11`.
+pub(crate) const BACKEND_ENV_VAR: &str = "ARBOARD_BACKEND";
+
+/// Returns the backend that the value of `ARBOARD_BACKEND` selects, or `None` if it's unset or
+/// empty.
+pub(crate) fn backend_override(value: Option<&OsStr>) -> Result<Option<LinuxBackend>, Error> {
+	let value = match value.filter(|value| !value.is_empty()) {
+		Some(value) => value.to_string_lossy(),
+		None => return Ok(None),
+	};
+	let name = value.trim().to_ascii_lowercase();
+	match LinuxBackend::SELECTABLE.iter().find(|(selectable, _)| *selectable == name) {
+		Some((_, backend)) => Ok(Some(*backend)),
+		None => {
+			Err(LinuxBackend::unavailable(&format!("`{}` (selected by {})", name, BACKEND_ENV_VAR)))
+		}
+	}
+}
+
+/// Returns whether the session runs a Wayland compositor, given the values of the
+/// `WAYLAND_DISPLAY` and `XDG_SESSION_TYPE` environment variables. Without `WAYLAND_DISPLAY`,
+/// clients connect to `wayland-0`, which is where the compositor of a Wayland session listens.
+pub(crate) fn is_wayland_session(
+	wayland_display: Option<&OsStr>,
+	session_type: Option<&OsStr>,
+) -> bool {
+	wayland_display.is_some_and(|display| !display.is_empty())
+		|| session_type.is_some_and(|session_type| session_type == "wayland")
+}
+
+fn wayland_session_from_env() -> bool {
+	is_wayland_session(
+		std::env::var_os("WAYLAND_DISPLAY").as_deref(),
+		std::env::var_os("XDG_SESSION_TYPE").as_deref(),
+	)
+}
+
+/// Returns the backends that `Clipboard::new` tries, in order, given whether the session runs a
+/// Wayland compositor and whether the process runs inside WSL.
+pub(crate) fn backend_candidates(wayland: bool, wsl: bool) -> Vec<LinuxBackend> {
+	let mut candidates = Vec::new();
+	if cfg!(feature = "wayland-data-control") && wayland {
+		candidates.push(LinuxBackend::WaylandDataControl);
+	}
+	// X11 is always tried. Under XWayland it keeps working even if the Wayland socket isn't
+	// accessible or the compositor lacks the data control protocol.
+	candidates.push(LinuxBackend::X11);
+	// Without XWayland, the core protocol is the last resort. On a connection of its own it never
+	// has keyboard focus, which most compositors require for accessing the clipboard.
+	if cfg!(feature = "wayland-native") && wayland {
+		candidates.push(LinuxBackend::WaylandNative);
+	}
+	// A display server shares the clipboard with Windows too (e.g. WSLg), and it's faster and
+	// supports more than text, so the Windows clipboard is only used if none is available.
+	if cfg!(feature = "wsl") && wsl {
+		candidates.push(LinuxBackend::Wsl);
+	}
+	candidates
+}
+
 /// Linux has a concept of clipboard "selections" which tend to be used in different contexts. This
 /// trait extension provides a way to get/set to a specific clipboard (the default
 /// [LinuxClipboardKind::Clipboard] being used for the common platform API).
@@ -113,30 +274,200 @@ pub trait ClipboardExtLinux {
 	/// Fetches utf-8 text from the selected clipboard and returns it. If wayland support is enabled
 	/// and available, attempting to use the Secondary clipboard will return an error.
 	fn get_text_with_clipboard(&mut self, clipboard: LinuxClipboardKind) -> Result<String, Error>;
+
+	/// Returns the protocol that this clipboard uses, which `Clipboard::new` chooses at runtime.
+	fn backend(&self) -> LinuxBackend;
 }
 
 impl ClipboardExtLinux for super::Clipboard {
-	fn get_text_with_clipboard(&mut self, selection: LinuxClipboardKind) -> Result<String, Error> {
-		match &mut self.platform {
-			LinuxClipboard::X11(cb) => cb.get_text_with_clipboard(selection),
-
-			#[cfg(feature = "wayland-data-control")]
-			LinuxClipboard::WlDataControl(cb) => cb.get_text_with_clipboard(selection),
+	fn backend(&self) -> LinuxBackend {
+		match &self.backend {
+			Backend::Platform(platform) => platform.backend(),
+			Backend::Custom(_) => LinuxBackend::Custom,
+			#[cfg(feature = "test-util")]
+			Backend::Mock(_) => LinuxBackend::Mock,
 		}
 	}
 
+	fn get_text_with_clipboard(&mut self, selection: LinuxClipboardKind) -> Result<String, Error> {
+		dispatch!(&mut self.backend, cb => cb.get_text_with_clipboard(selection))
+	}
+
 	fn set_text_with_clipboard(
 		&mut self,
 		text: String,
 		selection: LinuxClipboardKind,
 	) -> Result<(), Error> {
-		match &mut self.platform {
-			LinuxClipboard::X11(cb) => cb.set_text_with_clipboard(text, selection),
+		dispatch!(&mut self.backend, cb => cb.set_text_with_clipboard(text, selection))
+	}
+}
 
-			#[cfg(feature = "wayland-data-control")]
-			LinuxClipboard::WlDataControl(cb) => cb.set_text_with_clipboard(text, selection),
+/// Selects the clipboard that a [`ClipboardGet`](crate::ClipboardGet) reads from.
+///
+/// # Examples
+///
+/// ```no_run
+/// use arboard::{Clipboard, ClipboardGetExtLinux, LinuxClipboardKind};
+/// let mut ctx = Clipboard::new().unwrap();
+/// let selected = ctx.get().clipboard_kind(LinuxClipboardKind::Primary).text().unwrap();
+/// ```
+pub trait ClipboardGetExtLinux {
+	/// Reads from the given clipboard instead of [`LinuxClipboardKind::Clipboard`].
+	fn clipboard_kind(self, clipboard: LinuxClipboardKind) -> Self;
+}
+
+impl ClipboardGetExtLinux for crate::ClipboardGet<'_> {
+	fn clipboard_kind(mut self, clipboard: LinuxClipboardKind) -> Self {
+		self.selection = clipboard;
+		self
+	}
+}
+
+/// Selects the clipboard that a [`ClipboardWrite`](crate::ClipboardWrite) writes to.
+///
+/// Only the selected clipboard is replaced, the contents of the others stay as they were.
+///
+/// # Examples
+///
+/// ```no_run
+/// use arboard::{Clipboard, ClipboardWriteExtLinux, LinuxClipboardKind};
+/// let mut ctx = Clipboard::new().unwrap();
+/// ctx.write().clipboard_kind(LinuxClipboardKind::Primary).text("selected").commit().unwrap();
+/// ```
+pub trait ClipboardWriteExtLinux {
+	/// Writes to the given clipboard instead of [`LinuxClipboardKind::Clipboard`].
+	fn clipboard_kind(self, clipboard: LinuxClipboardKind) -> Self;
+}
+
+impl ClipboardWriteExtLinux for crate::ClipboardWrite<'_> {
+	fn clipboard_kind(mut self, clipboard: LinuxClipboardKind) -> Self {
+		self.selection = clipboard;
+		self
+	}
+}
+
+/// Selects the backend that a [`ClipboardBuilder`](crate::ClipboardBuilder) creates the clipboard
+/// with.
+///
+/// By default, `Clipboard::new` tries the Wayland data control protocol in Wayland sessions, then
+/// X11, then the core Wayland protocol in Wayland sessions, then the Windows host inside WSL. The
+/// `ARBOARD_BACKEND` environment variable forces a backend too, by one of the names `x11`,
+/// `wayland`, `wayland-native`, `osc52` and `wsl`, which helps with debugging and with headless
+/// CI. A backend selected here takes precedence over the variable.
+///
+/// A forced backend is never substituted by another one. If it can't be used, or if it wasn't
+/// compiled in (see the `wayland-data-control`, `wayland-native`, `osc52` and `wsl` features),
+/// `build` returns an error that lists the backends of this build. [`LinuxBackend::WaylandNative`]
+/// opens a connection of its own here, while
+/// [`Clipboard::from_wayland_display`](crate::Clipboard::from_wayland_display) shares the one of
+/// the application. Tests that shouldn't touch any clipboard can use `MockClipboard` from the
+/// `test-util` feature through the [`ClipboardProvider`](crate::ClipboardProvider) trait instead.
+///
+/// # Examples
+///
+/// ```no_run
+/// use arboard::{Clipboard, ClipboardBuilderExtLinux, LinuxBackend};
+///
+/// let mut ctx = Clipboard::builder().backend(LinuxBackend::X11).build().unwrap();
+/// assert_eq!(ctx.backend_name(), "X11");
+/// ```
+pub trait ClipboardBuilderExtLinux {
+	/// Uses the given backend instead of choosing one at runtime.
+	fn backend(self, backend: LinuxBackend) -> Self;
+}
+
+impl ClipboardBuilderExtLinux for crate::ClipboardBuilder {
+	fn backend(mut self, backend: LinuxBackend) -> Self {
+		self.backend = Some(backend);
+		self
+	}
+}
+
+/// Linux specific details of a [`ClipboardEvent`].
+pub trait ClipboardEventExtLinux {
+	/// The selection whose contents changed.
+	fn selection(&self) -> LinuxClipboardKind;
+
+	/// The id of the X11 window that owns the selection after the change, if it's known and the
+	/// selection has an owner.
+	fn owner(&self) -> Option<u32>;
+
+	/// The MIME types that the contents of the selection were offered in at the time of the
+	/// event.
+	///
+	/// Only known on Wayland. Returns `None` on X11, or if the selection was cleared.
+	fn mime_types(&self) -> Option<&[String]>;
+
+	/// Reads the contents of the selection in the given MIME type, from the same offer that
+	/// `mime_types` describes.
+	///
+	/// Returns `Error::ContentNotAvailable` if the selection was cleared or the MIME type isn't
+	/// offered. The owner may refuse to provide the contents once the selection has changed again,
+	/// in which case the returned bytes may be empty. Only supported on Wayland, returns
+	/// `Error::ClipboardNotSupported` on X11.
+	fn read(&self, mime_type: &str) -> Result<Vec<u8>, Error>;
+}
+
+impl ClipboardEventExtLinux for ClipboardEvent {
+	fn selection(&self) -> LinuxClipboardKind {
+		self.selection
+	}
+
+	fn owner(&self) -> Option<u32> {
+		self.owner
+	}
+
+	#[cfg(feature = "wayland-data-control")]
+	fn mime_types(&self) -> Option<&[String]> {
+		self.offer.as_ref().map(|offer| offer.mime_types())
+	}
+
+	#[cfg(not(feature = "wayland-data-control"))]
+	fn mime_types(&self) -> Option<&[String]> {
+		None
+	}
+
+	#[cfg(feature = "wayland-data-control")]
+	fn read(&self, mime_type: &str) -> Result<Vec<u8>, Error> {
+		match &self.offer {
+			Some(offer) => offer.read(mime_type),
+			None if self.owner.is_some() => Err(Error::ClipboardNotSupported),
+			None => Err(Error::ContentNotAvailable),
 		}
 	}
+
+	#[cfg(not(feature = "wayland-data-control"))]
+	fn read(&self, _mime_type: &str) -> Result<Vec<u8>, Error> {
+		Err(Error::ClipboardNotSupported)
+	}
+}
+
+/// Watches the clipboard with the same protocol that `LinuxClipboard` would use.
+// The watchers are only held on to until they are dropped.
+#[allow(dead_code)]
+pub(crate) enum LinuxWatcher {
+	X11(X11ClipboardWatcher),
+
+	#[cfg(feature = "wayland-data-control")]
+	WlDataControl(WaylandDataControlWatcher),
+}
+
+impl LinuxWatcher {
+	pub(crate) fn new(events: Sender<ClipboardEvent>) -> Result<Self, Error> {
+		#[cfg(feature = "wayland-data-control")]
+		{
+			if wayland_session_from_env() {
+				// There's no fallback to X11 here: the X11 selections don't reliably follow the
+				// Wayland clipboard, so the watcher would silently miss most of the changes.
+				// The receiver only goes away while the watcher is being dropped.
+				let watcher = WaylandDataControlWatcher::new(move |event| {
+					let _ = events.send(event);
+				})?;
+				return Ok(Self::WlDataControl(watcher));
+			}
+		}
+		Ok(Self::X11(X11ClipboardWatcher::new(events)?))
+	}
 }
 
 pub enum LinuxClipboard {
@@ -144,27 +475,195 @@ pub enum LinuxClipboard {
 
 	#[cfg(feature = "wayland-data-control")]
 	WlDataControl(WaylandDataControlClipboardContext),
+
+	#[cfg(feature = "wayland-native")]
+	WlNative(WaylandClipboardContext),
+
+	#[cfg(feature = "osc52")]
+	Osc52(Osc52ClipboardContext),
+
+	#[cfg(feature = "wsl")]
+	Wsl(WslClipboardContext),
 }
 
 impl LinuxClipboard {
-	pub fn new() -> Result<Self, Error> {
-		#[cfg(feature = "wayland-data-control")]
-		{
-			if std::env::var_os("WAYLAND_DISPLAY").is_some() {
-				// Wayland is available
-				match WaylandDataControlClipboardContext::new() {
-					Ok(clipboard) => {
-						info!("Successfully initialized the Wayland data control clipboard.");
-						return Ok( Self::WlDataControl(clipboard))
-					},
-					Err(e) => warn!(
-						"Tried to initialize the wayland data control protocol clipboard, but failed. Falling back to the X11 clipboard protocol. The error was: {}",
-						e
-					),
+	/// Returns the protocol that this clipboard uses.
+	pub(crate) fn backend(&self) -> LinuxBackend {
+		match self {
+			Self::X11(_) => LinuxBackend::X11,
+
+			#[cfg(feature = "wayland-data-control")]
+			Self::WlDataControl(_) => LinuxBackend::WaylandDataControl,
+
+			#[cfg(feature = "wayland-native")]
+			Self::WlNative(_) => LinuxBackend::WaylandNative,
+
+			#[cfg(feature = "osc52")]
+			Self::Osc52(_) => LinuxBackend::Osc52,
+
+			#[cfg(feature = "wsl")]
+			Self::Wsl(_) => LinuxBackend::Wsl,
+		}
+	}
+
+	pub(crate) fn get_text_with_clipboard(
+		&mut self,
+		selection: LinuxClipboardKind,
+	) -> Result<String, Error> {
+		match self {
+			Self::X11(cb) => cb.get_text_with_clipboard(selection),
+
+			#[cfg(feature = "wayland-data-control")]
+			Self::WlDataControl(cb) => cb.get_text_with_clipboard(selection),
+
+			#[cfg(feature = "wayland-native")]
+			Self::WlNative(cb) => cb.get_text_with_clipboard(selection),
+
+			#[cfg(feature = "osc52")]
+			Self::Osc52(cb) => cb.get_text_with_clipboard(selection),
+
+			#[cfg(feature = "wsl")]
+			Self::Wsl(cb) => cb.get_text_with_clipboard(selection),
+		}
+	}
+
+	pub(crate) fn set_text_with_clipboard(
+		&mut self,
+		text: String,
+		selection: LinuxClipboardKind,
+	) -> Result<(), Error> {
+		match self {
+			Self::X11(cb) => cb.set_text_with_clipboard(text, selection),
+
+			#[cfg(feature = "wayland-data-control")]
+			Self::WlDataControl(cb) => cb.set_text_with_clipboard(text, selection),
+
+			#[cfg(feature = "wayland-native")]
+			Self::WlNative(cb) => cb.set_text_with_clipboard(text, selection),
+
+			#[cfg(feature = "osc52")]
+			Self::Osc52(cb) => cb.set_text_with_clipboard(text, selection),
+
+			#[cfg(feature = "wsl")]
+			Self::Wsl(cb) => cb.set_text_with_clipboard(text, selection),
+		}
+	}
+
+	/// Sets how long a read waits for the data, on the backends that wait for another
+	/// application to answer (X11 and OSC 52).
+	pub(crate) fn set_timeout(&mut self, timeout: Duration) {
+		match self {
+			Self::X11(cb) => cb.set_read_timeout(timeout),
+			#[cfg(feature = "osc52")]
+			Self::Osc52(cb) => cb.set_query_timeout(timeout),
+			#[allow(unreachable_patterns)]
+			_ => {}
+		}
+	}
+
+	/// Only X11 can be used from another thread. The other backends run the operations of the
+	/// async methods on the calling thread, and don't support `clear_after`.
+	pub(crate) fn reopen(&self) -> Option<crate::common::Reopen<Self>> {
+		match self {
+			Self::X11(cb) => {
+				let cb = cb.clone();
+				Some(Box::new(move || Ok(Self::X11(cb))))
+			}
+			#[allow(unreachable_patterns)]
+			_ => None,
+		}
+	}
+
+	/// Connects to `backend`, or if it's `None`, to the first backend from `backend_candidates`
+	/// that works. `ARBOARD_BACKEND` is handled by the caller, since it can also select the mock.
+	///
+	/// If only a single backend was tried, its error is returned unchanged. If none of them is
+	/// supported in this environment (for example because no display server is running),
+	/// `Error::ClipboardNotSupported` is returned. Otherwise the error lists every backend that was
+	/// tried along with the reason it failed.
+	pub(crate) fn with_backend(backend: Option<LinuxBackend>) -> Result<Self, Error> {
+		if let Some(backend) = backend {
+			if !LinuxBackend::SELECTABLE.iter().any(|(_, selectable)| *selectable == backend) {
+				return Err(LinuxBackend::unavailable(backend.name()));
+			}
+			// A forced backend is used without falling back to the others.
+			return Self::connect(backend);
+		}
+
+		#[cfg(feature = "wsl")]
+		let wsl = crate::wsl_clipboard::running_in_wsl();
+		#[cfg(not(feature = "wsl"))]
+		let wsl = false;
+		let mut failures = Vec::new();
+		for backend in backend_candidates(wayland_session_from_env(), wsl) {
+			match Self::connect(backend) {
+				Ok(clipboard) => {
+					debug!("Successfully initialized the {} clipboard.", backend);
+					return Ok(clipboard);
+				}
+				Err(e) => {
+					warn!("Tried to initialize the {} clipboard, but failed: {}", backend, e);
+					failures.push((backend, e));
 				}
 			}
 		}
-		Ok(Self::X11(X11ClipboardContext::new()?))
+		if failures.len() == 1 {
+			return Err(failures.pop().unwrap().1);
+		}
+		// The reasons were logged above already.
+		if failures.iter().all(|(_, e)| matches!(e, Error::ClipboardNotSupported)) {
+			return Err(Error::ClipboardNotSupported);
+		}
+		let tried = failures
+			.iter()
+			.map(|(backend, e)| format!("{} ({})", backend, e))
+			.collect::<Vec<_>>()
+			.join(", ");
+		Err(Error::Unknown {
+			description: format!("no clipboard backend is available, tried: {}", tried),
+		})
+	}
+
+	fn connect(backend: LinuxBackend) -> Result<Self, Error> {
+		match backend {
+			LinuxBackend::X11 => Ok(Self::X11(X11ClipboardContext::new()?)),
+
+			#[cfg(feature = "wayland-data-control")]
+			LinuxBackend::WaylandDataControl => {
+				Ok(Self::WlDataControl(WaylandDataControlClipboardContext::new()?))
+			}
+
+			#[cfg(feature = "wayland-native")]
+			LinuxBackend::WaylandNative => Ok(Self::WlNative(WaylandClipboardContext::new()?)),
+
+			#[cfg(feature = "wsl")]
+			LinuxBackend::Wsl => Ok(Self::Wsl(WslClipboardContext::new()?)),
+
+			// OSC 52 has to be opted into, so it's only used when it was selected explicitly.
+			#[cfg(feature = "osc52")]
+			LinuxBackend::Osc52 => Self::new_osc52(),
+
+			// The backends that weren't compiled in, and the ones with constructors of their own.
+			_ => Err(Error::ClipboardNotSupported),
+		}
+	}
+
+	/// Creates a clipboard on the Wayland connection of the application.
+	///
+	/// # Safety
+	///
+	/// `display` must point to a valid `wl_display` that outlives the clipboard.
+	#[cfg(feature = "wayland-native")]
+	pub(crate) unsafe fn from_wayland_display(
+		display: *mut std::ffi::c_void,
+	) -> Result<Self, Error> {
+		Ok(Self::WlNative(WaylandClipboardContext::from_display(display)?))
+	}
+
+	/// Creates a clipboard that goes through the terminal emulator.
+	#[cfg(feature = "osc52")]
+	pub(crate) fn new_osc52() -> Result<Self, Error> {
+		Ok(Self::Osc52(Osc52ClipboardContext::new()?))
 	}
 
 	/// Fetches utf-8 text from the clipboard and returns it.
@@ -174,16 +673,246 @@ impl LinuxClipboard {
 
 			#[cfg(feature = "wayland-data-control")]
 			Self::WlDataControl(cb) => cb.get_text(),
+
+			#[cfg(feature = "wayland-native")]
+			Self::WlNative(cb) => cb.get_text(),
+
+			#[cfg(feature = "osc52")]
+			Self::Osc52(cb) => cb.get_text(),
+
+			#[cfg(feature = "wsl")]
+			Self::Wsl(cb) => cb.get_text(),
+		}
+	}
+
+	/// Fetches the raw contents of the clipboard in the given text format.
+	pub fn get_text_bytes(&mut self, format: &str) -> Result<Vec<u8>, Error> {
+		match self {
+			Self::X11(cb) => cb.get_text_bytes(format),
+
+			#[cfg(feature = "wayland-data-control")]
+			Self::WlDataControl(cb) => cb.get_text_bytes(format),
+
+			#[cfg(feature = "wayland-native")]
+			Self::WlNative(cb) => cb.get_text_bytes(format),
+
+			#[cfg(feature = "osc52")]
+			Self::Osc52(cb) => cb.get_text_bytes(format),
+
+			#[cfg(feature = "wsl")]
+			Self::Wsl(cb) => cb.get_text_bytes(format),
 		}
 	}
 
 	/// Places the text onto the clipboard. Any valid utf-8 string is accepted.
-	pub fn set_text(&mut self, text: String) -> Result<(), Error> {
+	///
+	/// The backends keep a copy of the text, so a borrowed one is copied.
+	pub fn set_text(&mut self, text: Cow<'_, str>) -> Result<(), Error> {
+		match self {
+			Self::X11(cb) => cb.set_text(text.into_owned()),
+
+			#[cfg(feature = "wayland-data-control")]
+			Self::WlDataControl(cb) => cb.set_text(text.into_owned()),
+
+			#[cfg(feature = "wayland-native")]
+			Self::WlNative(cb) => cb.set_text(text.into_owned()),
+
+			#[cfg(feature = "osc52")]
+			Self::Osc52(cb) => cb.set_text(text.into_owned()),
+
+			#[cfg(feature = "wsl")]
+			Self::Wsl(cb) => cb.set_text(text.into_owned()),
+		}
+	}
+
+	/// Returns a number that changes every time the contents of the clipboard change.
+	pub fn generation(&mut self) -> Result<u64, Error> {
+		match self {
+			Self::X11(cb) => Ok(cb.generation()),
+
+			#[cfg(feature = "wayland-data-control")]
+			Self::WlDataControl(cb) => cb.generation(),
+
+			#[cfg(feature = "wayland-native")]
+			Self::WlNative(cb) => cb.generation(),
+
+			#[cfg(feature = "osc52")]
+			Self::Osc52(cb) => cb.generation(),
+
+			#[cfg(feature = "wsl")]
+			Self::Wsl(cb) => cb.generation(),
+		}
+	}
+
+	/// Identifies the contents that were just written to `selection`, for `is_unchanged` to tell
+	/// whether they're still on the clipboard later. Only supported on X11, like `reopen`.
+	pub(crate) fn contents_stamp(&mut self, selection: LinuxClipboardKind) -> Result<u64, Error> {
+		match self {
+			Self::X11(cb) => Ok(cb.write_count(selection)),
+			#[allow(unreachable_patterns)]
+			_ => Err(Error::ClipboardNotSupported),
+		}
+	}
+
+	/// Returns whether the contents that `contents_stamp` identified are still on the clipboard.
+	pub(crate) fn is_unchanged(
+		&mut self,
+		selection: LinuxClipboardKind,
+		stamp: u64,
+	) -> Result<bool, Error> {
 		match self {
-			Self::X11(cb) => cb.set_text(text),
+			Self::X11(cb) => cb.is_written_by_us(selection, stamp),
+			#[allow(unreachable_patterns)]
+			_ => Err(Error::ClipboardNotSupported),
+		}
+	}
+
+	/// Returns the names of the formats on the CLIPBOARD selection.
+	pub fn available_formats(&mut self) -> Result<Vec<String>, Error> {
+		match self {
+			Self::X11(cb) => cb.available_formats(),
 
 			#[cfg(feature = "wayland-data-control")]
-			Self::WlDataControl(cb) => cb.set_text(text),
+			Self::WlDataControl(cb) => cb.available_formats(),
+
+			#[cfg(feature = "wayland-native")]
+			Self::WlNative(cb) => cb.available_formats(),
+
+			#[cfg(feature = "osc52")]
+			Self::Osc52(cb) => cb.available_formats(),
+
+			#[cfg(feature = "wsl")]
+			Self::Wsl(cb) => cb.available_formats(),
+		}
+	}
+
+	/// Captures every format that's currently available on the clipboard.
+	pub fn snapshot(&mut self, max_bytes: Option<usize>) -> Result<ClipboardSnapshot, Error> {
+		match self {
+			Self::X11(cb) => cb.snapshot(max_bytes),
+
+			#[cfg(feature = "wayland-data-control")]
+			Self::WlDataControl(cb) => cb.snapshot(max_bytes),
+
+			#[cfg(feature = "wayland-native")]
+			Self::WlNative(cb) => cb.snapshot(max_bytes),
+
+			#[cfg(feature = "osc52")]
+			Self::Osc52(cb) => cb.snapshot(max_bytes),
+
+			#[cfg(feature = "wsl")]
+			Self::Wsl(cb) => cb.snapshot(max_bytes),
+		}
+	}
+
+	/// Replaces the contents of the clipboard with the formats stored in `snapshot`.
+	pub fn restore(&mut self, snapshot: &ClipboardSnapshot) -> Result<(), Error> {
+		match self {
+			Self::X11(cb) => cb.restore(snapshot),
+
+			#[cfg(feature = "wayland-data-control")]
+			Self::WlDataControl(cb) => cb.restore(snapshot),
+
+			#[cfg(feature = "wayland-native")]
+			Self::WlNative(cb) => cb.restore(snapshot),
+
+			#[cfg(feature = "osc52")]
+			Self::Osc52(cb) => cb.restore(snapshot),
+
+			#[cfg(feature = "wsl")]
+			Self::Wsl(cb) => cb.restore(snapshot),
+		}
+	}
+
+	/// Hands the contents placed by this process over to a clipboard manager, on the backends
+	/// that need one to keep them after the process exits.
+	pub fn persist(&mut self) -> Result<(), Error> {
+		match self {
+			Self::X11(cb) => cb.persist(),
+
+			#[cfg(feature = "wayland-data-control")]
+			Self::WlDataControl(cb) => cb.persist(),
+
+			#[cfg(feature = "wayland-native")]
+			Self::WlNative(cb) => cb.persist(),
+
+			#[cfg(feature = "osc52")]
+			Self::Osc52(cb) => cb.persist(),
+
+			#[cfg(feature = "wsl")]
+			Self::Wsl(cb) => cb.persist(),
+		}
+	}
+
+	/// Blocks until the contents written to `selection` were pasted. Only X11 serves the contents
+	/// from this process, the other backends return right away.
+	pub(crate) fn wait_for_paste(
+		&mut self,
+		selection: LinuxClipboardKind,
+		wait: WaitConfig,
+	) -> Result<(), Error> {
+		let deadline = match wait {
+			WaitConfig::None => return Ok(()),
+			WaitConfig::Forever => None,
+			WaitConfig::Until(deadline) => Some(deadline),
+		};
+		match self {
+			Self::X11(cb) => cb.wait_for_paste(selection, deadline),
+
+			#[cfg(feature = "wayland-data-control")]
+			Self::WlDataControl(_) => Ok(()),
+
+			#[cfg(feature = "wayland-native")]
+			Self::WlNative(_) => Ok(()),
+
+			#[cfg(feature = "osc52")]
+			Self::Osc52(_) => Ok(()),
+
+			#[cfg(feature = "wsl")]
+			Self::Wsl(_) => Ok(()),
+		}
+	}
+
+	/// Places the text onto the clipboard and returns the text that was on the clipboard before.
+	pub fn swap_text(&mut self, text: String) -> Result<Option<String>, Error> {
+		match self {
+			Self::X11(cb) => cb.swap_text(text),
+
+			#[cfg(feature = "wayland-data-control")]
+			Self::WlDataControl(cb) => cb.swap_text(text),
+
+			#[cfg(feature = "wayland-native")]
+			Self::WlNative(cb) => cb.swap_text(text),
+
+			#[cfg(feature = "osc52")]
+			Self::Osc52(cb) => cb.swap_text(text),
+
+			#[cfg(feature = "wsl")]
+			Self::Wsl(cb) => cb.swap_text(text),
+		}
+	}
+
+	/// Clears the selected clipboard and places all the representations onto it. The other
+	/// clipboards are left untouched.
+	pub(crate) fn write_all_with_clipboard(
+		&mut self,
+		representations: &[Representation],
+		selection: LinuxClipboardKind,
+	) -> Result<(), Error> {
+		match self {
+			Self::X11(cb) => cb.write_all_with_clipboard(representations, selection),
+
+			#[cfg(feature = "wayland-data-control")]
+			Self::WlDataControl(cb) => cb.write_all_with_clipboard(representations, selection),
+
+			#[cfg(feature = "wayland-native")]
+			Self::WlNative(cb) => cb.write_all_with_clipboard(representations, selection),
+
+			#[cfg(feature = "osc52")]
+			Self::Osc52(cb) => cb.write_all_with_clipboard(representations, selection),
+
+			#[cfg(feature = "wsl")]
+			Self::Wsl(cb) => cb.write_all_with_clipboard(representations, selection),
 		}
 	}
 
@@ -192,13 +921,30 @@ impl LinuxClipboard {
 	/// Any image data placed on the clipboard with `set_image` will be possible read back, using
 	/// this function. However it's of not guaranteed that an image placed on the clipboard by any
 	/// other application will be of a supported format.
+	/// All backends read images as PNG.
+	#[cfg(feature = "image-data")]
+	pub(crate) fn get_image_with_format(
+		&mut self,
+	) -> Result<(ImageFormatTag, ImageData<'static>), Error> {
+		self.get_image().map(|image| (ImageFormatTag::Png, image))
+	}
+
 	#[cfg(feature = "image-data")]
-	pub fn get_image(&mut self) -> Result<ImageData, Error> {
+	pub fn get_image(&mut self) -> Result<ImageData<'static>, Error> {
 		match self {
 			Self::X11(cb) => cb.get_image(),
 
 			#[cfg(feature = "wayland-data-control")]
 			Self::WlDataControl(cb) => cb.get_image(),
+
+			#[cfg(feature = "wayland-native")]
+			Self::WlNative(cb) => cb.get_image(),
+
+			#[cfg(feature = "osc52")]
+			Self::Osc52(cb) => cb.get_image(),
+
+			#[cfg(feature = "wsl")]
+			Self::Wsl(cb) => cb.get_image(),
 		}
 	}
 
@@ -216,6 +962,67 @@ impl LinuxClipboard {
 
 			#[cfg(feature = "wayland-data-control")]
 			Self::WlDataControl(cb) => cb.set_image(image),
+
+			#[cfg(feature = "wayland-native")]
+			Self::WlNative(cb) => cb.set_image(image),
+
+			#[cfg(feature = "osc52")]
+			Self::Osc52(cb) => cb.set_image(image),
+
+			#[cfg(feature = "wsl")]
+			Self::Wsl(cb) => cb.set_image(image),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::Clipboard;
+
+	#[test]
+	fn linux_backend_selection() {
+		assert!(!is_wayland_session(None, None));
+		assert!(!is_wayland_session(Some(OsStr::new("")), Some(OsStr::new("x11"))));
+		assert!(is_wayland_session(Some(OsStr::new("wayland-0")), None));
+		assert!(is_wayland_session(None, Some(OsStr::new("wayland"))));
+
+		assert_eq!(backend_candidates(false, false), [LinuxBackend::X11]);
+		let mut expected = Vec::new();
+		if cfg!(feature = "wayland-data-control") {
+			expected.push(LinuxBackend::WaylandDataControl);
+		}
+		expected.push(LinuxBackend::X11);
+		if cfg!(feature = "wayland-native") {
+			expected.push(LinuxBackend::WaylandNative);
+		}
+		assert_eq!(backend_candidates(true, false), expected);
+		let wsl = backend_candidates(false, true);
+		if cfg!(feature = "wsl") {
+			assert_eq!(wsl, [LinuxBackend::X11, LinuxBackend::Wsl]);
+		} else {
+			assert_eq!(wsl, [LinuxBackend::X11]);
+		}
+
+		assert_eq!(backend_override(None).unwrap(), None);
+		assert_eq!(backend_override(Some(OsStr::new(""))).unwrap(), None);
+		assert_eq!(backend_override(Some(OsStr::new("X11"))).unwrap(), Some(LinuxBackend::X11));
+		let osc52 = backend_override(Some(OsStr::new("osc52")));
+		if cfg!(feature = "osc52") {
+			assert_eq!(osc52.unwrap(), Some(LinuxBackend::Osc52));
+		} else {
+			let message = osc52.unwrap_err().to_string();
+			assert!(message.contains("`osc52`") && message.contains("available backends are: x11"));
+		}
+		assert!(backend_override(Some(OsStr::new("quartz"))).is_err());
+		#[cfg(feature = "test-util")]
+		assert_eq!(backend_override(Some(OsStr::new("Mock"))).unwrap(), Some(LinuxBackend::Mock));
+		let native = backend_override(Some(OsStr::new("wayland-native")));
+		if cfg!(feature = "wayland-native") {
+			assert_eq!(native.unwrap(), Some(LinuxBackend::WaylandNative));
+		} else {
+			let native = Clipboard::builder().backend(LinuxBackend::WaylandNative).build();
+			assert!(native.err().unwrap().to_string().contains("isn't available in this build"));
 		}
 	}
 }
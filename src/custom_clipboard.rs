@@ -0,0 +1,273 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2020 The arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+#[cfg(all(target_arch = "wasm32", feature = "wasm", feature = "image-data"))]
+use crate::common::ImageData;
+use crate::common::{ClipboardSnapshot, Error, Representation};
+use crate::ClipboardProvider;
+
+/// A clipboard that forwards to a [`ClipboardProvider`] of the application, created by
+/// [`Clipboard::from_provider`](crate::Clipboard::from_provider).
+///
+/// The provider only offers the basic operations, so the others either fall back to them or
+/// return `Error::ClipboardNotSupported`.
+pub(crate) struct CustomClipboard {
+	provider: Box<dyn ClipboardProvider + Send>,
+}
+
+impl CustomClipboard {
+	pub(crate) fn new(provider: Box<dyn ClipboardProvider + Send>) -> Self {
+		CustomClipboard { provider }
+	}
+
+	pub(crate) fn provider(&mut self) -> &mut (dyn ClipboardProvider + Send) {
+		&mut *self.provider
+	}
+
+	pub(crate) fn get_text_bytes(&mut self, _format: &str) -> Result<Vec<u8>, Error> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub(crate) fn swap_text(&mut self, text: String) -> Result<Option<String>, Error> {
+		let previous = match self.provider.get_text() {
+			Ok(text) => Some(text),
+			Err(Error::ContentNotAvailable) => None,
+			Err(e) => return Err(e),
+		};
+		self.provider.set_text(text)?;
+		Ok(previous)
+	}
+
+	pub(crate) fn generation(&mut self) -> Result<u64, Error> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub(crate) fn snapshot(
+		&mut self,
+		_max_bytes: Option<usize>,
+	) -> Result<ClipboardSnapshot, Error> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub(crate) fn restore(&mut self, _snapshot: &ClipboardSnapshot) -> Result<(), Error> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	/// Whether the contents outlive the process is up to the provider.
+	pub(crate) fn persist(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+
+	/// Only the text can be transferred, the other representations are left out. Writing no
+	/// representations at all clears the clipboard.
+	pub(crate) fn write_all(&mut self, representations: &[Representation]) -> Result<(), Error> {
+		if representations.is_empty() {
+			return self.provider.clear();
+		}
+		let text = representations.iter().find_map(|representation| match representation {
+			Representation::Text(text) => Some(text),
+			_ => None,
+		});
+		match text {
+			Some(text) => self.provider.set_text(text.clone()),
+			None => Err(Error::ClipboardNotSupported),
+		}
+	}
+}
+
+/// The provider only has the regular clipboard, the other selections aren't supported.
+#[cfg(all(
+	unix,
+	not(any(
+		target_os = "macos",
+		target_os = "ios",
+		target_os = "android",
+		target_os = "emscripten"
+	)),
+))]
+impl CustomClipboard {
+	fn check_selection(selection: crate::LinuxClipboardKind) -> Result<(), Error> {
+		match selection {
+			crate::LinuxClipboardKind::Clipboard => Ok(()),
+			_ => Err(Error::ClipboardNotSupported),
+		}
+	}
+
+	pub(crate) fn get_text_with_clipboard(
+		&mut self,
+		selection: crate::LinuxClipboardKind,
+	) -> Result<String, Error> {
+		Self::check_selection(selection)?;
+		self.provider.get_text()
+	}
+
+	pub(crate) fn set_text_with_clipboard(
+		&mut self,
+		text: String,
+		selection: crate::LinuxClipboardKind,
+	) -> Result<(), Error> {
+		Self::check_selection(selection)?;
+		self.provider.set_text(text)
+	}
+
+	pub(crate) fn write_all_with_clipboard(
+		&mut self,
+		representations: &[Representation],
+		selection: crate::LinuxClipboardKind,
+	) -> Result<(), Error> {
+		Self::check_selection(selection)?;
+		self.write_all(representations)
+	}
+
+	pub(crate) fn wait_for_paste(
+		&mut self,
+		_selection: crate::LinuxClipboardKind,
+		_wait: crate::common::WaitConfig,
+	) -> Result<(), Error> {
+		Ok(())
+	}
+}
+
+#[cfg(windows)]
+impl CustomClipboard {
+	pub(crate) fn set_open_retry(&mut self, _attempts: usize, _delay: std::time::Duration) {}
+}
+
+#[cfg(target_os = "macos")]
+impl CustomClipboard {
+	pub(crate) fn types(&mut self) -> Vec<crate::ClipboardType> {
+		let mut types = Vec::new();
+		for format in self.provider.available_formats().unwrap_or_default() {
+			let clipboard_type = crate::ClipboardType::from_uti(format);
+			if !types.contains(&clipboard_type) {
+				types.push(clipboard_type);
+			}
+		}
+		types
+	}
+
+	pub(crate) fn is_concealed(&mut self) -> bool {
+		let formats = self.provider.available_formats().unwrap_or_default();
+		formats.iter().any(|format| format == crate::CONCEALED)
+	}
+
+	pub(crate) fn owner_hint(&mut self) -> Option<String> {
+		None
+	}
+
+	pub(crate) fn get_image_source_url(&mut self) -> Option<String> {
+		None
+	}
+
+	pub(crate) fn add_data_for_type(&mut self, _uti: &str, _bytes: &[u8]) -> Result<(), Error> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub(crate) fn clear_type(&mut self, _uti: &str) -> Result<(), Error> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub(crate) fn get_color(&mut self) -> Result<[f32; 4], Error> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub(crate) fn set_color(&mut self, _rgba: [f32; 4]) -> Result<(), Error> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub(crate) fn set_lazy_data(
+		&mut self,
+		_providers: std::collections::HashMap<String, crate::DataProvider>,
+	) -> Result<(), Error> {
+		Err(Error::ClipboardNotSupported)
+	}
+}
+
+#[cfg(target_os = "ios")]
+impl CustomClipboard {
+	pub(crate) fn write_all_with_options(
+		&mut self,
+		representations: &[Representation],
+		_options: crate::PasteboardOptions,
+	) -> Result<(), Error> {
+		self.write_all(representations)
+	}
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+impl CustomClipboard {
+	pub(crate) async fn get_text_async(&mut self) -> Result<String, Error> {
+		self.provider.get_text()
+	}
+
+	pub(crate) async fn set_text_async(&mut self, text: String) -> Result<(), Error> {
+		self.provider.set_text(text)
+	}
+
+	#[cfg(feature = "image-data")]
+	pub(crate) async fn get_image_async(&mut self) -> Result<ImageData<'static>, Error> {
+		self.provider.get_image()
+	}
+
+	#[cfg(feature = "image-data")]
+	pub(crate) async fn set_image_async(&mut self, image: ImageData<'_>) -> Result<(), Error> {
+		self.provider.set_image(image)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{Clipboard, ClipboardProvider, Error};
+
+	#[test]
+	fn custom_provider() {
+		#[derive(Default)]
+		struct Remote {
+			text: Option<String>,
+		}
+
+		impl ClipboardProvider for Remote {
+			fn get_text(&mut self) -> Result<String, Error> {
+				self.text.clone().ok_or(Error::ContentNotAvailable)
+			}
+
+			fn set_text(&mut self, text: String) -> Result<(), Error> {
+				self.text = Some(text);
+				Ok(())
+			}
+
+			fn clear(&mut self) -> Result<(), Error> {
+				self.text = None;
+				Ok(())
+			}
+
+			fn available_formats(&mut self) -> Result<Vec<String>, Error> {
+				Ok(self.text.iter().map(|_| "text/plain".to_owned()).collect())
+			}
+		}
+
+		let mut ctx = Clipboard::from_provider(Box::new(Remote::default()));
+		assert_eq!(ctx.backend_name(), "Custom");
+		ctx.set_text("remote").unwrap();
+		assert_eq!(ctx.get().text().unwrap(), "remote");
+		assert_eq!(ctx.available_formats().unwrap(), ["text/plain"]);
+		assert_eq!(ctx.swap_text("swapped".into()).unwrap().as_deref(), Some("remote"));
+		ctx.write().text("written").html("<i>written</i>").commit().unwrap();
+		assert_eq!(ctx.get_text().unwrap(), "written");
+		assert!(matches!(ctx.snapshot(), Err(Error::ClipboardNotSupported)));
+		// The image methods weren't implemented, so they keep their default.
+		#[cfg(feature = "image-data")]
+		assert!(matches!(ctx.get_image(), Err(Error::ClipboardNotSupported)));
+
+		ctx.clear().unwrap();
+		assert!(matches!(ctx.get_text(), Err(Error::ContentNotAvailable)));
+		assert!(ctx.available_formats().unwrap().is_empty());
+	}
+}
@@ -0,0 +1,374 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2020 The arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+//! A C interface, for using arboard from applications that aren't written in Rust.
+//!
+//! The declarations are in `include/arboard.h`, which is generated by running
+//! `cbindgen --config cbindgen.toml --output include/arboard.h` in the repository. The library
+//! itself is built with `cargo rustc --release --features ffi --crate-type cdylib` (or
+//! `staticlib`).
+//!
+//! The rules for the memory that crosses the boundary are:
+//!
+//! - A clipboard returned by `arboard_clipboard_new` is owned by the caller, and must be released
+//!   with `arboard_clipboard_free`. It must not be used from two threads at the same time. On
+//!   macOS and iOS it must also stay on the thread that created it, including when it's freed,
+//!   while elsewhere it may be handed over to another thread.
+//! - Text returned by `arboard_get_text` is owned by the caller, and must be released with
+//!   `arboard_string_free`, not with the `free` of the C library.
+//! - The pixels of `arboard_get_image` are written into a buffer of the caller, so nothing needs
+//!   to be released.
+//! - The message returned by `arboard_last_error_message` is owned by arboard. It stays valid until
+//!   the next call of an arboard function on the same thread.
+//!
+//! Every function returns an [`ArboardStatus`] (or `NULL` where it returns a pointer) instead of
+//! unwinding into the caller: a panic is caught at the boundary and reported as
+//! `ARBOARD_STATUS_PANIC`.
+
+use std::{
+	cell::RefCell,
+	ffi::{CStr, CString},
+	os::raw::c_char,
+	panic::{self, AssertUnwindSafe},
+	ptr, slice,
+};
+
+use crate::{Clipboard, Error};
+
+/// The outcome of a call, see `arboard_last_error_message` for the details of a failure.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArboardStatus {
+	/// The call succeeded.
+	Ok = 0,
+	/// The clipboard doesn't hold content in the requested format, or it's empty.
+	ContentNotAvailable = 1,
+	/// The clipboard can't be used on this system, or doesn't support the operation.
+	ClipboardNotSupported = 2,
+	/// Another application is holding the clipboard, so trying again later may succeed.
+	ClipboardOccupied = 3,
+	/// The content couldn't be converted, for example text that isn't valid UTF-8 or contains a
+	/// null character.
+	ConversionFailure = 4,
+	/// The buffer of `arboard_get_image` is too small for the image. The dimensions were written
+	/// anyway.
+	BufferTooSmall = 5,
+	/// A pointer that must not be `NULL` was `NULL`, or a text argument isn't valid UTF-8.
+	InvalidArgument = 6,
+	/// arboard panicked. This is a bug, which should be reported.
+	Panic = 7,
+	/// Any other error.
+	Unknown = 8,
+}
+
+impl From<&Error> for ArboardStatus {
+	fn from(error: &Error) -> Self {
+		match error {
+			Error::ContentNotAvailable => ArboardStatus::ContentNotAvailable,
+			Error::ClipboardNotSupported
+			| Error::UnsupportedFormat { .. }
+			| Error::PlatformUnavailable { .. } => ArboardStatus::ClipboardNotSupported,
+			Error::ClipboardOccupied => ArboardStatus::ClipboardOccupied,
+			Error::ConversionFailure | Error::InvalidUtf8 { .. } => {
+				ArboardStatus::ConversionFailure
+			}
+			_ => ArboardStatus::Unknown,
+		}
+	}
+}
+
+/// The clipboard that the functions operate on. Its contents are opaque to C.
+pub struct ArboardClipboard {
+	pub(crate) clipboard: Clipboard,
+}
+
+thread_local! {
+	/// The message of the last failure on this thread, see `arboard_last_error_message`.
+	static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+	// A message with a null character would be cut off there by C anyway.
+	let message = message.replace('\0', " ");
+	LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(message).ok());
+}
+
+/// Runs `call`, turning its error and any panic into a status and the last error message.
+pub(crate) fn guard(call: impl FnOnce() -> Result<(), (ArboardStatus, String)>) -> ArboardStatus {
+	LAST_ERROR.with(|last| *last.borrow_mut() = None);
+	match panic::catch_unwind(AssertUnwindSafe(call)) {
+		Ok(Ok(())) => ArboardStatus::Ok,
+		Ok(Err((status, message))) => {
+			set_last_error(message);
+			status
+		}
+		Err(payload) => {
+			let reason = payload
+				.downcast_ref::<&str>()
+				.map(|reason| reason.to_string())
+				.or_else(|| payload.downcast_ref::<String>().cloned())
+				.unwrap_or_default();
+			set_last_error(format!("arboard panicked: {}", reason));
+			ArboardStatus::Panic
+		}
+	}
+}
+
+fn failure(error: Error) -> (ArboardStatus, String) {
+	(ArboardStatus::from(&error), error.to_string())
+}
+
+fn invalid_argument(message: &str) -> (ArboardStatus, String) {
+	(ArboardStatus::InvalidArgument, message.to_owned())
+}
+
+/// Returns the clipboard that `clipboard` points to, or an error if it's `NULL`.
+///
+/// # Safety
+///
+/// `clipboard` must be `NULL` or a pointer returned by `arboard_clipboard_new` that wasn't freed.
+unsafe fn clipboard_mut<'a>(
+	clipboard: *mut ArboardClipboard,
+) -> Result<&'a mut Clipboard, (ArboardStatus, String)> {
+	match clipboard.as_mut() {
+		Some(clipboard) => Ok(&mut clipboard.clipboard),
+		None => Err(invalid_argument("the clipboard is NULL")),
+	}
+}
+
+/// Creates a clipboard, like `Clipboard::new`.
+///
+/// Returns `NULL` if the clipboard can't be created, in which case `arboard_last_error_message`
+/// describes why. The clipboard must be released with `arboard_clipboard_free`.
+#[no_mangle]
+pub extern "C" fn arboard_clipboard_new() -> *mut ArboardClipboard {
+	let mut created = ptr::null_mut();
+	guard(|| {
+		let clipboard = Clipboard::new().map_err(failure)?;
+		created = Box::into_raw(Box::new(ArboardClipboard { clipboard }));
+		Ok(())
+	});
+	created
+}
+
+/// Releases a clipboard created by `arboard_clipboard_new`. Does nothing if `clipboard` is `NULL`.
+///
+/// # Safety
+///
+/// `clipboard` must be `NULL` or a pointer returned by `arboard_clipboard_new` that wasn't freed
+/// yet. It must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn arboard_clipboard_free(clipboard: *mut ArboardClipboard) {
+	if !clipboard.is_null() {
+		guard(|| {
+			drop(Box::from_raw(clipboard));
+			Ok(())
+		});
+	}
+}
+
+/// Fetches the text on the clipboard, like `Clipboard::get_text`.
+///
+/// On success, `*text` is set to a null-terminated UTF-8 string, which the caller owns and must
+/// release with `arboard_string_free`. On failure, `*text` is set to `NULL`. Text that contains a
+/// null character is reported as `ARBOARD_STATUS_CONVERSION_FAILURE`, since C would cut it off.
+///
+/// # Safety
+///
+/// `clipboard` must be a valid clipboard, and `text` must be `NULL` or point to writable memory
+/// for a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn arboard_get_text(
+	clipboard: *mut ArboardClipboard,
+	text: *mut *mut c_char,
+) -> ArboardStatus {
+	guard(|| {
+		if text.is_null() {
+			return Err(invalid_argument("the output pointer for the text is NULL"));
+		}
+		*text = ptr::null_mut();
+		let contents = clipboard_mut(clipboard)?.get_text().map_err(failure)?;
+		let contents = CString::new(contents).map_err(|_| {
+			(ArboardStatus::ConversionFailure, "the text contains a null character".to_owned())
+		})?;
+		*text = contents.into_raw();
+		Ok(())
+	})
+}
+
+/// Places the text onto the clipboard, like `Clipboard::set_text`.
+///
+/// `text` must be a null-terminated UTF-8 string, which is copied, so the caller keeps owning it.
+/// Text that isn't valid UTF-8 is rejected with `ARBOARD_STATUS_INVALID_ARGUMENT`.
+///
+/// # Safety
+///
+/// `clipboard` must be a valid clipboard, and `text` must be `NULL` or point to a null-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn arboard_set_text(
+	clipboard: *mut ArboardClipboard,
+	text: *const c_char,
+) -> ArboardStatus {
+	guard(|| {
+		if text.is_null() {
+			return Err(invalid_argument("the text is NULL"));
+		}
+		let text = CStr::from_ptr(text)
+			.to_str()
+			.map_err(|_| invalid_argument("the text isn't valid UTF-8"))?;
+		clipboard_mut(clipboard)?.set_text(text).map_err(failure)
+	})
+}
+
+/// Fetches the image on the clipboard, like `Clipboard::get_image`, into a buffer of the caller.
+///
+/// The pixels are written as rows of 8 bit RGBA values, starting with the top row, and `*stride`
+/// is set to the number of bytes of each row. `*width`, `*height` and `*stride` are set whenever
+/// an image was read, even if `buffer_len` is too small for it, in which case
+/// `ARBOARD_STATUS_BUFFER_TOO_SMALL` is returned and nothing is written into `buffer`. So the size
+/// of the buffer can be found by passing `NULL` and 0 first, and then calling the function again
+/// with a buffer of `*stride * *height` bytes. The clipboard is read again by the second call, so
+/// another application may have placed a larger image there in the meantime.
+///
+/// # Safety
+///
+/// `clipboard` must be a valid clipboard, `buffer` must be `NULL` or point to `buffer_len`
+/// writable bytes, and `width`, `height` and `stride` must each be `NULL` or point to writable
+/// memory for a `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn arboard_get_image(
+	clipboard: *mut ArboardClipboard,
+	buffer: *mut u8,
+	buffer_len: usize,
+	width: *mut usize,
+	height: *mut usize,
+	stride: *mut usize,
+) -> ArboardStatus {
+	guard(|| {
+		if width.is_null() || height.is_null() || stride.is_null() {
+			return Err(invalid_argument("an output pointer for the dimensions is NULL"));
+		}
+		let image = clipboard_mut(clipboard)?.get_image().map_err(failure)?;
+		*width = image.width;
+		*height = image.height;
+		*stride = image.width * 4;
+		if buffer_len < image.bytes.len() {
+			return Err((
+				ArboardStatus::BufferTooSmall,
+				format!(
+					"the image needs {} bytes, the buffer has {}",
+					image.bytes.len(),
+					buffer_len
+				),
+			));
+		}
+		if buffer.is_null() {
+			return Err(invalid_argument("the buffer is NULL"));
+		}
+		slice::from_raw_parts_mut(buffer, image.bytes.len()).copy_from_slice(&image.bytes);
+		Ok(())
+	})
+}
+
+/// Places an image of `width` by `height` RGBA pixels onto the in-memory clipboard that
+/// `ARBOARD_BACKEND=mock` selects, so that the C tests can read it back. Only available with the
+/// `test-util` feature, and left out of `arboard.h`.
+///
+/// # Safety
+///
+/// `pixels` must be `NULL` or point to `width * height * 4` readable bytes.
+#[cfg(all(feature = "test-util", feature = "image-data"))]
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn arboard_mock_set_image(
+	pixels: *const u8,
+	width: usize,
+	height: usize,
+) -> ArboardStatus {
+	guard(|| {
+		if pixels.is_null() {
+			return Err(invalid_argument("the pixels are NULL"));
+		}
+		let len = width
+			.checked_mul(height)
+			.and_then(|len| len.checked_mul(4))
+			.ok_or_else(|| invalid_argument("the image is too large"))?;
+		let bytes = slice::from_raw_parts(pixels, len).into();
+		crate::MockClipboard::shared()
+			.set_image(crate::ImageData { width, height, bytes })
+			.map_err(failure)
+	})
+}
+
+/// Releases text returned by `arboard_get_text`. Does nothing if `text` is `NULL`.
+///
+/// # Safety
+///
+/// `text` must be `NULL` or a string returned by `arboard_get_text` that wasn't freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn arboard_string_free(text: *mut c_char) {
+	if !text.is_null() {
+		guard(|| {
+			drop(CString::from_raw(text));
+			Ok(())
+		});
+	}
+}
+
+/// Returns a description of why the last call of an arboard function on this thread failed, or
+/// `NULL` if it succeeded.
+///
+/// The message is a null-terminated UTF-8 string owned by arboard. It stays valid until the next
+/// call of an arboard function on the same thread, and must not be freed.
+#[no_mangle]
+pub extern "C" fn arboard_last_error_message() -> *const c_char {
+	LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+	use super::*;
+	use crate::MockClipboard;
+
+	#[test]
+	fn ffi_mock() {
+		let clipboard = Box::into_raw(Box::new(ArboardClipboard {
+			clipboard: Clipboard::from_mock(MockClipboard::new()),
+		}));
+		let text = CString::new("from C").unwrap();
+		let mut read = std::ptr::null_mut();
+		unsafe {
+			assert_eq!(arboard_set_text(clipboard, text.as_ptr()), ArboardStatus::Ok);
+			assert_eq!(arboard_get_text(clipboard, &mut read), ArboardStatus::Ok);
+			assert_eq!(CStr::from_ptr(read), text.as_c_str());
+			arboard_string_free(read);
+
+			let (mut width, mut height, mut stride) = (0, 0, 0);
+			let status = arboard_get_image(
+				clipboard,
+				std::ptr::null_mut(),
+				0,
+				&mut width,
+				&mut height,
+				&mut stride,
+			);
+			assert_ne!(status, ArboardStatus::Ok);
+			assert!(!arboard_last_error_message().is_null());
+			arboard_clipboard_free(clipboard);
+		}
+
+		// A panic doesn't unwind into the caller.
+		let status = guard(|| panic!("oops"));
+		assert_eq!(status, ArboardStatus::Panic);
+		let message = unsafe { CStr::from_ptr(arboard_last_error_message()) };
+		assert_eq!(message.to_str().unwrap(), "arboard panicked: oops");
+	}
+}
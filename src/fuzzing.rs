@@ -0,0 +1,60 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2020 The arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+//! The parsers of the data that other applications place onto the clipboard, for the fuzz targets
+//! in `fuzz/`. This module is only built with the `fuzzing` feature and isn't part of the public
+//! API, it may change in any release.
+
+use crate::common::{self, Error, LineEndings};
+#[cfg(feature = "image-data")]
+use crate::common::{ImageData, ImageFormatTag};
+
+/// See `common::unwrap_cf_html`.
+pub fn unwrap_cf_html(data: &[u8]) -> &[u8] {
+	common::unwrap_cf_html(data)
+}
+
+/// See `common::wrap_html`.
+pub fn wrap_html(fragment: &str) -> String {
+	common::wrap_html(fragment)
+}
+
+/// See `common::html_from_utf8`.
+pub fn html_from_utf8(bytes: Vec<u8>) -> Result<String, Error> {
+	common::html_from_utf8(bytes)
+}
+
+/// See `common::text_from_utf16`.
+pub fn text_from_utf16(units: &[u16]) -> Result<String, Error> {
+	common::text_from_utf16(units)
+}
+
+/// See `LineEndings::apply`.
+pub fn apply_line_endings(line_endings: LineEndings, text: &str) -> String {
+	line_endings.apply(text)
+}
+
+/// See `common::path_from_file_url`.
+#[cfg(feature = "image-data")]
+pub fn path_from_file_url(url: &str) -> Option<String> {
+	common::path_from_file_url(url)
+}
+
+/// See `lazy_image::probe_dimensions`.
+#[cfg(feature = "image-data")]
+pub fn probe_dimensions(format: ImageFormatTag, data: &[u8]) -> Option<(usize, usize)> {
+	crate::lazy_image::probe_dimensions(format, data)
+}
+
+/// See `common::decode_image`.
+#[cfg(feature = "image-data")]
+pub fn decode_image(format: ImageFormatTag, data: &[u8]) -> Result<ImageData<'static>, Error> {
+	common::decode_image(format, data)
+}
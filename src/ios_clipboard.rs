@@ -0,0 +1,419 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2020 The arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+// AppKit's NSPasteboard isn't available on iOS, so this backend uses UIKit's UIPasteboard. Each
+// item on a UIPasteboard is a dictionary from a UTI to the value stored under it, which is why
+// everything written here is first collected into such a dictionary.
+
+use std::{borrow::Cow, ffi::c_void, time::SystemTime};
+
+use objc::{
+	class, msg_send,
+	runtime::{Object, BOOL, YES},
+	sel, sel_impl,
+};
+
+#[cfg(feature = "image-data")]
+use crate::common::{decode_image, ImageData, ImageFormatTag};
+use crate::common::{lossy_text, text_from_utf8, ClipboardSnapshot, Error, Representation};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[allow(non_camel_case_types)]
+type id = *mut Object;
+type NSInteger = isize;
+type NSUInteger = usize;
+
+/// The uniform type identifier of UTF-8 text.
+const UTF8_PLAIN_TEXT: &str = "public.utf8-plain-text";
+/// The uniform type identifier of HTML text.
+const HTML: &str = "public.html";
+/// The uniform type identifier of PNG images.
+#[cfg(feature = "image-data")]
+const PNG: &str = "public.png";
+
+#[link(name = "UIKit", kind = "framework")]
+extern "C" {
+	static UIPasteboardOptionLocalOnly: id;
+	static UIPasteboardOptionExpirationDate: id;
+
+	#[cfg(feature = "image-data")]
+	fn UIImagePNGRepresentation(image: id) -> id;
+}
+
+/// Options that control where the contents written to the pasteboard may go, and for how long they
+/// stay there.
+///
+/// Used with [`ClipboardExtIos`](crate::ClipboardExtIos) and
+/// [`ClipboardWriteExtIos`](crate::ClipboardWriteExtIos).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PasteboardOptions {
+	/// Keeps the contents on this device, instead of making them available to nearby devices
+	/// through Universal Clipboard.
+	pub local_only: bool,
+	/// The time after which the system removes the contents from the pasteboard.
+	pub expiration: Option<SystemTime>,
+}
+
+/// Sets the [`PasteboardOptions`] of the contents placed onto the pasteboard.
+///
+/// # Examples
+///
+/// ```no_run
+/// use arboard::{Clipboard, ClipboardExtIos, PasteboardOptions};
+/// use std::time::{Duration, SystemTime};
+///
+/// let mut ctx = Clipboard::new().unwrap();
+/// let options = PasteboardOptions {
+///     local_only: true,
+///     expiration: Some(SystemTime::now() + Duration::from_secs(60)),
+/// };
+/// ctx.set_text_with_options("hunter2".to_string(), options).unwrap();
+/// ```
+pub trait ClipboardExtIos {
+	/// Places the text onto the pasteboard with the given options. Any valid utf-8 string is
+	/// accepted.
+	fn set_text_with_options(
+		&mut self,
+		text: String,
+		options: PasteboardOptions,
+	) -> Result<(), Error>;
+}
+
+impl ClipboardExtIos for crate::Clipboard {
+	fn set_text_with_options(
+		&mut self,
+		text: String,
+		options: PasteboardOptions,
+	) -> Result<(), Error> {
+		let representations = [Representation::Text(text)];
+		dispatch!(&mut self.backend, cb => cb.write_all_with_options(&representations, options))
+	}
+}
+
+/// Sets the [`PasteboardOptions`] of the contents written by a
+/// [`ClipboardWrite`](crate::ClipboardWrite).
+///
+/// # Examples
+///
+/// ```no_run
+/// use arboard::{Clipboard, ClipboardWriteExtIos, PasteboardOptions};
+/// let mut ctx = Clipboard::new().unwrap();
+/// let options = PasteboardOptions { local_only: true, ..Default::default() };
+/// ctx.write().pasteboard_options(options).text("local").commit().unwrap();
+/// ```
+pub trait ClipboardWriteExtIos {
+	/// Writes with the given options instead of the default ones.
+	fn pasteboard_options(self, options: PasteboardOptions) -> Self;
+}
+
+impl ClipboardWriteExtIos for crate::ClipboardWrite<'_> {
+	fn pasteboard_options(mut self, options: PasteboardOptions) -> Self {
+		self.options = options;
+		self
+	}
+}
+
+pub struct IosClipboardContext {
+	pasteboard: id,
+}
+
+impl IosClipboardContext {
+	/// The async methods run the operations on the calling thread, and `clear_after` isn't
+	/// supported.
+	pub(crate) fn reopen(&self) -> Option<crate::common::Reopen<Self>> {
+		None
+	}
+
+	pub(crate) fn new() -> Result<Self> {
+		let pasteboard: id = unsafe { msg_send![class!(UIPasteboard), generalPasteboard] };
+		if pasteboard.is_null() {
+			return Err(Error::ClipboardNotSupported);
+		}
+		Ok(IosClipboardContext { pasteboard })
+	}
+
+	pub(crate) fn get_text(&mut self) -> Result<String> {
+		let string: id = unsafe { msg_send![self.pasteboard, string] };
+		if string.is_null() {
+			return Err(Error::ContentNotAvailable);
+		}
+		text_from_nsstring(string)
+	}
+
+	pub(crate) fn get_text_bytes(&mut self, uti: &str) -> Result<Vec<u8>> {
+		Ok(self.data_for_type(uti)?.bytes().to_vec())
+	}
+
+	/// Returns the data of the given type without copying it.
+	fn data_for_type(&mut self, uti: &str) -> Result<RetainedData> {
+		let data: id =
+			unsafe { msg_send![self.pasteboard, dataForPasteboardType: make_nsstring(uti)] };
+		RetainedData::retain(data).ok_or(Error::ContentNotAvailable)
+	}
+
+	pub(crate) fn set_text(&mut self, text: Cow<'_, str>) -> Result<()> {
+		self.write_all(&[Representation::Text(text.into_owned())])
+	}
+
+	pub(crate) fn swap_text(&mut self, text: String) -> Result<Option<String>> {
+		let previous = match self.get_text() {
+			Ok(text) => Some(text),
+			Err(Error::ContentNotAvailable) => None,
+			Err(e) => return Err(e),
+		};
+		self.set_text(text.into())?;
+		Ok(previous)
+	}
+
+	pub(crate) fn generation(&mut self) -> Result<u64> {
+		let count: NSInteger = unsafe { msg_send![self.pasteboard, changeCount] };
+		Ok(count as u64)
+	}
+
+	pub(crate) fn count_items(&mut self) -> usize {
+		let count: NSInteger = unsafe { msg_send![self.pasteboard, numberOfItems] };
+		count as usize
+	}
+
+	pub(crate) fn available_formats(&mut self) -> Result<Vec<String>> {
+		let types: id = unsafe { msg_send![self.pasteboard, pasteboardTypes] };
+		Ok(array_objects(types).into_iter().map(from_nsstring).collect())
+	}
+
+	pub(crate) fn snapshot(&mut self, max_bytes: Option<usize>) -> Result<ClipboardSnapshot> {
+		let mut snapshot = ClipboardSnapshot::default();
+		let items: id = unsafe { msg_send![self.pasteboard, items] };
+		for item in array_objects(items) {
+			snapshot.new_item();
+			let types: id = unsafe { msg_send![item, allKeys] };
+			for uti in array_objects(types) {
+				let value: id = unsafe { msg_send![item, objectForKey: uti] };
+				let uti = from_nsstring(uti);
+				// Text is usually stored as a string, everything else as data. Other values (like
+				// images or URLs) are converted by the pasteboard when requested as data.
+				if is_kind_of(value, class!(NSString)) {
+					snapshot.push(uti, from_nsstring(value).into_bytes(), max_bytes);
+				} else if is_kind_of(value, class!(NSData)) {
+					let bytes = RetainedData::retain(value)
+						.map_or(Vec::new(), |data| data.bytes().to_vec());
+					snapshot.push(uti, bytes, max_bytes);
+				} else {
+					match self.get_text_bytes(&uti) {
+						Ok(bytes) => snapshot.push(uti, bytes, max_bytes),
+						Err(_) => snapshot.skip(&uti, "the value can't be converted to data"),
+					}
+				}
+			}
+		}
+		Ok(snapshot)
+	}
+
+	/// The pasteboard server keeps the contents.
+	pub(crate) fn persist(&mut self) -> Result<()> {
+		Ok(())
+	}
+
+	pub(crate) fn restore(&mut self, snapshot: &ClipboardSnapshot) -> Result<()> {
+		let items: Vec<id> = (snapshot.items.iter())
+			.filter(|formats| !formats.is_empty())
+			.map(|formats| {
+				let item = new_dictionary();
+				for (uti, bytes) in formats {
+					set_object(item, make_nsstring(uti), make_nsdata(bytes));
+				}
+				item
+			})
+			.collect();
+		self.set_items(&items, PasteboardOptions::default());
+		Ok(())
+	}
+
+	pub(crate) fn write_all(&mut self, representations: &[Representation]) -> Result<()> {
+		self.write_all_with_options(representations, PasteboardOptions::default())
+	}
+
+	/// Places all the representations into a single item on the pasteboard.
+	pub(crate) fn write_all_with_options(
+		&mut self,
+		representations: &[Representation],
+		options: PasteboardOptions,
+	) -> Result<()> {
+		let item = new_dictionary();
+		for representation in representations {
+			let (uti, value) = match representation {
+				Representation::Text(text) => (UTF8_PLAIN_TEXT, make_nsstring(text)),
+				Representation::Html(html) => (HTML, make_nsstring(html)),
+				Representation::Custom(uti, bytes) => (uti.as_str(), make_nsdata(bytes)),
+			};
+			set_object(item, make_nsstring(uti), value);
+		}
+		self.set_items(&[item], options);
+		Ok(())
+	}
+
+	/// Replaces the contents of the pasteboard with `items`, which are dictionaries from UTIs to
+	/// the values stored under them.
+	fn set_items(&mut self, items: &[id], options: PasteboardOptions) {
+		let items: id = unsafe {
+			msg_send![class!(NSArray), arrayWithObjects: items.as_ptr() count: items.len() as NSUInteger]
+		};
+		let options_dictionary = new_dictionary();
+		if options.local_only {
+			let yes: id = unsafe { msg_send![class!(NSNumber), numberWithBool: YES] };
+			set_object(options_dictionary, unsafe { UIPasteboardOptionLocalOnly }, yes);
+		}
+		if let Some(expiration) = options.expiration {
+			// Times before the epoch are already in the past, so the contents expire immediately.
+			let seconds = expiration
+				.duration_since(SystemTime::UNIX_EPOCH)
+				.map_or(0.0, |duration| duration.as_secs_f64());
+			let date: id =
+				unsafe { msg_send![class!(NSDate), dateWithTimeIntervalSince1970: seconds] };
+			set_object(options_dictionary, unsafe { UIPasteboardOptionExpirationDate }, date);
+		}
+		let _: () =
+			unsafe { msg_send![self.pasteboard, setItems: items options: options_dictionary] };
+	}
+
+	#[cfg(feature = "image-data")]
+	pub(crate) fn get_image(&mut self) -> Result<ImageData<'static>> {
+		self.get_image_with_format().map(|(_, image)| image)
+	}
+
+	#[cfg(feature = "image-data")]
+	pub(crate) fn get_image_with_format(&mut self) -> Result<(ImageFormatTag, ImageData<'static>)> {
+		// The PNG is decoded straight from the memory of the `NSData`, the pixels are the only copy.
+		let (format, png) = match self.data_for_type(PNG) {
+			Ok(png) => (ImageFormatTag::Png, png),
+			// Images that were placed as `UIImage` objects or in other formats are converted by
+			// UIKit.
+			Err(Error::ContentNotAvailable) => {
+				let image: id = unsafe { msg_send![self.pasteboard, image] };
+				if image.is_null() {
+					return Err(Error::ContentNotAvailable);
+				}
+				let data = unsafe { UIImagePNGRepresentation(image) };
+				(ImageFormatTag::Other, RetainedData::retain(data).ok_or(Error::ConversionFailure)?)
+			}
+			Err(e) => return Err(e),
+		};
+		let image = decode_image(ImageFormatTag::Png, png.bytes())?;
+		Ok((format, image))
+	}
+
+	#[cfg(feature = "image-data")]
+	pub(crate) fn set_image(&mut self, image: ImageData) -> Result<()> {
+		let image = image::RgbaImage::from_raw(
+			image.width as u32,
+			image.height as u32,
+			image.bytes.into_owned(),
+		)
+		.ok_or(Error::ConversionFailure)?;
+		let mut png = Vec::new();
+		image::DynamicImage::ImageRgba8(image)
+			.write_to(&mut png, image::ImageFormat::Png)
+			.map_err(|_| Error::ConversionFailure)?;
+		self.write_all(&[Representation::Custom(PNG.into(), png)])
+	}
+}
+
+fn new_dictionary() -> id {
+	unsafe { msg_send![class!(NSMutableDictionary), dictionary] }
+}
+
+fn set_object(dictionary: id, key: id, value: id) {
+	let _: () = unsafe { msg_send![dictionary, setObject: value forKey: key] };
+}
+
+fn is_kind_of(object: id, class: &objc::runtime::Class) -> bool {
+	let result: BOOL = unsafe { msg_send![object, isKindOfClass: class] };
+	result == YES
+}
+
+/// Returns the objects of an `NSArray`, or nothing if `array` is nil.
+fn array_objects(array: id) -> Vec<id> {
+	if array.is_null() {
+		return Vec::new();
+	}
+	let count: NSUInteger = unsafe { msg_send![array, count] };
+	(0..count).map(|i| unsafe { msg_send![array, objectAtIndex: i] }).collect()
+}
+
+/// `NSUTF8StringEncoding`
+const UTF8_ENCODING: NSUInteger = 4;
+
+fn make_nsstring(text: &str) -> id {
+	unsafe {
+		let string: id = msg_send![class!(NSString), alloc];
+		let string: id = msg_send![string,
+			initWithBytes: text.as_ptr() as *const c_void
+			length: text.len() as NSUInteger
+			encoding: UTF8_ENCODING];
+		msg_send![string, autorelease]
+	}
+}
+
+/// Converts an `NSString` to UTF-8, taking the length in UTF-8 bytes so that the text isn't cut off
+/// at null characters. Strings with unpaired surrogates have no UTF-8 representation, for those the
+/// error holds the text with the surrogates replaced.
+fn text_from_nsstring(string: id) -> Result<String> {
+	let bytes: *const u8 = unsafe { msg_send![string, UTF8String] };
+	if bytes.is_null() {
+		let length: NSUInteger = unsafe { msg_send![string, length] };
+		let units: Vec<u16> =
+			(0..length).map(|i| unsafe { msg_send![string, characterAtIndex: i] }).collect();
+		return Err(Error::InvalidUtf8 { bytes: String::from_utf16_lossy(&units).into_bytes() });
+	}
+	let length: NSUInteger =
+		unsafe { msg_send![string, lengthOfBytesUsingEncoding: UTF8_ENCODING] };
+	text_from_utf8(unsafe { std::slice::from_raw_parts(bytes, length) }.to_vec())
+}
+
+/// Converts an `NSString` to UTF-8, replacing the parts that have no UTF-8 representation.
+fn from_nsstring(string: id) -> String {
+	lossy_text(text_from_nsstring(string)).unwrap_or_default()
+}
+
+fn make_nsdata(bytes: &[u8]) -> id {
+	unsafe {
+		msg_send![class!(NSData), dataWithBytes: bytes.as_ptr() as *const c_void length: bytes.len() as NSUInteger]
+	}
+}
+
+/// An `NSData` object, which is retained so that its bytes can be borrowed without copying them,
+/// no matter when the autorelease pool that it may be in is drained.
+struct RetainedData(id);
+
+impl RetainedData {
+	/// Retains `data`, or returns `None` if it's nil.
+	fn retain(data: id) -> Option<Self> {
+		if data.is_null() {
+			return None;
+		}
+		let _: id = unsafe { msg_send![data, retain] };
+		Some(RetainedData(data))
+	}
+
+	fn bytes(&self) -> &[u8] {
+		let length: NSUInteger = unsafe { msg_send![self.0, length] };
+		if length == 0 {
+			return &[];
+		}
+		let bytes: *const u8 = unsafe { msg_send![self.0, bytes] };
+		unsafe { std::slice::from_raw_parts(bytes, length) }
+	}
+}
+
+impl Drop for RetainedData {
+	fn drop(&mut self) {
+		let _: () = unsafe { msg_send![self.0, release] };
+	}
+}
@@ -0,0 +1,281 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2020 The arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+use std::convert::TryInto;
+
+use crate::common::{decode_image, Error, ImageData, ImageFormatTag};
+
+/// An image that was read from the clipboard without decoding it, as returned by
+/// [`Clipboard::get_image_lazy`](crate::Clipboard::get_image_lazy).
+///
+/// It holds the data as the platform stored it, for example the PNG or TIFF file, together with
+/// the dimensions that were read from its header. Decoding the pixels is the expensive part of
+/// reading an image, and it's only done by [`decode`](Self::decode). The image doesn't keep the
+/// clipboard open, so it can be kept around, or sent to another thread, for as long as needed.
+#[derive(Clone)]
+pub struct LazyImage {
+	format: ImageFormatTag,
+	width: usize,
+	height: usize,
+	data: LazyData,
+	max_image_bytes: Option<usize>,
+}
+
+#[derive(Clone)]
+enum LazyData {
+	/// The data in `format`, which is decoded on demand.
+	Encoded(Vec<u8>),
+	/// The pixels, from a backend that only provides decoded images.
+	Decoded(ImageData<'static>),
+}
+
+impl LazyImage {
+	/// Wraps `data` in `format`, or returns `None` if the dimensions can't be read from its header.
+	pub(crate) fn encoded(
+		format: ImageFormatTag,
+		data: Vec<u8>,
+		max_image_bytes: Option<usize>,
+	) -> Option<Self> {
+		let (width, height) = probe_dimensions(format, &data)?;
+		Some(LazyImage { format, width, height, data: LazyData::Encoded(data), max_image_bytes })
+	}
+
+	/// Wraps an image that was decoded already.
+	pub(crate) fn decoded(format: ImageFormatTag, image: ImageData<'static>) -> Self {
+		let (width, height) = (image.width, image.height);
+		LazyImage { format, width, height, data: LazyData::Decoded(image), max_image_bytes: None }
+	}
+
+	/// The width of the image in pixels, as stated by the header of its data.
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	/// The height of the image in pixels, as stated by the header of its data.
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	/// The size that the pixels take up once decoded, in bytes.
+	pub fn decoded_len(&self) -> usize {
+		self.width.saturating_mul(self.height).saturating_mul(4)
+	}
+
+	/// The format that the data is stored in. `ImageFormatTag::Other` means that the backend only
+	/// provided decoded pixels, which `decode` returns as they are.
+	pub fn format(&self) -> ImageFormatTag {
+		self.format
+	}
+
+	/// The data as the platform stored it, or `None` if the backend only provided decoded pixels.
+	pub fn data(&self) -> Option<&[u8]> {
+		match &self.data {
+			LazyData::Encoded(data) => Some(data),
+			LazyData::Decoded(_) => None,
+		}
+	}
+
+	/// Decodes the pixels, like `Clipboard::get_image` does.
+	///
+	/// Returns `Error::ConversionFailure` if the data can't be decoded, and
+	/// `Error::ImageTooLarge` if the pixels exceed the `max_image_bytes` of the clipboard that the
+	/// image was read from.
+	pub fn decode(&self) -> Result<ImageData<'static>, Error> {
+		let data = match &self.data {
+			LazyData::Encoded(data) => data,
+			LazyData::Decoded(image) => return Ok(image.clone()),
+		};
+		if let Some(limit) = self.max_image_bytes {
+			if self.decoded_len() > limit {
+				return Err(Error::ImageTooLarge { size: self.decoded_len(), limit });
+			}
+		}
+		let image = decode_image(self.format, data)?;
+		// The header was checked against the limit, but the decoder is what counts.
+		match self.max_image_bytes {
+			Some(limit) if image.bytes.len() > limit => {
+				Err(Error::ImageTooLarge { size: image.bytes.len(), limit })
+			}
+			_ => Ok(image),
+		}
+	}
+}
+
+impl std::fmt::Debug for LazyImage {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("LazyImage")
+			.field("format", &self.format)
+			.field("width", &self.width)
+			.field("height", &self.height)
+			.field("data_len", &self.data().map(<[u8]>::len))
+			.finish()
+	}
+}
+
+/// Reads the width and height from the header of `data`, without decoding the pixels.
+pub(crate) fn probe_dimensions(format: ImageFormatTag, data: &[u8]) -> Option<(usize, usize)> {
+	let (width, height) = match format {
+		ImageFormatTag::Png => png_dimensions(data)?,
+		ImageFormatTag::Tiff => tiff_dimensions(data)?,
+		ImageFormatTag::Dib => dib_dimensions(data)?,
+		_ => return None,
+	};
+	if width == 0 || height == 0 {
+		return None;
+	}
+	Some((width as usize, height as usize))
+}
+
+/// The dimensions are the first fields of the `IHDR` chunk, which must come first.
+///
+/// A PNG has a single `IHDR` chunk, but the decoder of the `image` crate accepts repeated ones,
+/// using the last. So the largest dimensions of any of them are returned, which never understate
+/// the size of the pixels that the decoder allocates.
+fn png_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+	const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+	if data.get(..8)? != SIGNATURE || data.get(12..16)? != b"IHDR" {
+		return None;
+	}
+	let u32_at = |offset: usize| -> Option<u32> {
+		Some(u32::from_be_bytes(data.get(offset..offset.checked_add(4)?)?.try_into().ok()?))
+	};
+	let (mut width, mut height) = (u32_at(16)?, u32_at(20)?);
+	// Each chunk is its length, its type, the data and a checksum.
+	let mut chunk = 8;
+	while let Some(length) = u32_at(chunk) {
+		if data.get(chunk + 4..chunk + 8) == Some(b"IHDR") {
+			if let (Some(w), Some(h)) = (u32_at(chunk + 8), u32_at(chunk + 12)) {
+				width = width.max(w);
+				height = height.max(h);
+			}
+		}
+		chunk = match chunk.checked_add(12).and_then(|end| end.checked_add(length as usize)) {
+			Some(next) => next,
+			None => break,
+		};
+	}
+	Some((width, height))
+}
+
+/// The dimensions are the `ImageWidth` and `ImageLength` entries of the first image file
+/// directory, each stored as a `SHORT` or a `LONG`. BigTIFF isn't supported.
+fn tiff_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+	let big_endian = match data.get(..4)? {
+		b"II*\0" => false,
+		b"MM\0*" => true,
+		_ => return None,
+	};
+	let u16_at = |offset: usize| -> Option<u16> {
+		let bytes = data.get(offset..offset.checked_add(2)?)?.try_into().ok()?;
+		Some(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+	};
+	let u32_at = |offset: usize| -> Option<u32> {
+		let bytes = data.get(offset..offset.checked_add(4)?)?.try_into().ok()?;
+		Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+	};
+
+	let directory = u32_at(4)? as usize;
+	let entries = u16_at(directory)?;
+	let (mut width, mut height) = (None, None);
+	for index in 0..usize::from(entries) {
+		let entry = directory.checked_add(2 + index * 12)?;
+		let value = match u16_at(entry + 2)? {
+			3 => u32::from(u16_at(entry + 8)?),
+			4 => u32_at(entry + 8)?,
+			_ => continue,
+		};
+		match u16_at(entry)? {
+			256 => width = Some(value),
+			257 => height = Some(value),
+			_ => {}
+		}
+	}
+	Some((width?, height?))
+}
+
+/// `CF_DIB` starts with a `BITMAPINFOHEADER` (or one of its larger successors), or with the
+/// older `BITMAPCOREHEADER` of 12 bytes. A negative height marks a bitmap that's stored from the
+/// top row down.
+fn dib_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+	let header_size = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+	if header_size == 12 {
+		let width = u16::from_le_bytes(data.get(4..6)?.try_into().ok()?);
+		let height = u16::from_le_bytes(data.get(6..8)?.try_into().ok()?);
+		return Some((width.into(), height.into()));
+	}
+	if header_size < 40 {
+		return None;
+	}
+	let width = i32::from_le_bytes(data.get(4..8)?.try_into().ok()?);
+	let height = i32::from_le_bytes(data.get(8..12)?.try_into().ok()?);
+	Some((width.try_into().ok()?, height.checked_abs()? as u32))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn lazy_image_probing() {
+		let image = image::RgbaImage::from_pixel(3, 2, image::Rgba([10, 20, 30, 40]));
+		let mut png = Vec::new();
+		image::DynamicImage::ImageRgba8(image).write_to(&mut png, image::ImageFormat::Png).unwrap();
+		assert_eq!(probe_dimensions(ImageFormatTag::Png, &png), Some((3, 2)));
+		assert_eq!(probe_dimensions(ImageFormatTag::Png, &png[..20]), None);
+		assert_eq!(probe_dimensions(ImageFormatTag::Tiff, &png), None);
+
+		// A little endian TIFF whose width is a SHORT, and a big endian one whose height is a LONG.
+		#[rustfmt::skip]
+		let tiff_le = [
+			b'I', b'I', 42, 0, 8, 0, 0, 0,
+			2, 0,
+			0, 1, 3, 0, 1, 0, 0, 0, 0x20, 0x03, 0, 0,
+			1, 1, 4, 0, 1, 0, 0, 0, 0x58, 0x02, 0, 0,
+		];
+		assert_eq!(probe_dimensions(ImageFormatTag::Tiff, &tiff_le), Some((800, 600)));
+		#[rustfmt::skip]
+		let tiff_be = [
+			b'M', b'M', 0, 42, 0, 0, 0, 8,
+			0, 3,
+			1, 0, 0, 3, 0, 0, 0, 1, 0x07, 0x80, 0, 0,
+			1, 1, 0, 4, 0, 0, 0, 1, 0, 0, 0x04, 0x38,
+			1, 3, 0, 3, 0, 0, 0, 1, 0, 1, 0, 0,
+		];
+		assert_eq!(probe_dimensions(ImageFormatTag::Tiff, &tiff_be), Some((1920, 1080)));
+		assert_eq!(probe_dimensions(ImageFormatTag::Tiff, &tiff_be[..30]), None);
+
+		// A bottom-up and a top-down `BITMAPINFOHEADER`, and a `BITMAPCOREHEADER`.
+		let mut dib = vec![0; 40];
+		dib[..4].copy_from_slice(&40u32.to_le_bytes());
+		dib[4..8].copy_from_slice(&640i32.to_le_bytes());
+		dib[8..12].copy_from_slice(&480i32.to_le_bytes());
+		assert_eq!(probe_dimensions(ImageFormatTag::Dib, &dib), Some((640, 480)));
+		dib[8..12].copy_from_slice(&(-480i32).to_le_bytes());
+		assert_eq!(probe_dimensions(ImageFormatTag::Dib, &dib), Some((640, 480)));
+		dib[4..8].copy_from_slice(&(-640i32).to_le_bytes());
+		assert_eq!(probe_dimensions(ImageFormatTag::Dib, &dib), None);
+		let core = [12, 0, 0, 0, 16, 0, 8, 0, 1, 0, 24, 0];
+		assert_eq!(probe_dimensions(ImageFormatTag::Dib, &core), Some((16, 8)));
+		assert_eq!(probe_dimensions(ImageFormatTag::Dib, &[20, 0, 0, 0]), None);
+
+		let lazy = LazyImage::encoded(ImageFormatTag::Png, png.clone(), None).unwrap();
+		assert_eq!((lazy.width(), lazy.height(), lazy.format()), (3, 2, ImageFormatTag::Png));
+		assert_eq!(lazy.data(), Some(png.as_slice()));
+		let decoded = lazy.decode().unwrap();
+		assert_eq!((decoded.width, decoded.height), (3, 2));
+		assert!(decoded.bytes.chunks(4).all(|pixel| pixel == [10, 20, 30, 40]));
+		let limited = LazyImage::encoded(ImageFormatTag::Png, png, Some(16)).unwrap();
+		assert!(matches!(limited.decode(), Err(Error::ImageTooLarge { size: 24, limit: 16 })));
+		assert!(LazyImage::encoded(ImageFormatTag::Other, Vec::new(), None).is_none());
+
+		fn assert_send<T: Send>(_: &T) {}
+		assert_send(&lazy);
+	}
+}
@@ -13,94 +13,2539 @@ and conditions of the chosen license apply to this file.
 #![crate_type = "dylib"]
 #![crate_type = "rlib"]
 
+/// Calls a method on whichever backend `$backend` holds, binding it to `$clipboard`.
+macro_rules! dispatch {
+	($backend:expr, $clipboard:ident => $call:expr) => {
+		match $backend {
+			$crate::Backend::Platform($clipboard) => $call,
+			$crate::Backend::Custom($clipboard) => $call,
+			#[cfg(feature = "test-util")]
+			$crate::Backend::Mock($clipboard) => $call,
+		}
+	};
+}
+
+/// Logs through the macro of the `log` crate named `$level`. Without the `log` feature the
+/// arguments are only type-checked, so that the crate compiles without the dependency.
+#[allow(unused_macros)]
+macro_rules! forward_log {
+	($level:ident, $($arg:tt)+) => {{
+		#[cfg(feature = "log")]
+		::log::$level!($($arg)+);
+		#[cfg(not(feature = "log"))]
+		let _ = format_args!($($arg)+);
+	}};
+}
+
+#[allow(unused_macros)]
+macro_rules! trace {
+	($($arg:tt)+) => { forward_log!(trace, $($arg)+) };
+}
+
+#[allow(unused_macros)]
+macro_rules! debug {
+	($($arg:tt)+) => { forward_log!(debug, $($arg)+) };
+}
+
+#[allow(unused_macros)]
+macro_rules! info {
+	($($arg:tt)+) => { forward_log!(info, $($arg)+) };
+}
+
+#[allow(unused_macros)]
+macro_rules! warn {
+	($($arg:tt)+) => { forward_log!(warn, $($arg)+) };
+}
+
+#[allow(unused_macros)]
+macro_rules! error {
+	($($arg:tt)+) => { forward_log!(error, $($arg)+) };
+}
+
 mod common;
-pub use common::Error;
 #[cfg(feature = "image-data")]
-pub use common::ImageData;
+pub use common::{premultiply_rgba, unpremultiply_rgba};
+pub use common::{
+	ClipboardEvent, ClipboardItem, ClipboardSnapshot, Error, ImageData, ImageFormatTag,
+	LineEndings, PixelFormat, PixelOrder, PlatformError,
+};
+use common::{Representation, WaitConfig};
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+use std::{
+	borrow::Cow,
+	sync::mpsc,
+	time::{Duration, Instant},
+};
+
+#[cfg(all(
+	unix,
+	not(any(
+		target_os = "macos",
+		target_os = "ios",
+		target_os = "android",
+		target_os = "emscripten"
+	)),
+))]
+pub(crate) mod common_linux;
+
+#[cfg(all(
+	unix,
+	not(any(
+		target_os = "macos",
+		target_os = "ios",
+		target_os = "android",
+		target_os = "emscripten"
+	)),
+))]
+pub mod x11_clipboard;
+
+#[cfg(all(
+	unix,
+	not(any(
+		target_os = "macos",
+		target_os = "ios",
+		target_os = "android",
+		target_os = "emscripten"
+	)),
+	feature = "wayland-data-control"
+))]
+pub mod wayland_data_control_clipboard;
+
+#[cfg(all(
+	unix,
+	not(any(
+		target_os = "macos",
+		target_os = "ios",
+		target_os = "android",
+		target_os = "emscripten"
+	)),
+	feature = "wayland-native"
+))]
+pub mod wayland_clipboard;
+
+#[cfg(all(
+	unix,
+	not(any(
+		target_os = "macos",
+		target_os = "ios",
+		target_os = "android",
+		target_os = "emscripten"
+	)),
+	feature = "osc52"
+))]
+pub mod osc52_clipboard;
+
+#[cfg(all(
+	unix,
+	not(any(
+		target_os = "macos",
+		target_os = "ios",
+		target_os = "android",
+		target_os = "emscripten"
+	)),
+	feature = "wsl"
+))]
+pub mod wsl_clipboard;
+
+#[cfg(windows)]
+pub mod windows_clipboard;
+
+#[cfg(target_os = "macos")]
+pub mod osx_clipboard;
+
+#[cfg(target_os = "android")]
+pub mod android_clipboard;
+
+#[cfg(target_os = "ios")]
+pub mod ios_clipboard;
+
+#[cfg(target_arch = "wasm32")]
+pub mod web_clipboard;
+
+#[cfg(all(
+	unix,
+	not(any(
+		target_os = "macos",
+		target_os = "ios",
+		target_os = "android",
+		target_os = "emscripten"
+	)),
+))]
+type PlatformClipboard = common_linux::LinuxClipboard;
+#[cfg(windows)]
+type PlatformClipboard = windows_clipboard::WindowsClipboardContext;
+#[cfg(target_os = "macos")]
+type PlatformClipboard = osx_clipboard::OSXClipboardContext;
+#[cfg(target_os = "android")]
+type PlatformClipboard = android_clipboard::AndroidClipboardContext;
+#[cfg(target_os = "ios")]
+type PlatformClipboard = ios_clipboard::IosClipboardContext;
+#[cfg(target_arch = "wasm32")]
+type PlatformClipboard = web_clipboard::WebClipboardContext;
+
+/// The backend that a `Clipboard` forwards its methods to.
+pub(crate) enum Backend {
+	Platform(PlatformClipboard),
+	Custom(custom_clipboard::CustomClipboard),
+	#[cfg(feature = "test-util")]
+	Mock(MockClipboard),
+}
+
+impl Backend {
+	/// Returns the backend as a `ClipboardProvider`, which the basic operations go through.
+	fn provider(&mut self) -> &mut dyn ClipboardProvider {
+		match self {
+			Backend::Platform(platform) => platform,
+			Backend::Custom(custom) => custom.provider(),
+			#[cfg(feature = "test-util")]
+			Backend::Mock(mock) => mock,
+		}
+	}
+}
+
+#[cfg(all(
+	unix,
+	not(any(
+		target_os = "macos",
+		target_os = "ios",
+		target_os = "android",
+		target_os = "emscripten"
+	)),
+))]
+type PlatformWatcher = common_linux::LinuxWatcher;
+#[cfg(windows)]
+type PlatformWatcher = windows_clipboard::WindowsClipboardWatcher;
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "android", target_arch = "wasm32"))]
+type PlatformWatcher = common::UnsupportedWatcher;
+
+#[cfg(all(
+	unix,
+	not(any(
+		target_os = "macos",
+		target_os = "ios",
+		target_os = "android",
+		target_os = "emscripten"
+	)),
+))]
+pub use common_linux::{
+	ClipboardBuilderExtLinux, ClipboardEventExtLinux, ClipboardExtLinux, ClipboardGetExtLinux,
+	ClipboardWriteExtLinux, LinuxBackend, LinuxClipboardKind,
+};
+#[cfg(target_os = "ios")]
+pub use ios_clipboard::{ClipboardExtIos, ClipboardWriteExtIos, PasteboardOptions};
+#[cfg(all(target_os = "macos", feature = "image-data"))]
+pub use osx_clipboard::make_nsimage;
+#[cfg(target_os = "macos")]
+pub use osx_clipboard::{
+	ApplePasteboardKind, ClipboardType, DataProvider, COLOR, CONCEALED, FILE_URL, HTML, PDF, PNG,
+	RTF, TIFF, URL,
+};
+#[cfg(windows)]
+pub use windows_clipboard::{
+	ClipboardExtWindows, ClipboardWriteExtWindows, DelayedRendering, DelayedRenderingDrop,
+};
+
+mod custom_clipboard;
+
+mod thread_safe_clipboard;
+pub use thread_safe_clipboard::ThreadSafeClipboard;
+
+#[cfg(feature = "test-util")]
+mod mock_clipboard;
+#[cfg(feature = "test-util")]
+pub use mock_clipboard::{MockClipboard, MOCK_TEXT_FORMAT};
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
+
+#[cfg(feature = "image-data")]
+mod lazy_image;
+#[cfg(feature = "image-data")]
+pub use lazy_image::LazyImage;
+
+/// The name of the format that holds PDF documents, see `Clipboard::get_pdf`.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const PDF_FORMAT: &str = "com.adobe.pdf";
+#[cfg(windows)]
+const PDF_FORMAT: &str = "Portable Document Format";
+#[cfg(not(any(target_os = "macos", target_os = "ios", windows)))]
+const PDF_FORMAT: &str = "application/pdf";
+
+/// The name of the format that holds HTML, see `Clipboard::get_html`.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const HTML_FORMAT: &str = "public.html";
+#[cfg(windows)]
+const HTML_FORMAT: &str = "HTML Format";
+#[cfg(not(any(target_os = "macos", target_os = "ios", windows)))]
+const HTML_FORMAT: &str = "text/html";
+
+/// The formats whose data `Clipboard::get_image_lazy` reads without decoding it, in the order
+/// that `get_image` prefers them.
+#[cfg(all(feature = "image-data", target_os = "macos"))]
+const LAZY_IMAGE_FORMATS: &[(&str, ImageFormatTag)] =
+	&[(PNG, ImageFormatTag::Png), (TIFF, ImageFormatTag::Tiff)];
+#[cfg(all(feature = "image-data", target_os = "ios"))]
+const LAZY_IMAGE_FORMATS: &[(&str, ImageFormatTag)] = &[("public.png", ImageFormatTag::Png)];
+#[cfg(all(feature = "image-data", windows))]
+const LAZY_IMAGE_FORMATS: &[(&str, ImageFormatTag)] =
+	&[("PNG", ImageFormatTag::Png), ("CF_DIB", ImageFormatTag::Dib)];
+#[cfg(all(
+	feature = "image-data",
+	unix,
+	not(any(
+		target_os = "macos",
+		target_os = "ios",
+		target_os = "android",
+		target_os = "emscripten"
+	)),
+))]
+const LAZY_IMAGE_FORMATS: &[(&str, ImageFormatTag)] = &[("image/png", ImageFormatTag::Png)];
+#[cfg(all(
+	feature = "image-data",
+	any(target_arch = "wasm32", target_os = "android", target_os = "emscripten")
+))]
+const LAZY_IMAGE_FORMATS: &[(&str, ImageFormatTag)] = &[];
+
+/// The OS independent struct for accessing the clipboard.
+///
+/// Any number of `Clipboard` instances are allowed to exist at a single point in time. Note however
+/// that all `Clipboard`s must be 'dropped' before the program exits. In most scenarios this happens
+/// automatically but there are frameworks (for example `winit`) that take over the execution
+/// and where the objects don't get dropped when the application exits. In these cases you have to
+/// make sure the object is dropped by taking ownership of it in a confined scope when detecting
+/// that your application is about to quit.
+///
+/// It is also valid to have multiple `Clipboards` on separate threads at once but note that
+/// executing multiple clipboard operations in paralell might fail with a `ClipboardOccupied` error.
+///
+/// # Threads
+///
+/// A `Clipboard` is `Send`, except on macOS and iOS, so a `Mutex<Clipboard>` can be shared between
+/// threads. It isn't `Sync`, as every operation takes `&mut self`.
+///
+/// - On Windows, the clipboard is opened and closed again within each operation, and the
+///   windows that delayed rendering and watching need run on threads of their own.
+/// - On Linux, the X11 and Wayland connections are served by background threads, which the
+///   context talks to through shared state and channels.
+/// - On Android, the `ClipboardManager` is a global JNI reference, and each operation attaches
+///   the calling thread to the VM.
+/// - On macOS and iOS, the context holds Objective-C objects that aren't synchronized, so it
+///   can't be sent to another thread. Each thread that uses the clipboard creates its own, which
+///   is allowed on any thread, not just the main thread.
+///
+/// [`ThreadSafeClipboard`] is `Send` and `Sync` on every platform, and serializes the operations of
+/// all threads.
+///
+/// With the `test-util` feature, [`Clipboard::new_mock`] creates a clipboard that only exists in
+/// memory instead.
+pub struct Clipboard {
+	pub(crate) backend: Backend,
+	/// See `ClipboardBuilder::max_image_bytes`.
+	#[cfg_attr(not(feature = "image-data"), allow(dead_code))]
+	max_image_bytes: Option<usize>,
+}
+
+// The platforms where `Clipboard` is `Send` are documented above, so it mustn't stop being `Send`
+// by accident.
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+const _: () = {
+	fn assert_send<T: Send>() {}
+	let _ = assert_send::<Clipboard>;
+};
+
+impl Clipboard {
+	/// Creates an instance of the clipboard
+	///
+	/// On Linux, the `ARBOARD_BACKEND` environment variable can force a backend, see
+	/// [`ClipboardBuilderExtLinux`] for the details.
+	pub fn new() -> Result<Self, Error> {
+		Self::builder().build()
+	}
+
+	fn from_platform(platform: PlatformClipboard) -> Self {
+		Clipboard { backend: Backend::Platform(platform), max_image_bytes: None }
+	}
+
+	/// Creates a clipboard that forwards to `provider`, for example to route the clipboard through
+	/// a remote desktop connection, or through a backend that arboard doesn't have.
+	///
+	/// The basic operations of [`ClipboardProvider`] are forwarded as they are. Of the other
+	/// methods, `swap_text` and `write` are built on top of them (only the text of a `write` is
+	/// placed onto the clipboard), and the rest return [`Error::ClipboardNotSupported`]. Only the
+	/// regular clipboard is available through the platform specific extensions.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use arboard::{Clipboard, ClipboardProvider, Error};
+	///
+	/// struct Remote(Option<String>);
+	///
+	/// impl ClipboardProvider for Remote {
+	///     fn get_text(&mut self) -> Result<String, Error> {
+	///         self.0.clone().ok_or(Error::ContentNotAvailable)
+	///     }
+	///
+	///     fn set_text(&mut self, text: String) -> Result<(), Error> {
+	///         self.0 = Some(text);
+	///         Ok(())
+	///     }
+	///
+	///     fn clear(&mut self) -> Result<(), Error> {
+	///         self.0 = None;
+	///         Ok(())
+	///     }
+	///
+	///     fn available_formats(&mut self) -> Result<Vec<String>, Error> {
+	///         Ok(self.0.iter().map(|_| "text/plain".to_owned()).collect())
+	///     }
+	/// }
+	///
+	/// let mut clipboard = Clipboard::from_provider(Box::new(Remote(None)));
+	/// clipboard.set_text("Hello, world!").unwrap();
+	/// assert_eq!(clipboard.available_formats().unwrap(), ["text/plain"]);
+	/// ```
+	pub fn from_provider(provider: Box<dyn ClipboardProvider + Send>) -> Self {
+		Clipboard {
+			backend: Backend::Custom(custom_clipboard::CustomClipboard::new(provider)),
+			max_image_bytes: None,
+		}
+	}
+
+	/// Creates a clipboard that only exists in memory, backed by [`MockClipboard::shared`].
+	///
+	/// All methods work like on a real clipboard, including the platform specific extensions,
+	/// without touching the clipboard of the system. The contents are shared by all the mock
+	/// clipboards of the process, so they're still there after the clipboard is dropped. This is
+	/// useful for tests and CI, where there may be no display server. On Linux,
+	/// `ARBOARD_BACKEND=mock` makes `Clipboard::new` return such a clipboard too.
+	#[cfg(feature = "test-util")]
+	pub fn new_mock() -> Self {
+		Self::from_mock(MockClipboard::shared())
+	}
+
+	/// Creates a clipboard backed by `mock`.
+	///
+	/// Clones of a `MockClipboard` share their contents, so the test can keep a clone to inspect
+	/// what the code under test placed onto the clipboard, or to make its next operation fail.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use arboard::{Clipboard, Error, MockClipboard};
+	///
+	/// let mock = MockClipboard::new();
+	/// let mut clipboard = Clipboard::from_mock(mock.clone());
+	/// clipboard.set_text("Hello, world!").unwrap();
+	/// assert_eq!(mock.clone().get_text().unwrap(), "Hello, world!");
+	///
+	/// mock.fail_next_read(Error::ClipboardOccupied);
+	/// assert!(matches!(clipboard.get_text(), Err(Error::ClipboardOccupied)));
+	/// ```
+	#[cfg(feature = "test-util")]
+	pub fn from_mock(mock: MockClipboard) -> Self {
+		Clipboard { backend: Backend::Mock(mock), max_image_bytes: None }
+	}
+
+	/// Returns a builder for creating a clipboard with options. `Clipboard::new` is the same as
+	/// `Clipboard::builder().build()`.
+	pub fn builder() -> ClipboardBuilder {
+		ClipboardBuilder::default()
+	}
+
+	/// Returns a human readable name of the backend that this clipboard uses, for example
+	/// `"X11"` or `"Windows"`, which is useful to include in bug reports.
+	pub fn backend_name(&self) -> &'static str {
+		match self.backend {
+			Backend::Platform(_) => {}
+			Backend::Custom(_) => return "Custom",
+			#[cfg(feature = "test-util")]
+			Backend::Mock(_) => return "Mock",
+		}
+		#[cfg(all(
+			unix,
+			not(any(
+				target_os = "macos",
+				target_os = "ios",
+				target_os = "android",
+				target_os = "emscripten"
+			)),
+		))]
+		return ClipboardExtLinux::backend(self).name();
+		#[cfg(windows)]
+		return "Windows";
+		#[cfg(target_os = "macos")]
+		return "macOS";
+		#[cfg(target_os = "ios")]
+		return "iOS";
+		#[cfg(target_os = "android")]
+		return "Android";
+		#[cfg(target_arch = "wasm32")]
+		return "Web";
+	}
+
+	/// Creates an instance of the clipboard that uses the Wayland connection of the application.
+	///
+	/// This relies on the core Wayland protocol, so unlike the `wayland-data-control` backend it
+	/// works on every compositor. However, the compositor only lets the client with keyboard focus
+	/// access the clipboard. That's why this has to use the connection that the windows of the
+	/// application were created on, and why the clipboard operations fail while none of those
+	/// windows is focused. The PRIMARY selection is available if the compositor supports the
+	/// primary selection protocol, the SECONDARY selection is not supported.
+	///
+	/// `libwayland-client` is loaded at runtime, and it has to be the same library that created
+	/// `display`.
+	///
+	/// # Safety
+	///
+	/// `display` must point to a valid `wl_display` from `libwayland-client`, which must stay
+	/// valid until the returned clipboard is dropped.
+	#[cfg(all(
+		unix,
+		not(any(
+			target_os = "macos",
+			target_os = "ios",
+			target_os = "android",
+			target_os = "emscripten"
+		)),
+		feature = "wayland-native"
+	))]
+	pub unsafe fn from_wayland_display(display: *mut std::ffi::c_void) -> Result<Self, Error> {
+		Ok(Self::from_platform(common_linux::LinuxClipboard::from_wayland_display(display)?))
+	}
+
+	/// Creates a clipboard that's accessed through the terminal emulator, instead of through the
+	/// display server.
+	///
+	/// The text is sent to the controlling terminal of the process in an OSC 52 escape sequence,
+	/// and the terminal places it onto the clipboard of the machine that it's running on. This
+	/// works without a display server, for example over SSH, and also while one is available.
+	/// Sequences are wrapped so that they pass through tmux (which requires its
+	/// `allow-passthrough` option) and GNU screen.
+	///
+	/// Only text can be transferred. Many terminals don't allow reading the clipboard, in which
+	/// case `get_text` returns [`Error::ClipboardNotSupported`] after a short timeout. Returns
+	/// [`Error::ClipboardNotSupported`] if the process has no controlling terminal.
+	#[cfg(all(
+		unix,
+		not(any(
+			target_os = "macos",
+			target_os = "ios",
+			target_os = "android",
+			target_os = "emscripten"
+		)),
+		feature = "osc52"
+	))]
+	pub fn new_osc52() -> Result<Self, Error> {
+		Ok(Self::from_platform(common_linux::LinuxClipboard::new_osc52()?))
+	}
+
+	/// Creates an instance of the clipboard that accesses the given pasteboard instead of the
+	/// general one.
+	///
+	/// All the methods operate on that pasteboard. For example, the text of the
+	/// [`Find`](ApplePasteboardKind::Find) pasteboard is the search string that's shared between
+	/// applications.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use arboard::{ApplePasteboardKind, Clipboard};
+	///
+	/// let mut find = Clipboard::new_apple_pasteboard(ApplePasteboardKind::Find).unwrap();
+	/// find.set_text("needle").unwrap();
+	/// ```
+	#[cfg(target_os = "macos")]
+	pub fn new_apple_pasteboard(kind: ApplePasteboardKind) -> Result<Self, Error> {
+		Ok(Self::from_platform(osx_clipboard::OSXClipboardContext::with_pasteboard(&kind)?))
+	}
+
+	/// Creates an instance of the clipboard like `new`, but without accessing the general
+	/// pasteboard until the first operation, for tools that may end up not using the clipboard.
+	///
+	/// Creating the clipboard can't fail then. If the pasteboard server can't be reached, the
+	/// first operation returns [`Error::PlatformUnavailable`] instead, and so do the following
+	/// ones until it can be reached.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// # use arboard::Clipboard;
+	/// let mut clipboard = Clipboard::new_for_process();
+	/// // The pasteboard is only accessed here.
+	/// clipboard.set_text("Hello").unwrap();
+	/// ```
+	#[cfg(target_os = "macos")]
+	pub fn new_for_process() -> Self {
+		Self::from_platform(osx_clipboard::OSXClipboardContext::lazy(&ApplePasteboardKind::General))
+	}
+
+	/// Fetches utf-8 text from the clipboard and returns it.
+	///
+	/// Returns [`Error::InvalidUtf8`] if the text isn't valid, see `get_text_lossy`.
+	///
+	/// On macOS, the data of `public.utf8-plain-text` and `public.plain-text` is read in this
+	/// order when the pasteboard can't provide the text as a string, which happens with the
+	/// contents of some applications.
+	pub fn get_text(&mut self) -> Result<String, Error> {
+		traced(self.backend_name(), "get_text", NO_FORMATS, None, || {
+			self.backend.provider().get_text()
+		})
+	}
+
+	/// Fetches utf-8 text from the clipboard like `get_text`, but replaces the parts that aren't
+	/// valid with U+FFFD REPLACEMENT CHARACTER instead of returning [`Error::InvalidUtf8`].
+	///
+	/// This accepts text from applications that label it as UTF-8 while it's in another encoding,
+	/// like Latin-1, and text with unpaired surrogates on Windows.
+	pub fn get_text_lossy(&mut self) -> Result<String, Error> {
+		common::lossy_text(self.get_text())
+	}
+
+	/// Fetches the raw contents of the clipboard in the given text format, without decoding them.
+	///
+	/// This is useful for text that was placed onto the clipboard in an encoding other than UTF-8
+	/// (for example MacRoman or UTF-16), which `get_text` cannot return. The caller is responsible
+	/// for decoding the bytes with the appropriate encoding.
+	///
+	/// `format` uses the same naming as [`ClipboardSnapshot::formats`]: a UTI on macOS (e.g.
+	/// `public.utf16-plain-text`), a clipboard format name on Windows (e.g. `CF_OEMTEXT`), a target
+	/// atom name on X11 (e.g. `STRING`) and a MIME type on Wayland. Returns `ContentNotAvailable`
+	/// if the clipboard doesn't hold data in the requested format.
+	pub fn get_text_bytes(&mut self, format: &str) -> Result<Vec<u8>, Error> {
+		traced(
+			self.backend_name(),
+			"get_text_bytes",
+			[format],
+			None,
+			|| dispatch!(&mut self.backend, cb => cb.get_text_bytes(format)),
+		)
+	}
+
+	/// Fetches the PDF document on the clipboard, which Preview and some design tools place there
+	/// for vector content that would lose quality as an image.
+	///
+	/// The raw data is read from the `com.adobe.pdf` type on macOS and iOS, the
+	/// `Portable Document Format` format on Windows and `application/pdf` on Linux. Returns
+	/// `ContentNotAvailable` if the clipboard doesn't hold a PDF document.
+	pub fn get_pdf(&mut self) -> Result<Vec<u8>, Error> {
+		self.get_text_bytes(PDF_FORMAT)
+	}
+
+	/// Fetches the HTML on the clipboard, as placed there by browsers and word processors.
+	///
+	/// The HTML is cleaned up for parsing: a leading byte order mark, which some applications add,
+	/// is removed, and the line endings are converted to `\n`. On Windows, the header of the
+	/// `HTML Format` is removed as well. Returns [`Error::InvalidUtf8`] if the HTML isn't valid
+	/// UTF-8, and `ContentNotAvailable` if the clipboard doesn't hold HTML. Use `get_html_raw` for
+	/// the exact data.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// # use arboard::Clipboard;
+	/// let mut clipboard = Clipboard::new().unwrap();
+	/// let html = clipboard.get_html().unwrap();
+	/// assert!(!html.contains('\r'));
+	/// ```
+	pub fn get_html(&mut self) -> Result<String, Error> {
+		let bytes = self.get_html_raw()?;
+		#[cfg(windows)]
+		let bytes = common::unwrap_cf_html(&bytes).to_vec();
+		common::html_from_utf8(bytes)
+	}
+
+	/// Fetches the HTML on the clipboard exactly as it's stored, from the `public.html` type on
+	/// macOS and iOS, the `HTML Format` format on Windows (including its header) and `text/html`
+	/// on Linux. See `get_html` for a cleaned up version.
+	pub fn get_html_raw(&mut self) -> Result<Vec<u8>, Error> {
+		self.get_text_bytes(HTML_FORMAT)
+	}
+
+	/// Places the text onto the clipboard. Any valid utf-8 string is accepted.
+	///
+	/// The empty string is placed onto the clipboard like any other text: the previous contents
+	/// are replaced by an empty string in the text format, and `get_text` returns it afterwards.
+	/// This is different from [`Clipboard::clear`], which leaves no text on the clipboard. Through
+	/// OSC 52 and in the web browser an empty string can't be told apart from an empty clipboard,
+	/// so `get_text` returns [`Error::ContentNotAvailable`] there instead.
+	///
+	/// Borrowed text, like a `&str`, is only copied where the backend needs to keep a copy of it:
+	/// on X11 and Wayland, where this process serves the text to other applications, and by
+	/// custom providers, which take a `String`. Windows and macOS convert it straight into the
+	/// format of the clipboard.
+	pub fn set_text<'a>(&mut self, text: impl Into<Cow<'a, str>>) -> Result<(), Error> {
+		let text = text.into();
+		traced(self.backend_name(), "set_text", NO_FORMATS, Some(text.len()), || {
+			match &mut self.backend {
+				Backend::Platform(platform) => platform.set_text(text),
+				backend => backend.provider().set_text(text.into_owned()),
+			}
+		})
+	}
+
+	/// Places the text onto the clipboard like `set_text`, and returns the [generation] of the
+	/// clipboard that the write resulted in.
+	///
+	/// Recording the returned value lets a clipboard watcher recognize its own write, instead of
+	/// treating it as a change made by another application. On macOS the value is the
+	/// `changeCount` that clearing the pasteboard returned, and on Windows the sequence number is
+	/// read while the clipboard is still open, so no other application can change the contents
+	/// before the value is known. Elsewhere the generation is read right after the write, which
+	/// leaves a short window for other applications; on X11 the counter of this crate may not
+	/// have observed the write yet at that point. Where `generation` isn't supported, like with
+	/// the `wayland-data-control` backend, the text is written and then the error is returned.
+	///
+	/// [generation]: Clipboard::generation
+	pub fn set_text_with_generation<'a>(
+		&mut self,
+		text: impl Into<Cow<'a, str>>,
+	) -> Result<u64, Error> {
+		match &mut self.backend {
+			#[cfg(any(windows, target_os = "macos"))]
+			Backend::Platform(platform) => platform.set_text_with_generation(text.into()),
+			_ => {
+				self.set_text(text)?;
+				self.generation()
+			}
+		}
+	}
+
+	/// Removes the contents of the clipboard.
+	///
+	/// Returns [`Error::ClipboardNotSupported`] where the clipboard can't be emptied, for example
+	/// on Android, through OSC 52 or on the clipboard of the Windows host in WSL.
+	pub fn clear(&mut self) -> Result<(), Error> {
+		traced(self.backend_name(), "clear", NO_FORMATS, None, || self.backend.provider().clear())
+	}
+
+	/// Returns the names of the formats that are on the clipboard, using the same naming as
+	/// [`ClipboardSnapshot::formats`]. Unlike `snapshot`, this doesn't read any of the contents.
+	pub fn available_formats(&mut self) -> Result<Vec<String>, Error> {
+		traced(self.backend_name(), "available_formats", NO_FORMATS, None, || {
+			self.backend.provider().available_formats()
+		})
+	}
+
+	/// Places the text onto the clipboard in the canonical text format, along with any other
+	/// encodings of it that older applications may expect.
+	///
+	/// On macOS the text is additionally written as `public.utf16-plain-text` and
+	/// `public.utf16-external-plain-text`. On the other platforms this is the same as `set_text`,
+	/// since Windows converts `CF_UNICODETEXT` to the legacy formats on demand and the Linux
+	/// backends already offer the text under all the common targets and MIME types.
+	pub fn set_text_multi(&mut self, text: &str) -> Result<(), Error> {
+		let write = self.write().text(text);
+		#[cfg(target_os = "macos")]
+		let write = osx_clipboard::alternate_text_encodings(text)
+			.into_iter()
+			.fold(write, |write, (format, bytes)| write.data(format, bytes));
+		write.commit()
+	}
+
+	/// Fetches utf-8 text from the clipboard of the web browser and returns it.
+	///
+	/// The browser only allows reading the clipboard if the user granted the permission, or in
+	/// response to a user gesture (depending on the browser), and returns
+	/// [`Error::PermissionDenied`] otherwise. The clipboard is only available in secure contexts,
+	/// in other pages [`Error::ClipboardNotSupported`] is returned.
+	#[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+	pub async fn get_text_async(&mut self) -> Result<String, Error> {
+		dispatch!(&mut self.backend, cb => cb.get_text_async().await)
+	}
+
+	/// Places the text onto the clipboard of the web browser. Any valid utf-8 string is accepted.
+	///
+	/// See [`get_text_async`](Self::get_text_async) for the restrictions of the browser.
+	#[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+	pub async fn set_text_async(&mut self, text: String) -> Result<(), Error> {
+		dispatch!(&mut self.backend, cb => cb.set_text_async(text).await)
+	}
+
+	/// Fetches the image that's on the clipboard of the web browser as a PNG.
+	///
+	/// See [`get_text_async`](Self::get_text_async) for the restrictions of the browser.
+	#[cfg(all(target_arch = "wasm32", feature = "wasm", feature = "image-data"))]
+	pub async fn get_image_async(&mut self) -> Result<ImageData<'static>, Error> {
+		let image = dispatch!(&mut self.backend, cb => cb.get_image_async().await)?;
+		self.check_image_size(&image)?;
+		Ok(image)
+	}
+
+	/// Places the image onto the clipboard of the web browser as a PNG.
+	///
+	/// See [`get_text_async`](Self::get_text_async) for the restrictions of the browser.
+	#[cfg(all(target_arch = "wasm32", feature = "wasm", feature = "image-data"))]
+	pub async fn set_image_async(&mut self, image: ImageData<'_>) -> Result<(), Error> {
+		self.check_image_size(&image)?;
+		dispatch!(&mut self.backend, cb => cb.set_image_async(image).await)
+	}
+
+	/// Fetches utf-8 text from the clipboard without blocking the calling thread.
+	///
+	/// The operation runs on a background thread, which opens the same clipboard there, and the
+	/// returned future completes when it's done. It doesn't depend on an async runtime, so it can
+	/// be awaited from any executor, for example from the event loop of a GUI. This is supported on
+	/// macOS, Windows and X11. The other backends run the operation on the calling thread before
+	/// the future completes.
+	///
+	/// In web browsers, the `wasm` feature provides the methods with the same names instead.
+	#[cfg(all(feature = "async", not(target_arch = "wasm32")))]
+	pub async fn get_text_async(&mut self) -> Result<String, Error> {
+		match self.reopen() {
+			Some(reopen) => common::offload(move || reopen()?.get_text()).await,
+			None => self.get_text(),
+		}
+	}
+
+	/// Places the text onto the clipboard without blocking the calling thread.
+	///
+	/// See [`get_text_async`](Self::get_text_async) for how the operation is run.
+	#[cfg(all(feature = "async", not(target_arch = "wasm32")))]
+	pub async fn set_text_async(&mut self, text: String) -> Result<(), Error> {
+		match self.reopen() {
+			Some(reopen) => common::offload(move || reopen()?.set_text(text)).await,
+			None => self.set_text(text),
+		}
+	}
+
+	/// Fetches image data from the clipboard without blocking the calling thread. Reading and
+	/// decoding a large image can take a while, so this keeps the UI responsive in the meantime.
+	///
+	/// See [`get_text_async`](Self::get_text_async) for how the operation is run.
+	#[cfg(all(feature = "async", not(target_arch = "wasm32")))]
+	pub async fn get_image_async(&mut self) -> Result<ImageData<'static>, Error> {
+		match self.reopen() {
+			Some(reopen) => common::offload(move || reopen()?.get_image()).await,
+			None => self.get_image(),
+		}
+	}
+
+	/// Places an image onto the clipboard without blocking the calling thread.
+	///
+	/// The pixels are copied if `image` borrows them, since the background thread needs to own
+	/// them. See [`get_text_async`](Self::get_text_async) for how the operation is run.
+	#[cfg(all(feature = "async", not(target_arch = "wasm32")))]
+	pub async fn set_image_async(&mut self, image: ImageData<'_>) -> Result<(), Error> {
+		match self.reopen() {
+			Some(reopen) => {
+				let image = image.to_owned_img();
+				common::offload(move || reopen()?.set_image(image)).await
+			}
+			None => self.set_image(image),
+		}
+	}
+
+	/// Identifies the contents that were just written to `selection`, see
+	/// `ClipboardWrite::clear_after`.
+	#[cfg(all(
+		unix,
+		not(any(
+			target_os = "macos",
+			target_os = "ios",
+			target_os = "android",
+			target_os = "emscripten"
+		)),
+	))]
+	fn contents_stamp(&mut self, selection: LinuxClipboardKind) -> Result<u64, Error> {
+		match &mut self.backend {
+			Backend::Platform(platform) => platform.contents_stamp(selection),
+			_ => self.generation(),
+		}
+	}
+
+	/// Returns whether the contents that `contents_stamp` identified are still on the clipboard.
+	#[cfg(all(
+		unix,
+		not(any(
+			target_os = "macos",
+			target_os = "ios",
+			target_os = "android",
+			target_os = "emscripten"
+		)),
+	))]
+	fn is_unchanged(&mut self, selection: LinuxClipboardKind, stamp: u64) -> Result<bool, Error> {
+		match &mut self.backend {
+			Backend::Platform(platform) => platform.is_unchanged(selection, stamp),
+			_ => Ok(self.generation()? == stamp),
+		}
+	}
+
+	/// Returns a function that opens this clipboard again on another thread.
+	#[cfg(not(target_arch = "wasm32"))]
+	fn reopen(&self) -> Option<common::Reopen<Clipboard>> {
+		let max_image_bytes = self.max_image_bytes;
+		let reopen: common::Reopen<Clipboard> = match &self.backend {
+			Backend::Platform(platform) => {
+				let reopen = platform.reopen()?;
+				Box::new(move || reopen().map(Clipboard::from_platform))
+			}
+			// The provider can't be shared with another thread.
+			Backend::Custom(_) => return None,
+			#[cfg(feature = "test-util")]
+			Backend::Mock(mock) => {
+				let mock = mock.clone();
+				Box::new(move || Ok(Clipboard::from_mock(mock)))
+			}
+		};
+		Some(Box::new(move || {
+			let mut clipboard = reopen()?;
+			clipboard.max_image_bytes = max_image_bytes;
+			Ok(clipboard)
+		}))
+	}
+
+	/// Returns a number that changes every time the contents of the clipboard change.
+	///
+	/// This is meant to be cheap enough to call in a tight loop, and it can be used to tell whether
+	/// the clipboard was modified since an earlier call (for example when capturing a history
+	/// entry). The source of the number depends on the platform:
+	///
+	/// - On macOS: the `changeCount` of the pasteboard
+	/// - On Windows: `GetClipboardSequenceNumber`
+	/// - On X11: a counter maintained by this crate, which is incremented on every ownership change
+	///   of the clipboard selection. The counter only exists while at least one `Clipboard` is
+	///   alive and it starts from zero again after all of them have been dropped. Changes are
+	///   tracked using the XFIXES extension; if that is missing, only changes involving this
+	///   process are observed.
+	/// - On Wayland: a counter maintained by this crate, which is incremented every time the
+	///   compositor announces a new clipboard selection. With the data control protocol, the
+	///   changes are counted on a connection of its own from the first call on, so they may take a
+	///   moment to show up.
+	///
+	/// The values are not comparable across platforms, processes (except on macOS and Windows) or
+	/// reboots. The only meaningful operation is comparing two values obtained on the same machine
+	/// for equality.
+	pub fn generation(&mut self) -> Result<u64, Error> {
+		dispatch!(&mut self.backend, cb => cb.generation())
+	}
+
+	/// Captures every format that's currently available on the clipboard.
+	///
+	/// This is useful for temporarily replacing the clipboard contents (for example to simulate a
+	/// paste) and then putting the original contents back with `restore`. Formats that cannot be
+	/// read are left out of the snapshot, and are listed in [`ClipboardSnapshot::warnings`].
+	///
+	/// On macOS this captures each of the pasteboard items separately, on Windows each of the
+	/// clipboard formats that are backed by memory (GDI handles like `CF_BITMAP` are skipped), and
+	/// on Linux each of the targets advertised by the selection owner.
+	pub fn snapshot(&mut self) -> Result<ClipboardSnapshot, Error> {
+		traced(
+			self.backend_name(),
+			"snapshot",
+			NO_FORMATS,
+			None,
+			|| dispatch!(&mut self.backend, cb => cb.snapshot(None)),
+		)
+	}
+
+	/// Reads each of the items on the clipboard separately, like the files of a drag that put one
+	/// item per file onto the macOS pasteboard. The other platforms have at most one item.
+	///
+	/// This reads all the data on the clipboard, with the same caveats as `snapshot`: formats
+	/// that can't be read are left out of their item.
+	pub fn items(&mut self) -> Result<Vec<ClipboardItem>, Error> {
+		Ok(self.snapshot()?.into_items())
+	}
+
+	/// Returns the number of items on the clipboard, without reading any of them. See `items`.
+	///
+	/// This asks the pasteboard on macOS and iOS. Elsewhere, it's 1 if the clipboard holds
+	/// anything and 0 otherwise, which is also what's returned if the clipboard can't be accessed.
+	pub fn count_items(&mut self) -> usize {
+		#[cfg(any(target_os = "macos", target_os = "ios"))]
+		if let Backend::Platform(platform) = &mut self.backend {
+			return platform.count_items();
+		}
+		match self.available_formats() {
+			Ok(formats) if !formats.is_empty() => 1,
+			_ => 0,
+		}
+	}
+
+	/// Same as `snapshot`, but formats are only captured as long as the total size of the captured
+	/// data doesn't exceed `max_bytes`. The formats that don't fit are left out of the snapshot.
+	pub fn snapshot_with_limit(&mut self, max_bytes: usize) -> Result<ClipboardSnapshot, Error> {
+		traced(
+			self.backend_name(),
+			"snapshot_with_limit",
+			NO_FORMATS,
+			None,
+			|| dispatch!(&mut self.backend, cb => cb.snapshot(Some(max_bytes))),
+		)
+	}
+
+	/// Replaces the contents of the clipboard with the formats stored in `snapshot`.
+	///
+	/// All the formats are placed onto the clipboard in a single operation, so other applications
+	/// never observe a partially restored clipboard. Restoring an empty snapshot clears the
+	/// clipboard.
+	pub fn restore(&mut self, snapshot: &ClipboardSnapshot) -> Result<(), Error> {
+		let (backend, bytes) = (self.backend_name(), Some(snapshot.size()));
+		traced(
+			backend,
+			"restore",
+			snapshot.formats(),
+			bytes,
+			|| dispatch!(&mut self.backend, cb => cb.restore(snapshot)),
+		)
+	}
+
+	/// Makes sure that the contents this clipboard placed onto the clipboard stay available after
+	/// the process exits.
+	///
+	/// On X11, the contents are only available while the application that placed them is running,
+	/// unless a clipboard manager takes them over. This hands them over to the clipboard manager
+	/// and waits until it has stored them, and returns [`Error::NoClipboardManager`] if none is
+	/// running. The same happens automatically when the last `Clipboard` is dropped, but only
+	/// for a short time and without reporting failures.
+	///
+	/// With the core Wayland protocol, the contents can't be handed over to another process, so
+	/// this returns [`Error::ClipboardNotSupported`]. Everywhere else, the system already keeps the
+	/// contents and this does nothing. The exception are the formats of a `DelayedRendering`
+	/// handle on Windows, which are only kept if the handle is dropped with
+	/// `DelayedRenderingDrop::RenderAll` before the process exits.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// # use arboard::{Clipboard, Error};
+	/// let mut clipboard = Clipboard::new().unwrap();
+	/// clipboard.set_text("Hello from a short-lived process").unwrap();
+	/// if let Err(Error::NoClipboardManager) = clipboard.persist() {
+	///     // Keep serving the contents for a while instead of exiting right away.
+	///     std::thread::sleep(std::time::Duration::from_secs(10));
+	/// }
+	/// ```
+	pub fn persist(&mut self) -> Result<(), Error> {
+		dispatch!(&mut self.backend, cb => cb.persist())
+	}
+
+	/// Places the text onto the clipboard and returns the text that was on the clipboard before.
+	///
+	/// Returns `None` if the clipboard didn't hold any text, where `get_text` would return
+	/// [`Error::ContentNotAvailable`]; the new text is written either way. Any other error of
+	/// reading the previous text, like [`Error::InvalidUtf8`], is returned without writing the new
+	/// text. The previous contents are read as late as possible before the new text is written:
+	///
+	/// - On Windows both happen while the clipboard is open, so no other application can modify the
+	///   clipboard in between.
+	/// - On macOS and Linux there's no way to lock the clipboard, so another application may still
+	///   change the contents between reading the old text and writing the new one. The window for
+	///   this is very short, but callers should not rely on the operation being atomic.
+	pub fn swap_text(&mut self, text: String) -> Result<Option<String>, Error> {
+		traced(
+			self.backend_name(),
+			"swap_text",
+			NO_FORMATS,
+			None,
+			|| dispatch!(&mut self.backend, cb => cb.swap_text(text)),
+		)
+	}
+
+	/// Replaces the contents of the clipboard with `snapshot` and returns a snapshot of all the
+	/// formats that were on the clipboard before.
+	///
+	/// This is the equivalent of `swap_text` for arbitrary contents, and it's subject to the same
+	/// caveats regarding atomicity. Additionally the previous contents are captured and the new
+	/// ones are placed onto the clipboard in two separate steps on every platform.
+	pub fn swap_content(
+		&mut self,
+		snapshot: &ClipboardSnapshot,
+	) -> Result<ClipboardSnapshot, Error> {
+		dispatch!(&mut self.backend, cb => {
+			let previous = cb.snapshot(None)?;
+			cb.restore(snapshot)?;
+			Ok(previous)
+		})
+	}
+
+	/// Starts a write of multiple representations of the same content.
+	///
+	/// Each call to `set_text`, `set_image`, etc. replaces everything that's on the clipboard, so
+	/// they can't be used to offer the same content in multiple formats. The returned
+	/// [`ClipboardWrite`] collects the representations instead, and places all of them onto the
+	/// clipboard at once when [`ClipboardWrite::commit`] is called.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// # use arboard::Clipboard;
+	/// let mut clipboard = Clipboard::new().unwrap();
+	/// clipboard.write().text("Hello").html("<b>Hello</b>").commit().unwrap();
+	/// ```
+	pub fn write(&mut self) -> ClipboardWrite<'_> {
+		ClipboardWrite {
+			clipboard: self,
+			representations: Vec::new(),
+			#[cfg(all(
+				unix,
+				not(any(
+					target_os = "macos",
+					target_os = "ios",
+					target_os = "android",
+					target_os = "emscripten"
+				)),
+			))]
+			selection: LinuxClipboardKind::Clipboard,
+			#[cfg(target_os = "ios")]
+			options: PasteboardOptions::default(),
+			#[cfg(windows)]
+			delayed: Vec::new(),
+			wait: WaitConfig::None,
+			line_endings: None,
+		}
+	}
+
+	/// Starts reading from the clipboard.
+	///
+	/// The returned builder reads from the same clipboard as `get_text` does, unless configured
+	/// otherwise through a platform specific extension trait (see `ClipboardGetExtLinux`).
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// # use arboard::Clipboard;
+	/// let mut clipboard = Clipboard::new().unwrap();
+	/// let text = clipboard.get().text().unwrap();
+	/// ```
+	pub fn get(&mut self) -> ClipboardGet<'_> {
+		ClipboardGet {
+			clipboard: self,
+			#[cfg(all(
+				unix,
+				not(any(
+					target_os = "macos",
+					target_os = "ios",
+					target_os = "android",
+					target_os = "emscripten"
+				)),
+			))]
+			selection: LinuxClipboardKind::Clipboard,
+			line_endings: None,
+		}
+	}
+
+	/// Fetches image data from the clipboard, and returns the decoded pixels.
+	///
+	/// Any image data placed on the clipboard with `set_image` will be possible read back, using
+	/// this function. However it's of not guaranteed that an image placed on the clipboard by any
+	/// other application will be of a supported format.
+	#[cfg(feature = "image-data")]
+	pub fn get_image(&mut self) -> Result<ImageData<'static>, Error> {
+		self.get_image_with_format().map(|(_, image)| image)
+	}
+
+	/// Fetches image data from the clipboard like `get_image`, with the pixels in `format`.
+	///
+	/// The pixels are converted in place in the buffer that the image was decoded into, so
+	/// renderers that want BGRA or premultiplied alpha don't need to copy them again. The returned
+	/// `ImageData` holds pixels in `format` then, rather than RGBA.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use arboard::{Clipboard, PixelFormat};
+	///
+	/// let mut clipboard = Clipboard::new().unwrap();
+	/// let image = clipboard.get_image_as(PixelFormat::PremultipliedRgba8).unwrap();
+	/// ```
+	#[cfg(feature = "image-data")]
+	pub fn get_image_as(&mut self, format: PixelFormat) -> Result<ImageData<'static>, Error> {
+		self.get_image().map(|image| common::convert_pixels(image, format))
+	}
+
+	/// Fetches image data from the clipboard like `get_image`, but returns [`Error::Timeout`] if
+	/// that takes longer than `timeout`.
+	///
+	/// Decoding a huge image, like a file that was copied in the Finder, can take seconds. This
+	/// reads and decodes the image on a background thread, which opens the same clipboard there,
+	/// so that a UI stays responsive. When the timeout passes, the thread finishes in the
+	/// background and its result is dropped. A clipboard created with `from_provider` can't be
+	/// opened on another thread, so the image is read on the calling thread there, without a
+	/// timeout.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use arboard::{Clipboard, Error};
+	/// use std::time::Duration;
+	///
+	/// let mut clipboard = Clipboard::new().unwrap();
+	/// match clipboard.get_image_with_timeout(Duration::from_millis(500)) {
+	///     Ok(image) => println!("Pasted a {}x{} image", image.width, image.height),
+	///     Err(Error::Timeout) => println!("The image is too large to paste"),
+	///     Err(e) => println!("Could not paste: {}", e),
+	/// }
+	/// ```
+	#[cfg(all(feature = "image-data", not(target_arch = "wasm32")))]
+	pub fn get_image_with_timeout(
+		&mut self,
+		timeout: Duration,
+	) -> Result<ImageData<'static>, Error> {
+		let reopen = match self.reopen() {
+			Some(reopen) => reopen,
+			None => return self.get_image(),
+		};
+		let (sender, receiver) = mpsc::sync_channel(1);
+		thread::Builder::new()
+			.name("arboard-image".into())
+			.spawn(move || {
+				// The receiver is gone once the timeout passed.
+				let _ = sender.send(reopen().and_then(|mut clipboard| clipboard.get_image()));
+			})
+			.map_err(|e| Error::Unknown {
+				description: format!("couldn't start a thread for the clipboard: {}", e),
+			})?;
+		match receiver.recv_timeout(timeout) {
+			Ok(result) => result,
+			Err(mpsc::RecvTimeoutError::Timeout) => Err(Error::Timeout),
+			Err(mpsc::RecvTimeoutError::Disconnected) => {
+				Err(Error::Unknown { description: "the thread reading the image panicked".into() })
+			}
+		}
+	}
+
+	/// Reads the image on the clipboard without decoding it.
+	///
+	/// The returned [`LazyImage`] holds the data as the platform stored it, a PNG or TIFF on macOS,
+	/// a PNG or `CF_DIB` on Windows and a PNG on Linux and iOS, together with the dimensions read
+	/// from its header. The pixels are only decoded by [`LazyImage::decode`], which is useful for
+	/// a clipboard history that lists many images but only needs the pixels of a few of them. The
+	/// image doesn't keep the clipboard open.
+	///
+	/// Where the image isn't available in one of those formats, like an image copied as a file in
+	/// the Finder or on the backends without native image data, it's decoded right away and
+	/// `decode` returns the pixels. Returns [`Error::ImageTooLarge`] if the dimensions exceed
+	/// `max_image_bytes`, and the same errors as `get_image` otherwise.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use arboard::Clipboard;
+	///
+	/// let mut clipboard = Clipboard::new().unwrap();
+	/// let image = clipboard.get_image_lazy().unwrap();
+	/// println!("{}x{} image on the clipboard", image.width(), image.height());
+	/// // Later, when the pixels are needed:
+	/// let pixels = image.decode().unwrap();
+	/// ```
+	#[cfg(feature = "image-data")]
+	pub fn get_image_lazy(&mut self) -> Result<LazyImage, Error> {
+		if self.has_native_image_data()? {
+			for &(format, tag) in LAZY_IMAGE_FORMATS {
+				let data = match self.get_text_bytes(format) {
+					Ok(data) => data,
+					Err(_) => continue,
+				};
+				if let Some(image) = LazyImage::encoded(tag, data, self.max_image_bytes) {
+					match self.max_image_bytes {
+						Some(limit) if image.decoded_len() > limit => {
+							return Err(Error::ImageTooLarge { size: image.decoded_len(), limit })
+						}
+						_ => return Ok(image),
+					}
+				}
+			}
+		}
+		let (format, image) = self.get_image_with_format()?;
+		Ok(LazyImage::decoded(format, image))
+	}
+
+	/// Returns true if the image on the clipboard may be in one of the `LAZY_IMAGE_FORMATS`.
+	#[cfg(feature = "image-data")]
+	fn has_native_image_data(&mut self) -> Result<bool, Error> {
+		if !matches!(self.backend, Backend::Platform(_)) {
+			return Ok(false);
+		}
+		// `get_image` reads the file that a Finder copy points to, instead of its icon.
+		#[cfg(target_os = "macos")]
+		if self.available_formats()?.iter().any(|format| format == FILE_URL) {
+			return Ok(false);
+		}
+		Ok(true)
+	}
+
+	/// Fetches image data from the clipboard like `get_image`, together with the format that the
+	/// image was decoded from.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use arboard::{Clipboard, ImageFormatTag};
+	///
+	/// let mut clipboard = Clipboard::new().unwrap();
+	/// let (format, image) = clipboard.get_image_with_format().unwrap();
+	/// if format == ImageFormatTag::FileUrl {
+	///     println!("Read a {}x{} image from a file", image.width, image.height);
+	/// }
+	/// ```
+	#[cfg(feature = "image-data")]
+	pub fn get_image_with_format(&mut self) -> Result<(ImageFormatTag, ImageData<'static>), Error> {
+		traced(self.backend_name(), "get_image", NO_FORMATS, None, || {
+			let (format, image) = match &mut self.backend {
+				Backend::Platform(platform) => platform.get_image_with_format()?,
+				backend => (ImageFormatTag::Other, backend.provider().get_image()?),
+			};
+			self.check_image_size(&image)?;
+			Ok((format, image))
+		})
+	}
+
+	/// Fetches every frame of an animated image from the clipboard.
+	///
+	/// `get_image` only returns the first frame of an animated image. Currently the only animated
+	/// images that are recognized are GIF files copied in the Finder on macOS, which are decoded
+	/// frame by frame. Every other image is returned as a single frame, exactly like `get_image`
+	/// returns it. Each frame is checked against `max_image_bytes` separately.
+	#[cfg(feature = "image-data")]
+	pub fn get_image_frames(&mut self) -> Result<Vec<ImageData<'static>>, Error> {
+		#[cfg(target_os = "macos")]
+		if let Backend::Platform(platform) = &mut self.backend {
+			let frames = platform.get_image_frames()?;
+			for frame in &frames {
+				self.check_image_size(frame)?;
+			}
+			return Ok(frames);
+		}
+		self.get_image().map(|image| vec![image])
+	}
+
+	/// Returns the kinds of content that are currently available on the clipboard, in the order of
+	/// preference of the application that placed them there.
+	///
+	/// Each kind is only listed once, even if it's offered in multiple formats.
+	#[cfg(target_os = "macos")]
+	pub fn types(&mut self) -> Vec<ClipboardType> {
+		dispatch!(&mut self.backend, cb => cb.types())
+	}
+
+	/// Returns true if the contents of the clipboard are marked as sensitive with the
+	/// [`CONCEALED`] type, in which case they shouldn't be displayed or recorded.
+	#[cfg(target_os = "macos")]
+	pub fn is_concealed(&mut self) -> bool {
+		dispatch!(&mut self.backend, cb => cb.is_concealed())
+	}
+
+	/// Returns the bundle identifier of the application that placed the current contents onto the
+	/// clipboard, if it's known.
+	///
+	/// The pasteboard doesn't record its owner, so this relies on the application declaring itself
+	/// under the `org.nspasteboard.source` type, as proposed by nspasteboard.org. Many applications
+	/// don't do that, in which case `None` is returned. The value is provided by the application
+	/// itself, so it shouldn't be trusted for security decisions.
+	#[cfg(target_os = "macos")]
+	pub fn owner_hint(&mut self) -> Option<String> {
+		dispatch!(&mut self.backend, cb => cb.owner_hint())
+	}
+
+	/// Returns the URL that the image on the clipboard was copied from, if it's known.
+	///
+	/// Browsers such as Safari place the web address of the image under `public.url` next to the
+	/// image data. Returns `None` if the clipboard doesn't hold an image, or if the image doesn't
+	/// come with a URL.
+	#[cfg(target_os = "macos")]
+	pub fn get_image_source_url(&mut self) -> Option<String> {
+		dispatch!(&mut self.backend, cb => cb.get_image_source_url())
+	}
+
+	/// Adds data under the given UTI to the contents that are already on the clipboard.
+	///
+	/// Unlike `set_text`, `write` and the other setters, this doesn't clear the clipboard first,
+	/// so it can be used to attach another representation (for example an HTML version) to what
+	/// another application placed there. If the clipboard already holds data of the same type,
+	/// it's replaced.
+	#[cfg(target_os = "macos")]
+	pub fn add_data_for_type(&mut self, uti: &str, bytes: &[u8]) -> Result<(), Error> {
+		dispatch!(&mut self.backend, cb => cb.add_data_for_type(uti, bytes))
+	}
+
+	/// Removes the data of the given UTI from the clipboard, keeping all the other types.
+	///
+	/// The pasteboard can't remove a single type, so this reads all the contents, clears the
+	/// pasteboard and writes the other types back. The whole pasteboard is rewritten: its change
+	/// count increases, data that was provided lazily is produced, and types that can't be read
+	/// are lost. Does nothing if the clipboard doesn't hold `uti`.
+	#[cfg(target_os = "macos")]
+	pub fn clear_type(&mut self, uti: &str) -> Result<(), Error> {
+		dispatch!(&mut self.backend, cb => cb.clear_type(uti))
+	}
+
+	/// Places data onto the clipboard that's only produced when another application requests it.
+	///
+	/// `providers` maps each UTI, for example [`PNG`] or [`HTML`], to the function that produces
+	/// its data. The pasteboard calls each function at most once, through the main run loop of
+	/// the application, so this is only useful in applications that run one (as all AppKit
+	/// applications do). The functions that weren't called yet are dropped when the pasteboard is
+	/// done with them, usually because other contents were placed onto it.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use arboard::{Clipboard, DataProvider, HTML};
+	/// use std::collections::HashMap;
+	///
+	/// let mut ctx = Clipboard::new().unwrap();
+	/// let mut providers: HashMap<String, DataProvider> = HashMap::new();
+	/// providers.insert(HTML.into(), Box::new(|| b"<b>rendered on paste</b>".to_vec()));
+	/// ctx.set_lazy_data(providers).unwrap();
+	/// ```
+	#[cfg(target_os = "macos")]
+	pub fn set_lazy_data(
+		&mut self,
+		providers: std::collections::HashMap<String, DataProvider>,
+	) -> Result<(), Error> {
+		dispatch!(&mut self.backend, cb => cb.set_lazy_data(providers))
+	}
+
+	/// Fetches the color on the clipboard, as placed there by color pickers, converted to sRGB.
+	///
+	/// The components are red, green, blue and alpha, from 0 to 1. Returns `ContentNotAvailable`
+	/// if there's no color on the clipboard, and `ConversionFailure` if it can't be expressed in
+	/// sRGB, like a pattern color.
+	#[cfg(target_os = "macos")]
+	pub fn get_color(&mut self) -> Result<[f32; 4], Error> {
+		dispatch!(&mut self.backend, cb => cb.get_color())
+	}
+
+	/// Places the sRGB color with the red, green, blue and alpha components, from 0 to 1, onto the
+	/// clipboard, as an `NSColor` that color pickers accept.
+	#[cfg(target_os = "macos")]
+	pub fn set_color(&mut self, rgba: [f32; 4]) -> Result<(), Error> {
+		dispatch!(&mut self.backend, cb => cb.set_color(rgba))
+	}
+
+	/// Places an image to the clipboard.
+	///
+	/// The chosen output format, depending on the platform is the following:
+	///
+	/// - On macOS: `NSImage` object
+	/// - On Linux: PNG, under the atom `image/png`
+	/// - On Windows: In order of priority `CF_DIB` and `CF_BITMAP`
+	#[cfg(feature = "image-data")]
+	pub fn set_image(&mut self, image: ImageData) -> Result<(), Error> {
+		let bytes = Some(image.bytes.len());
+		traced(self.backend_name(), "set_image", NO_FORMATS, bytes, || {
+			self.check_image_size(&image)?;
+			self.backend.provider().set_image(image)
+		})
+	}
+
+	/// Places an image to the clipboard without taking ownership of it.
+	///
+	/// This behaves exactly like `set_image`, but it's more convenient when the same image may
+	/// need to be set multiple times (for example when retrying after a `ClipboardOccupied`
+	/// error). The pixels are only copied where the platform requires an owned buffer (currently
+	/// only macOS), and even then only once.
+	#[cfg(feature = "image-data")]
+	pub fn set_image_ref(&mut self, image: &ImageData) -> Result<(), Error> {
+		let bytes = Some(image.bytes.len());
+		traced(self.backend_name(), "set_image", NO_FORMATS, bytes, || {
+			self.check_image_size(image)?;
+			self.backend.provider().set_image(image.borrowed())
+		})
+	}
+
+	/// Places an image whose pixels are in `order` onto the clipboard, like `set_image`.
+	///
+	/// This saves callers whose pixels come from a Windows bitmap or a GPU framebuffer, which are
+	/// often BGRA, from reordering the channels themselves. Pixels that aren't RGBA are copied
+	/// once to reorder them, unless the image already owns them.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use arboard::{Clipboard, ImageData, PixelOrder};
+	///
+	/// // A single blue pixel, stored as BGRA.
+	/// let bgra = [255, 0, 0, 255];
+	/// let image = ImageData { width: 1, height: 1, bytes: bgra.as_ref().into() };
+	/// Clipboard::new().unwrap().set_image_with_order(image, PixelOrder::Bgra).unwrap();
+	/// ```
+	#[cfg(feature = "image-data")]
+	pub fn set_image_with_order(
+		&mut self,
+		image: ImageData,
+		order: PixelOrder,
+	) -> Result<(), Error> {
+		self.set_image(common::reorder_pixels(image, order))
+	}
+
+	/// Places an image onto the clipboard, first shrinking it if its width or height exceeds
+	/// `max_dimension`.
+	///
+	/// Some applications fail to paste very large images, this can be used to make sure that the
+	/// image stays within their limits. The aspect ratio is preserved, and images that are small
+	/// enough are placed onto the clipboard unchanged, just like with `set_image`.
+	#[cfg(feature = "image-data")]
+	pub fn set_image_scaled(
+		&mut self,
+		image: ImageData,
+		max_dimension: usize,
+	) -> Result<(), Error> {
+		let image = common::downscale_image(image, max_dimension)?;
+		self.set_image(image)
+	}
+
+	/// Places an image onto the clipboard like `set_image`, and returns the [generation] of the
+	/// clipboard that the write resulted in. See [`Clipboard::set_text_with_generation`] for how
+	/// reliable the value is on each platform.
+	///
+	/// [generation]: Clipboard::generation
+	#[cfg(feature = "image-data")]
+	pub fn set_image_with_generation(&mut self, image: ImageData) -> Result<u64, Error> {
+		self.check_image_size(&image)?;
+		match &mut self.backend {
+			#[cfg(any(windows, target_os = "macos"))]
+			Backend::Platform(platform) => platform.set_image_with_generation(image),
+			backend => {
+				backend.provider().set_image(image)?;
+				self.generation()
+			}
+		}
+	}
+
+	/// Returns `Error::ImageTooLarge` if the pixels of `image` exceed `max_image_bytes`.
+	#[cfg(feature = "image-data")]
+	fn check_image_size(&self, image: &ImageData) -> Result<(), Error> {
+		match self.max_image_bytes {
+			Some(limit) if image.bytes.len() > limit => {
+				Err(Error::ImageTooLarge { size: image.bytes.len(), limit })
+			}
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Without the `image-data` feature, the image methods still exist so that callers don't need
+/// feature gates of their own, but they always fail.
+#[cfg(not(feature = "image-data"))]
+impl Clipboard {
+	/// Returns [`Error::UnsupportedFormat`], as images need the `image-data` feature.
+	pub fn get_image(&mut self) -> Result<ImageData<'static>, Error> {
+		Err(Error::UnsupportedFormat { format: "image".into() })
+	}
+
+	/// Returns [`Error::UnsupportedFormat`], as images need the `image-data` feature.
+	#[cfg(not(target_arch = "wasm32"))]
+	pub fn get_image_with_timeout(
+		&mut self,
+		_timeout: Duration,
+	) -> Result<ImageData<'static>, Error> {
+		Err(Error::UnsupportedFormat { format: "image".into() })
+	}
+
+	/// Returns [`Error::UnsupportedFormat`], as images need the `image-data` feature.
+	pub fn get_image_as(&mut self, _format: PixelFormat) -> Result<ImageData<'static>, Error> {
+		Err(Error::UnsupportedFormat { format: "image".into() })
+	}
+
+	/// Returns [`Error::UnsupportedFormat`], as images need the `image-data` feature.
+	pub fn get_image_frames(&mut self) -> Result<Vec<ImageData<'static>>, Error> {
+		Err(Error::UnsupportedFormat { format: "image".into() })
+	}
+
+	/// Returns [`Error::UnsupportedFormat`], as images need the `image-data` feature.
+	pub fn set_image(&mut self, _image: ImageData) -> Result<(), Error> {
+		Err(Error::UnsupportedFormat { format: "image".into() })
+	}
+
+	/// Returns [`Error::UnsupportedFormat`], as images need the `image-data` feature.
+	pub fn set_image_with_order(
+		&mut self,
+		_image: ImageData,
+		_order: PixelOrder,
+	) -> Result<(), Error> {
+		Err(Error::UnsupportedFormat { format: "image".into() })
+	}
+
+	/// Returns [`Error::UnsupportedFormat`], as images need the `image-data` feature.
+	pub fn set_image_ref(&mut self, _image: &ImageData) -> Result<(), Error> {
+		Err(Error::UnsupportedFormat { format: "image".into() })
+	}
+
+	/// Returns [`Error::UnsupportedFormat`], as images need the `image-data` feature.
+	pub fn set_image_with_generation(&mut self, _image: ImageData) -> Result<u64, Error> {
+		Err(Error::UnsupportedFormat { format: "image".into() })
+	}
+
+	/// Returns [`Error::UnsupportedFormat`], as images need the `image-data` feature.
+	pub fn set_image_scaled(
+		&mut self,
+		_image: ImageData,
+		_max_dimension: usize,
+	) -> Result<(), Error> {
+		Err(Error::UnsupportedFormat { format: "image".into() })
+	}
+}
+
+/// The basic operations of a clipboard, implemented by [`Clipboard`] and, with the `test-util`
+/// feature, by `MockClipboard`.
+///
+/// Code that takes a `ClipboardProvider` instead of a `Clipboard` can be tested without a display
+/// server by passing it a `MockClipboard`. The trait is object safe, so the implementation can also
+/// be chosen at runtime by boxing it.
+///
+/// A `Clipboard` can also be backed by a `MockClipboard` itself, see [`Clipboard::from_mock`],
+/// which is the simpler choice for code that uses the platform specific extensions (like
+/// `ClipboardExtLinux`). In the same way, [`Clipboard::from_provider`] creates a `Clipboard` that
+/// forwards to an implementation of this trait, for example one that routes the clipboard through
+/// a remote desktop connection. The backends of arboard implement the trait too, and the methods
+/// of `Clipboard` with the same names go through it.
+///
+/// # Examples
+///
+/// ```no_run
+/// use arboard::{Clipboard, ClipboardProvider};
+///
+/// fn provider(headless: bool) -> Box<dyn ClipboardProvider> {
+///     if headless {
+///         // For example a `MockClipboard`, or a backend of your own.
+///         # unimplemented!()
+///     } else {
+///         Box::new(Clipboard::new().unwrap())
+///     }
+/// }
+///
+/// let mut clipboard = provider(false);
+/// clipboard.set_text("Hello, world!".into()).unwrap();
+/// ```
+pub trait ClipboardProvider {
+	/// Fetches utf-8 text from the clipboard and returns it.
+	fn get_text(&mut self) -> Result<String, Error>;
+
+	/// Places the text onto the clipboard. Any valid utf-8 string is accepted.
+	fn set_text(&mut self, text: String) -> Result<(), Error>;
+
+	/// Fetches image data from the clipboard, and returns the decoded pixels.
+	///
+	/// Returns [`Error::ClipboardNotSupported`] by default, for providers without images.
+	fn get_image(&mut self) -> Result<ImageData<'static>, Error> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	/// Places an image to the clipboard.
+	///
+	/// Returns [`Error::ClipboardNotSupported`] by default, for providers without images.
+	fn set_image(&mut self, _image: ImageData) -> Result<(), Error> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	/// Removes the contents of the clipboard.
+	fn clear(&mut self) -> Result<(), Error>;
+
+	/// Returns the names of the formats that are on the clipboard.
+	fn available_formats(&mut self) -> Result<Vec<String>, Error>;
+}
+
+impl<P: ClipboardProvider + ?Sized> ClipboardProvider for Box<P> {
+	fn get_text(&mut self) -> Result<String, Error> {
+		(**self).get_text()
+	}
+
+	fn set_text(&mut self, text: String) -> Result<(), Error> {
+		(**self).set_text(text)
+	}
+
+	fn get_image(&mut self) -> Result<ImageData<'static>, Error> {
+		(**self).get_image()
+	}
+
+	fn set_image(&mut self, image: ImageData) -> Result<(), Error> {
+		(**self).set_image(image)
+	}
+
+	fn clear(&mut self) -> Result<(), Error> {
+		(**self).clear()
+	}
+
+	fn available_formats(&mut self) -> Result<Vec<String>, Error> {
+		(**self).available_formats()
+	}
+}
+
+impl<P: ClipboardProvider + ?Sized> ClipboardProvider for &mut P {
+	fn get_text(&mut self) -> Result<String, Error> {
+		(**self).get_text()
+	}
+
+	fn set_text(&mut self, text: String) -> Result<(), Error> {
+		(**self).set_text(text)
+	}
+
+	fn get_image(&mut self) -> Result<ImageData<'static>, Error> {
+		(**self).get_image()
+	}
+
+	fn set_image(&mut self, image: ImageData) -> Result<(), Error> {
+		(**self).set_image(image)
+	}
+
+	fn clear(&mut self) -> Result<(), Error> {
+		(**self).clear()
+	}
+
+	fn available_formats(&mut self) -> Result<Vec<String>, Error> {
+		(**self).available_formats()
+	}
+}
+
+/// Passed to `traced` by the operations that don't involve particular formats.
+const NO_FORMATS: [&str; 0] = [];
+
+/// Runs one of the public operations of a clipboard.
+///
+/// With the `tracing` feature, the operation runs inside a span named `arboard`, which records the
+/// name of the `backend` and of the `operation`, the `formats` involved, the number of `bytes`
+/// that were read or written and the `error` the operation failed with. Without the feature, this
+/// only calls `operation`.
+#[cfg(feature = "tracing")]
+fn traced<T: TracedOutput>(
+	backend: &'static str,
+	operation: &'static str,
+	formats: impl IntoIterator<Item = impl AsRef<str>>,
+	bytes: Option<usize>,
+	run: impl FnOnce() -> Result<T, Error>,
+) -> Result<T, Error> {
+	let formats = formats.into_iter().map(|format| format.as_ref().to_owned()).collect::<Vec<_>>();
+	let span = tracing::debug_span!(
+		"arboard",
+		backend,
+		operation,
+		formats = ?formats,
+		bytes = tracing::field::Empty,
+		error = tracing::field::Empty,
+	);
+	if let Some(bytes) = bytes {
+		span.record("bytes", bytes);
+	}
+	let _entered = span.enter();
+	let result = run();
+	match &result {
+		Ok(output) => {
+			if let Some(bytes) = output.traced_bytes() {
+				span.record("bytes", bytes);
+			}
+		}
+		Err(e) => {
+			span.record("error", tracing::field::display(e));
+		}
+	}
+	result
+}
+
+#[cfg(not(feature = "tracing"))]
+#[inline]
+fn traced<T>(
+	_backend: &'static str,
+	_operation: &'static str,
+	_formats: impl IntoIterator<Item = impl AsRef<str>>,
+	_bytes: Option<usize>,
+	run: impl FnOnce() -> Result<T, Error>,
+) -> Result<T, Error> {
+	run()
+}
+
+/// The number of bytes that an operation read, which `traced` records.
+#[cfg(feature = "tracing")]
+trait TracedOutput {
+	fn traced_bytes(&self) -> Option<usize> {
+		None
+	}
+}
+
+#[cfg(feature = "tracing")]
+impl TracedOutput for () {}
+
+#[cfg(feature = "tracing")]
+impl TracedOutput for Vec<String> {}
+
+#[cfg(feature = "tracing")]
+impl TracedOutput for String {
+	fn traced_bytes(&self) -> Option<usize> {
+		Some(self.len())
+	}
+}
+
+#[cfg(feature = "tracing")]
+impl TracedOutput for Option<String> {
+	fn traced_bytes(&self) -> Option<usize> {
+		self.as_ref().map(String::len)
+	}
+}
+
+#[cfg(feature = "tracing")]
+impl TracedOutput for Vec<u8> {
+	fn traced_bytes(&self) -> Option<usize> {
+		Some(self.len())
+	}
+}
+
+#[cfg(feature = "tracing")]
+impl TracedOutput for ClipboardSnapshot {
+	fn traced_bytes(&self) -> Option<usize> {
+		Some(self.size())
+	}
+}
+
+#[cfg(all(feature = "tracing", feature = "image-data"))]
+impl TracedOutput for (ImageFormatTag, ImageData<'static>) {
+	fn traced_bytes(&self) -> Option<usize> {
+		Some(self.1.bytes.len())
+	}
+}
+
+impl ClipboardProvider for Clipboard {
+	fn get_text(&mut self) -> Result<String, Error> {
+		Clipboard::get_text(self)
+	}
+
+	fn set_text(&mut self, text: String) -> Result<(), Error> {
+		Clipboard::set_text(self, text)
+	}
+
+	fn get_image(&mut self) -> Result<ImageData<'static>, Error> {
+		Clipboard::get_image(self)
+	}
+
+	fn set_image(&mut self, image: ImageData) -> Result<(), Error> {
+		Clipboard::set_image(self, image)
+	}
+
+	fn clear(&mut self) -> Result<(), Error> {
+		Clipboard::clear(self)
+	}
+
+	fn available_formats(&mut self) -> Result<Vec<String>, Error> {
+		Clipboard::available_formats(self)
+	}
+}
+
+impl ClipboardProvider for PlatformClipboard {
+	fn get_text(&mut self) -> Result<String, Error> {
+		PlatformClipboard::get_text(self)
+	}
+
+	fn set_text(&mut self, text: String) -> Result<(), Error> {
+		PlatformClipboard::set_text(self, text.into())
+	}
 
-#[cfg(all(unix, not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),))]
-pub(crate) mod common_linux;
+	#[cfg(feature = "image-data")]
+	fn get_image(&mut self) -> Result<ImageData<'static>, Error> {
+		PlatformClipboard::get_image(self)
+	}
 
-#[cfg(all(unix, not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),))]
-pub mod x11_clipboard;
+	#[cfg(feature = "image-data")]
+	fn set_image(&mut self, image: ImageData) -> Result<(), Error> {
+		PlatformClipboard::set_image(self, image)
+	}
 
-#[cfg(all(
-	unix,
-	not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
-	feature = "wayland-data-control"
-))]
-pub mod wayland_data_control_clipboard;
+	/// Writes no representations at all, which leaves the clipboard empty.
+	fn clear(&mut self) -> Result<(), Error> {
+		#[cfg(all(
+			unix,
+			not(any(
+				target_os = "macos",
+				target_os = "ios",
+				target_os = "android",
+				target_os = "emscripten"
+			)),
+		))]
+		let result = self.write_all_with_clipboard(&[], LinuxClipboardKind::Clipboard);
+		#[cfg(not(all(
+			unix,
+			not(any(
+				target_os = "macos",
+				target_os = "ios",
+				target_os = "android",
+				target_os = "emscripten"
+			)),
+		)))]
+		let result = self.write_all(&[]);
+		result
+	}
 
-#[cfg(windows)]
-pub mod windows_clipboard;
+	fn available_formats(&mut self) -> Result<Vec<String>, Error> {
+		PlatformClipboard::available_formats(self)
+	}
+}
 
-#[cfg(target_os = "macos")]
-pub mod osx_clipboard;
+/// Options for creating a [`Clipboard`].
+///
+/// Created by [`Clipboard::builder`]. On Linux, [`ClipboardBuilderExtLinux`] selects the backend.
+///
+/// Options that don't apply to the platform or the backend are ignored, as described by each
+/// option, so the same builder can be used on every platform.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use arboard::Clipboard;
+///
+/// let clipboard = Clipboard::builder()
+///     .timeout(Duration::from_secs(1))
+///     .retry(5, Duration::from_millis(20))
+///     .max_image_bytes(64 * 1024 * 1024)
+///     .build()
+///     .unwrap();
+/// ```
+#[must_use]
+#[derive(Debug, Clone, Default)]
+pub struct ClipboardBuilder {
+	#[cfg(all(
+		unix,
+		not(any(
+			target_os = "macos",
+			target_os = "ios",
+			target_os = "android",
+			target_os = "emscripten"
+		)),
+	))]
+	backend: Option<LinuxBackend>,
+	#[cfg_attr(
+		any(target_os = "macos", target_os = "ios", target_os = "android", target_arch = "wasm32"),
+		allow(dead_code)
+	)]
+	timeout: Option<Duration>,
+	#[cfg_attr(not(any(windows, target_os = "macos")), allow(dead_code))]
+	retry: Option<(usize, Duration)>,
+	max_image_bytes: Option<usize>,
+}
 
-#[cfg(all(unix, not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),))]
-type PlatformClipboard = common_linux::LinuxClipboard;
-#[cfg(windows)]
-type PlatformClipboard = windows_clipboard::WindowsClipboardContext;
-#[cfg(target_os = "macos")]
-type PlatformClipboard = osx_clipboard::OSXClipboardContext;
+impl ClipboardBuilder {
+	/// Sets how long an operation waits for another application before giving up with
+	/// [`Error::Timeout`].
+	///
+	/// - On X11, this is how long a read waits for the owner of the selection to answer, 4
+	///   seconds by default. Once the owner sends the data in chunks, each chunk may take up to a
+	///   second, and the whole transfer up to a minute.
+	/// - On Windows, this limits how long opening the clipboard is retried while another process
+	///   has it open, on top of the attempts set by [`retry`](Self::retry). There's no limit by
+	///   default.
+	/// - Through OSC 52, this is how long a read waits for the terminal to answer, 500
+	///   milliseconds by default. Terminals that don't allow reading the clipboard don't answer,
+	///   so [`Error::ClipboardNotSupported`] is returned instead.
+	///
+	/// This option is ignored on the other platforms and backends. On macOS reading an image from
+	/// a file that's on the pasteboard isn't limited either.
+	pub fn timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = Some(timeout);
+		self
+	}
 
-#[cfg(all(
-	unix,
-	not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
-))]
-pub use common_linux::{ClipboardExtLinux, LinuxClipboardKind};
+	/// Sets how often an operation is attempted while the clipboard is in use by another process,
+	/// and how long to wait before the first retry. The delay is doubled before every subsequent
+	/// retry, and [`Error::ClipboardOccupied`] is returned once all attempts failed.
+	///
+	/// On Windows this applies to opening the clipboard, like
+	/// `ClipboardExtWindows::set_open_retry` does. On macOS it applies to writes, which fail
+	/// while another process is modifying the pasteboard. The other platforms have no notion of a
+	/// clipboard that's in use, and this option is ignored there.
+	pub fn retry(mut self, attempts: usize, delay: Duration) -> Self {
+		self.retry = Some((attempts, delay));
+		self
+	}
 
-/// The OS independent struct for accessing the clipboard.
-///
-/// Any number of `Clipboard` instances are allowed to exist at a single point in time. Note however
-/// that all `Clipboard`s must be 'dropped' before the program exits. In most scenarios this happens
-/// automatically but there are frameworks (for example `winit`) that take over the execution
-/// and where the objects don't get dropped when the application exits. In these cases you have to
-/// make sure the object is dropped by taking ownership of it in a confined scope when detecting
-/// that your application is about to quit.
+	/// Limits the size of the pixels of the images that `get_image` returns and `set_image`
+	/// accepts, on every platform. Larger images return [`Error::ImageTooLarge`] instead.
+	///
+	/// The size is that of the RGBA pixels, four bytes per pixel, not that of the encoded image on
+	/// the clipboard. By default, the size of the images isn't limited.
+	pub fn max_image_bytes(mut self, max_image_bytes: usize) -> Self {
+		self.max_image_bytes = Some(max_image_bytes);
+		self
+	}
+
+	/// Creates the clipboard.
+	pub fn build(self) -> Result<Clipboard, Error> {
+		#[cfg(all(
+			unix,
+			not(any(
+				target_os = "macos",
+				target_os = "ios",
+				target_os = "android",
+				target_os = "emscripten"
+			)),
+		))]
+		let platform = {
+			let backend = match self.backend {
+				Some(backend) => Some(backend),
+				None => common_linux::backend_override(
+					std::env::var_os(common_linux::BACKEND_ENV_VAR).as_deref(),
+				)?,
+			};
+			#[cfg(feature = "test-util")]
+			if backend == Some(LinuxBackend::Mock) {
+				let mut clipboard = Clipboard::new_mock();
+				clipboard.max_image_bytes = self.max_image_bytes;
+				return Ok(clipboard);
+			}
+			let mut platform = common_linux::LinuxClipboard::with_backend(backend)?;
+			if let Some(timeout) = self.timeout {
+				platform.set_timeout(timeout);
+			}
+			platform
+		};
+		#[cfg(not(all(
+			unix,
+			not(any(
+				target_os = "macos",
+				target_os = "ios",
+				target_os = "android",
+				target_os = "emscripten"
+			)),
+		)))]
+		#[allow(unused_mut)]
+		let mut platform = PlatformClipboard::new()?;
+		#[cfg(windows)]
+		if let Some((attempts, delay)) = self.retry {
+			platform.set_open_retry(attempts, delay);
+		}
+		#[cfg(windows)]
+		if let Some(timeout) = self.timeout {
+			platform.set_open_timeout(timeout);
+		}
+		#[cfg(target_os = "macos")]
+		if let Some((attempts, delay)) = self.retry {
+			platform.set_write_retry(attempts, delay);
+		}
+		let mut clipboard = Clipboard::from_platform(platform);
+		clipboard.max_image_bytes = self.max_image_bytes;
+		Ok(clipboard)
+	}
+}
+
+/// A read from the clipboard.
 ///
-/// It is also valid to have multiple `Clipboards` on separate threads at once but note that
-/// executing multiple clipboard operations in paralell might fail with a `ClipboardOccupied` error.
-pub struct Clipboard {
-	pub(crate) platform: PlatformClipboard,
+/// Created by [`Clipboard::get`]. Nothing is read until one of the methods below is called.
+#[must_use]
+pub struct ClipboardGet<'clipboard> {
+	clipboard: &'clipboard mut Clipboard,
+	#[cfg(all(
+		unix,
+		not(any(
+			target_os = "macos",
+			target_os = "ios",
+			target_os = "android",
+			target_os = "emscripten"
+		)),
+	))]
+	pub(crate) selection: LinuxClipboardKind,
+	line_endings: Option<LineEndings>,
 }
 
-impl Clipboard {
-	/// Creates an instance of the clipboard
-	pub fn new() -> Result<Self, Error> {
-		Ok(Clipboard { platform: PlatformClipboard::new()? })
+impl ClipboardGet<'_> {
+	/// Converts the line breaks of the text that's read to `line_endings`. By default, the text
+	/// is returned as it is on the clipboard.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// # use arboard::{Clipboard, LineEndings};
+	/// let mut clipboard = Clipboard::new().unwrap();
+	/// let text = clipboard.get().line_endings(LineEndings::Unix).text().unwrap();
+	/// ```
+	pub fn line_endings(mut self, line_endings: LineEndings) -> Self {
+		self.line_endings = Some(line_endings);
+		self
 	}
 
 	/// Fetches utf-8 text from the clipboard and returns it.
-	pub fn get_text(&mut self) -> Result<String, Error> {
-		self.platform.get_text()
+	pub fn text(self) -> Result<String, Error> {
+		#[cfg(all(
+			unix,
+			not(any(
+				target_os = "macos",
+				target_os = "ios",
+				target_os = "android",
+				target_os = "emscripten"
+			)),
+		))]
+		let text = self.clipboard.get_text_with_clipboard(self.selection);
+		#[cfg(not(all(
+			unix,
+			not(any(
+				target_os = "macos",
+				target_os = "ios",
+				target_os = "android",
+				target_os = "emscripten"
+			)),
+		)))]
+		let text = self.clipboard.get_text();
+		match self.line_endings {
+			Some(line_endings) => text.map(|text| line_endings.apply(&text)),
+			None => text,
+		}
 	}
+}
 
-	/// Places the text onto the clipboard. Any valid utf-8 string is accepted.
-	pub fn set_text(&mut self, text: String) -> Result<(), Error> {
-		self.platform.set_text(text)
+/// A set of representations that are placed onto the clipboard together.
+///
+/// Created by [`Clipboard::write`]. Nothing is written until `commit` is called.
+#[must_use]
+pub struct ClipboardWrite<'clipboard> {
+	pub(crate) clipboard: &'clipboard mut Clipboard,
+	pub(crate) representations: Vec<Representation>,
+	#[cfg(all(
+		unix,
+		not(any(
+			target_os = "macos",
+			target_os = "ios",
+			target_os = "android",
+			target_os = "emscripten"
+		)),
+	))]
+	pub(crate) selection: LinuxClipboardKind,
+	#[cfg(target_os = "ios")]
+	pub(crate) options: PasteboardOptions,
+	#[cfg(windows)]
+	pub(crate) delayed: Vec<(String, windows_clipboard::RenderFn)>,
+	// Only X11 needs to keep serving the contents after writing them.
+	#[cfg_attr(
+		not(all(
+			unix,
+			not(any(
+				target_os = "macos",
+				target_os = "ios",
+				target_os = "android",
+				target_os = "emscripten"
+			)),
+		)),
+		allow(dead_code)
+	)]
+	wait: WaitConfig,
+	line_endings: Option<LineEndings>,
+}
+
+impl<'clipboard> ClipboardWrite<'clipboard> {
+	/// Adds plain text, in the same format that `set_text` uses.
+	pub fn text(mut self, text: impl Into<String>) -> Self {
+		let text = match self.line_endings {
+			Some(line_endings) => line_endings.apply(&text.into()),
+			None => text.into(),
+		};
+		self.representations.push(Representation::Text(text));
+		self
 	}
 
-	/// Fetches image data from the clipboard, and returns the decoded pixels.
+	/// Converts the line breaks of the plain text that's written to `line_endings`, no matter
+	/// whether the text was added before or after this call. By default, the text is written as it
+	/// was given.
 	///
-	/// Any image data placed on the clipboard with `set_image` will be possible read back, using
-	/// this function. However it's of not guaranteed that an image placed on the clipboard by any
-	/// other application will be of a supported format.
-	#[cfg(feature = "image-data")]
-	pub fn get_image(&mut self) -> Result<ImageData, Error> {
-		self.platform.get_image()
+	/// # Examples
+	///
+	/// ```no_run
+	/// # use arboard::{Clipboard, LineEndings};
+	/// let mut clipboard = Clipboard::new().unwrap();
+	/// clipboard.write().text("one\ntwo").line_endings(LineEndings::Dos).commit().unwrap();
+	/// ```
+	pub fn line_endings(mut self, line_endings: LineEndings) -> Self {
+		self.line_endings = Some(line_endings);
+		for representation in &mut self.representations {
+			if let Representation::Text(text) = representation {
+				*text = line_endings.apply(text);
+			}
+		}
+		self
 	}
 
-	/// Places an image to the clipboard.
+	/// Adds an HTML fragment.
 	///
-	/// The chosen output format, depending on the platform is the following:
+	/// The format depends on the platform: `public.html` on macOS, `HTML Format` on Windows (the
+	/// required header is added automatically) and `text/html` on Linux.
+	pub fn html(mut self, html: impl Into<String>) -> Self {
+		self.representations.push(Representation::Html(html.into()));
+		self
+	}
+
+	/// Adds arbitrary data under a platform specific format name.
 	///
-	/// - On macOS: `NSImage` object
-	/// - On Linux: PNG, under the atom `image/png`
-	/// - On Windows: In order of priority `CF_DIB` and `CF_BITMAP`
-	#[cfg(feature = "image-data")]
-	pub fn set_image(&mut self, image: ImageData) -> Result<(), Error> {
-		self.platform.set_image(image)
+	/// `format` uses the same naming as [`ClipboardSnapshot::formats`].
+	pub fn data(mut self, format: impl Into<String>, bytes: impl Into<Vec<u8>>) -> Self {
+		self.representations.push(Representation::Custom(format.into(), bytes.into()));
+		self
+	}
+
+	/// Marks the contents as sensitive, like a password, asking the system and clipboard managers to
+	/// keep them out of their history and not to sync them to other devices.
+	///
+	/// This is best-effort, as it relies on the other applications honoring the markers:
+	///
+	/// - On Windows, the `ExcludeClipboardContentFromMonitorProcessing`,
+	///   `CanIncludeInClipboardHistory` and `CanUploadToCloudClipboard` formats are added, which
+	///   keep the contents out of the clipboard history and the cloud clipboard.
+	/// - On macOS, the [`CONCEALED`] type is added, see `concealed`.
+	/// - On Linux, the `x-kde-passwordManagerHint` MIME type is added, which KDE's clipboard history
+	///   honors. Backends that only transfer text, like OSC 52, leave it out.
+	/// - On iOS, the contents are kept on the device, like `PasteboardOptions::local_only` does.
+	/// - Elsewhere, this does nothing.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// # use arboard::Clipboard;
+	/// let mut clipboard = Clipboard::new().unwrap();
+	/// clipboard.write().text("hunter2").sensitive().commit().unwrap();
+	/// ```
+	#[cfg_attr(
+		not(any(
+			windows,
+			target_os = "macos",
+			target_os = "ios",
+			all(
+				unix,
+				not(any(
+					target_os = "macos",
+					target_os = "ios",
+					target_os = "android",
+					target_os = "emscripten"
+				)),
+			)
+		)),
+		allow(unused_mut)
+	)]
+	pub fn sensitive(mut self) -> Self {
+		#[cfg(windows)]
+		for (format, value) in windows_clipboard::EXCLUDE_FROM_HISTORY {
+			self.representations.push(Representation::Custom(format.to_string(), value.to_vec()));
+		}
+		#[cfg(target_os = "macos")]
+		self.representations.push(Representation::Custom(CONCEALED.into(), Vec::new()));
+		#[cfg(all(
+			unix,
+			not(any(
+				target_os = "macos",
+				target_os = "ios",
+				target_os = "android",
+				target_os = "emscripten"
+			)),
+		))]
+		self.representations.push(Representation::Custom(
+			common_linux::PASSWORD_MANAGER_HINT.into(),
+			b"secret".to_vec(),
+		));
+		#[cfg(target_os = "ios")]
+		{
+			self.options.local_only = true;
+		}
+		self
+	}
+
+	/// Marks the contents as sensitive with the [`CONCEALED`] type, asking clipboard history tools
+	/// not to record them.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// # use arboard::Clipboard;
+	/// let mut clipboard = Clipboard::new().unwrap();
+	/// clipboard.write().text("hunter2").concealed().commit().unwrap();
+	/// ```
+	#[cfg(target_os = "macos")]
+	pub fn concealed(self) -> Self {
+		self.data(CONCEALED, Vec::new())
+	}
+
+	/// Makes `commit` block after writing, until another application has retrieved the contents
+	/// at least once, or placed its own contents onto the clipboard.
+	///
+	/// On X11, the contents are served by the application that placed them onto the clipboard, so
+	/// they're lost if it exits right away (unless a clipboard manager takes them over, see
+	/// [`Clipboard::persist`]). Command-line tools can use this to stay alive until the contents
+	/// were pasted. Requests are answered on a background thread in the meantime, so any number
+	/// of applications can read the contents, in any format and of any size.
+	///
+	/// Everywhere else, the system keeps the contents and `commit` returns right after writing.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// # use arboard::Clipboard;
+	/// let mut clipboard = Clipboard::new().unwrap();
+	/// clipboard.write().text("Paste me before I exit").wait().commit().unwrap();
+	/// ```
+	pub fn wait(mut self) -> Self {
+		self.wait = WaitConfig::Forever;
+		self
+	}
+
+	/// The same as `wait`, but `commit` returns at `deadline` at the latest, even if the contents
+	/// weren't pasted yet.
+	pub fn wait_until(mut self, deadline: Instant) -> Self {
+		self.wait = WaitConfig::Until(deadline);
+		self
+	}
+
+	/// Commits the contents like `commit`, and clears the clipboard once `delay` has elapsed,
+	/// unless other contents were placed onto it in the meantime. This is meant for secrets like
+	/// one-time passwords, which shouldn't stay on the clipboard.
+	///
+	/// The clear is done by a background thread, and the returned handle can cancel it. It only
+	/// happens while the process is running, so command-line tools should wait for it with
+	/// [`ScheduledClear::wait`] before exiting.
+	///
+	/// Whether the contents are still the ones that were committed is checked through the
+	/// `changeCount` of the pasteboard on macOS and `GetClipboardSequenceNumber` on Windows. On
+	/// X11, the contents have to still be owned by this process, and it mustn't have written other
+	/// contents since. X11 keeps serving the contents until they're cleared, even if all the
+	/// `Clipboard`s are dropped.
+	///
+	/// Returns [`Error::ClipboardNotSupported`] without writing anything on the backends that
+	/// can't be used from another thread, which are all the others.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// # use arboard::Clipboard;
+	/// # use std::time::Duration;
+	/// let mut clipboard = Clipboard::new().unwrap();
+	/// let clear = clipboard.write().text("123456").clear_after(Duration::from_secs(30)).unwrap();
+	/// // Paste the password somewhere, then exit after the clipboard was cleared.
+	/// clear.wait().unwrap();
+	/// ```
+	#[cfg(not(target_arch = "wasm32"))]
+	pub fn clear_after(self, delay: Duration) -> Result<ScheduledClear, Error> {
+		let reopen = self.clipboard.reopen().ok_or(Error::ClipboardNotSupported)?;
+		#[cfg(all(
+			unix,
+			not(any(
+				target_os = "macos",
+				target_os = "ios",
+				target_os = "android",
+				target_os = "emscripten"
+			)),
+		))]
+		let selection = self.selection;
+		let clipboard = self.commit_to_clipboard()?;
+		#[cfg(all(
+			unix,
+			not(any(
+				target_os = "macos",
+				target_os = "ios",
+				target_os = "android",
+				target_os = "emscripten"
+			)),
+		))]
+		let stamp = clipboard.contents_stamp(selection)?;
+		#[cfg(not(all(
+			unix,
+			not(any(
+				target_os = "macos",
+				target_os = "ios",
+				target_os = "android",
+				target_os = "emscripten"
+			)),
+		)))]
+		let stamp = clipboard.generation()?;
+
+		let deadline = Instant::now() + delay;
+		let (cancel, cancelled) = mpsc::channel();
+		let spawned = thread::Builder::new().name("arboard-clear".into()).spawn(move || {
+			match cancelled.recv_timeout(delay) {
+				Ok(()) => return Ok(false),
+				Err(mpsc::RecvTimeoutError::Timeout) => {}
+				// The handle was dropped, which doesn't cancel the clear.
+				Err(mpsc::RecvTimeoutError::Disconnected) => {
+					thread::sleep(deadline.saturating_duration_since(Instant::now()))
+				}
+			}
+			let mut clipboard = reopen()?;
+			#[cfg(all(
+				unix,
+				not(any(
+					target_os = "macos",
+					target_os = "ios",
+					target_os = "android",
+					target_os = "emscripten"
+				)),
+			))]
+			{
+				if !clipboard.is_unchanged(selection, stamp)? {
+					return Ok(false);
+				}
+				let mut clear = clipboard.write();
+				clear.selection = selection;
+				clear.commit()?;
+			}
+			#[cfg(not(all(
+				unix,
+				not(any(
+					target_os = "macos",
+					target_os = "ios",
+					target_os = "android",
+					target_os = "emscripten"
+				)),
+			)))]
+			{
+				if clipboard.generation()? != stamp {
+					return Ok(false);
+				}
+				clipboard.clear()?;
+			}
+			Ok(true)
+		});
+		let thread = spawned.map_err(|e| Error::Unknown {
+			description: format!("couldn't start a thread for clearing the clipboard: {}", e),
+		})?;
+		Ok(ScheduledClear { cancel, thread })
+	}
+
+	/// Clears the clipboard and places all the representations onto it.
+	pub fn commit(self) -> Result<(), Error> {
+		let backend = self.clipboard.backend_name();
+		let bytes = self.representations.iter().map(Representation::len).sum();
+		#[cfg(feature = "tracing")]
+		let formats = self.representations.iter().map(Representation::name).collect::<Vec<_>>();
+		#[cfg(not(feature = "tracing"))]
+		let formats = NO_FORMATS;
+		traced(backend, "commit", formats, Some(bytes), || self.commit_to_clipboard().map(|_| ()))
+	}
+
+	/// Commits the contents, and returns the clipboard they were written to.
+	fn commit_to_clipboard(self) -> Result<&'clipboard mut Clipboard, Error> {
+		let clipboard = self.clipboard;
+		#[cfg(all(
+			unix,
+			not(any(
+				target_os = "macos",
+				target_os = "ios",
+				target_os = "android",
+				target_os = "emscripten"
+			)),
+		))]
+		let result = dispatch!(&mut clipboard.backend, cb => {
+			match cb.write_all_with_clipboard(&self.representations, self.selection) {
+				Ok(()) => cb.wait_for_paste(self.selection, self.wait),
+				Err(e) => Err(e),
+			}
+		});
+		#[cfg(target_os = "ios")]
+		let result = dispatch!(&mut clipboard.backend, cb => {
+			cb.write_all_with_options(&self.representations, self.options)
+		});
+		#[cfg(not(any(
+			all(
+				unix,
+				not(any(
+					target_os = "macos",
+					target_os = "ios",
+					target_os = "android",
+					target_os = "emscripten"
+				)),
+			),
+			target_os = "ios",
+			windows
+		)))]
+		let result = dispatch!(&mut clipboard.backend, cb => cb.write_all(&self.representations));
+		#[cfg(windows)]
+		let result = {
+			let mut representations = self.representations;
+			for (format, render) in self.delayed {
+				representations.push(Representation::Custom(format, render()));
+			}
+			dispatch!(&mut clipboard.backend, cb => cb.write_all(&representations))
+		};
+		result?;
+		Ok(clipboard)
+	}
+}
+
+/// A clear of the clipboard that [`ClipboardWrite::clear_after`] scheduled.
+///
+/// Dropping the handle doesn't cancel the clear, use `cancel` for that.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct ScheduledClear {
+	cancel: mpsc::Sender<()>,
+	thread: thread::JoinHandle<Result<bool, Error>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ScheduledClear {
+	/// Cancels the clear, unless it has already happened.
+	pub fn cancel(self) {
+		let _ = self.cancel.send(());
+		let _ = self.thread.join();
+	}
+
+	/// Blocks until the delay has elapsed, and returns whether the clipboard was cleared. It isn't
+	/// when other contents were placed onto it in the meantime.
+	pub fn wait(self) -> Result<bool, Error> {
+		self.thread.join().unwrap_or_else(|_| {
+			Err(Error::Unknown {
+				description: "the thread that clears the clipboard panicked".into(),
+			})
+		})
+	}
+}
+
+/// Receives a notification whenever the contents of the clipboard change.
+///
+/// The notifications are delivered by the operating system on a background thread owned by the
+/// watcher, and are queued up until they are received with one of the methods below. Dropping the
+/// watcher stops the background thread.
+///
+/// Note that the clipboard may still be held open by the application that changed it when the
+/// event is received, so reading it right away may fail with [`Error::ClipboardOccupied`].
+///
+/// On Linux, both the CLIPBOARD and the PRIMARY selection are watched. On X11, changes are reported
+/// through the XFIXES extension, or if that's not available, by checking which window owns each
+/// selection a few times a second. In the latter case, changes made by the application that
+/// already owned the selection aren't noticed. With the `wayland-data-control` feature enabled,
+/// the watcher uses the Wayland data control protocol when running under Wayland, and returns
+/// [`Error::ClipboardNotSupported`] if the compositor doesn't support it.
+///
+/// Currently only supported on Windows and Linux, creating a watcher on macOS returns
+/// [`Error::ClipboardNotSupported`].
+pub struct ClipboardWatcher {
+	events: mpsc::Receiver<ClipboardEvent>,
+	_platform: PlatformWatcher,
+}
+
+impl ClipboardWatcher {
+	/// Starts watching the clipboard.
+	pub fn new() -> Result<Self, Error> {
+		let (sender, events) = mpsc::channel();
+		Ok(ClipboardWatcher { events, _platform: PlatformWatcher::new(sender)? })
+	}
+
+	/// Blocks until the next event arrives.
+	pub fn recv(&self) -> Result<ClipboardEvent, Error> {
+		self.events.recv().map_err(|_| Self::stopped())
+	}
+
+	/// Blocks until the next event arrives or the timeout elapses, whichever happens first.
+	///
+	/// Returns `Ok(None)` if the timeout elapsed.
+	pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<ClipboardEvent>, Error> {
+		match self.events.recv_timeout(timeout) {
+			Ok(event) => Ok(Some(event)),
+			Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+			Err(mpsc::RecvTimeoutError::Disconnected) => Err(Self::stopped()),
+		}
+	}
+
+	/// Returns the next event if there's one queued up, without blocking.
+	pub fn try_recv(&self) -> Result<Option<ClipboardEvent>, Error> {
+		match self.events.try_recv() {
+			Ok(event) => Ok(Some(event)),
+			Err(mpsc::TryRecvError::Empty) => Ok(None),
+			Err(mpsc::TryRecvError::Disconnected) => Err(Self::stopped()),
+		}
+	}
+
+	/// Returns an iterator that blocks waiting for events, and ends if the watcher stops.
+	pub fn iter(&self) -> impl Iterator<Item = ClipboardEvent> + '_ {
+		self.events.iter()
+	}
+
+	fn stopped() -> Error {
+		Error::Unknown { description: "The clipboard watcher stopped unexpectedly".into() }
 	}
 }
 
@@ -128,15 +2573,284 @@ fn all_tests() {
 
 		let mut ctx = Clipboard::new().unwrap();
 		assert_eq!(ctx.get_text().unwrap(), text);
+
+		ctx.set_text("persisted".to_owned()).unwrap();
+		match ctx.persist() {
+			Ok(()) | Err(Error::NoClipboardManager) => {}
+			Err(e) => panic!("persisting the clipboard failed: {}", e),
+		}
+		assert_eq!(ctx.get_text().unwrap(), "persisted");
+	}
+	{
+		let mut ctx = Clipboard::new().unwrap();
+		ctx.set_text(String::new()).unwrap();
+		assert_eq!(ctx.get_text().unwrap(), "");
+		assert!(!ctx.available_formats().unwrap().is_empty());
+		ctx.clear().unwrap();
+		assert!(matches!(ctx.get_text(), Err(Error::ContentNotAvailable)));
 	}
 	{
 		let mut ctx = Clipboard::new().unwrap();
 		let text = "Some utf8: 🤓 ∑φ(n)<ε 🐔";
 		ctx.set_text(text.to_owned()).unwrap();
 		assert_eq!(ctx.get_text().unwrap(), text);
+		assert!(!ctx.available_formats().unwrap().is_empty());
+
+		ctx.set_text_multi(text).unwrap();
+		assert_eq!(ctx.get_text().unwrap(), text);
+		#[cfg(target_os = "macos")]
+		{
+			let utf16 = ctx.get_text_bytes("public.utf16-plain-text").unwrap();
+			let units: Vec<u16> =
+				utf16.chunks(2).map(|unit| u16::from_ne_bytes([unit[0], unit[1]])).collect();
+			assert_eq!(String::from_utf16(&units).unwrap(), text);
+		}
+	}
+	// On X11 the generation is updated asynchronously by the server thread.
+	#[cfg(any(windows, target_os = "macos"))]
+	{
+		let mut ctx = Clipboard::new().unwrap();
+		let before = ctx.generation().unwrap();
+		ctx.set_text("generation".to_owned()).unwrap();
+		assert_ne!(before, ctx.generation().unwrap());
+		let written = ctx.set_text_with_generation("generation again").unwrap();
+		assert_eq!(written, ctx.generation().unwrap());
+	}
+	{
+		let mut ctx = Clipboard::new().unwrap();
+		let text = "snapshot contents";
+		ctx.set_text(text.to_owned()).unwrap();
+		let snapshot = ctx.snapshot().unwrap();
+		assert!(!snapshot.is_empty());
+
+		ctx.set_text("something else".to_owned()).unwrap();
+		ctx.restore(&snapshot).unwrap();
+		assert_eq!(ctx.get_text().unwrap(), text);
+
+		assert_eq!(ctx.swap_text("swapped".to_owned()).unwrap().as_deref(), Some(text));
+		assert_eq!(ctx.get_text().unwrap(), "swapped");
+		let swapped = ctx.swap_content(&snapshot).unwrap();
+		assert!(swapped.formats().count() > 0);
+		assert_eq!(ctx.get_text().unwrap(), text);
+
+		ctx.write().text(text).html("<b>bold</b>").commit().unwrap();
+		assert_eq!(ctx.get_text().unwrap(), text);
+		#[cfg(any(windows, target_os = "macos"))]
+		assert!(ctx.get_html().unwrap().contains("<b>bold</b>"));
+
+		ctx.write().text("hunter2").sensitive().commit().unwrap();
+		assert_eq!(ctx.get_text().unwrap(), "hunter2");
+		#[cfg(windows)]
+		{
+			let formats = ctx.available_formats().unwrap();
+			for (format, _) in windows_clipboard::EXCLUDE_FROM_HISTORY {
+				assert!(formats.iter().any(|f| f == format), "{} is missing", format);
+			}
+		}
+		#[cfg(target_os = "macos")]
+		assert!(ctx.is_concealed());
+		#[cfg(any(windows, target_os = "macos"))]
+		{
+			let clear = ctx.write().text("123456").clear_after(Duration::from_millis(10)).unwrap();
+			assert!(clear.wait().unwrap());
+			assert!(matches!(ctx.get_text(), Err(Error::ContentNotAvailable)));
+			ctx.set_text("hunter2".to_owned()).unwrap();
+		}
+		#[cfg(target_os = "macos")]
+		{
+			let mut lazy = Clipboard::new_for_process();
+			assert_eq!(lazy.get_text().unwrap(), "hunter2");
+			lazy.set_text("lazy".to_owned()).unwrap();
+			assert_eq!(ctx.get_text().unwrap(), "lazy");
+		}
+
+		// Nobody pastes the contents here, so this only returns because of the deadline on X11.
+		let deadline = Instant::now() + Duration::from_millis(50);
+		ctx.write().text("waited").wait_until(deadline).commit().unwrap();
+		assert_eq!(ctx.get_text().unwrap(), "waited");
+
+		let limited = ctx.snapshot_with_limit(0).unwrap();
+		assert!(limited.is_empty());
+		assert!(!limited.warnings().is_empty());
+	}
+	#[cfg(target_os = "macos")]
+	{
+		let mut ctx = Clipboard::new().unwrap();
+		let url = "https://example.com/image.png";
+		ctx.write().data(PNG, [0u8; 8]).data(URL, url.as_bytes()).commit().unwrap();
+		assert_eq!(ctx.get_image_source_url().as_deref(), Some(url));
+		let types = ctx.types();
+		assert!(types.contains(&ClipboardType::Image) && types.contains(&ClipboardType::Url));
+		ctx.set_text(url.to_owned()).unwrap();
+		assert_eq!(ctx.get_image_source_url(), None);
+		assert_eq!(ctx.owner_hint(), None);
+
+		let source = "com.example.arboard";
+		ctx.write().text(url).data("org.nspasteboard.source", source.as_bytes()).commit().unwrap();
+		assert_eq!(ctx.owner_hint().as_deref(), Some(source));
+
+		assert!(!ctx.is_concealed());
+		ctx.write().text("hunter2").concealed().commit().unwrap();
+		assert!(ctx.is_concealed());
+		assert_eq!(ctx.get_text().unwrap(), "hunter2");
+
+		let named = ApplePasteboardKind::Named("rs.arboard.test".into());
+		let mut named = Clipboard::new_apple_pasteboard(named).unwrap();
+		named.set_text("named".to_owned()).unwrap();
+		assert_eq!(named.get_text().unwrap(), "named");
+		assert_eq!(ctx.get_text().unwrap(), "hunter2");
+		// The formats are those of the named pasteboard, not of the general one.
+		let formats = named.available_formats().unwrap();
+		assert!(formats.iter().any(|uti| uti == "public.utf8-plain-text"));
+		assert!(!formats.iter().any(|uti| uti == CONCEALED));
+		assert!(ctx.available_formats().unwrap().iter().any(|uti| uti == CONCEALED));
+
+		// Text that is only provided as the data of the abstract plain text type.
+		named.write().data("public.plain-text", "plain").commit().unwrap();
+		assert_eq!(named.get_text().unwrap(), "plain");
+
+		ctx.set_text(url.to_owned()).unwrap();
+		ctx.add_data_for_type(HTML, b"<a>link</a>").unwrap();
+		assert_eq!(ctx.get_text().unwrap(), url);
+		assert_eq!(ctx.get_text_bytes(HTML).unwrap(), b"<a>link</a>");
+		ctx.clear_type(HTML).unwrap();
+		assert_eq!(ctx.get_text().unwrap(), url);
+		assert!(matches!(ctx.get_text_bytes(HTML), Err(Error::ContentNotAvailable)));
+		ctx.clear_type(HTML).unwrap();
+
+		let mut providers: std::collections::HashMap<String, DataProvider> = Default::default();
+		providers.insert(RTF.into(), Box::new(|| b"{\\rtf1 lazy}".to_vec()));
+		ctx.set_lazy_data(providers).unwrap();
+		// The types are declared right away, even though the data wasn't produced yet.
+		assert_eq!(ctx.types(), [ClipboardType::Rtf]);
+
+		assert!(matches!(ctx.get_color(), Err(Error::ContentNotAvailable)));
+		ctx.set_color([1.0, 0.5, 0.0, 0.25]).unwrap();
+		let rgba = ctx.get_color().unwrap();
+		assert!((rgba[1] - 0.5).abs() < 0.01 && (rgba[3] - 0.25).abs() < 0.01);
+
+		let files = ClipboardSnapshot {
+			items: vec![
+				vec![(FILE_URL.into(), b"file:///tmp/a.png".to_vec())],
+				vec![(FILE_URL.into(), b"file:///tmp/b.png".to_vec())],
+			],
+			warnings: Vec::new(),
+		};
+		ctx.restore(&files).unwrap();
+		assert_eq!(ctx.count_items(), 2);
+		let items = ctx.items().unwrap();
+		assert_eq!(items.len(), 2);
+		assert_eq!(items[1].data_for_type(FILE_URL), Some(&b"file:///tmp/b.png"[..]));
+	}
+	#[cfg(windows)]
+	{
+		// An unpaired surrogate between `a` and `b`, followed by the terminating null character.
+		let text = [b'a', 0, 0x00, 0xD8, b'b', 0, 0, 0];
+		let mut ctx = Clipboard::new().unwrap();
+		ctx.write().data("CF_UNICODETEXT", &text[..]).commit().unwrap();
+		assert!(
+			matches!(ctx.get_text(), Err(Error::InvalidUtf8 { bytes }) if bytes == "a\u{FFFD}b".as_bytes())
+		);
+		assert_eq!(ctx.get_text_lossy().unwrap(), "a\u{FFFD}b");
+	}
+	#[cfg(windows)]
+	{
+		let watcher = ClipboardWatcher::new().unwrap();
+		let writer = std::thread::spawn(|| {
+			Clipboard::new().unwrap().set_text("watched".to_owned()).unwrap();
+		});
+		writer.join().unwrap();
+		let event = watcher.recv_timeout(Duration::from_secs(5)).unwrap();
+		assert!(event.is_some());
+	}
+	#[cfg(windows)]
+	{
+		use std::sync::{
+			atomic::{AtomicBool, Ordering},
+			Arc,
+		};
+
+		// Another thread keeps opening the clipboard for a few milliseconds, like clipboard
+		// managers do, which shouldn't make writing fail.
+		let done = Arc::new(AtomicBool::new(false));
+		let holder = {
+			let done = done.clone();
+			std::thread::spawn(move || {
+				while !done.load(Ordering::Relaxed) {
+					if let Ok(_cb) = clipboard_win::Clipboard::new() {
+						std::thread::sleep(Duration::from_millis(5));
+					}
+					std::thread::sleep(Duration::from_millis(1));
+				}
+			})
+		};
+		let mut ctx = Clipboard::new().unwrap();
+		for i in 0..100 {
+			ctx.set_text(format!("busy {}", i)).unwrap();
+		}
+		done.store(true, Ordering::Relaxed);
+		holder.join().unwrap();
+	}
+	#[cfg(windows)]
+	{
+		// Keeping the clipboard open on another thread makes the read give up at the timeout,
+		// even though there are attempts left.
+		let (opened, open) = mpsc::channel();
+		let holder = std::thread::spawn(move || {
+			let _cb = clipboard_win::Clipboard::new_attempts(100).unwrap();
+			opened.send(()).unwrap();
+			std::thread::sleep(Duration::from_millis(500));
+		});
+		open.recv().unwrap();
+		let mut ctx = Clipboard::builder()
+			.timeout(Duration::from_millis(50))
+			.retry(100, Duration::from_millis(10))
+			.build()
+			.unwrap();
+		let started = Instant::now();
+		assert!(matches!(ctx.get_text(), Err(Error::Timeout)));
+		assert!(started.elapsed() < Duration::from_millis(400));
+		holder.join().unwrap();
+	}
+	#[cfg(windows)]
+	{
+		use std::sync::{
+			atomic::{AtomicUsize, Ordering},
+			Arc,
+		};
+
+		let renders = Arc::new(AtomicUsize::new(0));
+		let mut ctx = Clipboard::new().unwrap();
+		let rendering = {
+			let renders = renders.clone();
+			ctx.write()
+				.text("delayed")
+				.with_provider("arboard delayed", move || {
+					renders.fetch_add(1, Ordering::SeqCst);
+					vec![1, 2, 3]
+				})
+				.commit_delayed(DelayedRenderingDrop::Empty)
+				.unwrap()
+		};
+		assert_eq!(renders.load(Ordering::SeqCst), 0);
+		assert_eq!(ctx.get_text().unwrap(), "delayed");
+		assert_eq!(ctx.get_text_bytes("arboard delayed").unwrap(), [1, 2, 3]);
+		assert_eq!(ctx.get_text_bytes("arboard delayed").unwrap(), [1, 2, 3]);
+		assert_eq!(renders.load(Ordering::SeqCst), 1);
+		drop(rendering);
+		assert!(matches!(ctx.get_text(), Err(Error::ContentNotAvailable)));
+
+		let rendering = ctx
+			.write()
+			.with_provider("arboard delayed", || vec![4])
+			.commit_delayed(DelayedRenderingDrop::RenderAll)
+			.unwrap();
+		drop(rendering);
+		assert_eq!(ctx.get_text_bytes("arboard delayed").unwrap(), [4]);
 	}
 	#[cfg(feature = "image-data")]
 	{
+		use std::convert::TryFrom;
 		let mut ctx = Clipboard::new().unwrap();
 		#[rustfmt::skip]
 		let bytes = [
@@ -149,14 +2863,87 @@ fn all_tests() {
 		ctx.set_image(img_data.clone()).unwrap();
 		let got = ctx.get_image().unwrap();
 		assert_eq!(img_data.bytes, got.bytes);
+		// Applications read the image back the way `set_image` placed it.
+		#[cfg(any(windows, target_os = "macos"))]
+		{
+			let (format, got) = ctx.get_image_with_format().unwrap();
+			assert_eq!(img_data.bytes, got.bytes);
+			assert_eq!(format, ImageFormatTag::Png);
+		}
+		// The colors of transparent and almost transparent pixels, like the shadows of a window in
+		// a screenshot, survive the round trip.
+		#[rustfmt::skip]
+		let translucent = [
+			10, 20, 30, 0,
+			200, 100, 50, 1,
+			0, 128, 255, 128,
+		];
+		let translucent = ImageData { width: 3, height: 1, bytes: translucent.as_ref().into() };
+		ctx.set_image(translucent.clone()).unwrap();
+		assert_eq!(ctx.get_image().unwrap(), translucent);
+		let lazy = ctx.get_image_lazy().unwrap();
+		assert_eq!((lazy.width(), lazy.height(), lazy.format()), (3, 1, ImageFormatTag::Png));
+		assert_eq!(lazy.decode().unwrap(), translucent);
+
+		let converted = image::DynamicImage::try_from(got).unwrap();
+		assert_eq!(converted.to_rgba8().as_raw().as_slice(), img_data.bytes.as_ref());
+		let truncated = ImageData { width: 2, height: 2, bytes: bytes[..15].as_ref().into() };
+		assert!(image::DynamicImage::try_from(truncated).is_err());
+
+		// Every thread uses its own clipboard, and the last write wins.
+		let texts: Vec<String> = (0..4).map(|i| format!("thread {}", i)).collect();
+		let threads: Vec<_> = texts
+			.iter()
+			.cloned()
+			.map(|text| {
+				let texts = texts.clone();
+				std::thread::spawn(move || {
+					let mut ctx = Clipboard::new().unwrap();
+					for _ in 0..100 {
+						ctx.set_text(text.clone()).unwrap();
+						assert!(texts.contains(&ctx.get_text().unwrap()));
+					}
+				})
+			})
+			.collect();
+		for thread in threads {
+			thread.join().unwrap();
+		}
+		assert!(texts.contains(&ctx.get_text().unwrap()));
+
+		ctx.set_image_scaled(img_data.clone(), 1).unwrap();
+		let scaled = ctx.get_image().unwrap();
+		assert_eq!((scaled.width, scaled.height), (1, 1));
+
+		#[cfg(target_os = "macos")]
+		{
+			ctx.set_image(img_data.clone()).unwrap();
+			ctx.add_data_for_type(FILE_URL, b"file:///nonexistent/arboard.png").unwrap();
+			assert_eq!(ctx.get_image().unwrap().bytes, img_data.bytes);
+		}
 	}
 	#[cfg(all(
 		unix,
-		not(any(target_os = "macos", target_os = "android", target_os = "emscripten")),
+		not(any(
+			target_os = "macos",
+			target_os = "ios",
+			target_os = "android",
+			target_os = "emscripten"
+		)),
 	))]
 	{
-		use crate::{ClipboardExtLinux, LinuxClipboardKind};
+		use crate::{
+			ClipboardEventExtLinux, ClipboardExtLinux, ClipboardGetExtLinux,
+			ClipboardWriteExtLinux, LinuxBackend, LinuxClipboardKind,
+		};
 		let mut ctx = Clipboard::new().unwrap();
+		// The mock only has the regular clipboard, and doesn't notify the watcher.
+		#[cfg(feature = "test-util")]
+		if ctx.backend() == LinuxBackend::Mock {
+			return;
+		}
+		let wayland = ctx.backend() == LinuxBackend::WaylandDataControl;
+		let watcher = ClipboardWatcher::new().unwrap();
 
 		const TEXT1: &str = "I'm a little teapot,";
 		const TEXT2: &str = "short and stout,";
@@ -167,19 +2954,121 @@ fn all_tests() {
 		ctx.set_text_with_clipboard(TEXT2.to_string(), LinuxClipboardKind::Primary).unwrap();
 
 		// The secondary clipboard is not available under wayland
-		if !cfg!(feature = "wayland-data-control") || std::env::var_os("WAYLAND_DISPLAY").is_none()
-		{
+		if !wayland {
 			ctx.set_text_with_clipboard(TEXT3.to_string(), LinuxClipboardKind::Secondary).unwrap();
 		}
 
 		assert_eq!(TEXT1, &ctx.get_text_with_clipboard(LinuxClipboardKind::Clipboard).unwrap());
 
+		let event = watcher.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+		assert_eq!(event.selection(), LinuxClipboardKind::Clipboard);
+		if wayland {
+			let mime_types = event.mime_types().unwrap();
+			assert!(mime_types.iter().any(|mime_type| mime_type == "text/plain;charset=utf-8"));
+			assert_eq!(event.read("text/plain;charset=utf-8").unwrap(), TEXT1.as_bytes());
+		} else {
+			assert!(event.owner().is_some());
+		}
+
 		assert_eq!(TEXT2, &ctx.get_text_with_clipboard(LinuxClipboardKind::Primary).unwrap());
 
+		ctx.write().clipboard_kind(LinuxClipboardKind::Primary).text(TEXT3).commit().unwrap();
+		assert_eq!(TEXT3, ctx.get().clipboard_kind(LinuxClipboardKind::Primary).text().unwrap());
+		assert_eq!(TEXT1, ctx.get().text().unwrap());
+		ctx.write().text(TEXT2).commit().unwrap();
+		assert_eq!(TEXT3, ctx.get().clipboard_kind(LinuxClipboardKind::Primary).text().unwrap());
+
 		// The secondary clipboard is not available under wayland
-		if !cfg!(feature = "wayland-data-control") || std::env::var_os("WAYLAND_DISPLAY").is_none()
-		{
+		if !wayland {
 			assert_eq!(TEXT3, &ctx.get_text_with_clipboard(LinuxClipboardKind::Secondary).unwrap());
 		}
+
+		// Large contents are transferred in chunks through the INCR mechanism.
+		#[allow(irrefutable_let_patterns)]
+		if let Backend::Platform(common_linux::LinuxClipboard::X11(x11)) = &ctx.backend {
+			let large: String = "0123456789abcdef".repeat(10 * 1024 * 1024 / 16);
+			x11.set_text(large.clone()).unwrap();
+			assert_eq!(x11.get_text_from_server().unwrap(), large);
+		}
+	}
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+	#[cfg(all(feature = "async", not(target_arch = "wasm32")))]
+	#[test]
+	fn async_methods() {
+		use super::{common, Clipboard, Error, MockClipboard};
+		use std::{
+			future::Future,
+			sync::Arc,
+			task::{Context, Poll, Wake},
+			thread::Thread,
+		};
+
+		struct ThreadWaker(Thread);
+
+		impl Wake for ThreadWaker {
+			fn wake(self: Arc<Self>) {
+				self.0.unpark();
+			}
+		}
+
+		fn block_on<F: Future>(future: F) -> F::Output {
+			let mut future = Box::pin(future);
+			let waker = Arc::new(ThreadWaker(std::thread::current())).into();
+			let mut cx = Context::from_waker(&waker);
+			loop {
+				match future.as_mut().poll(&mut cx) {
+					Poll::Ready(output) => return output,
+					Poll::Pending => std::thread::park(),
+				}
+			}
+		}
+
+		let mock = MockClipboard::new();
+		let mut ctx = Clipboard::from_mock(mock.clone());
+		block_on(ctx.set_text_async("async".into())).unwrap();
+		assert_eq!(block_on(ctx.get_text_async()).unwrap(), "async");
+		mock.fail_next_read(Error::ClipboardOccupied);
+		assert!(matches!(block_on(ctx.get_text_async()), Err(Error::ClipboardOccupied)));
+
+		#[cfg(feature = "image-data")]
+		{
+			use super::ImageData;
+
+			let bytes = [1, 2, 3, 4];
+			let image = ImageData { width: 1, height: 1, bytes: bytes.as_ref().into() };
+			block_on(ctx.set_image_async(image)).unwrap();
+			assert_eq!(block_on(ctx.get_image_async()).unwrap().bytes, bytes.as_ref());
+		}
+
+		let panicked = block_on(common::offload::<()>(|| panic!("the clipboard broke")));
+		assert!(matches!(panicked, Err(Error::Unknown { .. })));
+	}
+
+	/// Sets the limit directly, as only the builder on Linux can create a mock clipboard with one.
+	#[cfg(feature = "image-data")]
+	#[test]
+	fn max_image_bytes_mock() {
+		use super::{Clipboard, Error, ImageData, MockClipboard};
+		use std::time::Duration;
+
+		let mock = MockClipboard::new();
+		let mut ctx = Clipboard::from_mock(mock.clone());
+		ctx.max_image_bytes = Some(16);
+		let small = ImageData { width: 2, height: 2, bytes: vec![0; 16].into() };
+		let large = ImageData { width: 3, height: 2, bytes: vec![0; 24].into() };
+		ctx.set_image_ref(&small).unwrap();
+		assert_eq!(ctx.get_image().unwrap().bytes, small.bytes);
+		assert!(matches!(ctx.set_image_scaled(large.clone(), 3), Err(Error::ImageTooLarge { .. })));
+		assert_eq!(ctx.get_image().unwrap().bytes, small.bytes);
+
+		// Images that were placed by someone else are checked when they're read.
+		Clipboard::from_mock(mock).set_image(large).unwrap();
+		assert!(matches!(ctx.get_image(), Err(Error::ImageTooLarge { size: 24, limit: 16 })));
+		// The limit applies on the background thread too.
+		let result = ctx.get_image_with_timeout(Duration::from_secs(10));
+		assert!(matches!(result, Err(Error::ImageTooLarge { size: 24, limit: 16 })));
 	}
 }
@@ -0,0 +1,538 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2020 The arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+
+#[cfg(feature = "image-data")]
+use crate::common::ImageData;
+use crate::common::{text_from_utf8, ClipboardSnapshot, Error, Representation};
+use crate::ClipboardProvider;
+
+/// The format that text is stored under.
+pub const MOCK_TEXT_FORMAT: &str = "text/plain;charset=utf-8";
+/// The format that HTML is stored under.
+const MOCK_HTML_FORMAT: &str = "text/html";
+
+/// A clipboard that only exists in memory, for testing code that uses the clipboard without a
+/// display server.
+///
+/// The contents are stored as a map from format names to bytes, with the text stored under
+/// [`MOCK_TEXT_FORMAT`]. Like on a real clipboard, every write replaces all of the contents.
+/// Nothing is shared with the clipboard of the system.
+///
+/// Clones share the same contents, like two handles to the same clipboard, while instances
+/// created with `new` are independent of each other. [`MockClipboard::shared`] returns the one
+/// that stands in for the clipboard of the system. A `Clipboard` can be backed by a
+/// `MockClipboard` with [`Clipboard::from_mock`](crate::Clipboard::from_mock), so that all of its
+/// methods work without touching the system, while the test keeps a clone to inspect the contents
+/// or to inject failures with [`fail_next_read`](Self::fail_next_read) and
+/// [`fail_next_write`](Self::fail_next_write).
+///
+/// Code that should work with both a `Clipboard` and a `MockClipboard` can also be written against
+/// the [`ClipboardProvider`] trait.
+///
+/// # Examples
+///
+/// ```
+/// use arboard::{ClipboardProvider, MockClipboard};
+///
+/// fn shout(clipboard: &mut impl ClipboardProvider) {
+///     let text = clipboard.get_text().unwrap();
+///     clipboard.set_text(text.to_uppercase()).unwrap();
+/// }
+///
+/// let mut clipboard = MockClipboard::new();
+/// clipboard.set_text("hello".into()).unwrap();
+/// shout(&mut clipboard);
+/// assert_eq!(clipboard.get_text().unwrap(), "HELLO");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MockClipboard {
+	state: Arc<Mutex<MockState>>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+	formats: HashMap<String, Vec<u8>>,
+	#[cfg(feature = "image-data")]
+	image: Option<ImageData<'static>>,
+	generation: u64,
+	fail_read: Option<Error>,
+	fail_write: Option<Error>,
+	delay_read: Option<Duration>,
+}
+
+impl MockState {
+	/// Waits for the delay that the next read was set up with, and returns the error that it was
+	/// set up to fail with.
+	fn read(&mut self) -> Result<(), Error> {
+		if let Some(delay) = self.delay_read.take() {
+			std::thread::sleep(delay);
+		}
+		self.fail_read.take().map_or(Ok(()), Err)
+	}
+
+	/// Returns the error that the next write was set up to fail with, and otherwise clears the
+	/// contents for the write.
+	fn write(&mut self) -> Result<(), Error> {
+		if let Some(error) = self.fail_write.take() {
+			return Err(error);
+		}
+		self.clear();
+		Ok(())
+	}
+
+	fn clear(&mut self) {
+		self.formats.clear();
+		#[cfg(feature = "image-data")]
+		{
+			self.image = None;
+		}
+		self.generation += 1;
+	}
+
+	fn text(&self) -> Result<String, Error> {
+		let bytes = self.formats.get(MOCK_TEXT_FORMAT).ok_or(Error::ContentNotAvailable)?;
+		text_from_utf8(bytes.clone())
+	}
+}
+
+impl MockClipboard {
+	/// Creates an empty clipboard.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Returns the clipboard of the process, which [`Clipboard::new_mock`](crate::Clipboard::new_mock)
+	/// uses. Like the clipboard of the system, its contents outlive the instances that access it.
+	pub fn shared() -> Self {
+		static SHARED: Lazy<MockClipboard> = Lazy::new(MockClipboard::new);
+		SHARED.clone()
+	}
+
+	fn state(&self) -> MutexGuard<'_, MockState> {
+		// A test that panicked while holding the lock shouldn't make the other tests fail.
+		self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+
+	/// Returns `Error::ContentNotAvailable` if there's no text, and `Error::InvalidUtf8` if the
+	/// text isn't valid UTF-8.
+	pub fn get_text(&mut self) -> Result<String, Error> {
+		let mut state = self.state();
+		state.read()?;
+		state.text()
+	}
+
+	/// Returns the bytes stored under `format`.
+	pub fn get_text_bytes(&mut self, format: &str) -> Result<Vec<u8>, Error> {
+		let mut state = self.state();
+		state.read()?;
+		state.formats.get(format).cloned().ok_or(Error::ContentNotAvailable)
+	}
+
+	/// Replaces the contents with the text.
+	pub fn set_text(&mut self, text: String) -> Result<(), Error> {
+		self.set_data(MOCK_TEXT_FORMAT, text)
+	}
+
+	/// Replaces the contents with the bytes, stored under `format`.
+	pub fn set_data(
+		&mut self,
+		format: impl Into<String>,
+		bytes: impl Into<Vec<u8>>,
+	) -> Result<(), Error> {
+		let mut state = self.state();
+		state.write()?;
+		state.formats.insert(format.into(), bytes.into());
+		Ok(())
+	}
+
+	/// Returns the names of the formats that are currently stored, in no particular order.
+	pub fn formats(&self) -> impl Iterator<Item = String> {
+		self.state().formats.keys().cloned().collect::<Vec<_>>().into_iter()
+	}
+
+	/// Removes all of the contents.
+	pub fn clear(&mut self) {
+		self.state().clear();
+	}
+
+	/// Returns a number that's incremented every time the contents are replaced or cleared.
+	pub fn generation(&mut self) -> Result<u64, Error> {
+		Ok(self.state().generation)
+	}
+
+	/// Makes the next read (like `get_text` or `snapshot`) fail with `error`, for example with
+	/// `Error::ClipboardOccupied` to test how the code handles a clipboard that's in use.
+	pub fn fail_next_read(&self, error: Error) {
+		self.state().fail_read = Some(error);
+	}
+
+	/// Makes the next read take at least `delay`, for example to test how the code handles a
+	/// clipboard that answers slowly. Other reads and writes through any clone wait for it too.
+	pub fn delay_next_read(&self, delay: Duration) {
+		self.state().delay_read = Some(delay);
+	}
+
+	/// Makes the next write (like `set_text` or `restore`) fail with `error`. The contents are
+	/// left unchanged by the failed write.
+	pub fn fail_next_write(&self, error: Error) {
+		self.state().fail_write = Some(error);
+	}
+
+	/// Returns `Error::ContentNotAvailable` if there's no image.
+	#[cfg(feature = "image-data")]
+	pub fn get_image(&mut self) -> Result<ImageData<'static>, Error> {
+		let mut state = self.state();
+		state.read()?;
+		state.image.clone().ok_or(Error::ContentNotAvailable)
+	}
+
+	/// Replaces the contents with the image. Returns `Error::ConversionFailure` if the size of the
+	/// bytes doesn't match the dimensions, as a real clipboard couldn't encode the image either.
+	#[cfg(feature = "image-data")]
+	pub fn set_image(&mut self, image: ImageData) -> Result<(), Error> {
+		if image.width.checked_mul(image.height).and_then(|pixels| pixels.checked_mul(4))
+			!= Some(image.bytes.len())
+		{
+			return Err(Error::ConversionFailure);
+		}
+		let mut state = self.state();
+		state.write()?;
+		state.image = Some(image.to_owned_img());
+		Ok(())
+	}
+
+	pub(crate) fn swap_text(&mut self, text: String) -> Result<Option<String>, Error> {
+		let mut state = self.state();
+		state.read()?;
+		let previous = match state.text() {
+			Ok(text) => Some(text),
+			Err(Error::ContentNotAvailable) => None,
+			Err(e) => return Err(e),
+		};
+		state.write()?;
+		state.formats.insert(MOCK_TEXT_FORMAT.into(), text.into_bytes());
+		Ok(previous)
+	}
+
+	/// The image isn't part of the snapshot, since it's not stored as a format.
+	pub(crate) fn snapshot(
+		&mut self,
+		max_bytes: Option<usize>,
+	) -> Result<ClipboardSnapshot, Error> {
+		let mut state = self.state();
+		state.read()?;
+		let mut formats: Vec<_> = state.formats.iter().collect();
+		// Sorted, so that the formats that don't fit into the size limit are always the same.
+		formats.sort();
+		let mut snapshot = ClipboardSnapshot::default();
+		for (format, bytes) in formats {
+			snapshot.push(format.clone(), bytes.clone(), max_bytes);
+		}
+		Ok(snapshot)
+	}
+
+	pub(crate) fn restore(&mut self, snapshot: &ClipboardSnapshot) -> Result<(), Error> {
+		let mut state = self.state();
+		state.write()?;
+		for (format, bytes) in snapshot.items.iter().flatten() {
+			state.formats.insert(format.clone(), bytes.clone());
+		}
+		Ok(())
+	}
+
+	pub(crate) fn available_formats(&mut self) -> Result<Vec<String>, Error> {
+		self.state().read()?;
+		Ok(self.formats().collect())
+	}
+
+	/// There's no process that could take the contents over.
+	pub(crate) fn persist(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+
+	pub(crate) fn write_all(&mut self, representations: &[Representation]) -> Result<(), Error> {
+		let mut state = self.state();
+		state.write()?;
+		for representation in representations {
+			let (format, bytes) = match representation {
+				Representation::Text(text) => (MOCK_TEXT_FORMAT, text.as_bytes()),
+				Representation::Html(html) => (MOCK_HTML_FORMAT, html.as_bytes()),
+				Representation::Custom(format, bytes) => (format.as_str(), bytes.as_slice()),
+			};
+			state.formats.insert(format.to_owned(), bytes.to_owned());
+		}
+		Ok(())
+	}
+}
+
+/// The mock only has the regular clipboard, the other selections aren't supported.
+#[cfg(all(
+	unix,
+	not(any(
+		target_os = "macos",
+		target_os = "ios",
+		target_os = "android",
+		target_os = "emscripten"
+	)),
+))]
+impl MockClipboard {
+	fn check_selection(selection: crate::LinuxClipboardKind) -> Result<(), Error> {
+		match selection {
+			crate::LinuxClipboardKind::Clipboard => Ok(()),
+			_ => Err(Error::ClipboardNotSupported),
+		}
+	}
+
+	pub(crate) fn get_text_with_clipboard(
+		&mut self,
+		selection: crate::LinuxClipboardKind,
+	) -> Result<String, Error> {
+		Self::check_selection(selection)?;
+		self.get_text()
+	}
+
+	pub(crate) fn set_text_with_clipboard(
+		&mut self,
+		text: String,
+		selection: crate::LinuxClipboardKind,
+	) -> Result<(), Error> {
+		Self::check_selection(selection)?;
+		self.set_text(text)
+	}
+
+	pub(crate) fn write_all_with_clipboard(
+		&mut self,
+		representations: &[Representation],
+		selection: crate::LinuxClipboardKind,
+	) -> Result<(), Error> {
+		Self::check_selection(selection)?;
+		self.write_all(representations)
+	}
+
+	/// The contents stay available, so there's nothing to wait for.
+	pub(crate) fn wait_for_paste(
+		&mut self,
+		_selection: crate::LinuxClipboardKind,
+		_wait: crate::common::WaitConfig,
+	) -> Result<(), Error> {
+		Ok(())
+	}
+}
+
+#[cfg(windows)]
+impl MockClipboard {
+	pub(crate) fn set_open_retry(&mut self, _attempts: usize, _delay: std::time::Duration) {}
+}
+
+#[cfg(target_os = "macos")]
+impl MockClipboard {
+	pub(crate) fn types(&mut self) -> Vec<crate::ClipboardType> {
+		use crate::ClipboardType;
+
+		let state = self.state();
+		let mut types = Vec::new();
+		#[cfg(feature = "image-data")]
+		if state.image.is_some() {
+			types.push(ClipboardType::Image);
+		}
+		for format in state.formats.keys() {
+			let clipboard_type = match format.as_str() {
+				MOCK_TEXT_FORMAT => ClipboardType::Text,
+				MOCK_HTML_FORMAT => ClipboardType::Html,
+				uti => ClipboardType::from_uti(uti.to_owned()),
+			};
+			if !types.contains(&clipboard_type) {
+				types.push(clipboard_type);
+			}
+		}
+		types
+	}
+
+	pub(crate) fn is_concealed(&mut self) -> bool {
+		self.state().formats.contains_key(crate::CONCEALED)
+	}
+
+	pub(crate) fn owner_hint(&mut self) -> Option<String> {
+		let state = self.state();
+		let source = state.formats.get(crate::osx_clipboard::SOURCE)?;
+		Some(String::from_utf8_lossy(source).into_owned()).filter(|source| !source.is_empty())
+	}
+
+	pub(crate) fn get_image_source_url(&mut self) -> Option<String> {
+		let state = self.state();
+		let has_image =
+			state.formats.contains_key(crate::PNG) || state.formats.contains_key(crate::TIFF);
+		#[cfg(feature = "image-data")]
+		let has_image = has_image || state.image.is_some();
+		if !has_image {
+			return None;
+		}
+		let url = state.formats.get(crate::URL)?;
+		Some(String::from_utf8_lossy(url).into_owned()).filter(|url| !url.is_empty())
+	}
+
+	/// Unlike the other writes, this keeps the current contents.
+	pub(crate) fn add_data_for_type(&mut self, uti: &str, bytes: &[u8]) -> Result<(), Error> {
+		let mut state = self.state();
+		if let Some(error) = state.fail_write.take() {
+			return Err(error);
+		}
+		state.formats.insert(uti.to_owned(), bytes.to_owned());
+		state.generation += 1;
+		Ok(())
+	}
+
+	pub(crate) fn clear_type(&mut self, uti: &str) -> Result<(), Error> {
+		let mut state = self.state();
+		if let Some(error) = state.fail_write.take() {
+			return Err(error);
+		}
+		if state.formats.remove(uti).is_some() {
+			state.generation += 1;
+		}
+		Ok(())
+	}
+
+	/// The mock stores the components of colors instead of an archived `NSColor`.
+	pub(crate) fn get_color(&mut self) -> Result<[f32; 4], Error> {
+		let bytes = self.get_text_bytes(crate::COLOR)?;
+		if bytes.len() != 16 {
+			return Err(Error::ConversionFailure);
+		}
+		let mut rgba = [0.0; 4];
+		for (component, bytes) in rgba.iter_mut().zip(bytes.chunks_exact(4)) {
+			*component = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+		}
+		Ok(rgba)
+	}
+
+	pub(crate) fn set_color(&mut self, rgba: [f32; 4]) -> Result<(), Error> {
+		let bytes = rgba.iter().flat_map(|component| component.to_le_bytes()).collect();
+		self.write_all(&[Representation::Custom(crate::COLOR.into(), bytes)])
+	}
+
+	/// Nothing requests the data from the mock, so it's produced right away.
+	pub(crate) fn set_lazy_data(
+		&mut self,
+		providers: std::collections::HashMap<String, crate::DataProvider>,
+	) -> Result<(), Error> {
+		let representations = providers
+			.into_iter()
+			.map(|(uti, provider)| Representation::Custom(uti, provider()))
+			.collect::<Vec<_>>();
+		self.write_all(&representations)
+	}
+}
+
+#[cfg(target_os = "ios")]
+impl MockClipboard {
+	/// The options only matter to other devices, so they're ignored.
+	pub(crate) fn write_all_with_options(
+		&mut self,
+		representations: &[Representation],
+		_options: crate::PasteboardOptions,
+	) -> Result<(), Error> {
+		self.write_all(representations)
+	}
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+impl MockClipboard {
+	pub(crate) async fn get_text_async(&mut self) -> Result<String, Error> {
+		self.get_text()
+	}
+
+	pub(crate) async fn set_text_async(&mut self, text: String) -> Result<(), Error> {
+		self.set_text(text)
+	}
+
+	#[cfg(feature = "image-data")]
+	pub(crate) async fn get_image_async(&mut self) -> Result<ImageData<'static>, Error> {
+		self.get_image()
+	}
+
+	#[cfg(feature = "image-data")]
+	pub(crate) async fn set_image_async(&mut self, image: ImageData<'_>) -> Result<(), Error> {
+		self.set_image(image)
+	}
+}
+
+impl ClipboardProvider for MockClipboard {
+	fn get_text(&mut self) -> Result<String, Error> {
+		MockClipboard::get_text(self)
+	}
+
+	fn set_text(&mut self, text: String) -> Result<(), Error> {
+		MockClipboard::set_text(self, text)
+	}
+
+	fn clear(&mut self) -> Result<(), Error> {
+		self.write_all(&[])
+	}
+
+	fn available_formats(&mut self) -> Result<Vec<String>, Error> {
+		MockClipboard::available_formats(self)
+	}
+
+	#[cfg(feature = "image-data")]
+	fn get_image(&mut self) -> Result<ImageData<'static>, Error> {
+		MockClipboard::get_image(self)
+	}
+
+	#[cfg(feature = "image-data")]
+	fn set_image(&mut self, image: ImageData) -> Result<(), Error> {
+		MockClipboard::set_image(self, image)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn mock_clipboard() {
+		fn round_trip(clipboard: &mut impl ClipboardProvider, text: &str) -> String {
+			clipboard.set_text(text.to_owned()).unwrap();
+			clipboard.get_text().unwrap()
+		}
+
+		let mut ctx = MockClipboard::new();
+		assert!(matches!(ctx.get_text(), Err(Error::ContentNotAvailable)));
+		assert_eq!(round_trip(&mut ctx, "mocked"), "mocked");
+		let mut boxed: Box<dyn ClipboardProvider> = Box::new(ctx.clone());
+		assert_eq!(round_trip(&mut boxed, "boxed"), "boxed");
+		assert_eq!(ctx.formats().collect::<Vec<_>>(), [MOCK_TEXT_FORMAT]);
+
+		ctx.set_data("application/x-custom", [0xFF, 0xFE]).unwrap();
+		assert!(matches!(ctx.get_text(), Err(Error::ContentNotAvailable)));
+		assert_eq!(ctx.get_text_bytes("application/x-custom").unwrap(), [0xFF, 0xFE]);
+		ctx.set_data(MOCK_TEXT_FORMAT, [0xFF]).unwrap();
+		assert!(matches!(ctx.get_text(), Err(Error::InvalidUtf8 { .. })));
+
+		#[cfg(feature = "image-data")]
+		{
+			let image = ImageData { width: 1, height: 1, bytes: [1, 2, 3, 4].as_ref().into() };
+			ctx.set_image(image.clone()).unwrap();
+			assert_eq!(ctx.get_image().unwrap().bytes, image.bytes);
+			assert_eq!(ctx.formats().count(), 0);
+			let wrong_size = ImageData { width: 2, ..image };
+			assert!(matches!(ctx.set_image(wrong_size), Err(Error::ConversionFailure)));
+		}
+
+		ctx.clear();
+		assert!(matches!(ctx.get_text_bytes(MOCK_TEXT_FORMAT), Err(Error::ContentNotAvailable)));
+	}
+}
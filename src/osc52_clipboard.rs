@@ -0,0 +1,323 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2020 The arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+// OSC 52 is an escape sequence that asks the terminal emulator to access the clipboard of the
+// machine that it's running on, which also works when the application runs over SSH. See "Operating
+// System Commands" in https://invisible-island.net/xterm/ctlseqs/ctlseqs.html
+//
+// The sequences are written to the controlling terminal of the process, so they work even if the
+// standard streams are redirected.
+
+use std::{
+	env,
+	fs::{File, OpenOptions},
+	io::{self, Read, Write},
+	mem,
+	os::unix::io::{AsRawFd, RawFd},
+	time::{Duration, Instant},
+};
+
+#[cfg(feature = "image-data")]
+use crate::common::ImageData;
+use crate::{
+	common::{text_from_utf8, ClipboardSnapshot, Error, Representation},
+	common_linux::LinuxClipboardKind,
+};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// How long to wait for the terminal to answer a query by default. Terminals that don't allow
+/// reading the clipboard usually don't answer at all.
+const QUERY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// GNU screen drops DCS strings that are longer than this, so longer sequences are split up.
+const SCREEN_CHUNK_LEN: usize = 76;
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+/// The terminal multiplexer that the escape sequences have to pass through to reach the terminal.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Multiplexer {
+	None,
+	/// tmux only forwards the sequences if its `allow-passthrough` option is enabled.
+	Tmux,
+	Screen,
+}
+
+impl Multiplexer {
+	fn detect() -> Self {
+		if env::var_os("TMUX").is_some() {
+			Multiplexer::Tmux
+		} else if env::var_os("STY").is_some() {
+			Multiplexer::Screen
+		} else {
+			Multiplexer::None
+		}
+	}
+
+	/// Wraps `sequence` into the device control strings that make the multiplexer pass it on to
+	/// the terminal unchanged.
+	pub(crate) fn wrap(self, sequence: &str) -> String {
+		match self {
+			Multiplexer::None => sequence.to_owned(),
+			// The escape characters within the passthrough have to be doubled.
+			Multiplexer::Tmux => {
+				format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+			}
+			// The sequences only consist of ASCII characters, so splitting the bytes is fine.
+			Multiplexer::Screen => sequence
+				.as_bytes()
+				.chunks(SCREEN_CHUNK_LEN)
+				.map(|chunk| format!("\x1bP{}\x1b\\", String::from_utf8_lossy(chunk)))
+				.collect(),
+		}
+	}
+}
+
+/// Returns the OSC 52 sequence that places `text` onto the selected clipboard.
+pub(crate) fn set_sequence(text: &str, selection: LinuxClipboardKind) -> String {
+	format!("\x1b]52;{};{}\x07", selection_parameter(selection), base64::encode(text))
+}
+
+/// Returns the base64 data of the first complete OSC 52 reply in `input`, or `None` if the reply
+/// hasn't fully arrived yet.
+pub(crate) fn parse_reply(input: &[u8]) -> Option<&[u8]> {
+	const PREFIX: &[u8] = b"\x1b]52;";
+	let start = input.windows(PREFIX.len()).position(|window| window == PREFIX)? + PREFIX.len();
+	// Skip the selection parameter.
+	let rest = &input[start..];
+	let data = &rest[rest.iter().position(|&byte| byte == b';')? + 1..];
+	let end = data.iter().position(|&byte| byte == BEL || byte == ESC)?;
+	// The reply is either terminated by BEL or by ST, which is `ESC \`.
+	if data[end] == ESC && data.get(end + 1) != Some(&b'\\') {
+		return None;
+	}
+	Some(&data[..end])
+}
+
+fn selection_parameter(selection: LinuxClipboardKind) -> char {
+	match selection {
+		LinuxClipboardKind::Clipboard => 'c',
+		LinuxClipboardKind::Primary => 'p',
+		LinuxClipboardKind::Secondary => 'q',
+	}
+}
+
+/// Turns off line buffering and echoing on the terminal, until dropped.
+struct RawMode {
+	fd: RawFd,
+	original: libc::termios,
+}
+
+impl RawMode {
+	fn enable(fd: RawFd) -> io::Result<Self> {
+		let mut termios: libc::termios = unsafe { mem::zeroed() };
+		if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+			return Err(io::Error::last_os_error());
+		}
+		let original = termios;
+		termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+		termios.c_cc[libc::VMIN] = 0;
+		termios.c_cc[libc::VTIME] = 0;
+		if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(RawMode { fd, original })
+	}
+}
+
+impl Drop for RawMode {
+	fn drop(&mut self) {
+		unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.original) };
+	}
+}
+
+pub struct Osc52ClipboardContext {
+	tty: File,
+	multiplexer: Multiplexer,
+	query_timeout: Duration,
+}
+
+impl Osc52ClipboardContext {
+	/// Returns `Error::ClipboardNotSupported` if the process has no controlling terminal.
+	pub(crate) fn new() -> Result<Self> {
+		let tty = OpenOptions::new().read(true).write(true).open("/dev/tty").map_err(|e| {
+			warn!("Could not open the controlling terminal: {}", e);
+			Error::ClipboardNotSupported
+		})?;
+		Ok(Self { tty, multiplexer: Multiplexer::detect(), query_timeout: QUERY_TIMEOUT })
+	}
+
+	pub(crate) fn set_query_timeout(&mut self, timeout: Duration) {
+		self.query_timeout = timeout;
+	}
+
+	fn write_sequence(&mut self, sequence: &str) -> Result<()> {
+		let wrapped = self.multiplexer.wrap(sequence);
+		self.tty.write_all(wrapped.as_bytes()).map_err(io_error)?;
+		self.tty.flush().map_err(io_error)
+	}
+
+	/// Sends the query for the selected clipboard and returns the base64 data of the answer.
+	fn query(&mut self, selection: LinuxClipboardKind) -> Result<Vec<u8>> {
+		let fd = self.tty.as_raw_fd();
+		let _raw_mode = RawMode::enable(fd).map_err(io_error)?;
+		// Discard whatever was typed before, so that it's not mistaken for the answer.
+		unsafe { libc::tcflush(fd, libc::TCIFLUSH) };
+		self.write_sequence(&format!("\x1b]52;{};?\x07", selection_parameter(selection)))?;
+
+		let deadline = Instant::now() + self.query_timeout;
+		let mut reply = Vec::new();
+		loop {
+			if let Some(data) = parse_reply(&reply) {
+				return Ok(data.to_vec());
+			}
+			let remaining = deadline.saturating_duration_since(Instant::now());
+			if remaining == Duration::from_secs(0) {
+				warn!("The terminal didn't answer the OSC 52 query, reading the clipboard is probably disabled");
+				return Err(Error::ClipboardNotSupported);
+			}
+			let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+			let timeout = remaining.as_millis().clamp(1, libc::c_int::MAX as u128) as libc::c_int;
+			if unsafe { libc::poll(&mut pollfd, 1, timeout) } < 0 {
+				let error = io::Error::last_os_error();
+				if error.kind() == io::ErrorKind::Interrupted {
+					continue;
+				}
+				return Err(io_error(error));
+			}
+			if pollfd.revents != 0 {
+				let mut buffer = [0; 4096];
+				let read = self.tty.read(&mut buffer).map_err(io_error)?;
+				reply.extend_from_slice(&buffer[..read]);
+			}
+		}
+	}
+
+	pub fn get_text(&mut self) -> Result<String> {
+		self.get_text_with_clipboard(LinuxClipboardKind::Clipboard)
+	}
+
+	pub(crate) fn get_text_with_clipboard(
+		&mut self,
+		selection: LinuxClipboardKind,
+	) -> Result<String> {
+		let data = self.query(selection)?;
+		if data.is_empty() {
+			return Err(Error::ContentNotAvailable);
+		}
+		let bytes = base64::decode(&data).map_err(|_| Error::ConversionFailure)?;
+		text_from_utf8(bytes)
+	}
+
+	pub fn get_text_bytes(&mut self, _format: &str) -> Result<Vec<u8>> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub fn set_text(&mut self, text: String) -> Result<()> {
+		self.set_text_with_clipboard(text, LinuxClipboardKind::Clipboard)
+	}
+
+	pub(crate) fn set_text_with_clipboard(
+		&mut self,
+		text: String,
+		selection: LinuxClipboardKind,
+	) -> Result<()> {
+		self.write_sequence(&set_sequence(&text, selection))
+	}
+
+	pub fn swap_text(&mut self, text: String) -> Result<Option<String>> {
+		let previous = match self.get_text() {
+			Ok(text) => Some(text),
+			Err(Error::ContentNotAvailable) => None,
+			Err(e) => return Err(e),
+		};
+		self.set_text(text)?;
+		Ok(previous)
+	}
+
+	pub fn generation(&mut self) -> Result<u64> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub fn available_formats(&mut self) -> Result<Vec<String>> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub fn snapshot(&mut self, _max_bytes: Option<usize>) -> Result<ClipboardSnapshot> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	/// The terminal emulator keeps the contents.
+	pub fn persist(&mut self) -> Result<()> {
+		Ok(())
+	}
+
+	pub fn restore(&mut self, _snapshot: &ClipboardSnapshot) -> Result<()> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	/// Only the text can be transferred, the other representations are left out.
+	pub(crate) fn write_all_with_clipboard(
+		&mut self,
+		representations: &[Representation],
+		selection: LinuxClipboardKind,
+	) -> Result<()> {
+		let text = representations.iter().find_map(|representation| match representation {
+			Representation::Text(text) => Some(text),
+			_ => None,
+		});
+		match text {
+			Some(text) => self.write_sequence(&set_sequence(text, selection)),
+			None => Err(Error::ClipboardNotSupported),
+		}
+	}
+
+	#[cfg(feature = "image-data")]
+	pub fn get_image(&mut self) -> Result<ImageData<'static>> {
+		Err(Error::UnsupportedFormat { format: "image".into() })
+	}
+
+	#[cfg(feature = "image-data")]
+	pub fn set_image(&mut self, _image: ImageData) -> Result<()> {
+		Err(Error::UnsupportedFormat { format: "image".into() })
+	}
+}
+
+fn io_error(error: io::Error) -> Error {
+	Error::Unknown { description: format!("Could not talk to the terminal: {}", error) }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn osc52_sequences() {
+		let sequence = set_sequence("hello", LinuxClipboardKind::Clipboard);
+		assert_eq!(sequence, "\x1b]52;c;aGVsbG8=\x07");
+		assert_eq!(set_sequence("", LinuxClipboardKind::Primary), "\x1b]52;p;\x07");
+		assert_eq!(Multiplexer::None.wrap(&sequence), sequence);
+		assert_eq!(Multiplexer::Tmux.wrap(&sequence), "\x1bPtmux;\x1b\x1b]52;c;aGVsbG8=\x07\x1b\\");
+
+		let long = set_sequence(&"x".repeat(200), LinuxClipboardKind::Clipboard);
+		let wrapped = Multiplexer::Screen.wrap(&long);
+		let chunks: Vec<_> = wrapped.split("\x1b\\").filter(|chunk| !chunk.is_empty()).collect();
+		assert!(chunks.len() > 1);
+		assert!(chunks.iter().all(|chunk| chunk.starts_with("\x1bP")));
+		assert_eq!(chunks.iter().map(|chunk| &chunk[2..]).collect::<String>(), long);
+
+		assert_eq!(parse_reply(b"\x1b]52;c;aGVsbG8=\x07"), Some(&b"aGVsbG8="[..]));
+		assert_eq!(parse_reply(b"typed\x1b]52;c;aGVsbG8=\x1b\\"), Some(&b"aGVsbG8="[..]));
+		assert_eq!(parse_reply(b"\x1b]52;c;aGVs"), None);
+		assert_eq!(parse_reply(b"\x1b]52;c;aGVsbG8=\x1b"), None);
+	}
+}
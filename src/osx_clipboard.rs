@@ -1,147 +1,880 @@
-use cocoa::appkit::NSPasteboardTypeString;
-use cocoa::base::{id, nil};
-use cocoa::foundation::{NSInteger, NSString};
 #[cfg(feature = "image-data")]
 use core_graphics::{
-	base::{kCGBitmapByteOrderDefault, kCGImageAlphaLast, kCGRenderingIntentDefault, CGFloat},
+	base::{kCGBitmapByteOrderDefault, kCGImageAlphaLast, kCGRenderingIntentDefault},
 	color_space::CGColorSpace,
-	data_provider::{CGDataProvider, CustomData},
+	data_provider::CGDataProvider,
 	image::CGImage,
 };
-use image::DynamicImage;
-use log::{error, info};
-use objc::runtime::{BOOL, YES};
-use objc::{msg_send, sel, sel_impl};
+#[cfg(feature = "image-data")]
+use foreign_types_shared::ForeignType;
+#[cfg(feature = "image-data")]
+use image::{codecs::gif::GifDecoder, AnimationDecoder, DynamicImage};
+#[cfg(feature = "image-data")]
+use objc2::encode::{Encode, Encoding};
+use objc2::{
+	declare_class, msg_send_id, mutability,
+	rc::{autoreleasepool, Retained},
+	runtime::{NSObject, NSObjectProtocol, ProtocolObject},
+	ClassType, DeclaredClass,
+};
+#[cfg(feature = "image-data")]
+use objc2_app_kit::NSImage;
+use objc2_app_kit::{
+	NSColor, NSColorSpace, NSPasteboard, NSPasteboardItem, NSPasteboardItemDataProvider,
+	NSPasteboardNameDrag, NSPasteboardNameFind, NSPasteboardNameFont, NSPasteboardNameGeneral,
+	NSPasteboardNameRuler, NSPasteboardType, NSPasteboardTypeString, NSPasteboardWriting,
+};
+#[cfg(feature = "image-data")]
+use objc2_foundation::{CGFloat, NSSize};
+use objc2_foundation::{
+	NSArray, NSData, NSInteger, NSRange, NSString, NSStringEncodingConversionOptions,
+	NSUTF8StringEncoding,
+};
+use std::{
+	borrow::Cow,
+	cell::{Cell, RefCell},
+	collections::HashMap,
+	panic,
+	rc::Rc,
+	time::Duration,
+};
 
-use super::common::Error;
 #[cfg(feature = "image-data")]
-use super::common::ImageData;
+use super::common::{decode_image, path_from_file_url, ImageData, ImageFormatTag};
+use super::common::{
+	lossy_text, text_from_utf8, ClipboardSnapshot, Error, PlatformError, Representation,
+};
 
+/// The uniform type identifier of TIFF images.
 pub const TIFF: &str = "public.tiff";
+/// The uniform type identifier of PNG images.
+pub const PNG: &str = "public.png";
+/// The uniform type identifier of URLs pointing to local files.
 pub const FILE_URL: &str = "public.file-url";
+/// The uniform type identifier of URLs.
+pub const URL: &str = "public.url";
+/// The uniform type identifier of HTML text.
+pub const HTML: &str = "public.html";
+/// The uniform type identifier of Rich Text Format text.
+pub const RTF: &str = "public.rtf";
+/// The uniform type identifier of PDF documents.
+pub const PDF: &str = "com.adobe.pdf";
+/// The type of colors, as exchanged by color pickers. The data is an archived `NSColor`.
+pub const COLOR: &str = "com.apple.cocoa.pasteboard.color";
+/// The type that marks sensitive contents, like passwords, which clipboard history tools shouldn't
+/// record. Follows the conventions of nspasteboard.org.
+pub const CONCEALED: &str = "org.nspasteboard.ConcealedType";
+
+/// Produces the data of a type that's provided lazily, see
+/// [`Clipboard::set_lazy_data`](crate::Clipboard::set_lazy_data).
+pub type DataProvider = Box<dyn FnOnce() -> Vec<u8> + Send>;
+
+/// The UTF-16 plain text types, in the native byte order and with a byte order mark respectively.
+const UTF16_PLAIN_TEXT: &str = "public.utf16-plain-text";
+const UTF16_EXTERNAL_PLAIN_TEXT: &str = "public.utf16-external-plain-text";
+
+/// The text types that `get_text` reads the raw data of, in order, when the pasteboard can't
+/// provide a string for `NSPasteboardTypeString`.
+const FALLBACK_TEXT_TYPES: [&str; 2] = ["public.utf8-plain-text", "public.plain-text"];
+
+/// The type that some applications store their bundle identifier under, following the conventions
+/// of nspasteboard.org.
+pub(crate) const SOURCE: &str = "org.nspasteboard.source";
 
+/// A format available on the pasteboard, as returned by
+/// [`Clipboard::types`](crate::Clipboard::types).
+///
+/// The well-known uniform type identifiers are mapped to the kind of content they hold, everything
+/// else is returned as `Other` with the original type identifier.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClipboardType {
+	/// Plain text, in any of the standard encodings.
+	Text,
+	/// HTML text.
+	Html,
+	/// Rich Text Format text.
+	Rtf,
+	/// A TIFF or PNG image.
+	Image,
+	/// A URL pointing to a local file.
+	FileUrl,
+	/// A URL.
+	Url,
+	/// Any other type, identified by its UTI.
+	Other(String),
+}
+
+impl ClipboardType {
+	pub(crate) fn from_uti(uti: String) -> Self {
+		match uti.as_str() {
+			"public.utf8-plain-text"
+			| "public.plain-text"
+			| UTF16_PLAIN_TEXT
+			| UTF16_EXTERNAL_PLAIN_TEXT => ClipboardType::Text,
+			HTML => ClipboardType::Html,
+			RTF => ClipboardType::Rtf,
+			TIFF | PNG => ClipboardType::Image,
+			FILE_URL => ClipboardType::FileUrl,
+			URL => ClipboardType::Url,
+			_ => ClipboardType::Other(uti),
+		}
+	}
+}
+
+impl std::fmt::Display for ClipboardType {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			ClipboardType::Text => "Text",
+			ClipboardType::Html => "HTML",
+			ClipboardType::Rtf => "Rich Text",
+			ClipboardType::Image => "Image",
+			ClipboardType::FileUrl => "File",
+			ClipboardType::Url => "URL",
+			ClipboardType::Other(uti) => uti,
+		})
+	}
+}
+
+/// A pasteboard that a [`Clipboard`](crate::Clipboard) can be created for with
+/// [`Clipboard::new_apple_pasteboard`](crate::Clipboard::new_apple_pasteboard).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ApplePasteboardKind {
+	/// The pasteboard used for copy and paste, which `Clipboard::new` uses.
+	General,
+	/// The text of the search fields, shared by the applications that use it for Find and
+	/// Find Next.
+	Find,
+	/// The font and character information, used by Copy Font and Paste Font.
+	Font,
+	/// The paragraph formatting information, used by Copy Ruler and Paste Ruler.
+	Ruler,
+	/// The data being dragged in a drag and drop operation.
+	Drag,
+	/// A pasteboard with an application defined name, for example for exchanging data with other
+	/// processes. It's created if it doesn't exist yet.
+	Named(String),
+}
+
+/// The number of times a write to the pasteboard is attempted before giving up by default. Writes
+/// may fail transiently when another process (for example Universal Clipboard) is in the middle of
+/// modifying the pasteboard.
+const MAX_WRITE_ATTEMPTS: usize = 3;
+/// The time to wait before the first retry by default. It's doubled before every subsequent retry.
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// A handle to a pasteboard.
+///
+/// The pasteboard may be accessed from any thread, as every operation goes through the pasteboard
+/// server. But the context holds Objective-C objects that aren't synchronized, so it's neither
+/// `Send` nor `Sync`, and each thread has to create its own `Clipboard`. Every operation runs in
+/// its own autorelease pool, so that the temporary objects are freed right away even on threads
+/// that don't have a pool or a run loop.
 pub struct OSXClipboardContext {
-	pasteboard: cocoa::base::id,
+	/// The pasteboard, or `None` until it's first used if the context was created with `lazy`.
+	pasteboard: Option<Retained<NSPasteboard>>,
+	/// The pasteboard that was requested, for looking it up lazily or opening it again on another
+	/// thread.
+	kind: ApplePasteboardKind,
+	/// The types on the pasteboard, together with the `changeCount` they were read at. Reading
+	/// the count is a single message, while reading the types converts each of them.
+	type_names: Option<(NSInteger, Rc<[String]>)>,
+	/// See `ClipboardBuilder::retry`.
+	write_attempts: usize,
+	write_retry_delay: Duration,
 }
 
 impl OSXClipboardContext {
 	pub fn new() -> Result<Self, Error> {
-		let pasteboard = unsafe { cocoa::appkit::NSPasteboard::generalPasteboard(nil) };
-		Ok(OSXClipboardContext { pasteboard })
+		Self::with_pasteboard(&ApplePasteboardKind::General)
+	}
+
+	pub(crate) fn with_pasteboard(kind: &ApplePasteboardKind) -> Result<Self, Error> {
+		Self::from_pasteboard(Self::look_up(kind), kind)
+	}
+
+	/// Creates the context without looking up the pasteboard, which is done when it's first used
+	/// instead. If that fails, the method that used it returns the error.
+	pub(crate) fn lazy(kind: &ApplePasteboardKind) -> Self {
+		OSXClipboardContext {
+			pasteboard: None,
+			kind: kind.clone(),
+			type_names: None,
+			write_attempts: MAX_WRITE_ATTEMPTS,
+			write_retry_delay: WRITE_RETRY_DELAY,
+		}
+	}
+
+	fn look_up(kind: &ApplePasteboardKind) -> Option<Retained<NSPasteboard>> {
+		autoreleasepool(|_| {
+			let name = match kind {
+				ApplePasteboardKind::General => unsafe { NSPasteboardNameGeneral },
+				ApplePasteboardKind::Find => unsafe { NSPasteboardNameFind },
+				ApplePasteboardKind::Font => unsafe { NSPasteboardNameFont },
+				ApplePasteboardKind::Ruler => unsafe { NSPasteboardNameRuler },
+				ApplePasteboardKind::Drag => unsafe { NSPasteboardNameDrag },
+				ApplePasteboardKind::Named(name) => &*NSString::from_str(name),
+			};
+			// The binding assumes that a pasteboard is always returned, but there's none when the
+			// pasteboard server can't be reached, like in sandboxed helpers, in daemons without a
+			// GUI session or over SSH.
+			unsafe { msg_send_id![NSPasteboard::class(), pasteboardWithName: name] }
+		})
+	}
+
+	/// Creates the context for the pasteboard that was looked up for `kind`.
+	///
+	/// Every method sends messages to the pasteboard, so it must exist. Once it does, the context
+	/// keeps it alive, and if the pasteboard server goes away later, the messages return nothing,
+	/// which the methods report as errors.
+	pub(crate) fn from_pasteboard(
+		pasteboard: Option<Retained<NSPasteboard>>,
+		kind: &ApplePasteboardKind,
+	) -> Result<Self, Error> {
+		let pasteboard = Self::require(pasteboard, kind)?;
+		Ok(OSXClipboardContext { pasteboard: Some(pasteboard), ..Self::lazy(kind) })
+	}
+
+	fn require(
+		pasteboard: Option<Retained<NSPasteboard>>,
+		kind: &ApplePasteboardKind,
+	) -> Result<Retained<NSPasteboard>, Error> {
+		pasteboard.ok_or_else(|| {
+			warn!("Could not get the {:?} pasteboard, the pasteboard server is unreachable", kind);
+			Error::PlatformUnavailable {
+				reason: format!(
+					"the {:?} pasteboard can't be reached, check the sandbox entitlements and \
+					 whether the process runs in a GUI session",
+					kind
+				),
+			}
+		})
+	}
+
+	/// Returns the pasteboard, looking it up first if the context was created with `lazy`.
+	fn pasteboard(&mut self) -> Result<Retained<NSPasteboard>, Error> {
+		match &self.pasteboard {
+			Some(pasteboard) => Ok(Retained::clone(pasteboard)),
+			None => {
+				let pasteboard = Self::require(Self::look_up(&self.kind), &self.kind)?;
+				self.pasteboard = Some(Retained::clone(&pasteboard));
+				Ok(pasteboard)
+			}
+		}
+	}
+
+	/// The Objective-C objects can't be sent to another thread, so the pasteboard is looked up
+	/// again by its name there.
+	pub(crate) fn reopen(&self) -> Option<crate::common::Reopen<Self>> {
+		let kind = self.kind.clone();
+		let (attempts, delay) = (self.write_attempts, self.write_retry_delay);
+		Some(Box::new(move || {
+			let mut context = Self::with_pasteboard(&kind)?;
+			context.set_write_retry(attempts, delay);
+			Ok(context)
+		}))
+	}
+
+	/// Sets how often a write is attempted, and the delay before the first retry.
+	pub(crate) fn set_write_retry(&mut self, attempts: usize, delay: Duration) {
+		self.write_attempts = attempts.max(1);
+		self.write_retry_delay = delay;
 	}
 
 	pub(crate) fn get_text(&mut self) -> Result<String, Error> {
-		unsafe {
-			let pasteboard: id = self.pasteboard;
-			let contents: id = msg_send![pasteboard, stringForType: NSPasteboardTypeString];
-			if contents.is_null() {
-				Err(Error::Unknown { description: "can not get string from clipboard".to_string() })
-			} else {
-				Ok(from_nsstring(contents))
+		autoreleasepool(|_| {
+			let pasteboard = self.pasteboard()?;
+			if let Some(contents) = unsafe { pasteboard.stringForType(NSPasteboardTypeString) } {
+				return text_from(&contents);
+			}
+			// Some applications only provide the data of a text type, which the pasteboard doesn't
+			// always convert to a string. Text that isn't valid UTF-8 is only returned if none of
+			// the types holds valid text.
+			let mut invalid = None;
+			for uti in FALLBACK_TEXT_TYPES {
+				let data = unsafe { pasteboard.dataForType(&NSString::from_str(uti)) };
+				if let Some(data) = data {
+					match text_from_utf8(data.bytes().to_vec()) {
+						Ok(text) => return Ok(text),
+						Err(e) => invalid = invalid.or(Some(e)),
+					}
+				}
+			}
+			match invalid {
+				Some(e) => Err(e),
+				None => Err(self.content_not_available(unsafe { NSPasteboardTypeString })),
+			}
+		})
+	}
+
+	pub(crate) fn write_all(&mut self, representations: &[Representation]) -> Result<(), Error> {
+		autoreleasepool(|_| {
+			self.write_with_retries(|pasteboard| {
+				representations.iter().all(|representation| unsafe {
+					match representation {
+						Representation::Text(text) => pasteboard
+							.setString_forType(&NSString::from_str(text), NSPasteboardTypeString),
+						Representation::Html(html) => pasteboard.setString_forType(
+							&NSString::from_str(html),
+							&NSString::from_str(HTML),
+						),
+						Representation::Custom(uti, bytes) => pasteboard.setData_forType(
+							Some(&NSData::with_bytes(bytes)),
+							&NSString::from_str(uti),
+						),
+					}
+				})
+			})
+		})
+	}
+
+	pub(crate) fn swap_text(&mut self, data: String) -> Result<Option<String>, Error> {
+		autoreleasepool(|_| {
+			// Convert before writing, as the string may be owned by the pasteboard contents.
+			let previous = unsafe { self.pasteboard()?.stringForType(NSPasteboardTypeString) }
+				.map(|previous| text_from(&previous))
+				.transpose()?;
+			self.set_text(data.into())?;
+			Ok(previous)
+		})
+	}
+
+	pub(crate) fn get_text_bytes(&mut self, uti: &str) -> Result<Vec<u8>, Error> {
+		autoreleasepool(|_| {
+			match unsafe { self.pasteboard()?.dataForType(&NSString::from_str(uti)) } {
+				Some(data) => Ok(data.bytes().to_vec()),
+				None => Err(self.content_not_available(&NSString::from_str(uti))),
+			}
+		})
+	}
+
+	pub(crate) fn set_text(&mut self, data: Cow<'_, str>) -> Result<(), Error> {
+		self.set_text_with_generation(data).map(drop)
+	}
+
+	pub(crate) fn set_text_with_generation(&mut self, data: Cow<'_, str>) -> Result<u64, Error> {
+		autoreleasepool(|_| {
+			let nsstring = NSString::from_str(&data);
+			self.write_with_retries_counted(|pasteboard| unsafe {
+				pasteboard.setString_forType(&nsstring, NSPasteboardTypeString)
+			})
+		})
+	}
+
+	/// Clears the pasteboard and calls `write` with it, retrying with an exponential backoff
+	/// when `write` returns false. Returns `Error::ClipboardOccupied` if all the attempts failed,
+	/// since that means other processes kept modifying the pasteboard.
+	fn write_with_retries(
+		&mut self,
+		write: impl FnMut(&NSPasteboard) -> bool,
+	) -> Result<(), Error> {
+		self.write_with_retries_counted(write).map(drop)
+	}
+
+	/// Like `write_with_retries`, but returns the `changeCount` that clearing the pasteboard
+	/// resulted in. Writing the contents doesn't change it again, and reading it this way leaves
+	/// no room for another process to change the pasteboard before the count is known.
+	fn write_with_retries_counted(
+		&mut self,
+		mut write: impl FnMut(&NSPasteboard) -> bool,
+	) -> Result<u64, Error> {
+		let pasteboard = self.pasteboard()?;
+		let mut delay = self.write_retry_delay;
+		for attempt in 1..=self.write_attempts {
+			let count = unsafe { pasteboard.clearContents() };
+			if write(&pasteboard) {
+				return Ok(count as u64);
+			}
+			if attempt < self.write_attempts {
+				warn!("Writing to the pasteboard failed (attempt {}), retrying.", attempt);
+				std::thread::sleep(delay);
+				delay = delay.saturating_mul(2);
 			}
 		}
+		Err(Error::ClipboardOccupied)
 	}
 
-	pub(crate) fn set_text(&mut self, data: String) -> Result<(), Error> {
-		unsafe {
-			let nsstring = make_nsstring(data.as_str());
-			let pasteboard: id = self.pasteboard;
-			let _: NSInteger = msg_send![pasteboard, clearContents];
-			let result: BOOL =
-				msg_send![pasteboard, setString: nsstring forType: NSPasteboardTypeString];
-			if result != YES {
-				Err(Error::Unknown { description: "failed to set clipboard".to_string() })
-			} else {
+	pub(crate) fn add_data_for_type(&mut self, uti: &str, bytes: &[u8]) -> Result<(), Error> {
+		autoreleasepool(|_| {
+			let uti_string = NSString::from_str(uti);
+			let types = NSArray::from_vec(vec![NSString::from_str(uti)]);
+			let pasteboard = self.pasteboard()?;
+			let success = unsafe {
+				// Unlike `declareTypes:owner:`, this keeps the types that are already on the pasteboard.
+				pasteboard.addTypes_owner(&types, None);
+				pasteboard.setData_forType(Some(&NSData::with_bytes(bytes)), &uti_string)
+			};
+			if success {
 				Ok(())
+			} else {
+				Err(Error::ClipboardOccupied)
 			}
+		})
+	}
+
+	pub(crate) fn clear_type(&mut self, uti: &str) -> Result<(), Error> {
+		let mut snapshot = self.snapshot(None)?;
+		if snapshot.formats().all(|format| format != uti) {
+			return Ok(());
 		}
+		for warning in snapshot.warnings() {
+			warn!("Clearing `{}` drops a format that couldn't be read. {}", uti, warning);
+		}
+		for item in &mut snapshot.items {
+			item.retain(|(format, _)| format != uti);
+		}
+		// This clears the pasteboard if `uti` was the only type.
+		self.restore(&snapshot)
 	}
 
-	#[cfg(feature = "image-data")]
-	pub(crate) fn get_image(&mut self) -> Result<ImageData, Error> {
-		let available_type = available_type_names();
-		info!("available_type : {:?}", available_type);
+	pub(crate) fn set_lazy_data(
+		&mut self,
+		providers: HashMap<String, DataProvider>,
+	) -> Result<(), Error> {
+		autoreleasepool(|_| {
+			let types =
+				NSArray::from_vec(providers.keys().map(|uti| NSString::from_str(uti)).collect());
+			let provider = LazyDataProvider::new(providers);
+			let item = unsafe { NSPasteboardItem::new() };
+			if !unsafe {
+				item.setDataProvider_forTypes(ProtocolObject::from_ref(&*provider), &types)
+			} {
+				let error = PlatformError::new("setDataProvider:forTypes:").with_detail(format!(
+					"{:?}",
+					types.iter().map(string_from).collect::<Vec<_>>()
+				));
+				return Err(Error::Platform(error));
+			}
+			let objects =
+				NSArray::from_vec(vec![ProtocolObject::<dyn NSPasteboardWriting>::from_retained(
+					item,
+				)]);
+			// The provider keeps itself alive until the pasteboard is done with it, no matter how
+			// long the item lives.
+			provider.ivars().this.set(Some(Retained::clone(&provider)));
+			let result =
+				self.write_with_retries(|pasteboard| unsafe { pasteboard.writeObjects(&objects) });
+			if result.is_err() {
+				provider.ivars().this.take();
+			}
+			result
+		})
+	}
 
-		if !available_type.contains(&String::from(TIFF)) {
-			return Err(Error::Unknown { description: "probably not a picture".to_string() });
+	pub(crate) fn get_color(&mut self) -> Result<[f32; 4], Error> {
+		autoreleasepool(|_| {
+			if !self.available_type_names()?.iter().any(|uti| uti == COLOR) {
+				return Err(self.content_not_available(&NSString::from_str(COLOR)));
+			}
+			let color = unsafe { NSColor::colorFromPasteboard(&*self.pasteboard()?) }
+				.ok_or(Error::ContentNotAvailable)?;
+			// Colors in other color spaces are converted, but some, like pattern colors, can't be.
+			let color = unsafe { color.colorUsingColorSpace(&NSColorSpace::sRGBColorSpace()) }
+				.ok_or(Error::ConversionFailure)?;
+			Ok(unsafe {
+				[
+					color.redComponent() as f32,
+					color.greenComponent() as f32,
+					color.blueComponent() as f32,
+					color.alphaComponent() as f32,
+				]
+			})
+		})
+	}
+
+	pub(crate) fn set_color(&mut self, rgba: [f32; 4]) -> Result<(), Error> {
+		autoreleasepool(|_| {
+			let [red, green, blue, alpha] = rgba;
+			let color = unsafe {
+				NSColor::colorWithSRGBRed_green_blue_alpha(
+					red.into(),
+					green.into(),
+					blue.into(),
+					alpha.into(),
+				)
+			};
+			let objects =
+				NSArray::from_vec(vec![ProtocolObject::<dyn NSPasteboardWriting>::from_retained(
+					color,
+				)]);
+			self.write_with_retries(|pasteboard| unsafe { pasteboard.writeObjects(&objects) })
+		})
+	}
+
+	pub(crate) fn snapshot(
+		&mut self,
+		max_bytes: Option<usize>,
+	) -> Result<ClipboardSnapshot, Error> {
+		autoreleasepool(|_| {
+			let mut snapshot = ClipboardSnapshot::default();
+			let items = match unsafe { self.pasteboard()?.pasteboardItems() } {
+				Some(items) => items,
+				None => return Ok(snapshot),
+			};
+			for item in items.iter() {
+				snapshot.new_item();
+				for uti in unsafe { item.types() }.iter() {
+					let data = unsafe { item.dataForType(uti) };
+					let uti = string_from(uti);
+					match data {
+						Some(data) => snapshot.push(uti, data.bytes().to_vec(), max_bytes),
+						None => {
+							warn!("Could not read `{}` for the snapshot", uti);
+							snapshot.skip(&uti, "the pasteboard returned no data");
+						}
+					}
+				}
+			}
+			Ok(snapshot)
+		})
+	}
+
+	/// The pasteboard server keeps the contents.
+	pub(crate) fn persist(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+
+	pub(crate) fn restore(&mut self, snapshot: &ClipboardSnapshot) -> Result<(), Error> {
+		autoreleasepool(|_| {
+			if snapshot.is_empty() {
+				unsafe { self.pasteboard()?.clearContents() };
+				return Ok(());
+			}
+			let mut items = Vec::with_capacity(snapshot.items.len());
+			for formats in snapshot.items.iter().filter(|item| !item.is_empty()) {
+				let item = unsafe { NSPasteboardItem::new() };
+				for (uti, bytes) in formats {
+					unsafe {
+						item.setData_forType(&NSData::with_bytes(bytes), &NSString::from_str(uti))
+					};
+				}
+				items.push(ProtocolObject::<dyn NSPasteboardWriting>::from_retained(item));
+			}
+			let objects = NSArray::from_vec(items);
+			self.write_with_retries(|pasteboard| unsafe { pasteboard.writeObjects(&objects) })
+		})
+	}
+
+	pub(crate) fn generation(&mut self) -> Result<u64, Error> {
+		autoreleasepool(|_| Ok(unsafe { self.pasteboard()?.changeCount() } as u64))
+	}
+
+	pub(crate) fn count_items(&mut self) -> usize {
+		autoreleasepool(|_| {
+			let items = self
+				.pasteboard()
+				.ok()
+				.and_then(|pasteboard| unsafe { pasteboard.pasteboardItems() });
+			items.map_or(0, |items| items.count())
+		})
+	}
+
+	/// Logs which types the pasteboard has instead of the requested `uti`, as the error doesn't
+	/// carry them.
+	fn content_not_available(&mut self, uti: &NSString) -> Error {
+		debug!(
+			"`{}` isn't on the pasteboard, the available types are {:?}",
+			uti,
+			self.available_type_names().unwrap_or_else(|_| Rc::new([]))
+		);
+		Error::ContentNotAvailable
+	}
+
+	/// Returns the types on the pasteboard. They're only read again when the contents changed
+	/// since the last call.
+	fn available_type_names(&mut self) -> Result<Rc<[String]>, Error> {
+		let pasteboard = self.pasteboard()?;
+		let change_count = unsafe { pasteboard.changeCount() };
+		match &self.type_names {
+			Some((count, names)) if *count == change_count => Ok(Rc::clone(names)),
+			_ => {
+				let names: Rc<[String]> = match unsafe { pasteboard.types() } {
+					Some(types) => types.iter().map(string_from).collect(),
+					None => Rc::new([]),
+				};
+				self.type_names = Some((change_count, Rc::clone(&names)));
+				Ok(names)
+			}
 		}
+	}
+
+	pub(crate) fn available_formats(&mut self) -> Result<Vec<String>, Error> {
+		autoreleasepool(|_| Ok(self.available_type_names()?.to_vec()))
+	}
 
-		if available_type.contains(&String::from(FILE_URL)) {
-			let pb_type = make_nsstring(FILE_URL);
-			let data: id = unsafe { msg_send![self.pasteboard, dataForType: pb_type] };
-			if data.is_null() {
-				return Err(Error::Unknown { description: "can not get data".to_string() });
+	pub(crate) fn types(&mut self) -> Vec<ClipboardType> {
+		autoreleasepool(|_| {
+			let mut types: Vec<ClipboardType> = Vec::new();
+			let names = self.available_type_names().unwrap_or_else(|_| Rc::new([]));
+			for clipboard_type in names.iter().cloned().map(ClipboardType::from_uti) {
+				// Several UTIs map to the same type, e.g. an image that's offered as both TIFF and PNG.
+				if !types.contains(&clipboard_type) {
+					types.push(clipboard_type);
+				}
 			}
-			let data = from_nsdata(data);
-			let file_url = String::from_utf8_lossy(&data);
-			info!("img file url : {:?}", file_url);
+			types
+		})
+	}
+
+	pub(crate) fn is_concealed(&mut self) -> bool {
+		autoreleasepool(|_| {
+			self.available_type_names().is_ok_and(|names| names.iter().any(|uti| uti == CONCEALED))
+		})
+	}
+
+	pub(crate) fn owner_hint(&mut self) -> Option<String> {
+		autoreleasepool(|_| {
+			let pasteboard = self.pasteboard().ok()?;
+			let source = unsafe { pasteboard.stringForType(&NSString::from_str(SOURCE)) }?;
+			Some(string_from(&source)).filter(|source| !source.is_empty())
+		})
+	}
+
+	pub(crate) fn get_image_source_url(&mut self) -> Option<String> {
+		autoreleasepool(|_| {
+			let available_types = self.available_type_names().ok()?;
+			let has_image = available_types.iter().any(|uti| uti == TIFF || uti == PNG);
+			if !has_image || !available_types.iter().any(|uti| uti == URL) {
+				return None;
+			}
+			let url = unsafe { self.pasteboard().ok()?.stringForType(&NSString::from_str(URL)) }?;
+			Some(string_from(&url)).filter(|url| !url.is_empty())
+		})
+	}
+
+	#[cfg(feature = "image-data")]
+	pub(crate) fn get_image(&mut self) -> Result<ImageData<'static>, Error> {
+		self.get_image_with_format().map(|(_, image)| image)
+	}
+
+	#[cfg(feature = "image-data")]
+	pub(crate) fn get_image_with_format(
+		&mut self,
+	) -> Result<(ImageFormatTag, ImageData<'static>), Error> {
+		autoreleasepool(|_| {
+			let available_type = self.available_type_names()?;
+			trace!("available_type : {:?}", available_type);
 
-			let file_url = file_url.strip_prefix("file://");
-			if file_url.is_none() {
-				return Err(Error::Unknown { description: "file url illegal".to_string() });
+			let has_png = available_type.iter().any(|uti| uti == PNG);
+			let has_tiff = available_type.iter().any(|uti| uti == TIFF);
+			if !has_png && !has_tiff {
+				return Err(self.content_not_available(&NSString::from_str(TIFF)));
 			}
-			let decode_url = urlencoding::decode(file_url.unwrap());
-			if decode_url.is_err() {
-				return Err(Error::Unknown { description: "decode url error".to_string() });
+
+			// The file may have been moved or deleted since it was copied, while the image data is
+			// still valid. So if the file can't be read, the image data is used instead.
+			if has_tiff && available_type.iter().any(|uti| uti == FILE_URL) {
+				match self.image_from_file_url() {
+					Ok(image) => return Ok((ImageFormatTag::FileUrl, image)),
+					Err(e) => {
+						warn!(
+							"Could not read the image from the file URL, using the image data: {}",
+							e
+						)
+					}
+				}
 			}
 
-			// TODO deal unwrap
-			let dyna_img = match image::io::Reader::open(decode_url.unwrap().into_owned())
-				.unwrap()
-				.with_guessed_format()
-				.unwrap()
-				.decode()
+			// PNG is preferred, since the TIFF that AppKit writes may have premultiplied alpha.
+			let (uti, tag) =
+				if has_png { (PNG, ImageFormatTag::Png) } else { (TIFF, ImageFormatTag::Tiff) };
+			let data = unsafe { self.pasteboard()?.dataForType(&NSString::from_str(uti)) }
+				.ok_or(Error::ContentNotAvailable)?;
+			Ok((tag, decode_image(tag, data.bytes())?))
+		})
+	}
+
+	/// Returns every frame of an animated image on the pasteboard. Only a GIF file that
+	/// `public.file-url` points to can have more than one frame, any other image is returned as
+	/// the single frame that `get_image` returns.
+	#[cfg(feature = "image-data")]
+	pub(crate) fn get_image_frames(&mut self) -> Result<Vec<ImageData<'static>>, Error> {
+		autoreleasepool(|_| {
+			let available_type = self.available_type_names()?;
+			if available_type.iter().any(|uti| uti == TIFF)
+				&& available_type.iter().any(|uti| uti == FILE_URL)
 			{
-				Ok(img) => img,
-				Err(e) => {
-					error!("open img error: {:?}", e);
-					return Err(Error::Unknown { description: "open img error".to_string() });
+				match self.frames_from_file_url(usize::MAX) {
+					Ok(frames) => return Ok(frames),
+					Err(e) => {
+						warn!(
+							"Could not read the frames from the file URL, using the TIFF data: {}",
+							e
+						)
+					}
 				}
-			};
-			return deal_dynamic_image(dyna_img);
-		}
+			}
+			self.get_image().map(|image| vec![image])
+		})
+	}
 
-		let pb_type = make_nsstring(TIFF);
-		let data: id = unsafe { msg_send![self.pasteboard, dataForType: pb_type] };
-		if data.is_null() {
-			return Err(Error::Unknown { description: "can not get data".to_string() });
-		}
-		let data = from_nsdata(data);
-		let reader =
-			image::io::Reader::with_format(std::io::Cursor::new(data), image::ImageFormat::Tiff);
-		return match reader.decode() {
-			Ok(img) => deal_dynamic_image(img),
-			Err(_) => Err(Error::ConversionFailure),
-		};
+	/// Decodes the image file that `public.file-url` points to.
+	#[cfg(feature = "image-data")]
+	fn image_from_file_url(&mut self) -> Result<ImageData<'static>, Error> {
+		let mut frames = self.frames_from_file_url(1)?;
+		Ok(frames.remove(0))
+	}
+
+	/// Decodes at most `max_frames` frames of the image file that `public.file-url` points to.
+	#[cfg(feature = "image-data")]
+	fn frames_from_file_url(
+		&mut self,
+		max_frames: usize,
+	) -> Result<Vec<ImageData<'static>>, Error> {
+		let data = unsafe { self.pasteboard()?.dataForType(&NSString::from_str(FILE_URL)) }
+			.ok_or(Error::ContentNotAvailable)?;
+		let file_url = String::from_utf8_lossy(data.bytes());
+		trace!("img file url : {:?}", file_url);
+
+		let path = path_from_file_url(&file_url).ok_or(Error::ConversionFailure)?;
+		frames_from_file(&path, max_frames)
 	}
 
 	#[cfg(feature = "image-data")]
 	pub(crate) fn set_image(&mut self, data: ImageData) -> Result<(), Error> {
-		use objc_foundation::INSArray;
-		let pixels = data.bytes.into();
-		let image = image_from_pixels(pixels, data.width, data.height)
-			.map_err(|_| Error::ConversionFailure)?;
-		let objects: objc_id::Id<
-			objc_foundation::NSArray<objc_foundation::NSObject, objc_id::Owned>,
-		> = objc_foundation::NSArray::from_vec(vec![image]);
-		let _: usize = unsafe { msg_send![self.pasteboard, clearContents] };
-		let success: BOOL = unsafe { msg_send![self.pasteboard, writeObjects: objects] };
-		if success == objc::runtime::NO {
-			return Err(Error::Unknown {
-				description:
-					"Failed to write the image to the pasteboard (`writeObjects` returned NO)."
-						.into(),
-			});
+		self.set_image_with_generation(data).map(drop)
+	}
+
+	#[cfg(feature = "image-data")]
+	pub(crate) fn set_image_with_generation(&mut self, data: ImageData) -> Result<u64, Error> {
+		autoreleasepool(|_| {
+			// AppKit may premultiply the alpha of the TIFF that it writes for the `NSImage`, which
+			// loses the colors of transparent pixels. The PNG keeps the pixels exactly as they are,
+			// and is preferred by `get_image` and most other applications.
+			let mut png = Vec::new();
+			image::png::PngEncoder::new(&mut png)
+				.encode(&data.bytes, data.width as u32, data.height as u32, image::ColorType::Rgba8)
+				.map_err(|_| Error::ConversionFailure)?;
+			let png = NSData::with_bytes(&png);
+			let png_type = NSString::from_str(PNG);
+			let png_types = NSArray::from_vec(vec![NSString::from_str(PNG)]);
+			let image = make_nsimage(data.bytes.into(), data.width, data.height)?;
+			let objects =
+				NSArray::from_vec(vec![ProtocolObject::<dyn NSPasteboardWriting>::from_retained(
+					image,
+				)]);
+			self.write_with_retries_counted(|pasteboard| unsafe {
+				pasteboard.writeObjects(&objects) && {
+					pasteboard.addTypes_owner(&png_types, None);
+					pasteboard.setData_forType(Some(&png), &png_type)
+				}
+			})
+		})
+	}
+}
+
+/// The instance variables of a [`LazyDataProvider`].
+struct LazyDataProviderIvars {
+	/// The providers of the types that weren't requested yet.
+	providers: RefCell<HashMap<String, DataProvider>>,
+	/// The provider itself, until the pasteboard is finished with it.
+	this: Cell<Option<Retained<LazyDataProvider>>>,
+}
+
+declare_class!(
+	/// Provides the data of a pasteboard item when another application requests it.
+	///
+	/// The pasteboard calls the methods through the main run loop.
+	struct LazyDataProvider;
+
+	// SAFETY:
+	// - The superclass NSObject does not have any subclassing requirements.
+	// - The ivars are only accessed through shared references, with interior mutability.
+	// - `LazyDataProvider` does not implement `Drop`.
+	unsafe impl ClassType for LazyDataProvider {
+		type Super = NSObject;
+		type Mutability = mutability::InteriorMutable;
+		const NAME: &'static str = "ArboardLazyDataProvider";
+	}
+
+	impl DeclaredClass for LazyDataProvider {
+		type Ivars = LazyDataProviderIvars;
+	}
+
+	unsafe impl NSObjectProtocol for LazyDataProvider {}
+
+	unsafe impl NSPasteboardItemDataProvider for LazyDataProvider {
+		#[method(pasteboard:item:provideDataForType:)]
+		unsafe fn provide_data_for_type(
+			&self,
+			_pasteboard: Option<&NSPasteboard>,
+			item: &NSPasteboardItem,
+			r#type: &NSPasteboardType,
+		) {
+			let provider = self.ivars().providers.borrow_mut().remove(&string_from(r#type));
+			// The provider is called without the map borrowed, so that it can't observe it. A
+			// panic can't unwind into the caller, so the type is left without data instead.
+			if let Some(provider) = provider {
+				if let Ok(bytes) = panic::catch_unwind(panic::AssertUnwindSafe(provider)) {
+					item.setData_forType(&NSData::with_bytes(&bytes), r#type);
+				}
+			}
 		}
-		Ok(())
+
+		#[method(pasteboardFinishedWithDataProvider:)]
+		unsafe fn finished_with_data_provider(&self, _pasteboard: &NSPasteboard) {
+			self.ivars().providers.borrow_mut().clear();
+			// The pasteboard still holds a reference while calling this method.
+			drop(self.ivars().this.take());
+		}
+	}
+);
+
+impl LazyDataProvider {
+	fn new(providers: HashMap<String, DataProvider>) -> Retained<Self> {
+		let this = Self::alloc().set_ivars(LazyDataProviderIvars {
+			providers: RefCell::new(providers),
+			this: Cell::new(None),
+		});
+		unsafe { msg_send_id![super(this), init] }
 	}
 }
 
+/// Decodes at most `max_frames` frames of the image file at `path`.
+///
+/// Animated GIFs are decoded frame by frame, so that which frames are returned doesn't depend on
+/// how `image` decodes them otherwise. Each frame is the whole canvas as it's displayed at that
+/// point of the animation. Other files are decoded as a single frame.
+#[cfg(feature = "image-data")]
+pub(crate) fn frames_from_file(
+	path: &str,
+	max_frames: usize,
+) -> Result<Vec<ImageData<'static>>, Error> {
+	let reader = image::io::Reader::open(path)
+		.and_then(|reader| reader.with_guessed_format())
+		.map_err(|e| {
+			let error = PlatformError::new("open").with_detail(path);
+			Error::Platform(error.with_source(e))
+		})?;
+	let conversion_failure = |e: image::ImageError| {
+		error!("open img error: {:?}", e);
+		Error::ConversionFailure
+	};
+	if reader.format() != Some(image::ImageFormat::Gif) {
+		let dyna_img = reader.decode().map_err(conversion_failure)?;
+		return Ok(vec![deal_dynamic_image(dyna_img)?]);
+	}
+
+	let decoder = GifDecoder::new(reader.into_inner()).map_err(conversion_failure)?;
+	let frames = decoder
+		.into_frames()
+		.take(max_frames)
+		.map(|frame| {
+			let frame = frame.map_err(conversion_failure)?;
+			deal_dynamic_image(DynamicImage::ImageRgba8(frame.into_buffer()))
+		})
+		.collect::<Result<Vec<_>, Error>>()?;
+	if frames.is_empty() {
+		return Err(Error::ConversionFailure);
+	}
+	Ok(frames)
+}
+
+#[cfg(feature = "image-data")]
 fn deal_dynamic_image(dyna_img: DynamicImage) -> Result<ImageData<'static>, Error> {
 	let rgba = dyna_img.into_rgba8();
 	let (w, h) = rgba.dimensions();
 	let img = ImageData { width: w as usize, height: h as usize, bytes: rgba.into_raw().into() };
-	info!(
+	trace!(
 		"img: width: {:?}; height: {:?};  bytes len: {:?}",
 		img.width,
 		img.height,
@@ -150,38 +883,52 @@ fn deal_dynamic_image(dyna_img: DynamicImage) -> Result<ImageData<'static>, Erro
 	Ok(img)
 }
 
-/// Returns an NSImage object on success.
+/// A `CGImageRef`, encoded as a pointer to the opaque `CGImage` struct so that the message send
+/// to `initWithCGImage:size:` can be verified.
+#[cfg(feature = "image-data")]
+#[repr(transparent)]
+struct CGImageRef(*const std::ffi::c_void);
+
+#[cfg(feature = "image-data")]
+unsafe impl Encode for CGImageRef {
+	const ENCODING: Encoding = Encoding::Pointer(&Encoding::Struct("CGImage", &[]));
+}
+
+/// Creates an `NSImage` from RGBA pixels, the same way `Clipboard::set_image` does before placing
+/// the image onto the pasteboard. This is useful for images that are used elsewhere, like the
+/// icon of the application or the image of a drag.
+///
+/// `pixels` holds the rows of the image from top to bottom, with four bytes per pixel and alpha
+/// that isn't premultiplied, like [`ImageData`]. Returns `Error::ConversionFailure` if its length
+/// doesn't match `width` and `height`, or if the image couldn't be created.
+///
+/// # Examples
+///
+/// ```no_run
+/// let red = [255, 0, 0, 255].repeat(16 * 16);
+/// let image = arboard::make_nsimage(red, 16, 16).unwrap();
+/// ```
 #[cfg(feature = "image-data")]
-fn image_from_pixels(
+pub fn make_nsimage(
 	pixels: Vec<u8>,
 	width: usize,
 	height: usize,
-) -> Result<objc_id::Id<objc_foundation::NSObject>, Box<dyn std::error::Error>> {
-	#[repr(C)]
-	#[derive(Copy, Clone)]
-	pub struct NSSize {
-		pub width: CGFloat,
-		pub height: CGFloat,
-	}
-
-	#[derive(Debug, Clone)]
-	struct PixelArray {
-		data: Vec<u8>,
-	}
-
-	impl CustomData for PixelArray {
-		unsafe fn ptr(&self) -> *const u8 {
-			self.data.as_ptr()
-		}
-		unsafe fn len(&self) -> usize {
-			self.data.len()
-		}
+) -> Result<Retained<NSImage>, Error> {
+	let expected = width.checked_mul(height).and_then(|count| count.checked_mul(4));
+	if width == 0 || height == 0 || expected != Some(pixels.len()) {
+		warn!("Can't create a {}x{} image from {} bytes of pixels", width, height, pixels.len());
+		return Err(Error::ConversionFailure);
 	}
+	image_from_pixels(pixels, width, height).ok_or(Error::ConversionFailure)
+}
 
+/// Returns `None` if the image couldn't be created.
+#[cfg(feature = "image-data")]
+fn image_from_pixels(pixels: Vec<u8>, width: usize, height: usize) -> Option<Retained<NSImage>> {
 	let colorspace = CGColorSpace::create_device_rgb();
 	let bitmap_info: u32 = kCGBitmapByteOrderDefault | kCGImageAlphaLast;
-	let pixel_data: Box<Box<dyn CustomData>> = Box::new(Box::new(PixelArray { data: pixels }));
-	let provider = unsafe { CGDataProvider::from_custom_data(pixel_data) };
+	// The provider takes ownership of the pixels, and releases them once the image is destroyed.
+	let provider = CGDataProvider::from_buffer(std::sync::Arc::new(pixels));
 	let rendering_intent = kCGRenderingIntentDefault;
 	let cg_image = CGImage::new(
 		width,
@@ -195,47 +942,163 @@ fn image_from_pixels(
 		false,
 		rendering_intent,
 	);
-	let size = NSSize { width: width as CGFloat, height: height as CGFloat };
-	let nsimage_class = objc::runtime::Class::get("NSImage").ok_or("Class::get(\"NSImage\")")?;
-	let image: objc_id::Id<objc_foundation::NSObject> =
-		unsafe { objc_id::Id::from_ptr(msg_send![nsimage_class, alloc]) };
-	let () = unsafe { msg_send![image, initWithCGImage:cg_image size:size] };
-	Ok(image)
+	let size = NSSize::new(width as CGFloat, height as CGFloat);
+	let cg_image_ref = CGImageRef(cg_image.as_ptr() as *const _);
+	// The image retains the `CGImage`.
+	unsafe { msg_send_id![NSImage::alloc(), initWithCGImage: cg_image_ref, size: size] }
+}
+
+/// Converts the string to UTF-8 without trusting the pasteboard to hand out valid UTF-8. Strings
+/// with unpaired surrogates have no UTF-8 representation, for those the error holds the text with
+/// the surrogates replaced.
+pub(crate) fn text_from(s: &NSString) -> Result<String, Error> {
+	// The length is exact, and 0 if the string can't be converted. Taking it from the string
+	// keeps text with null characters intact.
+	let length = s.lengthOfBytesUsingEncoding(NSUTF8StringEncoding);
+	let range = NSRange::new(0, s.length());
+	if range.length == 0 {
+		return Ok(String::new());
+	}
+	// The bytes are converted straight into the memory of the `String`, instead of into a
+	// temporary C string that would be copied afterwards.
+	let mut bytes = Vec::<u8>::with_capacity(length);
+	let (mut used, mut remaining) = (0, NSRange::new(0, 0));
+	let converted = length > 0
+		&& unsafe {
+			s.getBytes_maxLength_usedLength_encoding_options_range_remainingRange(
+				bytes.as_mut_ptr().cast(),
+				length,
+				&mut used,
+				NSUTF8StringEncoding,
+				NSStringEncodingConversionOptions(0),
+				range,
+				&mut remaining,
+			)
+		};
+	if !converted || remaining.length != 0 {
+		let units: Vec<u16> = (0..s.length()).map(|i| unsafe { s.characterAtIndex(i) }).collect();
+		return Err(Error::InvalidUtf8 { bytes: String::from_utf16_lossy(&units).into_bytes() });
+	}
+	// SAFETY: `getBytes` wrote `used` bytes, which is at most the capacity.
+	unsafe { bytes.set_len(used.min(length)) };
+	text_from_utf8(bytes)
 }
 
-fn make_nsstring(s: &str) -> id {
-	use cocoa::foundation::NSAutoreleasePool;
-	unsafe { NSString::alloc(nil).init_str(s).autorelease() }
+/// Converts the string to UTF-8, replacing the parts that have no UTF-8 representation.
+fn string_from(s: &NSString) -> String {
+	lossy_text(text_from(s)).unwrap_or_default()
 }
 
-fn from_nsdata(data: id) -> Vec<u8> {
-	unsafe {
-		let len: cocoa::foundation::NSUInteger = msg_send![data, length];
-		let bytes: *const std::ffi::c_void = msg_send![data, bytes];
-		let mut out: Vec<u8> = Vec::with_capacity(len as usize);
-		std::ptr::copy_nonoverlapping(bytes as *const u8, out.as_mut_ptr(), len as usize);
-		out.set_len(len as usize);
-		out
-	}
+/// Returns the encodings of `text` that are written next to `NSPasteboardTypeString` by
+/// [`Clipboard::set_text_multi`](crate::Clipboard::set_text_multi), for applications that only
+/// read the UTF-16 types.
+pub(crate) fn alternate_text_encodings(text: &str) -> Vec<(&'static str, Vec<u8>)> {
+	let utf16 = text.encode_utf16().flat_map(u16::to_ne_bytes);
+	// The external representation starts with a byte order mark.
+	let external = std::iter::once(0xFEFF).chain(text.encode_utf16()).flat_map(u16::to_ne_bytes);
+	vec![(UTF16_PLAIN_TEXT, utf16.collect()), (UTF16_EXTERNAL_PLAIN_TEXT, external.collect())]
 }
 
-fn from_nsstring(s: id) -> String {
-	unsafe {
-		let slice = std::slice::from_raw_parts(s.UTF8String() as *const _, s.len());
-		let result = std::str::from_utf8_unchecked(slice);
-		result.into()
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::Clipboard;
+
+	#[test]
+	fn osx_text_conversion() {
+		use std::ptr::NonNull;
+
+		let text = NSString::from_str("a\0b");
+		assert_eq!(text_from(&text).unwrap(), "a\0b");
+		let mut units = [u16::from(b'a'), 0xD800, u16::from(b'b')];
+		let text = unsafe {
+			NSString::stringWithCharacters_length(NonNull::new(units.as_mut_ptr()).unwrap(), 3)
+		};
+		assert!(
+			matches!(text_from(&text), Err(Error::InvalidUtf8 { bytes }) if bytes == "a\u{FFFD}b".as_bytes())
+		);
 	}
-}
 
-fn available_type_names() -> Vec<String> {
-	use cocoa::foundation::NSArray;
-	let res = unsafe {
-		let pasteboard = cocoa::appkit::NSPasteboard::generalPasteboard(nil);
-		let types: id = msg_send![pasteboard, types];
-		let types_len = types.count() as usize;
-		(0..types_len)
-			.map(|i| from_nsstring(types.objectAtIndex(i as cocoa::foundation::NSUInteger)))
-			.collect()
-	};
-	res
+	#[test]
+	fn osx_missing_pasteboard() {
+		let kind = ApplePasteboardKind::Named("arboard.test".into());
+		let result = OSXClipboardContext::from_pasteboard(None, &kind);
+		assert!(
+			matches!(result, Err(Error::PlatformUnavailable { reason }) if reason.contains("arboard.test"))
+		);
+	}
+
+	/// Reads and writes a named pasteboard many times from a thread without a run loop, whose
+	/// autoreleased objects are only reclaimed by the pools of arboard. Slow, so it's ignored by
+	/// default: run it with `cargo test osx_autorelease_soak -- --ignored`.
+	#[test]
+	#[ignore]
+	fn osx_autorelease_soak() {
+		/// The resident memory of the process in KiB, as reported by `ps`.
+		fn resident_kib() -> u64 {
+			let output = std::process::Command::new("ps")
+				.args(["-o", "rss=", "-p", &std::process::id().to_string()])
+				.output()
+				.unwrap();
+			String::from_utf8(output.stdout).unwrap().trim().parse().unwrap()
+		}
+
+		std::thread::spawn(|| {
+			let kind = ApplePasteboardKind::Named("rs.arboard.soak".into());
+			let mut ctx = Clipboard::new_apple_pasteboard(kind).unwrap();
+			// Large enough that leaking the objects of every call would add hundreds of MiB.
+			let text = "soak ".repeat(400);
+			let mut round_trip = |count: usize| {
+				for _ in 0..count {
+					ctx.set_text(text.as_str()).unwrap();
+					assert_eq!(ctx.get_text().unwrap().len(), text.len());
+				}
+			};
+			round_trip(1_000);
+			let before = resident_kib();
+			round_trip(100_000);
+			let grown = resident_kib().saturating_sub(before);
+			assert!(grown < 32 * 1024, "the resident memory grew by {} KiB", grown);
+		})
+		.join()
+		.unwrap();
+	}
+
+	#[cfg(feature = "image-data")]
+	#[test]
+	fn osx_gif_frames() {
+		use image::{codecs::gif::GifEncoder, Frame, Rgba, RgbaImage};
+
+		let path = std::env::temp_dir().join(format!("arboard-frames-{}.gif", std::process::id()));
+		let colors = [[255, 0, 0, 255], [0, 0, 255, 255]];
+		{
+			let file = std::fs::File::create(&path).unwrap();
+			let frames =
+				colors.iter().map(|&color| Frame::new(RgbaImage::from_pixel(2, 2, Rgba(color))));
+			GifEncoder::new(file).encode_frames(frames).unwrap();
+		}
+		let path_str = path.to_str().unwrap();
+		let frames = frames_from_file(path_str, usize::MAX).unwrap();
+		let first = frames_from_file(path_str, 1).unwrap();
+		std::fs::remove_file(&path).unwrap();
+
+		assert_eq!(frames.len(), 2);
+		for (frame, color) in frames.iter().zip(&colors) {
+			assert_eq!((frame.width, frame.height), (2, 2));
+			assert!(frame.bytes.chunks(4).all(|pixel| pixel == color));
+		}
+		assert_eq!(first.len(), 1);
+		assert_eq!(first[0].bytes, frames[0].bytes);
+	}
+
+	#[cfg(feature = "image-data")]
+	#[test]
+	fn osx_make_nsimage() {
+		let image = make_nsimage(vec![255; 3 * 2 * 4], 3, 2).unwrap();
+		let size = unsafe { image.size() };
+		assert_eq!((size.width, size.height), (3.0, 2.0));
+		assert!(matches!(make_nsimage(vec![255; 5], 3, 2), Err(Error::ConversionFailure)));
+		assert!(matches!(make_nsimage(Vec::new(), 0, 0), Err(Error::ConversionFailure)));
+		assert!(matches!(make_nsimage(vec![0; 4], usize::MAX, 2), Err(Error::ConversionFailure)));
+	}
 }
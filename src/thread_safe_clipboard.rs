@@ -0,0 +1,283 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2020 The arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+use std::sync::{Mutex, PoisonError};
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+use std::{
+	panic::{self, AssertUnwindSafe},
+	sync::mpsc,
+	thread,
+};
+
+#[cfg(feature = "image-data")]
+use crate::ImageData;
+use crate::{Clipboard, ClipboardSnapshot, Error};
+
+/// A clipboard that can be shared between threads, for example through an `Arc` in a server that
+/// handles requests on several threads.
+///
+/// The operations are serialized, so only one of them runs at a time and each of them sees the
+/// results of the ones before it. Where a [`Clipboard`] is `Send`, it's kept behind a mutex. On
+/// macOS and iOS, where it isn't, the clipboard is created on a thread of its own, and the
+/// operations are sent there and run one after the other.
+///
+/// The most common operations are available directly, and [`with_clipboard`] runs any other
+/// operation of `Clipboard`.
+///
+/// [`with_clipboard`]: ThreadSafeClipboard::with_clipboard
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::{sync::Arc, thread};
+/// use arboard::ThreadSafeClipboard;
+///
+/// let clipboard = Arc::new(ThreadSafeClipboard::new().unwrap());
+/// let writer = {
+///     let clipboard = Arc::clone(&clipboard);
+///     thread::spawn(move || clipboard.set_text("Hello, world!".into()))
+/// };
+/// writer.join().unwrap().unwrap();
+/// assert_eq!(clipboard.get_text().unwrap(), "Hello, world!");
+/// ```
+pub struct ThreadSafeClipboard {
+	#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+	clipboard: Mutex<Clipboard>,
+	/// Where the operations are sent to the thread that owns the clipboard. Dropping it stops the
+	/// thread.
+	#[cfg(any(target_os = "macos", target_os = "ios"))]
+	jobs: Option<Mutex<mpsc::Sender<Job>>>,
+	#[cfg(any(target_os = "macos", target_os = "ios"))]
+	thread: Option<thread::JoinHandle<()>>,
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+type Job = Box<dyn FnOnce(&mut Clipboard) + Send>;
+
+impl ThreadSafeClipboard {
+	/// Creates the clipboard like [`Clipboard::new`] does.
+	pub fn new() -> Result<Self, Error> {
+		Self::new_with(Clipboard::new)
+	}
+
+	/// Creates the clipboard that `create` returns, for example one of
+	/// [`Clipboard::builder`](crate::Clipboard::builder). On macOS and iOS, `create` runs on the
+	/// thread that owns the clipboard.
+	#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+	pub fn new_with(
+		create: impl FnOnce() -> Result<Clipboard, Error> + Send + 'static,
+	) -> Result<Self, Error> {
+		Ok(ThreadSafeClipboard { clipboard: Mutex::new(create()?) })
+	}
+
+	/// Creates the clipboard that `create` returns, for example one of
+	/// [`Clipboard::builder`](crate::Clipboard::builder). On macOS and iOS, `create` runs on the
+	/// thread that owns the clipboard.
+	#[cfg(any(target_os = "macos", target_os = "ios"))]
+	pub fn new_with(
+		create: impl FnOnce() -> Result<Clipboard, Error> + Send + 'static,
+	) -> Result<Self, Error> {
+		let (jobs, receiver) = mpsc::channel::<Job>();
+		let (init_sender, init_receiver) = mpsc::channel();
+		let spawned = thread::Builder::new().name("arboard-clipboard".into()).spawn(move || {
+			let mut clipboard = match create() {
+				Ok(clipboard) => {
+					let _ = init_sender.send(Ok(()));
+					clipboard
+				}
+				Err(e) => {
+					let _ = init_sender.send(Err(e));
+					return;
+				}
+			};
+			for job in receiver {
+				job(&mut clipboard);
+			}
+		});
+		let thread = spawned.map_err(|e| Error::Unknown {
+			description: format!("couldn't start a thread for the clipboard: {}", e),
+		})?;
+		match init_receiver.recv() {
+			Ok(Ok(())) => {
+				Ok(ThreadSafeClipboard { jobs: Some(Mutex::new(jobs)), thread: Some(thread) })
+			}
+			Ok(Err(e)) => Err(e),
+			Err(_) => Err(Error::Unknown {
+				description: "the clipboard thread panicked while creating the clipboard".into(),
+			}),
+		}
+	}
+
+	/// Runs `f` with exclusive access to the clipboard, and returns its result.
+	///
+	/// If `f` panics, the panic is resumed on the calling thread, and the clipboard stays usable.
+	#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+	pub fn with_clipboard<R: Send + 'static>(
+		&self,
+		f: impl FnOnce(&mut Clipboard) -> R + Send + 'static,
+	) -> R {
+		let mut clipboard = self.clipboard.lock().unwrap_or_else(PoisonError::into_inner);
+		f(&mut clipboard)
+	}
+
+	/// Runs `f` with exclusive access to the clipboard, and returns its result.
+	///
+	/// If `f` panics, the panic is resumed on the calling thread, and the clipboard stays usable.
+	#[cfg(any(target_os = "macos", target_os = "ios"))]
+	pub fn with_clipboard<R: Send + 'static>(
+		&self,
+		f: impl FnOnce(&mut Clipboard) -> R + Send + 'static,
+	) -> R {
+		let (reply, result) = mpsc::channel();
+		let job: Job = Box::new(move |clipboard| {
+			let _ = reply.send(panic::catch_unwind(AssertUnwindSafe(|| f(clipboard))));
+		});
+		// The thread only stops once `jobs` is dropped, and it catches the panics of the jobs, so
+		// it always replies.
+		let jobs = self.jobs.as_ref().expect("the clipboard thread was stopped");
+		jobs.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.send(job)
+			.expect("the clipboard thread stopped");
+		match result.recv().expect("the clipboard thread stopped") {
+			Ok(result) => result,
+			Err(payload) => panic::resume_unwind(payload),
+		}
+	}
+
+	/// Fetches utf-8 text from the clipboard, see [`Clipboard::get_text`].
+	pub fn get_text(&self) -> Result<String, Error> {
+		self.with_clipboard(|clipboard| clipboard.get_text())
+	}
+
+	/// Fetches text from the clipboard, replacing invalid utf-8, see [`Clipboard::get_text_lossy`].
+	pub fn get_text_lossy(&self) -> Result<String, Error> {
+		self.with_clipboard(|clipboard| clipboard.get_text_lossy())
+	}
+
+	/// Places the text onto the clipboard, see [`Clipboard::set_text`].
+	pub fn set_text(&self, text: String) -> Result<(), Error> {
+		self.with_clipboard(move |clipboard| clipboard.set_text(text))
+	}
+
+	/// Replaces the text on the clipboard and returns the previous text, see
+	/// [`Clipboard::swap_text`]. No other operation runs in between.
+	pub fn swap_text(&self, text: String) -> Result<Option<String>, Error> {
+		self.with_clipboard(move |clipboard| clipboard.swap_text(text))
+	}
+
+	/// Clears the clipboard, see [`Clipboard::clear`].
+	pub fn clear(&self) -> Result<(), Error> {
+		self.with_clipboard(|clipboard| clipboard.clear())
+	}
+
+	/// Returns the formats on the clipboard, see [`Clipboard::available_formats`].
+	pub fn available_formats(&self) -> Result<Vec<String>, Error> {
+		self.with_clipboard(|clipboard| clipboard.available_formats())
+	}
+
+	/// Returns a number that changes whenever the clipboard changes, see
+	/// [`Clipboard::generation`].
+	pub fn generation(&self) -> Result<u64, Error> {
+		self.with_clipboard(|clipboard| clipboard.generation())
+	}
+
+	/// Copies the contents of the clipboard, see [`Clipboard::snapshot`].
+	pub fn snapshot(&self) -> Result<ClipboardSnapshot, Error> {
+		self.with_clipboard(|clipboard| clipboard.snapshot())
+	}
+
+	/// Places the contents of `snapshot` back onto the clipboard, see [`Clipboard::restore`].
+	pub fn restore(&self, snapshot: &ClipboardSnapshot) -> Result<(), Error> {
+		let snapshot = snapshot.clone();
+		self.with_clipboard(move |clipboard| clipboard.restore(&snapshot))
+	}
+
+	/// Fetches image data from the clipboard, see [`Clipboard::get_image`].
+	#[cfg(feature = "image-data")]
+	pub fn get_image(&self) -> Result<ImageData<'static>, Error> {
+		self.with_clipboard(|clipboard| clipboard.get_image())
+	}
+
+	/// Places the image onto the clipboard, see [`Clipboard::set_image`]. Borrowed pixels are
+	/// copied first.
+	#[cfg(feature = "image-data")]
+	pub fn set_image(&self, image: ImageData) -> Result<(), Error> {
+		let image = image.to_owned_img();
+		self.with_clipboard(move |clipboard| clipboard.set_image(image))
+	}
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+impl Drop for ThreadSafeClipboard {
+	fn drop(&mut self) {
+		// Waits for the clipboard to be dropped on its thread, as the clipboards have to be dropped
+		// before the program exits.
+		drop(self.jobs.take());
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	#[cfg(feature = "test-util")]
+	use crate::MockClipboard;
+
+	#[test]
+	fn thread_safe_clipboard_is_send_and_sync() {
+		fn assert_send_sync<T: Send + Sync>() {}
+		assert_send_sync::<ThreadSafeClipboard>();
+	}
+
+	#[cfg(feature = "test-util")]
+	#[test]
+	fn thread_safe_clipboard_mock() {
+		use std::{sync::Arc, thread};
+
+		let mut mock = MockClipboard::new();
+		let clipboard = {
+			let mock = mock.clone();
+			Arc::new(ThreadSafeClipboard::new_with(move || Ok(Clipboard::from_mock(mock))).unwrap())
+		};
+		let threads: Vec<_> = (0..8)
+			.map(|i| {
+				let clipboard = Arc::clone(&clipboard);
+				thread::spawn(move || {
+					for j in 0..50 {
+						let text = format!("{} {}", i, j);
+						clipboard.set_text(text.clone()).unwrap();
+						let read = clipboard.get_text().unwrap();
+						assert_eq!(read.split(' ').count(), 2, "torn text {:?}", read);
+						let swapped = clipboard.with_clipboard(move |clipboard| {
+							clipboard.set_text(text.clone())?;
+							clipboard.get_text().map(|read| read == text)
+						});
+						assert!(swapped.unwrap());
+					}
+				})
+			})
+			.collect();
+		for thread in threads {
+			thread.join().unwrap();
+		}
+		assert_eq!(clipboard.generation().unwrap(), 800);
+
+		// A panicking operation doesn't make the clipboard unusable for the other threads.
+		let panicking = Arc::clone(&clipboard);
+		assert!(thread::spawn(move || panicking.with_clipboard(|_| panic!("oops")))
+			.join()
+			.is_err());
+		clipboard.set_text("after".into()).unwrap();
+		assert_eq!(mock.get_text().unwrap(), "after");
+	}
+}
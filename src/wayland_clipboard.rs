@@ -0,0 +1,890 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2020 The arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+// More info about the clipboard in the core Wayland protocol:
+// https://wayland.freedesktop.org/docs/html/ch04.html#sect-Protocol-data-sharing
+//
+// The compositor only sends the selection to, and only accepts a new selection from, the client
+// that has keyboard focus. That's why this backend is meant to work on the connection of the
+// application, and why it keeps track of the input events of every seat. On a connection of its
+// own, it never has focus, so it can only be used with compositors that are lenient about that.
+//
+// The PRIMARY selection is provided by the `zwp_primary_selection_device_manager_v1` protocol,
+// which works the same way as the core protocol but is optional:
+// https://gitlab.freedesktop.org/wayland/wayland-protocols/-/blob/main/unstable/primary-selection/primary-selection-unstable-v1.xml
+
+use std::{
+	cell::RefCell,
+	convert::TryFrom,
+	ffi::c_void,
+	fs::File,
+	io::{self, Read, Write},
+	mem,
+	os::unix::io::{AsRawFd, FromRawFd, RawFd},
+	rc::Rc,
+	sync::{mpsc, Arc},
+	thread::{self, JoinHandle},
+};
+
+use os_pipe::{PipeReader, PipeWriter};
+use wayland_client::{
+	global_filter,
+	protocol::{
+		wl_data_device::{self, WlDataDevice},
+		wl_data_device_manager::WlDataDeviceManager,
+		wl_data_offer::{self, WlDataOffer},
+		wl_data_source::{self, WlDataSource},
+		wl_keyboard::{self, WlKeyboard},
+		wl_pointer::{self, WlPointer},
+		wl_seat::{self, WlSeat},
+	},
+	sys::client::wl_display,
+	Display, EventQueue, GlobalManager, Main, UserData,
+};
+use wayland_protocols::unstable::primary_selection::v1::client::{
+	zwp_primary_selection_device_manager_v1::ZwpPrimarySelectionDeviceManagerV1,
+	zwp_primary_selection_device_v1::{self, ZwpPrimarySelectionDeviceV1},
+	zwp_primary_selection_offer_v1::{self, ZwpPrimarySelectionOfferV1},
+	zwp_primary_selection_source_v1::{self, ZwpPrimarySelectionSourceV1},
+};
+
+#[cfg(feature = "image-data")]
+use crate::{
+	common::{decode_image, ImageData, ImageFormatTag},
+	common_linux::encode_as_png,
+};
+use crate::{
+	common::{text_from_utf8, ClipboardSnapshot, Error, Representation},
+	common_linux::{into_unknown, LinuxClipboardKind},
+};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+// In the order of preference when reading text.
+const TEXT_MIME_TYPES: &[&str] =
+	&["text/plain;charset=utf-8", "UTF8_STRING", "text/plain", "STRING", "TEXT"];
+#[cfg(feature = "image-data")]
+const MIME_PNG: &str = "image/png";
+const MIME_HTML: &str = "text/html";
+
+/// The selections that are available on Wayland.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum SelectionKind {
+	/// The selection of the core protocol.
+	Clipboard,
+	/// The selection of the primary selection protocol.
+	Primary,
+}
+
+impl TryFrom<LinuxClipboardKind> for SelectionKind {
+	type Error = Error;
+
+	fn try_from(kind: LinuxClipboardKind) -> Result<Self> {
+		match kind {
+			LinuxClipboardKind::Clipboard => Ok(SelectionKind::Clipboard),
+			LinuxClipboardKind::Primary => Ok(SelectionKind::Primary),
+			LinuxClipboardKind::Secondary => Err(Error::ClipboardNotSupported),
+		}
+	}
+}
+
+/// A request to the thread that dispatches the events of the clipboard.
+enum Command {
+	/// Lists the MIME types of the current selection.
+	MimeTypes(SelectionKind, mpsc::Sender<Result<Vec<String>>>),
+	/// Starts reading the current selection in the first one of the MIME types that's offered.
+	Load(SelectionKind, Vec<String>, mpsc::Sender<Result<PipeReader>>),
+	/// Takes ownership of the selection, offering each of the MIME types with its data. An empty
+	/// list clears the selection.
+	Store(SelectionKind, Vec<(String, Arc<[u8]>)>, mpsc::Sender<Result<()>>),
+	Generation(mpsc::Sender<u64>),
+}
+
+/// The data offered by the owner of one of the selections.
+enum Offer {
+	Clipboard(WlDataOffer),
+	Primary(ZwpPrimarySelectionOfferV1),
+}
+
+impl Offer {
+	fn receive(&self, mime_type: String, fd: RawFd) {
+		match self {
+			Offer::Clipboard(offer) => offer.receive(mime_type, fd),
+			Offer::Primary(offer) => offer.receive(mime_type, fd),
+		}
+	}
+
+	fn destroy(&self) {
+		match self {
+			Offer::Clipboard(offer) => offer.destroy(),
+			Offer::Primary(offer) => offer.destroy(),
+		}
+	}
+}
+
+/// The managers of the protocols, bound on our own queue.
+struct Globals {
+	data_device: Main<WlDataDeviceManager>,
+	/// Only available if the compositor supports the primary selection protocol.
+	primary_selection: Option<Main<ZwpPrimarySelectionDeviceManagerV1>>,
+}
+
+/// What's known about a seat from the events of its input devices and its data device.
+struct SeatState {
+	seat: Main<WlSeat>,
+	device: Option<Main<WlDataDevice>>,
+	primary_device: Option<Main<ZwpPrimarySelectionDeviceV1>>,
+	keyboard: Option<Main<WlKeyboard>>,
+	pointer: Option<Main<WlPointer>>,
+
+	/// The serial of the latest input event, which is needed for setting the selection.
+	serial: Option<u32>,
+
+	/// The current selection, along with the MIME types it's offered in.
+	selection: Option<(Offer, Vec<String>)>,
+
+	/// The current primary selection, along with the MIME types it's offered in.
+	primary: Option<(Offer, Vec<String>)>,
+
+	/// The offer of an ongoing drag and drop operation. Those are handled by the application, we
+	/// only have to destroy our copy of the offer.
+	drag_offer: Option<WlDataOffer>,
+}
+
+impl SeatState {
+	fn selection(&self, kind: SelectionKind) -> Option<&(Offer, Vec<String>)> {
+		match kind {
+			SelectionKind::Clipboard => self.selection.as_ref(),
+			SelectionKind::Primary => self.primary.as_ref(),
+		}
+	}
+}
+
+#[derive(Default)]
+struct State {
+	seats: Vec<SeatState>,
+
+	/// The seat that received input most recently.
+	active_seat: Option<usize>,
+
+	/// The source that we are serving the selection from.
+	source: Option<Main<WlDataSource>>,
+
+	/// The source that we are serving the primary selection from.
+	primary_source: Option<Main<ZwpPrimarySelectionSourceV1>>,
+
+	/// Incremented every time the selection changes.
+	generation: u64,
+}
+
+impl State {
+	fn record_input(&mut self, seat: usize, serial: u32) {
+		self.seats[seat].serial = Some(serial);
+		self.active_seat = Some(seat);
+	}
+
+	/// Returns the selection of the seat that received input most recently, or if there's no such
+	/// seat, of the first seat with a selection.
+	fn selection(&self, kind: SelectionKind) -> Option<&(Offer, Vec<String>)> {
+		let active = self.active_seat.and_then(|seat| self.seats[seat].selection(kind));
+		active.or_else(|| self.seats.iter().find_map(|seat| seat.selection(kind)))
+	}
+
+	fn handle_capabilities(
+		state: &Rc<RefCell<State>>,
+		index: usize,
+		capabilities: wl_seat::Capability,
+	) {
+		let mut this = state.borrow_mut();
+		let seat = &mut this.seats[index];
+		if capabilities.contains(wl_seat::Capability::Keyboard) {
+			if seat.keyboard.is_none() {
+				let keyboard = seat.seat.get_keyboard();
+				let state = Rc::clone(state);
+				keyboard.quick_assign(move |_, event, _| match event {
+					// We have no use for the keymap, but the file descriptor must be closed.
+					wl_keyboard::Event::Keymap { fd, .. } => drop(unsafe { File::from_raw_fd(fd) }),
+					wl_keyboard::Event::Enter { serial, .. }
+					| wl_keyboard::Event::Key { serial, .. } => state.borrow_mut().record_input(index, serial),
+					_ => {}
+				});
+				seat.keyboard = Some(keyboard);
+			}
+		} else if let Some(keyboard) = seat.keyboard.take() {
+			release_keyboard(&keyboard);
+		}
+		if capabilities.contains(wl_seat::Capability::Pointer) {
+			if seat.pointer.is_none() {
+				let pointer = seat.seat.get_pointer();
+				let state = Rc::clone(state);
+				pointer.quick_assign(move |_, event, _| match event {
+					wl_pointer::Event::Enter { serial, .. }
+					| wl_pointer::Event::Button { serial, .. } => state.borrow_mut().record_input(index, serial),
+					_ => {}
+				});
+				seat.pointer = Some(pointer);
+			}
+		} else if let Some(pointer) = seat.pointer.take() {
+			release_pointer(&pointer);
+		}
+	}
+
+	fn handle_device_event(state: &RefCell<State>, index: usize, event: wl_data_device::Event) {
+		match event {
+			wl_data_device::Event::DataOffer { id } => {
+				// The MIME types are announced before the offer is used.
+				id.as_ref().user_data().set(|| RefCell::new(Vec::<String>::new()));
+				id.quick_assign(|offer, event, _| {
+					if let wl_data_offer::Event::Offer { mime_type } = event {
+						add_mime_type(offer.as_ref().user_data(), mime_type);
+					}
+				});
+			}
+			wl_data_device::Event::Selection { id } => {
+				let selection = id.map(|offer| {
+					let mime_types = mime_types(offer.as_ref().user_data());
+					(Offer::Clipboard(offer), mime_types)
+				});
+				let mut this = state.borrow_mut();
+				if let Some((previous, _)) =
+					mem::replace(&mut this.seats[index].selection, selection)
+				{
+					previous.destroy();
+				}
+				this.generation += 1;
+			}
+			wl_data_device::Event::Enter { id, .. } => {
+				let mut this = state.borrow_mut();
+				if let Some(previous) = mem::replace(&mut this.seats[index].drag_offer, id) {
+					previous.destroy();
+				}
+			}
+			wl_data_device::Event::Leave | wl_data_device::Event::Drop => {
+				if let Some(offer) = state.borrow_mut().seats[index].drag_offer.take() {
+					offer.destroy();
+				}
+			}
+			_ => {}
+		}
+	}
+
+	fn handle_primary_device_event(
+		state: &RefCell<State>,
+		index: usize,
+		event: zwp_primary_selection_device_v1::Event,
+	) {
+		match event {
+			zwp_primary_selection_device_v1::Event::DataOffer { offer } => {
+				offer.as_ref().user_data().set(|| RefCell::new(Vec::<String>::new()));
+				offer.quick_assign(|offer, event, _| {
+					if let zwp_primary_selection_offer_v1::Event::Offer { mime_type } = event {
+						add_mime_type(offer.as_ref().user_data(), mime_type);
+					}
+				});
+			}
+			zwp_primary_selection_device_v1::Event::Selection { id } => {
+				let primary = id.map(|offer| {
+					let mime_types = mime_types(offer.as_ref().user_data());
+					(Offer::Primary(offer), mime_types)
+				});
+				let mut this = state.borrow_mut();
+				if let Some((previous, _)) = mem::replace(&mut this.seats[index].primary, primary) {
+					previous.destroy();
+				}
+			}
+			_ => {}
+		}
+	}
+
+	fn handle_command(&mut self, display: &Display, globals: &Globals, command: Command) {
+		match command {
+			Command::MimeTypes(kind, reply) => {
+				let mime_types = self
+					.selection(kind)
+					.map(|(_, mime_types)| mime_types.clone())
+					.unwrap_or_default();
+				let _ = reply.send(Ok(mime_types));
+			}
+			Command::Load(kind, mime_types, reply) => {
+				let _ = reply.send(self.load(display, kind, &mime_types));
+			}
+			Command::Store(kind, data, reply) => {
+				let _ = reply.send(self.store(globals, kind, data));
+			}
+			Command::Generation(reply) => {
+				let _ = reply.send(self.generation);
+			}
+		}
+	}
+
+	fn load(
+		&self,
+		display: &Display,
+		kind: SelectionKind,
+		mime_types: &[String],
+	) -> Result<PipeReader> {
+		let (offer, offered) = self.selection(kind).ok_or(Error::ContentNotAvailable)?;
+		let mime_type = mime_types
+			.iter()
+			.find(|mime_type| offered.contains(mime_type))
+			.ok_or(Error::ContentNotAvailable)?;
+		let (reader, writer) = os_pipe::pipe().map_err(into_unknown)?;
+		offer.receive(mime_type.clone(), writer.as_raw_fd());
+		display.flush().map_err(into_unknown)?;
+		// Our copy of the write end must be closed, otherwise reading would never finish.
+		drop(writer);
+		Ok(reader)
+	}
+
+	fn store(
+		&mut self,
+		globals: &Globals,
+		kind: SelectionKind,
+		data: Vec<(String, Arc<[u8]>)>,
+	) -> Result<()> {
+		let seat = self.active_seat.map(|seat| &self.seats[seat]);
+		let (seat, serial) = match seat.and_then(|seat| Some((seat, seat.serial?))) {
+			Some(seat_and_serial) => seat_and_serial,
+			// The compositor only lets the application with the keyboard focus set the clipboard.
+			None => return Err(Error::PermissionDenied),
+		};
+		match kind {
+			SelectionKind::Clipboard => {
+				let device = seat.device.as_ref().ok_or(Error::ClipboardNotSupported)?;
+				if data.is_empty() {
+					device.set_selection(None, serial);
+					return Ok(());
+				}
+				let source = globals.data_device.create_data_source();
+				for (mime_type, _) in &data {
+					source.offer(mime_type.clone());
+				}
+				source.quick_assign(move |source, event, _| match event {
+					wl_data_source::Event::Send { mime_type, fd } => send(&data, mime_type, fd),
+					wl_data_source::Event::Cancelled => source.destroy(),
+					_ => {}
+				});
+				device.set_selection(Some(&source), serial);
+				// The source of the previous selection gets cancelled by the compositor.
+				self.source = Some(source);
+			}
+			SelectionKind::Primary => {
+				let (manager, device) =
+					match (&globals.primary_selection, seat.primary_device.as_ref()) {
+						(Some(manager), Some(device)) => (manager, device),
+						_ => return Err(Error::ClipboardNotSupported),
+					};
+				if data.is_empty() {
+					device.set_selection(None, serial);
+					return Ok(());
+				}
+				let source = manager.create_source();
+				for (mime_type, _) in &data {
+					source.offer(mime_type.clone());
+				}
+				source.quick_assign(move |source, event, _| match event {
+					zwp_primary_selection_source_v1::Event::Send { mime_type, fd } => {
+						send(&data, mime_type, fd)
+					}
+					zwp_primary_selection_source_v1::Event::Cancelled => source.destroy(),
+					_ => {}
+				});
+				device.set_selection(Some(&source), serial);
+				self.primary_source = Some(source);
+			}
+		}
+		Ok(())
+	}
+
+	/// Destroys every object that was created on the connection of the application.
+	fn destroy(&mut self) {
+		if let Some(source) = self.source.take() {
+			source.destroy();
+		}
+		if let Some(source) = self.primary_source.take() {
+			source.destroy();
+		}
+		for seat in self.seats.drain(..) {
+			if let Some((offer, _)) = seat.selection {
+				offer.destroy();
+			}
+			if let Some((offer, _)) = seat.primary {
+				offer.destroy();
+			}
+			if let Some(device) = seat.primary_device {
+				device.destroy();
+			}
+			if let Some(offer) = seat.drag_offer {
+				offer.destroy();
+			}
+			if let Some(device) = seat.device {
+				if device.as_ref().version() >= 2 {
+					device.release();
+				}
+			}
+			if let Some(keyboard) = seat.keyboard {
+				release_keyboard(&keyboard);
+			}
+			if let Some(pointer) = seat.pointer {
+				release_pointer(&pointer);
+			}
+			if seat.seat.as_ref().version() >= 5 {
+				seat.seat.release();
+			}
+		}
+	}
+}
+
+/// Records a MIME type announced by an offer, in the user data that was set up for it.
+fn add_mime_type(user_data: &UserData, mime_type: String) {
+	if let Some(mime_types) = user_data.get::<RefCell<Vec<String>>>() {
+		mime_types.borrow_mut().push(mime_type);
+	}
+}
+
+/// Returns the MIME types recorded by `add_mime_type`.
+fn mime_types(user_data: &UserData) -> Vec<String> {
+	user_data
+		.get::<RefCell<Vec<String>>>()
+		.map(|mime_types| mime_types.borrow().clone())
+		.unwrap_or_default()
+}
+
+/// Writes the data offered in `mime_type` to a client that's pasting it.
+fn send(data: &[(String, Arc<[u8]>)], mime_type: String, fd: RawFd) {
+	let mut file = unsafe { File::from_raw_fd(fd) };
+	if let Some((_, bytes)) = data.iter().find(|(offered, _)| *offered == mime_type) {
+		let bytes = Arc::clone(bytes);
+		// Writing may block until the receiver reads, which must not stop the event loop because
+		// the receiver may be waiting on this very clipboard.
+		thread::spawn(move || {
+			if let Err(e) = file.write_all(&bytes) {
+				warn!("Could not send `{}` to the pasting client: {}", mime_type, e);
+			}
+		});
+	}
+}
+
+fn release_keyboard(keyboard: &WlKeyboard) {
+	if keyboard.as_ref().version() >= 3 {
+		keyboard.release();
+	}
+}
+
+fn release_pointer(pointer: &WlPointer) {
+	if pointer.as_ref().version() >= 3 {
+		pointer.release();
+	}
+}
+
+/// Binds the globals needed for the clipboard, on a queue of our own.
+fn connect(display: &Display) -> Result<(EventQueue, Globals, Rc<RefCell<State>>)> {
+	let mut queue = display.create_event_queue();
+	let attached = display.attach(queue.token());
+
+	let state = Rc::new(RefCell::new(State::default()));
+	let globals = {
+		let state = Rc::clone(&state);
+		GlobalManager::new_with_cb(
+			&attached,
+			global_filter!([WlSeat, 5, move |seat: Main<WlSeat>, _: DispatchData| {
+				// The capabilities are sent right after binding, so they must be handled here.
+				let index = state.borrow().seats.len();
+				let seat_state = Rc::clone(&state);
+				seat.quick_assign(move |_, event, _| {
+					if let wl_seat::Event::Capabilities { capabilities } = event {
+						State::handle_capabilities(&seat_state, index, capabilities);
+					}
+				});
+				state.borrow_mut().seats.push(SeatState {
+					seat,
+					device: None,
+					primary_device: None,
+					keyboard: None,
+					pointer: None,
+					serial: None,
+					selection: None,
+					primary: None,
+					drag_offer: None,
+				});
+			}]),
+		)
+	};
+	queue.sync_roundtrip(&mut (), |_, _, _| {}).map_err(into_unknown)?;
+
+	let data_device = globals
+		.instantiate_range::<WlDataDeviceManager>(1, 3)
+		.map_err(|_| Error::ClipboardNotSupported)?;
+	let primary_selection = globals.instantiate_exact::<ZwpPrimarySelectionDeviceManagerV1>(1).ok();
+	for (index, seat) in state.borrow_mut().seats.iter_mut().enumerate() {
+		let device = data_device.get_data_device(&seat.seat);
+		let device_state = Rc::clone(&state);
+		device.quick_assign(move |_, event, _| {
+			State::handle_device_event(&device_state, index, event)
+		});
+		seat.device = Some(device);
+
+		if let Some(manager) = &primary_selection {
+			let device = manager.get_device(&seat.seat);
+			let device_state = Rc::clone(&state);
+			device.quick_assign(move |_, event, _| {
+				State::handle_primary_device_event(&device_state, index, event)
+			});
+			seat.primary_device = Some(device);
+		}
+	}
+	// Receive the current selections and the capabilities of the seats.
+	queue.sync_roundtrip(&mut (), |_, _, _| {}).map_err(into_unknown)?;
+
+	Ok((queue, Globals { data_device, primary_selection }, state))
+}
+
+/// Dispatches the events of the clipboard and executes the commands until the other end of
+/// `wake` is closed.
+fn serve_commands(
+	display: &Display,
+	queue: &mut EventQueue,
+	globals: &Globals,
+	state: &RefCell<State>,
+	commands: &mpsc::Receiver<Command>,
+	mut wake: PipeReader,
+) -> io::Result<()> {
+	loop {
+		queue.dispatch_pending(&mut (), |_, _, _| {})?;
+		display.flush()?;
+		let guard = match queue.prepare_read() {
+			Some(guard) => guard,
+			// There are events left to dispatch.
+			None => continue,
+		};
+		let mut fds = [
+			libc::pollfd { fd: display.get_connection_fd(), events: libc::POLLIN, revents: 0 },
+			libc::pollfd { fd: wake.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+		];
+		if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+			guard.cancel();
+			let error = io::Error::last_os_error();
+			if error.kind() == io::ErrorKind::Interrupted {
+				continue;
+			}
+			return Err(error);
+		}
+		if fds[1].revents != 0 {
+			guard.cancel();
+			let mut buffer = [0; 64];
+			if wake.read(&mut buffer)? == 0 {
+				return Ok(());
+			}
+			while let Ok(command) = commands.try_recv() {
+				state.borrow_mut().handle_command(display, globals, command);
+			}
+		} else if fds[0].revents != 0 {
+			guard.read_events()?;
+		} else {
+			guard.cancel();
+		}
+	}
+}
+
+pub struct WaylandClipboardContext {
+	commands: mpsc::Sender<Command>,
+	primary_supported: bool,
+
+	/// A byte is written after each command to wake up the thread, and dropping this stops it.
+	wake: Option<PipeWriter>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl WaylandClipboardContext {
+	/// Opens a connection of its own to the compositor from `WAYLAND_DISPLAY`.
+	pub(crate) fn new() -> Result<Self> {
+		if !wayland_client::sys::client::is_lib_available() {
+			return Err(Error::ClipboardNotSupported);
+		}
+		let display = Display::connect_to_env().map_err(|e| {
+			warn!("Could not connect to the Wayland compositor: {}", e);
+			Error::ClipboardNotSupported
+		})?;
+		// The connection is closed when the thread drops the display.
+		Self::start(display)
+	}
+
+	/// # Safety
+	///
+	/// `display` must point to a valid `wl_display` that outlives the returned context.
+	pub(crate) unsafe fn from_display(display: *mut c_void) -> Result<Self> {
+		// The library is loaded at runtime, so a display can't have been created without it, but
+		// calling into a missing library would panic.
+		if !wayland_client::sys::client::is_lib_available() {
+			return Err(Error::ClipboardNotSupported);
+		}
+		Self::start(Display::from_external_display(display as *mut wl_display))
+	}
+
+	/// Starts the thread that dispatches the events of the clipboard on `display`.
+	fn start(display: Display) -> Result<Self> {
+		let (wake_reader, wake_writer) = os_pipe::pipe().map_err(into_unknown)?;
+		let (commands, command_receiver) = mpsc::channel();
+		let (init_sender, init_receiver) = mpsc::channel();
+		let thread = thread::spawn(move || {
+			let (mut queue, globals, state) = match connect(&display) {
+				Ok(connection) => connection,
+				Err(e) => {
+					let _ = init_sender.send(Err(e));
+					return;
+				}
+			};
+			let _ = init_sender.send(Ok(globals.primary_selection.is_some()));
+			let result = serve_commands(
+				&display,
+				&mut queue,
+				&globals,
+				&state,
+				&command_receiver,
+				wake_reader,
+			);
+			if let Err(e) = result {
+				error!("The Wayland clipboard thread errored with: {}", e);
+			}
+			state.borrow_mut().destroy();
+			if let Some(manager) = globals.primary_selection {
+				manager.destroy();
+			}
+			let _ = display.flush();
+		});
+		match init_receiver.recv() {
+			Ok(Ok(primary_supported)) => Ok(Self {
+				commands,
+				primary_supported,
+				wake: Some(wake_writer),
+				thread: Some(thread),
+			}),
+			Ok(Err(e)) => {
+				let _ = thread.join();
+				Err(e)
+			}
+			Err(_) => {
+				let _ = thread.join();
+				Err(Self::stopped())
+			}
+		}
+	}
+
+	fn stopped() -> Error {
+		Error::Unknown { description: "The Wayland clipboard thread stopped unexpectedly".into() }
+	}
+
+	/// Sends a command to the clipboard thread and waits for the reply.
+	fn request<T>(&mut self, command: impl FnOnce(mpsc::Sender<T>) -> Command) -> Result<T> {
+		let (reply, response) = mpsc::channel();
+		self.commands.send(command(reply)).map_err(|_| Self::stopped())?;
+		let wake = self.wake.as_mut().ok_or_else(Self::stopped)?;
+		wake.write_all(&[0]).map_err(|_| Self::stopped())?;
+		response.recv().map_err(|_| Self::stopped())
+	}
+
+	/// Returns `Error::ClipboardNotSupported` for the selections that the compositor doesn't
+	/// support.
+	fn selection_kind(&self, selection: LinuxClipboardKind) -> Result<SelectionKind> {
+		match SelectionKind::try_from(selection)? {
+			SelectionKind::Primary if !self.primary_supported => Err(Error::ClipboardNotSupported),
+			kind => Ok(kind),
+		}
+	}
+
+	/// Reads the selection in the first one of the MIME types that's offered.
+	fn load(&mut self, kind: SelectionKind, mime_types: &[&str]) -> Result<Vec<u8>> {
+		let mime_types = mime_types.iter().map(|&mime_type| mime_type.to_owned()).collect();
+		let mut reader = self.request(|reply| Command::Load(kind, mime_types, reply))??;
+		let mut bytes = Vec::new();
+		reader.read_to_end(&mut bytes).map_err(into_unknown)?;
+		Ok(bytes)
+	}
+
+	fn store(&mut self, kind: SelectionKind, data: Vec<(String, Arc<[u8]>)>) -> Result<()> {
+		self.request(|reply| Command::Store(kind, data, reply))?
+	}
+
+	fn text_data(text: String) -> Vec<(String, Arc<[u8]>)> {
+		let bytes: Arc<[u8]> = text.into_bytes().into();
+		TEXT_MIME_TYPES
+			.iter()
+			.map(|&mime_type| (mime_type.to_owned(), Arc::clone(&bytes)))
+			.collect()
+	}
+
+	pub fn get_text(&mut self) -> Result<String> {
+		self.get_text_with_clipboard(LinuxClipboardKind::Clipboard)
+	}
+
+	pub(crate) fn get_text_with_clipboard(
+		&mut self,
+		selection: LinuxClipboardKind,
+	) -> Result<String> {
+		let bytes = self.load(self.selection_kind(selection)?, TEXT_MIME_TYPES)?;
+		text_from_utf8(bytes)
+	}
+
+	pub fn get_text_bytes(&mut self, format: &str) -> Result<Vec<u8>> {
+		self.load(SelectionKind::Clipboard, &[format])
+	}
+
+	pub fn set_text(&mut self, text: String) -> Result<()> {
+		self.set_text_with_clipboard(text, LinuxClipboardKind::Clipboard)
+	}
+
+	pub(crate) fn set_text_with_clipboard(
+		&mut self,
+		text: String,
+		selection: LinuxClipboardKind,
+	) -> Result<()> {
+		self.store(self.selection_kind(selection)?, Self::text_data(text))
+	}
+
+	pub fn swap_text(&mut self, text: String) -> Result<Option<String>> {
+		let previous = match self.get_text() {
+			Ok(text) => Some(text),
+			Err(Error::ContentNotAvailable) => None,
+			Err(e) => return Err(e),
+		};
+		self.set_text(text)?;
+		Ok(previous)
+	}
+
+	pub fn generation(&mut self) -> Result<u64> {
+		self.request(Command::Generation)
+	}
+
+	pub fn available_formats(&mut self) -> Result<Vec<String>> {
+		self.request(|reply| Command::MimeTypes(SelectionKind::Clipboard, reply))?
+	}
+
+	pub fn snapshot(&mut self, max_bytes: Option<usize>) -> Result<ClipboardSnapshot> {
+		let mut snapshot = ClipboardSnapshot::default();
+		snapshot.new_item();
+		let mime_types =
+			self.request(|reply| Command::MimeTypes(SelectionKind::Clipboard, reply))??;
+		for mime_type in mime_types {
+			match self.load(SelectionKind::Clipboard, &[&mime_type]) {
+				Ok(bytes) => snapshot.push(mime_type, bytes, max_bytes),
+				Err(err) => {
+					warn!("Could not read `{}` for the snapshot: {}", mime_type, err);
+					snapshot.skip(&mime_type, err);
+				}
+			}
+		}
+		Ok(snapshot)
+	}
+
+	/// The core protocol has no way to hand the contents over to another client.
+	pub fn persist(&mut self) -> Result<()> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub fn restore(&mut self, snapshot: &ClipboardSnapshot) -> Result<()> {
+		let data = snapshot
+			.items
+			.iter()
+			.flatten()
+			.map(|(mime_type, bytes)| (mime_type.clone(), bytes.as_slice().into()))
+			.collect();
+		self.store(SelectionKind::Clipboard, data)
+	}
+
+	pub(crate) fn write_all_with_clipboard(
+		&mut self,
+		representations: &[Representation],
+		selection: LinuxClipboardKind,
+	) -> Result<()> {
+		let kind = self.selection_kind(selection)?;
+		let mut data = Vec::new();
+		for representation in representations {
+			match representation {
+				Representation::Text(text) => data.extend(Self::text_data(text.clone())),
+				Representation::Html(html) => {
+					data.push((MIME_HTML.to_owned(), html.as_bytes().into()))
+				}
+				Representation::Custom(mime_type, bytes) => {
+					data.push((mime_type.clone(), bytes.as_slice().into()))
+				}
+			}
+		}
+		self.store(kind, data)
+	}
+
+	#[cfg(feature = "image-data")]
+	pub fn get_image(&mut self) -> Result<ImageData<'static>> {
+		let bytes = self.load(SelectionKind::Clipboard, &[MIME_PNG])?;
+		decode_image(ImageFormatTag::Png, &bytes)
+	}
+
+	#[cfg(feature = "image-data")]
+	pub fn set_image(&mut self, image: ImageData) -> Result<()> {
+		let encoded = encode_as_png(&image)?;
+		self.store(SelectionKind::Clipboard, vec![(MIME_PNG.to_owned(), encoded.into())])
+	}
+}
+
+impl Drop for WaylandClipboardContext {
+	fn drop(&mut self) {
+		drop(self.wake.take());
+		if let Some(thread) = self.thread.take() {
+			if thread.join().is_err() {
+				error!("The Wayland clipboard thread paniced.");
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::os::unix::io::IntoRawFd;
+
+	#[test]
+	fn selection_kind_mapping() {
+		let kind = |kind: LinuxClipboardKind| SelectionKind::try_from(kind).ok();
+		assert_eq!(kind(LinuxClipboardKind::Clipboard), Some(SelectionKind::Clipboard));
+		assert_eq!(kind(LinuxClipboardKind::Primary), Some(SelectionKind::Primary));
+		assert!(matches!(
+			SelectionKind::try_from(LinuxClipboardKind::Secondary),
+			Err(Error::ClipboardNotSupported)
+		));
+	}
+
+	#[test]
+	fn text_data_offers_every_text_mime_type() {
+		let data = WaylandClipboardContext::text_data("Grüße".to_owned());
+		let mime_types: Vec<_> = data.iter().map(|(mime_type, _)| mime_type.as_str()).collect();
+		assert_eq!(mime_types, TEXT_MIME_TYPES);
+		// The text is shared between the MIME types rather than copied for each of them.
+		assert!(data.iter().all(|(_, bytes)| Arc::ptr_eq(bytes, &data[0].1)));
+		assert_eq!(&*data[0].1, "Grüße".as_bytes());
+	}
+
+	#[test]
+	fn send_writes_the_offered_data() {
+		let data = vec![
+			(MIME_HTML.to_owned(), Arc::from(&b"<b>bold</b>"[..])),
+			("text/plain".to_owned(), Arc::from(&b"bold"[..])),
+		];
+		let receive = |mime_type: &str| {
+			let (mut reader, writer) = os_pipe::pipe().unwrap();
+			send(&data, mime_type.to_owned(), writer.into_raw_fd());
+			let mut bytes = Vec::new();
+			reader.read_to_end(&mut bytes).unwrap();
+			bytes
+		};
+		assert_eq!(receive("text/plain"), b"bold");
+		assert_eq!(receive(MIME_HTML), b"<b>bold</b>");
+		// A MIME type that wasn't offered closes the pipe without writing anything.
+		assert!(receive("image/png").is_empty());
+	}
+}
@@ -1,23 +1,58 @@
 use std::convert::TryInto;
-use std::io::Read;
+use std::io::{self, Read};
+use std::{
+	cell::{Cell, RefCell},
+	fmt,
+	os::unix::io::AsRawFd,
+	rc::Rc,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		mpsc, Arc,
+	},
+	thread::{self, JoinHandle},
+};
 
+use os_pipe::{PipeReader, PipeWriter};
+use parking_lot::Mutex;
+use wayland_client::{
+	global_filter, protocol::wl_seat::WlSeat, Display, EventQueue, GlobalManager, Main,
+};
+use wayland_protocols::wlr::unstable::data_control::v1::client::{
+	zwlr_data_control_device_v1,
+	zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+	zwlr_data_control_offer_v1::{self, ZwlrDataControlOfferV1},
+};
 use wl_clipboard_rs::{
-	copy::{self, Error as CopyError, Options, Source},
-	paste::{self, get_contents, Error as PasteError, Seat},
+	copy::{self, Error as CopyError, MimeSource, Options, Source},
+	paste::{self, get_contents, get_mime_types, Error as PasteError, Seat},
 	utils::is_primary_selection_supported,
 };
 
+#[cfg(feature = "image-data")]
 use crate::{
-	common::Error,
+	common::{decode_image, ImageData, ImageFormatTag},
+	common_linux::encode_as_png,
+};
+use crate::{
+	common::{text_from_utf8, ClipboardEvent, ClipboardSnapshot, Error, Representation},
 	common_linux::{into_unknown, LinuxClipboardKind},
 };
-#[cfg(feature = "image-data")]
-use crate::{common::ImageData, common_linux::encode_as_png};
 
 #[cfg(feature = "image-data")]
 const MIME_PNG: &str = "image/png";
+const MIME_HTML: &str = "text/html";
 
-pub struct WaylandDataControlClipboardContext {}
+pub struct WaylandDataControlClipboardContext {
+	/// Counts the changes of the clipboard selection, from the first call of `generation` on.
+	changes: Option<SelectionCounter>,
+}
+
+/// Counts the changes of the clipboard selection on a connection of its own, as the requests of
+/// `wl-clipboard-rs` each make a new one.
+struct SelectionCounter {
+	count: Arc<AtomicU64>,
+	_watcher: WaylandDataControlWatcher,
+}
 
 impl TryInto<copy::ClipboardType> for LinuxClipboardKind {
 	type Error = Error;
@@ -26,9 +61,7 @@ impl TryInto<copy::ClipboardType> for LinuxClipboardKind {
 		match self {
 			LinuxClipboardKind::Clipboard => Ok(copy::ClipboardType::Regular),
 			LinuxClipboardKind::Primary => Ok(copy::ClipboardType::Primary),
-			LinuxClipboardKind::Secondary => {
-				return Err(Error::ClipboardNotSupported);
-			}
+			LinuxClipboardKind::Secondary => Err(Error::ClipboardNotSupported),
 		}
 	}
 }
@@ -40,9 +73,7 @@ impl TryInto<paste::ClipboardType> for LinuxClipboardKind {
 		match self {
 			LinuxClipboardKind::Clipboard => Ok(paste::ClipboardType::Regular),
 			LinuxClipboardKind::Primary => Ok(paste::ClipboardType::Primary),
-			LinuxClipboardKind::Secondary => {
-				return Err(Error::ClipboardNotSupported);
-			}
+			LinuxClipboardKind::Secondary => Err(Error::ClipboardNotSupported),
 		}
 	}
 }
@@ -52,9 +83,10 @@ impl WaylandDataControlClipboardContext {
 	pub(crate) fn new() -> Result<Self, Error> {
 		// Check if it's possible to communicate with the wayland compositor
 		if let Err(e) = is_primary_selection_supported() {
-			return Err(into_unknown(e));
+			warn!("Could not use the Wayland data control protocol: {}", e);
+			return Err(Error::ClipboardNotSupported);
 		}
-		Ok(Self {})
+		Ok(Self { changes: None })
 	}
 
 	pub fn get_text(&mut self) -> Result<String, Error> {
@@ -72,7 +104,7 @@ impl WaylandDataControlClipboardContext {
 			Ok((mut pipe, _)) => {
 				let mut contents = vec![];
 				pipe.read_to_end(&mut contents).map_err(into_unknown)?;
-				String::from_utf8(contents).map_err(|_| Error::ConversionFailure)
+				text_from_utf8(contents)
 			}
 
 			Err(PasteError::ClipboardEmpty) | Err(PasteError::NoMimeType) => {
@@ -81,7 +113,57 @@ impl WaylandDataControlClipboardContext {
 
 			Err(PasteError::PrimarySelectionUnsupported) => Err(Error::ClipboardNotSupported),
 
-			Err(err) => return Err(Error::Unknown { description: format!("{}", err) }),
+			Err(err) => Err(Error::Unknown { description: format!("{}", err) }),
+		}
+	}
+
+	pub fn swap_text(&mut self, text: String) -> Result<Option<String>, Error> {
+		let previous = match self.get_text() {
+			Ok(text) => Some(text),
+			Err(Error::ContentNotAvailable) => None,
+			Err(e) => return Err(e),
+		};
+		self.set_text(text)?;
+		Ok(previous)
+	}
+
+	/// Returns how many times the clipboard selection changed since the first call. The changes
+	/// are observed on another thread, so a change may take a moment to be counted.
+	pub fn generation(&mut self) -> Result<u64, Error> {
+		if let Some(changes) = &self.changes {
+			return Ok(changes.count.load(Ordering::Relaxed));
+		}
+		let count = Arc::new(AtomicU64::new(0));
+		let counted = Arc::clone(&count);
+		let watcher = WaylandDataControlWatcher::new(move |event: ClipboardEvent| {
+			if event.selection == LinuxClipboardKind::Clipboard {
+				counted.fetch_add(1, Ordering::Relaxed);
+			}
+		})?;
+		self.changes = Some(SelectionCounter { count, _watcher: watcher });
+		Ok(0)
+	}
+
+	pub fn get_text_bytes(&mut self, format: &str) -> Result<Vec<u8>, Error> {
+		use wl_clipboard_rs::paste::MimeType;
+
+		let result = get_contents(
+			paste::ClipboardType::Regular,
+			Seat::Unspecified,
+			MimeType::Specific(format),
+		);
+		match result {
+			Ok((mut pipe, _)) => {
+				let mut contents = vec![];
+				pipe.read_to_end(&mut contents).map_err(into_unknown)?;
+				Ok(contents)
+			}
+
+			Err(PasteError::ClipboardEmpty) | Err(PasteError::NoMimeType) => {
+				Err(Error::ContentNotAvailable)
+			}
+
+			Err(err) => Err(Error::Unknown { description: format!("{}", err) }),
 		}
 	}
 
@@ -106,8 +188,7 @@ impl WaylandDataControlClipboardContext {
 	}
 
 	#[cfg(feature = "image-data")]
-	pub fn get_image(&mut self) -> Result<ImageData, Error> {
-		use std::io::Cursor;
+	pub fn get_image(&mut self) -> Result<ImageData<'static>, Error> {
 		use wl_clipboard_rs::paste::MimeType;
 
 		let result = get_contents(
@@ -119,29 +200,14 @@ impl WaylandDataControlClipboardContext {
 			Ok((mut pipe, _mime_type)) => {
 				let mut buffer = vec![];
 				pipe.read_to_end(&mut buffer).map_err(into_unknown)?;
-				dbg!(&buffer);
-				let image = image::io::Reader::new(Cursor::new(buffer))
-					.with_guessed_format()
-					.map_err(|_| Error::ConversionFailure)?
-					.decode()
-					.map_err(|e| {
-						dbg!(e);
-						Error::ConversionFailure
-					})?;
-				let image = image.into_rgba8();
-
-				Ok(ImageData {
-					width: image.width() as usize,
-					height: image.height() as usize,
-					bytes: image.into_raw().into(),
-				})
+				decode_image(ImageFormatTag::Png, &buffer)
 			}
 
 			Err(PasteError::ClipboardEmpty) | Err(PasteError::NoMimeType) => {
 				Err(Error::ContentNotAvailable)
 			}
 
-			Err(err) => return Err(Error::Unknown { description: format!("{}", err) }),
+			Err(err) => Err(Error::Unknown { description: format!("{}", err) }),
 		}
 	}
 
@@ -155,4 +221,311 @@ impl WaylandDataControlClipboardContext {
 		opts.copy(source, MimeType::Specific(MIME_PNG.into())).map_err(into_unknown)?;
 		Ok(())
 	}
+
+	pub(crate) fn write_all_with_clipboard(
+		&mut self,
+		representations: &[Representation],
+		selection: LinuxClipboardKind,
+	) -> Result<(), Error> {
+		use wl_clipboard_rs::copy::MimeType;
+
+		let sources = representations
+			.iter()
+			.map(|representation| match representation {
+				Representation::Text(text) => MimeSource {
+					source: Source::Bytes(text.as_bytes().into()),
+					mime_type: MimeType::Text,
+				},
+				Representation::Html(html) => MimeSource {
+					source: Source::Bytes(html.as_bytes().into()),
+					mime_type: MimeType::Specific(MIME_HTML.into()),
+				},
+				Representation::Custom(mime_type, bytes) => MimeSource {
+					source: Source::Bytes(bytes.as_slice().into()),
+					mime_type: MimeType::Specific(mime_type.clone()),
+				},
+			})
+			.collect();
+		let mut opts = Options::new();
+		opts.clipboard(selection.try_into()?);
+		opts.copy_multi(sources).map_err(|e| match e {
+			CopyError::PrimarySelectionUnsupported => Error::ClipboardNotSupported,
+			other => into_unknown(other),
+		})
+	}
+
+	pub fn available_formats(&mut self) -> Result<Vec<String>, Error> {
+		match get_mime_types(paste::ClipboardType::Regular, Seat::Unspecified) {
+			Ok(mime_types) => Ok(mime_types.into_iter().collect()),
+			Err(PasteError::ClipboardEmpty) => Ok(Vec::new()),
+			Err(err) => Err(into_unknown(err)),
+		}
+	}
+
+	pub fn snapshot(&mut self, max_bytes: Option<usize>) -> Result<ClipboardSnapshot, Error> {
+		use wl_clipboard_rs::paste::MimeType;
+
+		let mut snapshot = ClipboardSnapshot::default();
+		snapshot.new_item();
+		let mime_types = match get_mime_types(paste::ClipboardType::Regular, Seat::Unspecified) {
+			Ok(mime_types) => mime_types,
+			Err(PasteError::ClipboardEmpty) => return Ok(snapshot),
+			Err(err) => return Err(into_unknown(err)),
+		};
+		for mime_type in mime_types {
+			let result = get_contents(
+				paste::ClipboardType::Regular,
+				Seat::Unspecified,
+				MimeType::Specific(&mime_type),
+			);
+			let mut buffer = vec![];
+			match result.map(|(mut pipe, _)| pipe.read_to_end(&mut buffer)) {
+				Ok(Ok(_)) => snapshot.push(mime_type, buffer, max_bytes),
+				Ok(Err(err)) => {
+					warn!("Could not read `{}` for the snapshot: {}", mime_type, err);
+					snapshot.skip(&mime_type, err);
+				}
+				Err(err) => {
+					warn!("Could not read `{}` for the snapshot: {}", mime_type, err);
+					snapshot.skip(&mime_type, err);
+				}
+			}
+		}
+		Ok(snapshot)
+	}
+
+	/// The contents are served by a process that `wl-clipboard-rs` forks for every write, which
+	/// keeps running until the contents are replaced.
+	pub fn persist(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+
+	pub fn restore(&mut self, snapshot: &ClipboardSnapshot) -> Result<(), Error> {
+		use wl_clipboard_rs::copy::MimeType;
+
+		let sources: Vec<MimeSource> = snapshot
+			.items
+			.iter()
+			.flatten()
+			.map(|(mime_type, bytes)| MimeSource {
+				source: Source::Bytes(bytes.clone().into_boxed_slice()),
+				mime_type: MimeType::Specific(mime_type.clone()),
+			})
+			.collect();
+		if sources.is_empty() {
+			return copy::clear(copy::ClipboardType::Regular, copy::Seat::All)
+				.map_err(into_unknown);
+		}
+		Options::new().copy_multi(sources).map_err(into_unknown)
+	}
+}
+
+/// The contents of a selection, as offered by its owner at the time of a watcher event.
+pub(crate) struct WaylandOffer {
+	offer: ZwlrDataControlOfferV1,
+	display: Display,
+	mime_types: Vec<String>,
+}
+
+impl WaylandOffer {
+	pub(crate) fn mime_types(&self) -> &[String] {
+		&self.mime_types
+	}
+
+	pub(crate) fn read(&self, mime_type: &str) -> Result<Vec<u8>, Error> {
+		if !self.mime_types.iter().any(|offered| offered == mime_type) {
+			return Err(Error::ContentNotAvailable);
+		}
+		let (mut reader, writer) = os_pipe::pipe().map_err(into_unknown)?;
+		self.offer.receive(mime_type.to_owned(), writer.as_raw_fd());
+		self.display.flush().map_err(into_unknown)?;
+		// Our copy of the write end must be closed, otherwise reading would never finish.
+		drop(writer);
+		let mut bytes = Vec::new();
+		reader.read_to_end(&mut bytes).map_err(into_unknown)?;
+		Ok(bytes)
+	}
+}
+
+impl Drop for WaylandOffer {
+	fn drop(&mut self) {
+		self.offer.destroy();
+		let _ = self.display.flush();
+	}
+}
+
+impl fmt::Debug for WaylandOffer {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("WaylandOffer").field("mime_types", &self.mime_types).finish()
+	}
+}
+
+/// Connects to the compositor and starts listening to the selections of every seat.
+///
+/// `on_change` is only called for the changes that happen after this function returns, the
+/// selections that are announced right after binding the data devices are skipped.
+fn connect_watcher(on_change: Rc<dyn Fn(ClipboardEvent)>) -> Result<(Display, EventQueue), Error> {
+	let display = Display::connect_to_env().map_err(into_unknown)?;
+	let mut queue = display.create_event_queue();
+	let attached = display.attach(queue.token());
+
+	let seats = Rc::new(RefCell::new(Vec::<Main<WlSeat>>::new()));
+	let globals = {
+		let seats = Rc::clone(&seats);
+		GlobalManager::new_with_cb(
+			&attached,
+			global_filter!([WlSeat, 1, move |seat: Main<WlSeat>, _: DispatchData| {
+				seats.borrow_mut().push(seat);
+			}]),
+		)
+	};
+	queue.sync_roundtrip(&mut (), |_, _, _| {}).map_err(into_unknown)?;
+
+	// Version 2 is required for the primary selection, but the clipboard works with either one.
+	let manager = globals
+		.instantiate_range::<ZwlrDataControlManagerV1>(1, 2)
+		.map_err(|_| Error::ClipboardNotSupported)?;
+
+	let started = Rc::new(Cell::new(false));
+	for seat in seats.borrow().iter() {
+		let device = manager.get_data_device(seat);
+		let on_change = Rc::clone(&on_change);
+		let display = display.clone();
+		let started = Rc::clone(&started);
+		device.quick_assign(move |device, event, _| {
+			let (selection, offer) = match event {
+				zwlr_data_control_device_v1::Event::DataOffer { id } => {
+					// The MIME types are announced before the offer becomes a selection.
+					id.as_ref().user_data().set_threadsafe(|| Mutex::new(Vec::<String>::new()));
+					id.quick_assign(|offer, event, _| {
+						if let zwlr_data_control_offer_v1::Event::Offer { mime_type } = event {
+							if let Some(mime_types) =
+								offer.as_ref().user_data().get::<Mutex<Vec<String>>>()
+							{
+								mime_types.lock().push(mime_type);
+							}
+						}
+					});
+					return;
+				}
+				zwlr_data_control_device_v1::Event::Selection { id } => {
+					(LinuxClipboardKind::Clipboard, id)
+				}
+				zwlr_data_control_device_v1::Event::PrimarySelection { id } => {
+					(LinuxClipboardKind::Primary, id)
+				}
+				zwlr_data_control_device_v1::Event::Finished => {
+					device.destroy();
+					return;
+				}
+				_ => return,
+			};
+			// Even if the event is skipped, the offer is wrapped so that it gets destroyed.
+			let offer = offer.map(|offer| {
+				let mime_types = offer
+					.as_ref()
+					.user_data()
+					.get::<Mutex<Vec<String>>>()
+					.map(|mime_types| mime_types.lock().clone())
+					.unwrap_or_default();
+				Arc::new(WaylandOffer { offer, display: display.clone(), mime_types })
+			});
+			if started.get() {
+				on_change(ClipboardEvent { selection, owner: None, offer });
+			}
+		});
+	}
+	queue.sync_roundtrip(&mut (), |_, _, _| {}).map_err(into_unknown)?;
+	started.set(true);
+
+	Ok((display, queue))
+}
+
+/// Dispatches the events of the watcher connection until `stop` becomes readable, which happens
+/// when the other end of the pipe is closed.
+fn watch_selections(
+	display: &Display,
+	queue: &mut EventQueue,
+	stop: &PipeReader,
+) -> io::Result<()> {
+	loop {
+		queue.dispatch_pending(&mut (), |_, _, _| {})?;
+		display.flush()?;
+		let guard = match queue.prepare_read() {
+			Some(guard) => guard,
+			// There are events left to dispatch.
+			None => continue,
+		};
+		let mut fds = [
+			libc::pollfd { fd: display.get_connection_fd(), events: libc::POLLIN, revents: 0 },
+			libc::pollfd { fd: stop.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+		];
+		if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+			guard.cancel();
+			let error = io::Error::last_os_error();
+			if error.kind() == io::ErrorKind::Interrupted {
+				continue;
+			}
+			return Err(error);
+		}
+		if fds[1].revents != 0 {
+			guard.cancel();
+			return Ok(());
+		}
+		if fds[0].revents != 0 {
+			guard.read_events()?;
+		} else {
+			guard.cancel();
+		}
+	}
+}
+
+/// Owns the thread that dispatches the events of a Wayland connection dedicated to watching.
+pub(crate) struct WaylandDataControlWatcher {
+	/// Dropping this stops the watcher thread.
+	stop: Option<PipeWriter>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl WaylandDataControlWatcher {
+	/// Starts a thread that calls `on_change` for every change of the selections.
+	pub(crate) fn new(on_change: impl Fn(ClipboardEvent) + Send + 'static) -> Result<Self, Error> {
+		let (stop_reader, stop_writer) = os_pipe::pipe().map_err(into_unknown)?;
+		let (init_sender, init_receiver) = mpsc::channel();
+		let thread = thread::spawn(move || {
+			let (display, mut queue) = match connect_watcher(Rc::new(on_change)) {
+				Ok(connection) => connection,
+				Err(e) => {
+					let _ = init_sender.send(Err(e));
+					return;
+				}
+			};
+			let _ = init_sender.send(Ok(()));
+			if let Err(e) = watch_selections(&display, &mut queue, &stop_reader) {
+				error!("Clipboard watcher thread errored with: {}", e);
+			}
+		});
+		match init_receiver.recv() {
+			Ok(Ok(())) => Ok(Self { stop: Some(stop_writer), thread: Some(thread) }),
+			Ok(Err(e)) => {
+				let _ = thread.join();
+				Err(e)
+			}
+			Err(_) => {
+				let _ = thread.join();
+				Err(Error::Unknown { description: "The clipboard watcher thread panicked".into() })
+			}
+		}
+	}
+}
+
+impl Drop for WaylandDataControlWatcher {
+	fn drop(&mut self) {
+		drop(self.stop.take());
+		if let Some(thread) = self.thread.take() {
+			if thread.join().is_err() {
+				error!("The clipboard watcher thread paniced.");
+			}
+		}
+	}
 }
@@ -0,0 +1,192 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2020 The arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+// Web pages can only access the clipboard through the asynchronous Clipboard API of the browser,
+// which can't be waited for from synchronous code on the main thread. That's why the synchronous
+// methods aren't supported here, and the asynchronous ones are available with the `wasm` feature.
+// See https://w3c.github.io/clipboard-apis/#async-clipboard-api
+
+use std::borrow::Cow;
+
+#[cfg(feature = "wasm")]
+use crate::common::PlatformError;
+#[cfg(feature = "image-data")]
+use crate::common::{decode_image, ImageData, ImageFormatTag};
+use crate::common::{ClipboardSnapshot, Error, Representation};
+
+#[cfg(feature = "wasm")]
+use js_sys::Promise;
+#[cfg(all(feature = "wasm", feature = "image-data"))]
+use js_sys::{Array, Object, Reflect, Uint8Array};
+#[cfg(feature = "wasm")]
+use wasm_bindgen::{JsCast, JsValue};
+#[cfg(feature = "wasm")]
+use wasm_bindgen_futures::JsFuture;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[cfg(all(feature = "wasm", feature = "image-data"))]
+const PNG: &str = "image/png";
+
+pub struct WebClipboardContext;
+
+impl WebClipboardContext {
+	pub(crate) fn new() -> Result<Self> {
+		Ok(WebClipboardContext)
+	}
+
+	pub(crate) fn get_text(&mut self) -> Result<String> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub(crate) fn get_text_bytes(&mut self, _format: &str) -> Result<Vec<u8>> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub(crate) fn set_text(&mut self, _text: Cow<'_, str>) -> Result<()> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub(crate) fn swap_text(&mut self, _text: String) -> Result<Option<String>> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub(crate) fn generation(&mut self) -> Result<u64> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub(crate) fn available_formats(&mut self) -> Result<Vec<String>> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub(crate) fn snapshot(&mut self, _max_bytes: Option<usize>) -> Result<ClipboardSnapshot> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	/// The browser keeps the contents.
+	pub(crate) fn persist(&mut self) -> Result<()> {
+		Ok(())
+	}
+
+	pub(crate) fn restore(&mut self, _snapshot: &ClipboardSnapshot) -> Result<()> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub(crate) fn write_all(&mut self, _representations: &[Representation]) -> Result<()> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	#[cfg(feature = "image-data")]
+	pub(crate) fn get_image(&mut self) -> Result<ImageData<'static>> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	#[cfg(feature = "image-data")]
+	pub(crate) fn get_image_with_format(&mut self) -> Result<(ImageFormatTag, ImageData<'static>)> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	#[cfg(feature = "image-data")]
+	pub(crate) fn set_image(&mut self, _image: ImageData) -> Result<()> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	#[cfg(feature = "wasm")]
+	pub(crate) async fn get_text_async(&mut self) -> Result<String> {
+		let text =
+			wait(clipboard()?.read_text()).await?.as_string().ok_or(Error::ConversionFailure)?;
+		// The browser returns an empty string if the clipboard doesn't hold text.
+		if text.is_empty() {
+			return Err(Error::ContentNotAvailable);
+		}
+		Ok(text)
+	}
+
+	#[cfg(feature = "wasm")]
+	pub(crate) async fn set_text_async(&mut self, text: String) -> Result<()> {
+		wait(clipboard()?.write_text(&text)).await?;
+		Ok(())
+	}
+
+	#[cfg(all(feature = "wasm", feature = "image-data"))]
+	pub(crate) async fn get_image_async(&mut self) -> Result<ImageData<'static>> {
+		let items: Array = wait(clipboard()?.read()).await?.unchecked_into();
+		for item in items.iter() {
+			let item: web_sys::ClipboardItem = item.unchecked_into();
+			if !item.types().includes(&JsValue::from_str(PNG), 0) {
+				continue;
+			}
+			let blob: web_sys::Blob = wait(item.get_type(PNG)).await?.unchecked_into();
+			let png = Uint8Array::new(&wait(blob.array_buffer()).await?).to_vec();
+			return decode_image(ImageFormatTag::Png, &png);
+		}
+		Err(Error::ContentNotAvailable)
+	}
+
+	#[cfg(all(feature = "wasm", feature = "image-data"))]
+	pub(crate) async fn set_image_async(&mut self, image: ImageData<'_>) -> Result<()> {
+		let image = image::RgbaImage::from_raw(
+			image.width as u32,
+			image.height as u32,
+			image.bytes.into_owned(),
+		)
+		.ok_or(Error::ConversionFailure)?;
+		let mut png = Vec::new();
+		image::DynamicImage::ImageRgba8(image)
+			.write_to(&mut png, image::ImageFormat::Png)
+			.map_err(|_| Error::ConversionFailure)?;
+
+		let options = web_sys::BlobPropertyBag::new();
+		options.set_type(PNG);
+		let parts = Array::of1(&Uint8Array::from(png.as_slice()));
+		let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+			.map_err(js_error)?;
+		let record = Object::new();
+		Reflect::set(&record, &JsValue::from_str(PNG), &blob).map_err(js_error)?;
+		let item = web_sys::ClipboardItem::new_with_record_from_str_to_blob_promise(&record)
+			.map_err(js_error)?;
+		wait(clipboard()?.write(&Array::of1(&item))).await?;
+		Ok(())
+	}
+}
+
+/// Returns the clipboard of the browser, which is only available in secure contexts.
+#[cfg(feature = "wasm")]
+fn clipboard() -> Result<web_sys::Clipboard> {
+	let window = web_sys::window().ok_or(Error::ClipboardNotSupported)?;
+	if !window.is_secure_context() {
+		return Err(Error::ClipboardNotSupported);
+	}
+	let clipboard = window.navigator().clipboard();
+	if clipboard.is_undefined() {
+		return Err(Error::ClipboardNotSupported);
+	}
+	Ok(clipboard)
+}
+
+#[cfg(feature = "wasm")]
+async fn wait(promise: Promise) -> Result<JsValue> {
+	JsFuture::from(promise).await.map_err(js_error)
+}
+
+/// Converts the value that a promise of the Clipboard API was rejected with.
+#[cfg(feature = "wasm")]
+fn js_error(value: JsValue) -> Error {
+	match value.dyn_ref::<web_sys::DomException>().map(|exception| exception.name()).as_deref() {
+		// Browsers differ in which exception they use when the permission wasn't granted, or when
+		// the call didn't happen in response to a user gesture.
+		Some("NotAllowedError") | Some("SecurityError") => Error::PermissionDenied,
+		Some("NotFoundError") => Error::ContentNotAvailable,
+		Some("DataError") => Error::ConversionFailure,
+		_ => Error::Platform(
+			PlatformError::new("navigator.clipboard").with_detail(format!("{:?}", value)),
+		),
+	}
+}
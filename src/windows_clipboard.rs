@@ -10,20 +10,23 @@ and conditions of the chosen license apply to this file.
 
 #[cfg(feature = "image-data")]
 use std::io::{self, Read, Seek};
+use std::{
+	borrow::Cow,
+	cell::RefCell,
+	mem, panic, ptr,
+	sync::mpsc,
+	thread,
+	time::{Duration, Instant},
+};
 
 use clipboard_win::Clipboard as SystemClipboard;
 #[cfg(feature = "image-data")]
 use image::{
 	bmp::{BmpDecoder, BmpEncoder},
+	png::PngEncoder,
 	ColorType, ImageDecoder,
 };
 use scopeguard::defer;
-use winapi::um::{
-	stringapiset::WideCharToMultiByte,
-	winbase::{GlobalLock, GlobalSize, GlobalUnlock},
-	winnls::CP_UTF8,
-	winuser::{GetClipboardData, CF_UNICODETEXT},
-};
 #[cfg(feature = "image-data")]
 use winapi::{
 	shared::minwindef::DWORD,
@@ -33,15 +36,51 @@ use winapi::{
 			DIB_RGB_COLORS,
 		},
 		winnt::LONG,
-		winuser::{GetDC, SetClipboardData, CF_BITMAP},
+		winuser::{GetDC, CF_BITMAP},
+	},
+};
+use winapi::{
+	shared::{
+		minwindef::{LPARAM, LRESULT, UINT, WPARAM},
+		windef::HWND,
+		winerror::{ERROR_ACCESS_DENIED, ERROR_CLASS_ALREADY_EXISTS},
+	},
+	um::{
+		errhandlingapi::GetLastError,
+		libloaderapi::GetModuleHandleW,
+		winbase::{GlobalLock, GlobalSize, GlobalUnlock},
+		winuser::{
+			AddClipboardFormatListener, CreateWindowExW, DefWindowProcW, DestroyWindow,
+			DispatchMessageW, GetClipboardData, GetClipboardOwner, GetClipboardSequenceNumber,
+			GetMessageW, PostMessageW, PostQuitMessage, RegisterClassExW,
+			RemoveClipboardFormatListener, SetClipboardData, TranslateMessage, CF_UNICODETEXT,
+			HWND_MESSAGE, MSG, WM_CLIPBOARDUPDATE, WM_CLOSE, WM_DESTROY, WM_DESTROYCLIPBOARD,
+			WM_RENDERALLFORMATS, WM_RENDERFORMAT, WNDCLASSEXW,
+		},
 	},
 };
 
-use super::common::Error;
 #[cfg(feature = "image-data")]
-use super::common::ImageData;
+use super::common::{decode_image, ImageData, ImageFormatTag};
+use super::common::{
+	text_from_utf16, wrap_html, ClipboardEvent, ClipboardSnapshot, Error, PlatformError,
+	Representation,
+};
 
-const MAX_OPEN_ATTEMPTS: usize = 5;
+/// How often opening the clipboard is attempted by default while another process holds it open.
+const DEFAULT_OPEN_ATTEMPTS: usize = 5;
+/// The time waited after the first failed attempt by default. It doubles after every attempt, so
+/// that the default attempts take about 100 ms in total.
+const DEFAULT_OPEN_RETRY_DELAY: Duration = Duration::from_millis(7);
+
+/// The registered formats that keep the contents out of the clipboard history and the cloud
+/// clipboard of Windows 10 and later, and out of clipboard monitors that honor them. The value of
+/// the last two is a `DWORD` that's zero, the first one only has to be present.
+pub(crate) const EXCLUDE_FROM_HISTORY: &[(&str, [u8; 4])] = &[
+	("ExcludeClipboardContentFromMonitorProcessing", [0; 4]),
+	("CanIncludeInClipboardHistory", [0; 4]),
+	("CanUploadToCloudClipboard", [0; 4]),
+];
 
 #[cfg(feature = "image-data")]
 const BITMAP_FILE_HEADER_SIZE: usize = 14;
@@ -111,6 +150,14 @@ impl Read for FakeBitmapFile {
 	}
 }
 
+/// Describes the failure of `api` with the error code it reported, either directly or through
+/// `GetLastError`.
+fn system_error(api: &'static str, code: i32) -> PlatformError {
+	PlatformError::new(api)
+		.with_code(code.into())
+		.with_source(std::io::Error::from_raw_os_error(code))
+}
+
 #[cfg(feature = "image-data")]
 unsafe fn add_cf_bitmap(image: &ImageData) -> Result<(), Error> {
 	let header = BITMAPV4HEADER {
@@ -147,17 +194,17 @@ unsafe fn add_cf_bitmap(image: &ImageData) -> Result<(), Error> {
 		DIB_RGB_COLORS,
 	);
 	if SetClipboardData(CF_BITMAP, hbitmap as _).is_null() {
+		let error =
+			system_error("SetClipboardData", GetLastError() as i32).with_detail("CF_BITMAP");
 		DeleteObject(hbitmap as _);
-		return Err(Error::Unknown {
-			description: String::from("Call to `SetClipboardData` returned NULL"),
-		});
+		return Err(Error::Platform(error));
 	}
 	Ok(())
 }
 
-pub fn get_string(out: &mut Vec<u8>) -> Result<(), Error> {
+/// Reads the `CF_UNICODETEXT` text, see `text_from_utf16`.
+fn get_string() -> Result<String, Error> {
 	use std::mem;
-	use std::ptr;
 
 	// This pointer must not be free'd.
 	let ptr = unsafe { GetClipboardData(CF_UNICODETEXT) };
@@ -168,122 +215,686 @@ pub fn get_string(out: &mut Vec<u8>) -> Result<(), Error> {
 	unsafe {
 		let data_ptr = GlobalLock(ptr);
 		if data_ptr.is_null() {
-			return Err(Error::Unknown {
-				description: "GlobalLock on clipboard data returned null.".into(),
-			});
+			return Err(Error::Platform(system_error("GlobalLock", GetLastError() as i32)));
 		}
 		defer!( GlobalUnlock(ptr); );
 
+		// The memory block can be larger than the text, which ends at the first null.
 		let char_count = GlobalSize(ptr) as usize / mem::size_of::<u16>();
-		let storage_req_size = WideCharToMultiByte(
-			CP_UTF8,
-			0,
-			data_ptr as _,
-			char_count as _,
-			ptr::null_mut(),
-			0,
-			ptr::null(),
-			ptr::null_mut(),
-		);
-		if storage_req_size == 0 {
-			return Err(Error::ConversionFailure);
+		text_from_utf16(std::slice::from_raw_parts(data_ptr as *const u16, char_count))
+	}
+}
+
+/// The standard clipboard formats whose data is stored in global memory, together with the names
+/// `clipboard_win::raw::format_name_big` reports for them. Registered formats are identified by
+/// their registered name instead.
+const MEMORY_FORMATS: &[(&str, u32)] = &[
+	("CF_TEXT", clipboard_win::formats::CF_TEXT),
+	("CF_OEMTEXT", clipboard_win::formats::CF_OEMTEXT),
+	("CF_UNICODETEXT", clipboard_win::formats::CF_UNICODETEXT),
+	("CF_LOCALE", clipboard_win::formats::CF_LOCALE),
+	("CF_DIB", clipboard_win::formats::CF_DIB),
+	("CF_DIBV5", clipboard_win::formats::CF_DIBV5),
+	("CF_DIF", clipboard_win::formats::CF_DIF),
+	("CF_SYLK", clipboard_win::formats::CF_SYLK),
+	("CF_TIFF", clipboard_win::formats::CF_TIFF),
+	("CF_RIFF", clipboard_win::formats::CF_RIFF),
+	("CF_WAVE", clipboard_win::formats::CF_WAVE),
+	("CF_PENDATA", clipboard_win::formats::CF_PENDATA),
+	("CF_HDROP", clipboard_win::formats::CF_HDROP),
+	("CF_DSPTEXT", clipboard_win::formats::CF_DSPTEXT),
+];
+
+/// Returns the clipboard format identified by `name` in a `ClipboardSnapshot`.
+fn format_from_name(name: &str) -> Option<u32> {
+	match MEMORY_FORMATS.iter().find(|(n, _)| *n == name) {
+		Some((_, format)) => Some(*format),
+		None if name.starts_with("CF_") => None,
+		None => clipboard_win::raw::register_format(name).map(|f| f.get()),
+	}
+}
+
+/// Places the representations onto the clipboard, which must be open and emptied already.
+fn place_representations(representations: &[Representation]) -> Result<(), Error> {
+	for representation in representations {
+		let (format, bytes) = match representation {
+			Representation::Text(text) => {
+				let wide = text.encode_utf16().chain(std::iter::once(0));
+				let bytes = wide.flat_map(u16::to_le_bytes).collect::<Vec<u8>>();
+				(clipboard_win::formats::CF_UNICODETEXT, bytes)
+			}
+			Representation::Html(html) => {
+				let mut bytes = wrap_html(html).into_bytes();
+				bytes.push(0);
+				(format_from_name("HTML Format").unwrap_or_default(), bytes)
+			}
+			Representation::Custom(name, bytes) => {
+				let format = format_from_name(name)
+					.ok_or_else(|| Error::UnsupportedFormat { format: name.clone() })?;
+				(format, bytes.clone())
+			}
+		};
+		if format == 0 {
+			let error = system_error("RegisterClipboardFormatW", unsafe { GetLastError() } as i32);
+			return Err(Error::Platform(error.with_detail("HTML Format")));
 		}
+		clipboard_win::raw::set_without_clear(format, &bytes)
+			.map_err(|e| Error::Platform(system_error("SetClipboardData", e.raw_code())))?;
+	}
+	Ok(())
+}
+
+/// Returns the registered `PNG` clipboard format, which is used by most applications that
+/// place images with an alpha channel onto the clipboard.
+#[cfg(feature = "image-data")]
+fn png_format() -> Option<u32> {
+	clipboard_win::raw::register_format("PNG").map(|f| f.get())
+}
+
+/// Decodes the data of `CF_DIB`, a bitmap without the file header.
+#[cfg(feature = "image-data")]
+pub(crate) fn decode_dib(data: Vec<u8>) -> Result<ImageData<'static>, Error> {
+	use std::convert::TryInto;
+
+	if data.len() < 4 {
+		return Err(Error::ConversionFailure);
+	}
+	let info_header_size = u32::from_le_bytes(data[..4].try_into().unwrap());
+	let mut fake_bitmap_file =
+		FakeBitmapFile { bitmap: data, file_header: [0; BITMAP_FILE_HEADER_SIZE], curr_pos: 0 };
+	fake_bitmap_file.file_header[0] = b'B';
+	fake_bitmap_file.file_header[1] = b'M';
+
+	let file_size =
+		u32::to_le_bytes((fake_bitmap_file.bitmap.len() + BITMAP_FILE_HEADER_SIZE) as u32);
+	fake_bitmap_file.file_header[2..6].copy_from_slice(&file_size);
 
-		let storage_cursor = out.len();
-		out.reserve(storage_req_size as usize);
-		let storage_ptr = out.as_mut_ptr().add(storage_cursor) as *mut _;
-		let output_size = WideCharToMultiByte(
-			CP_UTF8,
-			0,
-			data_ptr as _,
-			char_count as _,
-			storage_ptr,
-			storage_req_size,
-			ptr::null(),
-			ptr::null_mut(),
-		);
-		if output_size == 0 {
-			return Err(Error::ConversionFailure);
+	let data_offset = u32::to_le_bytes(info_header_size + BITMAP_FILE_HEADER_SIZE as u32);
+	fake_bitmap_file.file_header[10..14].copy_from_slice(&data_offset);
+
+	// The decoder rejects the bitmap variants it doesn't implement, like JPEG compression.
+	let bmp_decoder = BmpDecoder::new(fake_bitmap_file)
+		.map_err(|_| Error::UnsupportedFormat { format: "CF_DIB".into() })?;
+	let (w, h) = bmp_decoder.dimensions();
+	let width = w as usize;
+	let height = h as usize;
+	let image =
+		image::DynamicImage::from_decoder(bmp_decoder).map_err(|_| Error::ConversionFailure)?;
+	Ok(ImageData { width, height, bytes: Cow::from(image.into_rgba8().into_raw()) })
+}
+
+#[derive(Clone)]
+pub struct WindowsClipboardContext {
+	open_attempts: usize,
+	open_retry_delay: Duration,
+	/// How long opening the clipboard may take in total, see `ClipboardBuilder::timeout`.
+	open_timeout: Option<Duration>,
+}
+
+thread_local! {
+	/// Where the events of the watcher window that was created on this thread are sent to.
+	static WATCHER_EVENTS: RefCell<Option<mpsc::Sender<ClipboardEvent>>> = const { RefCell::new(None) };
+}
+
+unsafe extern "system" fn watcher_window_proc(
+	window: HWND,
+	message: UINT,
+	wparam: WPARAM,
+	lparam: LPARAM,
+) -> LRESULT {
+	match message {
+		WM_CLIPBOARDUPDATE => {
+			WATCHER_EVENTS.with(|events| {
+				if let Some(events) = &*events.borrow() {
+					// The receiver only goes away while the watcher is being dropped.
+					let _ = events.send(ClipboardEvent {});
+				}
+			});
+			0
+		}
+		WM_CLOSE => {
+			RemoveClipboardFormatListener(window);
+			DestroyWindow(window);
+			0
 		}
-		out.set_len(storage_cursor + storage_req_size as usize);
+		WM_DESTROY => {
+			PostQuitMessage(0);
+			0
+		}
+		_ => DefWindowProcW(window, message, wparam, lparam),
+	}
+}
 
-		//It seems WinAPI always supposed to have at the end null char.
-		//But just to be safe let's check for it and only then remove.
-		if let Some(last_byte) = out.last() {
-			if *last_byte == 0 {
-				out.set_len(out.len() - 1);
+/// Creates a message-only window of a class named after `purpose`, whose messages are handled by
+/// `window_proc`.
+unsafe fn create_message_window(
+	purpose: &str,
+	window_proc: unsafe extern "system" fn(HWND, UINT, WPARAM, LPARAM) -> LRESULT,
+) -> Result<HWND, Error> {
+	let class_name: Vec<u16> = format!("arboard {}\0", purpose).encode_utf16().collect();
+	let instance = GetModuleHandleW(ptr::null());
+	let mut class: WNDCLASSEXW = mem::zeroed();
+	class.cbSize = mem::size_of::<WNDCLASSEXW>() as UINT;
+	class.lpfnWndProc = Some(window_proc);
+	class.hInstance = instance;
+	class.lpszClassName = class_name.as_ptr();
+	// The class is registered by the first window and then reused by all the others.
+	if RegisterClassExW(&class) == 0 {
+		let code = GetLastError();
+		if code != ERROR_CLASS_ALREADY_EXISTS {
+			let error = system_error("RegisterClassExW", code as i32);
+			return Err(Error::Platform(
+				error.with_detail(format!("the {} window class", purpose)),
+			));
+		}
+	}
+	let window = CreateWindowExW(
+		0,
+		class_name.as_ptr(),
+		ptr::null(),
+		0,
+		0,
+		0,
+		0,
+		0,
+		HWND_MESSAGE,
+		ptr::null_mut(),
+		instance,
+		ptr::null_mut(),
+	);
+	if window.is_null() {
+		let error = system_error("CreateWindowExW", GetLastError() as i32);
+		return Err(Error::Platform(error.with_detail(format!("the {} window", purpose))));
+	}
+	Ok(window)
+}
+
+/// Runs the message loop of the current thread until `WM_QUIT` is received.
+unsafe fn pump_messages() {
+	let mut message: MSG = mem::zeroed();
+	// Returns 0 on WM_QUIT, and -1 on failure.
+	while GetMessageW(&mut message, ptr::null_mut(), 0, 0) > 0 {
+		TranslateMessage(&message);
+		DispatchMessageW(&message);
+	}
+}
+
+/// Creates a message-only window that receives `WM_CLIPBOARDUPDATE` messages.
+unsafe fn create_watcher_window() -> Result<HWND, Error> {
+	let window = create_message_window("clipboard watcher", watcher_window_proc)?;
+	if AddClipboardFormatListener(window) == 0 {
+		let error = system_error("AddClipboardFormatListener", GetLastError() as i32);
+		DestroyWindow(window);
+		return Err(Error::Platform(error));
+	}
+	Ok(window)
+}
+
+/// Owns the thread that pumps the messages of the watcher window.
+pub(crate) struct WindowsClipboardWatcher {
+	/// The `HWND` of the watcher window, which isn't `Send`.
+	window: usize,
+	thread: Option<thread::JoinHandle<()>>,
+}
+
+impl WindowsClipboardWatcher {
+	pub(crate) fn new(events: mpsc::Sender<ClipboardEvent>) -> Result<Self, Error> {
+		let (init_sender, init_receiver) = mpsc::channel();
+		let thread = thread::Builder::new()
+			.name("arboard clipboard watcher".into())
+			.spawn(move || {
+				WATCHER_EVENTS.with(|cell| *cell.borrow_mut() = Some(events));
+				let window = match unsafe { create_watcher_window() } {
+					Ok(window) => window,
+					Err(e) => {
+						let _ = init_sender.send(Err(e));
+						return;
+					}
+				};
+				let _ = init_sender.send(Ok(window as usize));
+				unsafe { pump_messages() };
+			})
+			.map_err(|e| Error::Unknown {
+				description: format!("Could not start the clipboard watcher thread: {}", e),
+			})?;
+		match init_receiver.recv() {
+			Ok(Ok(window)) => Ok(Self { window, thread: Some(thread) }),
+			Ok(Err(e)) => {
+				let _ = thread.join();
+				Err(e)
+			}
+			Err(_) => {
+				let _ = thread.join();
+				Err(Error::Unknown { description: "The clipboard watcher thread panicked".into() })
 			}
 		}
 	}
-	Ok(())
 }
 
-pub struct WindowsClipboardContext;
+impl Drop for WindowsClipboardWatcher {
+	fn drop(&mut self) {
+		// The window can only be destroyed from its own thread, so ask it to close itself.
+		unsafe { PostMessageW(self.window as HWND, WM_CLOSE, 0, 0) };
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+/// Produces the data of a format that's rendered on demand.
+pub(crate) type RenderFn = Box<dyn FnOnce() -> Vec<u8> + Send>;
+
+/// The formats whose data the provider window on this thread hasn't rendered yet.
+struct DelayedFormats {
+	context: WindowsClipboardContext,
+	formats: Vec<(u32, RenderFn)>,
+	on_drop: DelayedRenderingDrop,
+}
+
+thread_local! {
+	/// The state of the provider window that was created on this thread.
+	static DELAYED_FORMATS: RefCell<Option<DelayedFormats>> = const { RefCell::new(None) };
+}
+
+/// Renders the data of `format` and places it onto the clipboard, which must be open already.
+///
+/// Does nothing if the format was rendered before. If the callback panics, the format is left
+/// without data, as the panic can't unwind out of the window procedure.
+fn render_format(format: u32) {
+	let render = DELAYED_FORMATS.with(|state| {
+		let mut state = state.borrow_mut();
+		let formats = &mut state.as_mut()?.formats;
+		let index = formats.iter().position(|(f, _)| *f == format)?;
+		Some(formats.swap_remove(index).1)
+	});
+	// The callback runs without the state borrowed, so that it can't observe it in any way.
+	if let Some(render) = render {
+		if let Ok(bytes) = panic::catch_unwind(panic::AssertUnwindSafe(render)) {
+			let _ = clipboard_win::raw::set_without_clear(format, &bytes);
+		}
+	}
+}
+
+/// Opens the clipboard on behalf of the provider window on this thread, and returns `None` if
+/// that fails or the window doesn't own the clipboard anymore.
+unsafe fn open_owned(window: HWND) -> Option<SystemClipboard> {
+	let context = DELAYED_FORMATS.with(|state| state.borrow().as_ref().map(|s| s.context.clone()));
+	let clipboard = context?.open_for(window).ok()?;
+	// Another application may have placed its contents onto the clipboard in the meantime.
+	if GetClipboardOwner() != window {
+		return None;
+	}
+	Some(clipboard)
+}
+
+/// Renders all remaining formats, if `window` still owns the clipboard.
+unsafe fn render_all_formats(window: HWND) {
+	let _cb = match open_owned(window) {
+		Some(clipboard) => clipboard,
+		None => return,
+	};
+	let formats = DELAYED_FORMATS.with(|state| match &*state.borrow() {
+		Some(state) => state.formats.iter().map(|(format, _)| *format).collect(),
+		None => Vec::new(),
+	});
+	for format in formats {
+		render_format(format);
+	}
+}
+
+unsafe extern "system" fn provider_window_proc(
+	window: HWND,
+	message: UINT,
+	wparam: WPARAM,
+	lparam: LPARAM,
+) -> LRESULT {
+	match message {
+		// The application that requested the data has the clipboard open already.
+		WM_RENDERFORMAT => {
+			render_format(wparam as u32);
+			0
+		}
+		// Sent before the window is destroyed while it still has formats that weren't rendered,
+		// which happens when the handle is dropped.
+		WM_RENDERALLFORMATS => {
+			render_all_formats(window);
+			0
+		}
+		// Something else was placed onto the clipboard, so nothing has to be rendered anymore.
+		WM_DESTROYCLIPBOARD => {
+			DELAYED_FORMATS.with(|state| {
+				if let Some(state) = &mut *state.borrow_mut() {
+					state.formats.clear();
+				}
+			});
+			0
+		}
+		WM_CLOSE => {
+			let empty = DELAYED_FORMATS.with(
+				|state| matches!(&*state.borrow(), Some(s) if s.on_drop == DelayedRenderingDrop::Empty),
+			);
+			if empty {
+				if let Some(_cb) = open_owned(window) {
+					// Sends WM_DESTROYCLIPBOARD, so no formats are left to render below.
+					let _ = clipboard_win::raw::empty();
+				}
+			}
+			DestroyWindow(window);
+			0
+		}
+		WM_DESTROY => {
+			PostQuitMessage(0);
+			0
+		}
+		_ => DefWindowProcW(window, message, wparam, lparam),
+	}
+}
+
+/// Creates the provider window, and places the representations and the delayed formats onto the
+/// clipboard on its behalf.
+unsafe fn create_provider_window(
+	context: &WindowsClipboardContext,
+	representations: &[Representation],
+	delayed: Vec<(String, RenderFn)>,
+) -> Result<(HWND, Vec<(u32, RenderFn)>), Error> {
+	let mut formats = Vec::with_capacity(delayed.len());
+	for (name, render) in delayed {
+		let format = format_from_name(&name)
+			.ok_or_else(|| Error::UnsupportedFormat { format: name.clone() })?;
+		formats.push((format, render));
+	}
+	let window = create_message_window("clipboard provider", provider_window_proc)?;
+	let result = context.open_for(window).and_then(|_cb| {
+		clipboard_win::raw::empty()
+			.map_err(|e| Error::Platform(system_error("EmptyClipboard", e.raw_code())))?;
+		place_representations(representations)?;
+		for (format, _) in &formats {
+			// Passing no data asks the system to send WM_RENDERFORMAT when it's requested.
+			SetClipboardData(*format, ptr::null_mut());
+		}
+		Ok(())
+	});
+	match result {
+		Ok(()) => Ok((window, formats)),
+		Err(e) => {
+			DestroyWindow(window);
+			Err(e)
+		}
+	}
+}
+
+impl WindowsClipboardContext {
+	/// Places the contents onto the clipboard, with the data of `delayed` rendered on demand by a
+	/// window that lives on a thread of its own.
+	pub(crate) fn write_delayed(
+		&self,
+		representations: &[Representation],
+		delayed: Vec<(String, RenderFn)>,
+		on_drop: DelayedRenderingDrop,
+	) -> Result<DelayedRendering, Error> {
+		let (init_sender, init_receiver) = mpsc::channel();
+		let context = self.clone();
+		let representations = representations.to_vec();
+		let thread = thread::Builder::new()
+			.name("arboard clipboard provider".into())
+			.spawn(move || {
+				let window = match unsafe {
+					create_provider_window(&context, &representations, delayed)
+				} {
+					Ok((window, formats)) => {
+						DELAYED_FORMATS.with(|state| {
+							*state.borrow_mut() = Some(DelayedFormats { context, formats, on_drop })
+						});
+						window
+					}
+					Err(e) => {
+						let _ = init_sender.send(Err(e));
+						return;
+					}
+				};
+				let _ = init_sender.send(Ok(window as usize));
+				unsafe { pump_messages() };
+			})
+			.map_err(|e| Error::Unknown {
+				description: format!("Could not start the clipboard provider thread: {}", e),
+			})?;
+		match init_receiver.recv() {
+			Ok(Ok(window)) => Ok(DelayedRendering { window: Some(window), thread: Some(thread) }),
+			Ok(Err(e)) => {
+				let _ = thread.join();
+				Err(e)
+			}
+			Err(_) => {
+				let _ = thread.join();
+				Err(Error::Unknown { description: "The clipboard provider thread panicked".into() })
+			}
+		}
+	}
+}
 
 impl WindowsClipboardContext {
 	pub(crate) fn new() -> Result<Self, Error> {
-		Ok(WindowsClipboardContext)
+		Ok(WindowsClipboardContext {
+			open_attempts: DEFAULT_OPEN_ATTEMPTS,
+			open_retry_delay: DEFAULT_OPEN_RETRY_DELAY,
+			open_timeout: None,
+		})
+	}
+
+	/// Opens the clipboard, which is closed again when the returned object is dropped.
+	///
+	/// `OpenClipboard` fails with `ERROR_ACCESS_DENIED` while another process has the clipboard
+	/// open, which usually only lasts a few milliseconds. In that case opening is retried with an
+	/// increasing delay, and `Error::ClipboardOccupied` is returned once all attempts failed, or
+	/// `Error::Timeout` if the next attempt would be made after the timeout. Any other error is
+	/// returned right away.
+	fn open(&self) -> Result<SystemClipboard, Error> {
+		self.open_for(ptr::null_mut())
+	}
+
+	/// Opens the clipboard like `open`, on behalf of `owner`. The window that has the clipboard
+	/// open while it's emptied becomes the clipboard owner.
+	fn open_for(&self, owner: HWND) -> Result<SystemClipboard, Error> {
+		let deadline = self.open_timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+		let mut delay = self.open_retry_delay;
+		for attempt in 1.. {
+			match SystemClipboard::new_for(owner) {
+				Ok(clipboard) => return Ok(clipboard),
+				Err(e) if e.raw_code() == ERROR_ACCESS_DENIED as i32 => {
+					if attempt >= self.open_attempts {
+						break;
+					}
+					if deadline.is_some_and(|deadline| Instant::now() + delay > deadline) {
+						return Err(Error::Timeout);
+					}
+					thread::sleep(delay);
+					delay = delay.saturating_mul(2);
+				}
+				Err(e) => return Err(Error::Platform(system_error("OpenClipboard", e.raw_code()))),
+			}
+		}
+		Err(Error::ClipboardOccupied)
+	}
+
+	pub(crate) fn reopen(&self) -> Option<crate::common::Reopen<Self>> {
+		let context = self.clone();
+		Some(Box::new(move || Ok(context)))
+	}
+
+	pub(crate) fn set_open_timeout(&mut self, timeout: Duration) {
+		self.open_timeout = Some(timeout);
+	}
+
+	pub(crate) fn set_open_retry(&mut self, attempts: usize, delay: Duration) {
+		self.open_attempts = attempts;
+		self.open_retry_delay = delay;
 	}
+
 	pub(crate) fn get_text(&mut self) -> Result<String, Error> {
 		// Using this nifty RAII object to open and close the clipboard.
-		let _cb = SystemClipboard::new_attempts(MAX_OPEN_ATTEMPTS)
-			.map_err(|_| Error::ClipboardOccupied)?;
-		let mut result = String::new();
-		get_string(unsafe { result.as_mut_vec() })?;
-		Ok(result)
-	}
-	pub(crate) fn set_text(&mut self, data: String) -> Result<(), Error> {
-		let _cb = SystemClipboard::new_attempts(MAX_OPEN_ATTEMPTS)
-			.map_err(|_| Error::ClipboardOccupied)?;
-		clipboard_win::set(clipboard_win::formats::Unicode, data).map_err(|_| Error::Unknown {
-			description: "Could not place the specified text to the clipboard".into(),
-		})
+		let _cb = self.open()?;
+		get_string()
+	}
+	pub(crate) fn get_text_bytes(&mut self, format: &str) -> Result<Vec<u8>, Error> {
+		let _cb = self.open()?;
+		let format = format_from_name(format).ok_or(Error::ContentNotAvailable)?;
+		if !clipboard_win::raw::is_format_avail(format) {
+			return Err(Error::ContentNotAvailable);
+		}
+		let mut bytes = Vec::new();
+		clipboard_win::raw::get_vec(format, &mut bytes)
+			.map_err(|e| Error::Platform(system_error("GetClipboardData", e.raw_code())))?;
+		Ok(bytes)
+	}
+
+	pub(crate) fn set_text(&mut self, data: Cow<'_, str>) -> Result<(), Error> {
+		self.set_text_with_generation(data).map(drop)
+	}
+
+	/// Returns the sequence number as it was right after the write. It's read before closing the
+	/// clipboard, so no other application can have modified the contents in between.
+	pub(crate) fn set_text_with_generation(&mut self, data: Cow<'_, str>) -> Result<u64, Error> {
+		let _cb = self.open()?;
+		clipboard_win::set(clipboard_win::formats::Unicode, &*data)
+			.map_err(|e| Error::Platform(system_error("SetClipboardData", e.raw_code())))?;
+		Ok(unsafe { GetClipboardSequenceNumber() } as u64)
+	}
+
+	pub(crate) fn write_all(&mut self, representations: &[Representation]) -> Result<(), Error> {
+		let _cb = self.open()?;
+		clipboard_win::raw::empty()
+			.map_err(|e| Error::Platform(system_error("EmptyClipboard", e.raw_code())))?;
+		place_representations(representations)
+	}
+
+	pub(crate) fn swap_text(&mut self, data: String) -> Result<Option<String>, Error> {
+		// Keeping the clipboard open for both steps guarantees that nobody else can modify the
+		// contents between reading and writing.
+		let _cb = self.open()?;
+		let previous = match get_string() {
+			Ok(previous) => Some(previous),
+			Err(Error::ContentNotAvailable) => None,
+			Err(e) => return Err(e),
+		};
+		clipboard_win::set(clipboard_win::formats::Unicode, data)
+			.map_err(|e| Error::Platform(system_error("SetClipboardData", e.raw_code())))?;
+		Ok(previous)
+	}
+
+	/// Returns the names of the formats on the clipboard. The standard formats are named after
+	/// their constants, like `CF_UNICODETEXT`.
+	pub(crate) fn available_formats(&mut self) -> Result<Vec<String>, Error> {
+		let _cb = self.open()?;
+		Ok(clipboard_win::raw::EnumFormats::new()
+			.map(|format| {
+				clipboard_win::raw::format_name_big(format)
+					.unwrap_or_else(|| format!("#{}", format))
+			})
+			.collect())
+	}
+
+	pub(crate) fn snapshot(
+		&mut self,
+		max_bytes: Option<usize>,
+	) -> Result<ClipboardSnapshot, Error> {
+		let _cb = self.open()?;
+		let mut snapshot = ClipboardSnapshot::default();
+		snapshot.new_item();
+		for format in clipboard_win::raw::EnumFormats::new() {
+			let name = match clipboard_win::raw::format_name_big(format) {
+				Some(name) => name,
+				None => {
+					snapshot.skip(&format!("#{}", format), "the format has no name");
+					continue;
+				}
+			};
+			let is_standard = format < 0xC000;
+			if is_standard && !MEMORY_FORMATS.iter().any(|(_, f)| *f == format) {
+				// GDI handles and private formats can't be copied as bytes.
+				snapshot.skip(&name, "the data is not stored in global memory");
+				continue;
+			}
+			let mut bytes = Vec::new();
+			match clipboard_win::raw::get_vec(format, &mut bytes) {
+				Ok(_) => snapshot.push(name, bytes, max_bytes),
+				Err(e) => snapshot.skip(&name, e),
+			}
+		}
+		Ok(snapshot)
+	}
+
+	/// The data is placed onto the clipboard right away, so the system keeps it after the process
+	/// exits. The formats added with `commit_delayed` are the exception: they're only kept if the
+	/// `DelayedRendering` handle is dropped with `DelayedRenderingDrop::RenderAll` before the
+	/// process exits, as they're rendered by the thread that the handle owns.
+	pub(crate) fn persist(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+
+	pub(crate) fn restore(&mut self, snapshot: &ClipboardSnapshot) -> Result<(), Error> {
+		let _cb = self.open()?;
+		clipboard_win::raw::empty()
+			.map_err(|e| Error::Platform(system_error("EmptyClipboard", e.raw_code())))?;
+		for (name, bytes) in snapshot.items.iter().flatten() {
+			let format = format_from_name(name)
+				.ok_or_else(|| Error::UnsupportedFormat { format: name.clone() })?;
+			clipboard_win::raw::set_without_clear(format, bytes).map_err(|e| {
+				Error::Platform(system_error("SetClipboardData", e.raw_code()).with_detail(name))
+			})?;
+		}
+		Ok(())
+	}
+
+	pub(crate) fn generation(&mut self) -> Result<u64, Error> {
+		// This doesn't require the clipboard to be open.
+		Ok(unsafe { GetClipboardSequenceNumber() } as u64)
 	}
 
 	#[cfg(feature = "image-data")]
-	pub(crate) fn get_image(&mut self) -> Result<ImageData, Error> {
-		use std::borrow::Cow;
-		use std::convert::TryInto;
+	pub(crate) fn get_image(&mut self) -> Result<ImageData<'static>, Error> {
+		self.get_image_with_format().map(|(_, image)| image)
+	}
 
-		let _cb = SystemClipboard::new_attempts(MAX_OPEN_ATTEMPTS)
-			.map_err(|_| Error::ClipboardOccupied)?;
+	#[cfg(feature = "image-data")]
+	pub(crate) fn get_image_with_format(
+		&mut self,
+	) -> Result<(ImageFormatTag, ImageData<'static>), Error> {
+		let _cb = self.open()?;
+		// Applications that care about transparency (browsers, image editors) also offer a PNG,
+		// which is preferred because the alpha channel of a `CF_DIB` is often left undefined.
+		if let Some(png) = png_format().filter(|&f| clipboard_win::raw::is_format_avail(f)) {
+			let mut data = Vec::new();
+			if clipboard_win::raw::get_vec(png, &mut data).is_ok() {
+				match decode_image(ImageFormatTag::Png, &data) {
+					Ok(image) => return Ok((ImageFormatTag::Png, image)),
+					// The bitmap has the same dimensions, so it's no use trying it.
+					Err(e @ Error::ImageTooLarge { .. }) => return Err(e),
+					Err(_) => {}
+				}
+			}
+		}
 		let format = clipboard_win::formats::CF_DIB;
-		let size;
-		match clipboard_win::raw::size(format) {
-			Some(s) => size = s,
+		let size = match clipboard_win::raw::size(format) {
+			Some(s) => s,
 			None => return Err(Error::ContentNotAvailable),
-		}
+		};
 		let mut data = vec![0u8; size.into()];
-		clipboard_win::raw::get(format, &mut data).map_err(|_| Error::Unknown {
-			description: "failed to get image data from the clipboard".into(),
+		clipboard_win::raw::get(format, &mut data).map_err(|e| {
+			Error::Platform(system_error("GetClipboardData", e.raw_code()).with_detail("CF_DIB"))
 		})?;
-		let info_header_size = u32::from_le_bytes(data[..4].try_into().unwrap());
-		let mut fake_bitmap_file =
-			FakeBitmapFile { bitmap: data, file_header: [0; BITMAP_FILE_HEADER_SIZE], curr_pos: 0 };
-		fake_bitmap_file.file_header[0] = b'B';
-		fake_bitmap_file.file_header[1] = b'M';
-
-		let file_size =
-			u32::to_le_bytes((fake_bitmap_file.bitmap.len() + BITMAP_FILE_HEADER_SIZE) as u32);
-		fake_bitmap_file.file_header[2..6].copy_from_slice(&file_size);
-
-		let data_offset = u32::to_le_bytes(info_header_size + BITMAP_FILE_HEADER_SIZE as u32);
-		fake_bitmap_file.file_header[10..14].copy_from_slice(&data_offset);
-
-		let bmp_decoder = BmpDecoder::new(fake_bitmap_file).unwrap();
-		let (w, h) = bmp_decoder.dimensions();
-		let width = w as usize;
-		let height = h as usize;
-		let image =
-			image::DynamicImage::from_decoder(bmp_decoder).map_err(|_| Error::ConversionFailure)?;
-		Ok(ImageData { width, height, bytes: Cow::from(image.into_rgba8().into_raw()) })
+		Ok((ImageFormatTag::Dib, decode_image(ImageFormatTag::Dib, &data)?))
 	}
 
 	#[cfg(feature = "image-data")]
 	pub(crate) fn set_image(&mut self, image: ImageData) -> Result<(), Error> {
+		self.set_image_with_generation(image).map(drop)
+	}
+
+	/// Like `set_text_with_generation`, the sequence number is read before closing the clipboard.
+	#[cfg(feature = "image-data")]
+	pub(crate) fn set_image_with_generation(&mut self, image: ImageData) -> Result<u64, Error> {
 		use std::convert::TryInto;
 
-		//let clipboard = SystemClipboard::new()?;
 		let mut bmp_data = Vec::with_capacity(image.bytes.len());
 		let mut cursor = std::io::Cursor::new(&mut bmp_data);
 		let mut encoder = BmpEncoder::new(&mut cursor);
@@ -297,19 +908,145 @@ impl WindowsClipboardContext {
 		} else {
 			clipboard_win::formats::CF_DIB
 		};
-		let mut result: Result<(), Error> = Ok(());
-		//let mut success = false;
-		clipboard_win::with_clipboard(|| {
-			let success = clipboard_win::raw::set(format, data_without_file_header).is_ok();
-			let bitmap_result = unsafe { add_cf_bitmap(&image) };
-			if bitmap_result.is_err() && !success {
-				result = Err(Error::Unknown {
-					description: "Could not set the image for the clipboard in neither of `CF_DIB` and `CG_BITMAP` formats.".into(),
-				});
-			}
-		})
-		.map_err(|_| Error::ClipboardOccupied)?;
+		let mut png_data = Vec::new();
+		PngEncoder::new(&mut png_data)
+			.encode(&image.bytes, image.width as u32, image.height as u32, ColorType::Rgba8)
+			.map_err(|_| Error::ConversionFailure)?;
+		let _cb = self.open()?;
+		let success = clipboard_win::raw::set(format, data_without_file_header).is_ok();
+		let bitmap_result = unsafe { add_cf_bitmap(&image) };
+		if bitmap_result.is_err() && !success {
+			return Err(Error::Unknown {
+				description: "Could not set the image for the clipboard in neither of `CF_DIB` and `CG_BITMAP` formats.".into(),
+			});
+		}
+		// The PNG is only an addition for applications that read it, so failing to set it isn't
+		// reported as long as the bitmap formats are there.
+		if let Some(png) = png_format() {
+			let _ = clipboard_win::raw::set_without_clear(png, &png_data);
+		}
+		Ok(unsafe { GetClipboardSequenceNumber() } as u64)
+	}
+}
+
+/// Windows-specific extensions to the [`Clipboard`](crate::Clipboard) type.
+///
+/// # Examples
+///
+/// ```no_run
+/// use arboard::{Clipboard, ClipboardExtWindows};
+/// use std::time::Duration;
+///
+/// let mut ctx = Clipboard::new().unwrap();
+/// ctx.set_open_retry(10, Duration::from_millis(5));
+/// ctx.set_text("Hello, world!").unwrap();
+/// ```
+pub trait ClipboardExtWindows {
+	/// Sets how often opening the clipboard is attempted while another process holds it open,
+	/// and how long to wait after the first failed attempt. The delay doubles after every
+	/// further attempt. The clipboard is opened at least once, even if `attempts` is zero.
+	///
+	/// By default, the clipboard is opened up to 5 times over about 100 ms, before
+	/// [`Error::ClipboardOccupied`] is returned.
+	fn set_open_retry(&mut self, attempts: usize, delay: Duration);
+}
+
+impl ClipboardExtWindows for crate::Clipboard {
+	fn set_open_retry(&mut self, attempts: usize, delay: Duration) {
+		dispatch!(&mut self.backend, cb => cb.set_open_retry(attempts, delay))
+	}
+}
+
+/// What happens to the formats that weren't rendered yet when a [`DelayedRendering`] is dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DelayedRenderingDrop {
+	/// Renders all of them, so that the contents stay available after the handle is gone.
+	RenderAll,
+	/// Empties the clipboard, unless another application placed its contents onto it already.
+	Empty,
+}
+
+/// Keeps rendering the formats that were added with
+/// [`ClipboardWriteExtWindows::with_provider`] on demand, until it's dropped.
+///
+/// The data is rendered by a hidden window that's owned by a background thread. Windows asks
+/// that window to render everything before it's destroyed, so the contents aren't lost when the
+/// handle is dropped with [`DelayedRenderingDrop::RenderAll`].
+///
+/// The handle must be dropped before the process exits, that is before returning from `main` or
+/// calling [`std::process::exit`]. The background thread is terminated along with the process
+/// without its window getting the chance to render anything, so the formats that weren't
+/// rendered yet are lost.
+#[must_use = "the formats are only rendered on demand while the handle exists"]
+pub struct DelayedRendering {
+	/// The `HWND` of the provider window, which isn't `Send`. It's `None` if the formats were
+	/// rendered right away instead.
+	window: Option<usize>,
+	thread: Option<thread::JoinHandle<()>>,
+}
 
-		result
+impl Drop for DelayedRendering {
+	fn drop(&mut self) {
+		if let Some(window) = self.window {
+			// Like for the watcher, the window has to be destroyed by its own thread.
+			unsafe { PostMessageW(window as HWND, WM_CLOSE, 0, 0) };
+		}
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+/// Windows-specific extensions to [`ClipboardWrite`](crate::ClipboardWrite), for formats whose
+/// data is only produced when an application requests it ("delayed rendering").
+///
+/// # Examples
+///
+/// ```no_run
+/// use arboard::{Clipboard, ClipboardWriteExtWindows, DelayedRenderingDrop};
+///
+/// let mut ctx = Clipboard::new().unwrap();
+/// let rendering = ctx
+///     .write()
+///     .text("A large table")
+///     .with_provider("Csv", || b"a,b\n1,2\n".to_vec())
+///     .commit_delayed(DelayedRenderingDrop::RenderAll)
+///     .unwrap();
+/// // Other applications can paste the CSV data for as long as `rendering` exists.
+/// drop(rendering);
+/// ```
+pub trait ClipboardWriteExtWindows {
+	/// Adds a format whose data is produced by `render` the first time an application requests
+	/// it. `format` uses the same naming as [`ClipboardSnapshot::formats`].
+	///
+	/// [`ClipboardWrite::commit`](crate::ClipboardWrite::commit) renders all such formats right
+	/// away; use `commit_delayed` to render them on demand.
+	fn with_provider<F>(self, format: impl Into<String>, render: F) -> Self
+	where
+		F: FnOnce() -> Vec<u8> + Send + 'static;
+
+	/// Clears the clipboard and places all the representations onto it, leaving the formats
+	/// added with `with_provider` to be rendered on demand while the returned handle exists.
+	///
+	/// The callbacks run on a background thread. With a backend other than the system clipboard,
+	/// they're run right away, like `commit` does.
+	fn commit_delayed(self, on_drop: DelayedRenderingDrop) -> Result<DelayedRendering, Error>;
+}
+
+impl ClipboardWriteExtWindows for crate::ClipboardWrite<'_> {
+	fn with_provider<F>(mut self, format: impl Into<String>, render: F) -> Self
+	where
+		F: FnOnce() -> Vec<u8> + Send + 'static,
+	{
+		self.delayed.push((format.into(), Box::new(render)));
+		self
+	}
+
+	fn commit_delayed(self, on_drop: DelayedRenderingDrop) -> Result<DelayedRendering, Error> {
+		if let crate::Backend::Platform(cb) = &mut self.clipboard.backend {
+			return cb.write_delayed(&self.representations, self.delayed, on_drop);
+		}
+		self.commit()?;
+		Ok(DelayedRendering { window: None, thread: None })
 	}
 }
@@ -0,0 +1,243 @@
+/*
+SPDX-License-Identifier: Apache-2.0 OR MIT
+
+Copyright 2020 The arboard contributors
+
+The project to which this file belongs is licensed under either of
+the Apache 2.0 or the MIT license at the licensee's choice. The terms
+and conditions of the chosen license apply to this file.
+*/
+
+// Inside the Windows Subsystem for Linux, Windows executables can be started like Linux ones. This
+// backend uses them to access the clipboard of the Windows host, for when there's no display server
+// that would share it.
+
+use std::{
+	io::{self, Write},
+	path::Path,
+	process::{Command, Output, Stdio},
+};
+
+#[cfg(feature = "image-data")]
+use crate::common::ImageData;
+use crate::{
+	common::{text_from_utf8, ClipboardSnapshot, Error, Representation},
+	common_linux::LinuxClipboardKind,
+};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Where the Windows executables are found if the Windows directories aren't on the `PATH`.
+const CLIP: (&str, &str) = ("clip.exe", "/mnt/c/Windows/System32/clip.exe");
+const POWERSHELL: (&str, &str) =
+	("powershell.exe", "/mnt/c/Windows/System32/WindowsPowerShell/v1.0/powershell.exe");
+
+/// Prints the clipboard as UTF-8 without a BOM or a trailing newline, and exits with 2 if the
+/// clipboard holds no text.
+const GET_CLIPBOARD: &str =
+	"[Console]::OutputEncoding = New-Object System.Text.UTF8Encoding $false; \
+	$text = Get-Clipboard -Raw; if ($text -eq $null) { exit 2 }; [Console]::Out.Write($text)";
+
+/// Returns true if the kernel release is the one of WSL, which is `...-Microsoft` on WSL 1 and
+/// `...-microsoft-standard-WSL2` on WSL 2.
+pub(crate) fn is_wsl_release(release: &str) -> bool {
+	release.to_ascii_lowercase().contains("microsoft")
+}
+
+/// Returns true if running inside WSL.
+pub(crate) fn running_in_wsl() -> bool {
+	std::fs::read_to_string("/proc/sys/kernel/osrelease")
+		.is_ok_and(|release| is_wsl_release(&release))
+}
+
+/// Encodes the text for `clip.exe`, which expects Windows line endings and only keeps non-ASCII
+/// characters intact if the input is UTF-16 with a byte order mark.
+pub(crate) fn encode_for_windows(text: &str) -> Vec<u8> {
+	let text = text.replace("\r\n", "\n").replace('\n', "\r\n");
+	let mut bytes = vec![0xFF, 0xFE];
+	bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+	bytes
+}
+
+/// Decodes the UTF-8 output of PowerShell, converting the line endings to Unix ones.
+pub(crate) fn decode_from_windows(bytes: Vec<u8>) -> Result<String> {
+	let text = text_from_utf8(bytes)?;
+	Ok(text.trim_start_matches('\u{FEFF}').replace("\r\n", "\n"))
+}
+
+/// Starts the Windows executable, falling back to its location on the default mount point of the
+/// `C:` drive if it's not on the `PATH`.
+fn run(executable: (&str, &str), args: &[&str], stdin: Option<&[u8]>) -> Result<Output> {
+	let (name, fallback) = executable;
+	let spawn = |program: &Path| {
+		Command::new(program)
+			.args(args)
+			.stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn()
+	};
+	let mut child = match spawn(Path::new(name)) {
+		Err(e) if e.kind() == io::ErrorKind::NotFound => spawn(Path::new(fallback)),
+		result => result,
+	}
+	.map_err(|e| match e.kind() {
+		// Without the Windows interop, the clipboard of the host can't be reached at all.
+		io::ErrorKind::NotFound => Error::ClipboardNotSupported,
+		_ => Error::Unknown { description: format!("Could not start `{}`: {}", name, e) },
+	})?;
+	if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+		// The pipe is closed when dropped, which tells the executable that the input ended.
+		pipe.write_all(input).map_err(|e| Error::Unknown {
+			description: format!("Could not write to `{}`: {}", name, e),
+		})?;
+	}
+	child
+		.wait_with_output()
+		.map_err(|e| Error::Unknown { description: format!("`{}` failed: {}", name, e) })
+}
+
+fn failure(name: &str, output: &Output) -> Error {
+	Error::Unknown {
+		description: format!(
+			"`{}` exited with {}: {}",
+			name,
+			output.status,
+			String::from_utf8_lossy(&output.stderr).trim()
+		),
+	}
+}
+
+pub struct WslClipboardContext;
+
+impl WslClipboardContext {
+	pub(crate) fn new() -> Result<Self> {
+		Ok(Self)
+	}
+
+	pub fn get_text(&mut self) -> Result<String> {
+		let output =
+			run(POWERSHELL, &["-NoProfile", "-NonInteractive", "-Command", GET_CLIPBOARD], None)?;
+		match output.status.code() {
+			Some(0) => decode_from_windows(output.stdout),
+			Some(2) => Err(Error::ContentNotAvailable),
+			_ => Err(failure(POWERSHELL.0, &output)),
+		}
+	}
+
+	/// The Windows clipboard is the only one available.
+	pub(crate) fn get_text_with_clipboard(
+		&mut self,
+		selection: LinuxClipboardKind,
+	) -> Result<String> {
+		match selection {
+			LinuxClipboardKind::Clipboard => self.get_text(),
+			LinuxClipboardKind::Primary | LinuxClipboardKind::Secondary => {
+				Err(Error::ClipboardNotSupported)
+			}
+		}
+	}
+
+	pub fn get_text_bytes(&mut self, _format: &str) -> Result<Vec<u8>> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub fn set_text(&mut self, text: String) -> Result<()> {
+		let output = run(CLIP, &[], Some(&encode_for_windows(&text)))?;
+		if !output.status.success() {
+			return Err(failure(CLIP.0, &output));
+		}
+		Ok(())
+	}
+
+	pub(crate) fn set_text_with_clipboard(
+		&mut self,
+		text: String,
+		selection: LinuxClipboardKind,
+	) -> Result<()> {
+		match selection {
+			LinuxClipboardKind::Clipboard => self.set_text(text),
+			LinuxClipboardKind::Primary | LinuxClipboardKind::Secondary => {
+				Err(Error::ClipboardNotSupported)
+			}
+		}
+	}
+
+	pub fn swap_text(&mut self, text: String) -> Result<Option<String>> {
+		let previous = match self.get_text() {
+			Ok(text) => Some(text),
+			Err(Error::ContentNotAvailable) => None,
+			Err(e) => return Err(e),
+		};
+		self.set_text(text)?;
+		Ok(previous)
+	}
+
+	pub fn generation(&mut self) -> Result<u64> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub fn available_formats(&mut self) -> Result<Vec<String>> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	pub fn snapshot(&mut self, _max_bytes: Option<usize>) -> Result<ClipboardSnapshot> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	/// The Windows host keeps the contents.
+	pub fn persist(&mut self) -> Result<()> {
+		Ok(())
+	}
+
+	pub fn restore(&mut self, _snapshot: &ClipboardSnapshot) -> Result<()> {
+		Err(Error::ClipboardNotSupported)
+	}
+
+	/// Only the text can be transferred, the other representations are left out.
+	pub(crate) fn write_all_with_clipboard(
+		&mut self,
+		representations: &[Representation],
+		selection: LinuxClipboardKind,
+	) -> Result<()> {
+		let text = representations.iter().find_map(|representation| match representation {
+			Representation::Text(text) => Some(text),
+			_ => None,
+		});
+		match text {
+			Some(text) => self.set_text_with_clipboard(text.clone(), selection),
+			None => Err(Error::ClipboardNotSupported),
+		}
+	}
+
+	#[cfg(feature = "image-data")]
+	pub fn get_image(&mut self) -> Result<ImageData<'static>> {
+		Err(Error::UnsupportedFormat { format: "image".into() })
+	}
+
+	#[cfg(feature = "image-data")]
+	pub fn set_image(&mut self, _image: ImageData) -> Result<()> {
+		Err(Error::UnsupportedFormat { format: "image".into() })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn wsl_text_conversion() {
+		assert!(is_wsl_release("4.4.0-19041-Microsoft"));
+		assert!(is_wsl_release("5.15.90.1-microsoft-standard-WSL2"));
+		assert!(!is_wsl_release("6.1.0-13-amd64"));
+
+		assert_eq!(
+			encode_for_windows("a\nb\r\nπ"),
+			[0xFF, 0xFE, b'a', 0, b'\r', 0, b'\n', 0, b'b', 0, b'\r', 0, b'\n', 0, 0xC0, 0x03]
+		);
+		assert_eq!(decode_from_windows("a\r\nπ".as_bytes().to_vec()).unwrap(), "a\nπ");
+		assert!(
+			matches!(decode_from_windows(vec![0xFF]), Err(Error::InvalidUtf8 { bytes }) if bytes == [0xFF])
+		);
+	}
+}
@@ -15,40 +15,91 @@ and conditions of the chosen license apply to this file.
 use std::{
 	cell::RefCell,
 	collections::{hash_map::Entry, HashMap},
+	convert::TryFrom,
+	sync::mpsc::Sender,
 	sync::{
-		atomic::{AtomicBool, Ordering},
+		atomic::{AtomicBool, AtomicU64, Ordering},
 		Arc,
 	},
 	thread::JoinHandle,
 	thread_local,
 	time::{Duration, Instant},
-	usize,
 };
 
-use log::{error, trace, warn};
 use once_cell::sync::Lazy;
 use parking_lot::{Condvar, Mutex, MutexGuard, RwLock};
 use x11rb::{
-	connection::Connection,
+	connection::{Connection, RequestConnection as _},
+	errors::{ConnectionError, ReplyError, ReplyOrIdError},
 	protocol::{
+		xfixes::{ConnectionExt as _, SelectionEventMask},
 		xproto::{
-			Atom, AtomEnum, ConnectionExt as _, CreateWindowAux, EventMask, PropMode, Property,
-			PropertyNotifyEvent, SelectionNotifyEvent, SelectionRequestEvent, Time, WindowClass,
-			SELECTION_NOTIFY_EVENT,
+			Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, CreateWindowAux,
+			EventMask, PropMode, Property, PropertyNotifyEvent, SelectionNotifyEvent,
+			SelectionRequestEvent, Time, Window, WindowClass, SELECTION_NOTIFY_EVENT,
 		},
 		Event,
 	},
 	rust_connection::RustConnection,
 	wrapper::ConnectionExt as _,
+	x11_utils::X11Error,
 	COPY_DEPTH_FROM_PARENT, COPY_FROM_PARENT, NONE,
 };
 
 #[cfg(feature = "image-data")]
-use crate::{common_linux::encode_as_png, ImageData};
-use crate::{common_linux::into_unknown, Error, LinuxClipboardKind};
+use crate::{common::decode_image, common_linux::encode_as_png, ImageData, ImageFormatTag};
+use crate::{
+	common::{text_from_utf8, Representation},
+	common_linux::into_unknown,
+	ClipboardEvent, ClipboardSnapshot, Error, LinuxClipboardKind, PlatformError,
+};
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// The errors of `x11rb`, some of which carry the error that the X server sent.
+trait ServerError: std::error::Error + Send + Sync + 'static {
+	fn server_error(&self) -> Option<&X11Error>;
+}
+
+impl ServerError for ConnectionError {
+	fn server_error(&self) -> Option<&X11Error> {
+		None
+	}
+}
+
+impl ServerError for ReplyError {
+	fn server_error(&self) -> Option<&X11Error> {
+		match self {
+			ReplyError::X11Error(error) => Some(error),
+			ReplyError::ConnectionError(_) => None,
+		}
+	}
+}
+
+impl ServerError for ReplyOrIdError {
+	fn server_error(&self) -> Option<&X11Error> {
+		match self {
+			ReplyOrIdError::X11Error(error) => Some(error),
+			_ => None,
+		}
+	}
+}
+
+/// Describes the failure of `request`, including the error code and the bad value (like the atom
+/// or window that the server didn't accept) of the error the X server sent.
+fn x11_error<E: ServerError>(request: &'static str) -> impl FnOnce(E) -> Error {
+	move |error| {
+		let mut details = PlatformError::new(request);
+		if let Some(server_error) = error.server_error() {
+			details = details.with_code(server_error.error_code.into()).with_detail(format!(
+				"{:?}, bad value {}",
+				server_error.error_kind, server_error.bad_value
+			));
+		}
+		Error::Platform(details.with_source(error))
+	}
+}
+
 static CLIPBOARD: Lazy<Mutex<Option<GlobalClipboard>>> = Lazy::new(|| Mutex::new(None));
 
 x11rb::atom_manager! {
@@ -62,6 +113,11 @@ x11rb::atom_manager! {
 		TARGETS,
 		ATOM,
 		INCR,
+		MULTIPLE,
+		TIMESTAMP,
+		DELETE,
+		INSERT_SELECTION,
+		INSERT_PROPERTY,
 
 		UTF8_STRING,
 		UTF8_MIME_0: b"text/plain;charset=utf-8",
@@ -75,6 +131,7 @@ x11rb::atom_manager! {
 		TEXT_MIME_UNKNOWN: b"text/plain",
 
 		PNG_MIME: b"image/png",
+		HTML_MIME: b"text/html",
 
 		// This is just some random name for the property on our window, into which
 		// the clipboard owner writes the data we requested.
@@ -87,9 +144,24 @@ thread_local! {
 }
 
 // Some clipboard items, like images, may take a very long time to produce a
-// `SelectionNotify`. Multiple seconds long.
+// `SelectionNotify`. Multiple seconds long. This is the default of `ClipboardBuilder::timeout`.
 const LONG_TIMEOUT_DUR: Duration = Duration::from_millis(4000);
-const SHORT_TIMEOUT_DUR: Duration = Duration::from_millis(10);
+
+// How long the clipboard manager is given to take the contents over when the last clipboard is
+// dropped. `persist` waits longer, since the caller explicitly asked for it.
+const HANDOVER_TIMEOUT_DUR: Duration = Duration::from_millis(100);
+
+// Contents larger than this are sent in chunks of this size through the INCR mechanism, see
+// https://tronche.com/gui/x/icccm/sec-2.html#s-2.7.2
+// The chunks are made smaller if the X server only accepts smaller requests.
+const INCR_CHUNK_SIZE: usize = 64 * 1024;
+// How long the other side of an INCR transfer may take to send or accept a single chunk, and how
+// long the whole transfer may take, before it's abandoned.
+const INCR_CHUNK_TIMEOUT_DUR: Duration = Duration::from_millis(1000);
+const INCR_TRANSFER_TIMEOUT_DUR: Duration = Duration::from_secs(60);
+
+// How often the selection owners are checked by a watcher when XFIXES is not available.
+const WATCHER_POLL_INTERVAL: Duration = Duration::from_millis(250);
 
 #[derive(Debug, PartialEq, Eq)]
 enum ManagerHandoverState {
@@ -115,27 +187,46 @@ struct ClipboardContext {
 	/// requests coming to us.
 	server: XContext,
 	atoms: Atoms,
-	clipboard_data: RwLock<Option<ClipboardData>>,
-	primary_data: RwLock<Option<ClipboardData>>,
-	secondary_data: RwLock<Option<ClipboardData>>,
+	clipboard_data: RwLock<Option<Vec<ClipboardData>>>,
+	primary_data: RwLock<Option<Vec<ClipboardData>>>,
+	secondary_data: RwLock<Option<Vec<ClipboardData>>>,
 
 	handover_state: Mutex<ManagerHandoverState>,
 	handover_cv: Condvar,
 
+	/// How many times the contents of the CLIPBOARD, PRIMARY and SECONDARY selection were sent to
+	/// another client since they were written. `delivery_cv` is notified whenever a count changes
+	/// or we lose a selection.
+	deliveries: Mutex<[u32; 3]>,
+	delivery_cv: Condvar,
+
 	serve_stopped: AtomicBool,
+
+	/// Incremented every time the owner of the CLIPBOARD selection changes.
+	generation: AtomicU64,
+
+	/// How many times the CLIPBOARD, PRIMARY and SECONDARY selection were written by this process.
+	writes: [AtomicU64; 3],
+
+	/// Whether the server window receives XFIXES selection notifications. If it doesn't, we can
+	/// only observe the ownership changes that involve us.
+	xfixes_enabled: bool,
 }
 
 impl XContext {
 	fn new() -> Result<Self> {
 		// create a new connection to an X11 server
 		let (conn, screen_num): (RustConnection, _) =
-			RustConnection::connect(None).map_err(into_unknown)?;
+			RustConnection::connect(None).map_err(|e| {
+				warn!("Could not connect to the X server: {}", e);
+				Error::ClipboardNotSupported
+			})?;
 		let screen = conn
 			.setup()
 			.roots
 			.get(screen_num)
 			.ok_or(Error::Unknown { description: String::from("no screen found") })?;
-		let win_id = conn.generate_id().map_err(into_unknown)?;
+		let win_id = conn.generate_id().map_err(x11_error("generate_id"))?;
 
 		let event_mask =
             // Just in case that some program reports SelectionNotify events
@@ -159,11 +250,55 @@ impl XContext {
 			// don't subscribe to any special events because we are requesting everything we need ourselves
 			&CreateWindowAux::new().event_mask(event_mask),
 		)
-		.map_err(into_unknown)?;
-		conn.flush().map_err(into_unknown)?;
+		.map_err(x11_error("create_window"))?;
+		conn.flush().map_err(x11_error("flush"))?;
 
 		Ok(Self { conn, win_id })
 	}
+
+	/// Asks the X server to notify the window about the ownership changes of the given selections
+	/// through the XFIXES extension. An empty mask stops the notifications.
+	fn select_selection_input(
+		&self,
+		selections: &[Atom],
+		mask: SelectionEventMask,
+	) -> Result<(), Box<dyn std::error::Error>> {
+		// The version must be negotiated before any other XFIXES request is made.
+		self.conn.xfixes_query_version(1, 0)?.reply()?;
+		for &selection in selections {
+			self.conn.xfixes_select_selection_input(self.win_id, selection, mask)?;
+		}
+		self.conn.flush()?;
+		Ok(())
+	}
+}
+
+fn selection_change_mask() -> SelectionEventMask {
+	SelectionEventMask::SET_SELECTION_OWNER
+		| SelectionEventMask::SELECTION_WINDOW_DESTROY
+		| SelectionEventMask::SELECTION_CLIENT_CLOSE
+}
+
+/// The contents of a selection that are being sent to a requestor in chunks. The next chunk is
+/// sent whenever the requestor deletes the property, and an empty chunk ends the transfer.
+struct IncrTransfer {
+	selection: LinuxClipboardKind,
+	requestor: Window,
+	property: Atom,
+	target: Atom,
+	bytes: Vec<u8>,
+	/// How many bytes were already sent.
+	offset: usize,
+	started: Instant,
+	/// When the requestor last accepted a chunk.
+	last_chunk: Instant,
+}
+
+impl IncrTransfer {
+	fn is_stalled(&self) -> bool {
+		self.last_chunk.elapsed() > INCR_CHUNK_TIMEOUT_DUR
+			|| self.started.elapsed() > INCR_TRANSFER_TIMEOUT_DUR
+	}
 }
 
 #[derive(Debug, Clone)]
@@ -183,8 +318,19 @@ enum ReadSelNotifyResult {
 impl ClipboardContext {
 	fn new() -> Result<Self> {
 		let server = XContext::new()?;
-		let atoms =
-			Atoms::new(&server.conn).map_err(into_unknown)?.reply().map_err(into_unknown)?;
+		let atoms = Atoms::new(&server.conn)
+			.map_err(x11_error("intern_atom"))?
+			.reply()
+			.map_err(x11_error("intern_atom"))?;
+		let xfixes_enabled = match server
+			.select_selection_input(&[atoms.CLIPBOARD], selection_change_mask())
+		{
+			Ok(()) => true,
+			Err(e) => {
+				warn!("XFIXES is not available, clipboard changes made by other applications will not be tracked: {}", e);
+				false
+			}
+		};
 
 		Ok(Self {
 			server,
@@ -194,11 +340,25 @@ impl ClipboardContext {
 			secondary_data: RwLock::default(),
 			handover_state: Mutex::new(ManagerHandoverState::Idle),
 			handover_cv: Condvar::new(),
+			deliveries: Mutex::default(),
+			delivery_cv: Condvar::new(),
 			serve_stopped: AtomicBool::new(false),
+			generation: AtomicU64::new(0),
+			writes: Default::default(),
+			xfixes_enabled,
 		})
 	}
 
-	fn write(&self, data: ClipboardData, selection: LinuxClipboardKind) -> Result<()> {
+	/// Must be called whenever the CLIPBOARD selection changes hands in a way that we know
+	/// of without XFIXES.
+	fn bump_generation_without_xfixes(&self) {
+		if !self.xfixes_enabled {
+			self.generation.fetch_add(1, Ordering::Relaxed);
+		}
+	}
+
+	/// Takes ownership of the selection and serves each element of `data` as a separate target.
+	fn write(&self, data: Vec<ClipboardData>, selection: LinuxClipboardKind) -> Result<()> {
 		if self.serve_stopped.load(Ordering::Relaxed) {
 			return Err(Error::Unknown {
                 description: "The clipboard handler thread seems to have stopped. Logging messages may reveal the cause. (See the `log` crate.)".into()
@@ -214,10 +374,16 @@ impl ClipboardContext {
 			.set_selection_owner(server_win, self.atom_of(selection), Time::CURRENT_TIME)
 			.map_err(|_| Error::ClipboardOccupied)?;
 
-		self.server.conn.flush().map_err(into_unknown)?;
+		self.server.conn.flush().map_err(x11_error("flush"))?;
 
 		// Just setting the data, and the `serve_requests` will take care of the rest.
 		*self.data_of(selection).write() = Some(data);
+		self.deliveries.lock()[selection_index(selection)] = 0;
+		self.writes[selection_index(selection)].fetch_add(1, Ordering::Relaxed);
+
+		if let LinuxClipboardKind::Clipboard = selection {
+			self.bump_generation_without_xfixes();
+		}
 
 		Ok(())
 	}
@@ -225,13 +391,18 @@ impl ClipboardContext {
 	/// `formats` must be a slice of atoms, where each atom represents a target format.
 	/// The first format from `formats`, which the clipboard owner supports will be the
 	/// format of the return value.
-	fn read(&self, formats: &[Atom], selection: LinuxClipboardKind) -> Result<ClipboardData> {
+	fn read(
+		&self,
+		formats: &[Atom],
+		selection: LinuxClipboardKind,
+		timeout: Duration,
+	) -> Result<ClipboardData> {
 		// if we are the current owner, we can get the current clipboard ourselves
 		if self.is_owner(selection)? {
 			let data = self.data_of(selection).read();
 			if let Some(data) = &*data {
 				for format in formats {
-					if *format == data.format {
+					if let Some(data) = data.iter().find(|d| d.format == *format) {
 						return Ok(data.clone());
 					}
 				}
@@ -241,11 +412,13 @@ impl ClipboardContext {
 		// if let Some(data) = self.data.read().clone() {
 		//     return Ok(data)
 		// }
+		// Every read uses a connection of its own, so that the events of an abandoned transfer
+		// aren't received by the next one.
 		let reader = XContext::new()?;
 
 		trace!("Trying to get the clipboard data.");
 		for format in formats {
-			match self.read_single(&reader, selection, *format) {
+			match self.read_single(&reader, selection, *format, timeout) {
 				Ok(bytes) => {
 					return Ok(ClipboardData { bytes, format: *format });
 				}
@@ -263,13 +436,14 @@ impl ClipboardContext {
 		reader: &XContext,
 		selection: LinuxClipboardKind,
 		target_format: Atom,
+		timeout: Duration,
 	) -> Result<Vec<u8>> {
 		// Delete the property so that we can detect (using property notify)
 		// when the selection owner receives our request.
 		reader
 			.conn
 			.delete_property(reader.win_id, self.atoms.ARBOARD_CLIPBOARD)
-			.map_err(into_unknown)?;
+			.map_err(x11_error("delete_property"))?;
 
 		// request to convert the clipboard selection to our data type(s)
 		reader
@@ -281,18 +455,21 @@ impl ClipboardContext {
 				self.atoms.ARBOARD_CLIPBOARD,
 				Time::CURRENT_TIME,
 			)
-			.map_err(into_unknown)?;
-		reader.conn.sync().map_err(into_unknown)?;
+			.map_err(x11_error("convert_selection"))?;
+		reader.conn.sync().map_err(x11_error("sync"))?;
 
 		trace!("Finished `convert_selection`");
 
 		let mut incr_data: Vec<u8> = Vec::new();
 		let mut using_incr = false;
 
-		let mut timeout_end = Instant::now() + LONG_TIMEOUT_DUR;
+		let mut timeout_end = Instant::now() + timeout;
+		// Set once an INCR transfer starts. Even if every chunk arrives in time, a transfer that
+		// takes longer than this is abandoned.
+		let mut incr_end = None;
 
-		while Instant::now() < timeout_end {
-			let event = reader.conn.poll_for_event().map_err(into_unknown)?;
+		while Instant::now() < timeout_end.min(incr_end.unwrap_or(timeout_end)) {
+			let event = reader.conn.poll_for_event().map_err(x11_error("poll_for_event"))?;
 			let event = match event {
 				Some(e) => e,
 				None => {
@@ -305,7 +482,7 @@ impl ClipboardContext {
 				Event::SelectionNotify(event) => {
 					trace!("Read SelectionNotify");
 					let result = self.handle_read_selection_notify(
-						&reader,
+						reader,
 						target_format,
 						&mut using_incr,
 						&mut incr_data,
@@ -317,7 +494,8 @@ impl ClipboardContext {
 							// This means we received an indication that an the
 							// data is going to be sent INCRementally. Let's
 							// reset our timeout.
-							timeout_end += SHORT_TIMEOUT_DUR;
+							timeout_end = Instant::now() + INCR_CHUNK_TIMEOUT_DUR;
+							incr_end = Some(Instant::now() + INCR_TRANSFER_TIMEOUT_DUR);
 						}
 						ReadSelNotifyResult::EventNotRecognized => (),
 					}
@@ -327,8 +505,7 @@ impl ClipboardContext {
 				// a PropertyNotify event.
 				Event::PropertyNotify(event) => {
 					let result = self.handle_read_property_notify(
-						&reader,
-						target_format,
+						reader,
 						&mut using_incr,
 						&mut incr_data,
 						&mut timeout_end,
@@ -338,11 +515,18 @@ impl ClipboardContext {
 						return Ok(incr_data);
 					}
 				}
-				_ => log::trace!("An unexpected event arrived while reading the clipboard."),
+				_ => trace!("An unexpected event arrived while reading the clipboard."),
 			}
 		}
-		log::info!("Time-out hit while reading the clipboard.");
-		Err(Error::ContentNotAvailable)
+		if using_incr {
+			// The data exists, but the owner stopped sending it.
+			info!("Time-out hit after receiving {} bytes of the clipboard data.", incr_data.len());
+		} else {
+			info!("Time-out hit while reading the clipboard.");
+		}
+		// Nothing about the transfer outlives `reader`, whose connection is closed by the caller,
+		// so a late answer or chunk of the owner can't end up in a later read.
+		Err(Error::Timeout)
 	}
 
 	fn atom_of(&self, selection: LinuxClipboardKind) -> Atom {
@@ -353,7 +537,7 @@ impl ClipboardContext {
 		}
 	}
 
-	fn data_of(&self, selection: LinuxClipboardKind) -> &RwLock<Option<ClipboardData>> {
+	fn data_of(&self, selection: LinuxClipboardKind) -> &RwLock<Option<Vec<ClipboardData>>> {
 		match selection {
 			LinuxClipboardKind::Clipboard => &self.clipboard_data,
 			LinuxClipboardKind::Primary => &self.primary_data,
@@ -375,9 +559,9 @@ impl ClipboardContext {
 			.server
 			.conn
 			.get_selection_owner(self.atom_of(selection))
-			.map_err(into_unknown)?
+			.map_err(x11_error("get_selection_owner"))?
 			.reply()
-			.map_err(into_unknown)?
+			.map_err(x11_error("get_selection_owner"))?
 			.owner;
 
 		Ok(current == self.server.win_id)
@@ -388,9 +572,9 @@ impl ClipboardContext {
 			self.server
 				.conn
 				.get_atom_name(atom)
-				.map_err(into_unknown)?
+				.map_err(x11_error("get_atom_name"))?
 				.reply()
-				.map_err(into_unknown)?
+				.map_err(x11_error("get_atom_name"))?
 				.name,
 		)
 		.map_err(into_unknown)
@@ -429,64 +613,64 @@ impl ClipboardContext {
 			return Err(Error::ContentNotAvailable);
 		}
 		if self.kind_of(event.selection).is_none() {
-			log::info!("Received a SelectionNotify for a selection other than CLIPBOARD, PRIMARY or SECONDARY. This is unexpected.");
+			info!("Received a SelectionNotify for a selection other than CLIPBOARD, PRIMARY or SECONDARY. This is unexpected.");
 			return Ok(ReadSelNotifyResult::EventNotRecognized);
 		}
 		if *using_incr {
-			log::warn!("Received a SelectionNotify while already expecting INCR segments.");
+			warn!("Received a SelectionNotify while already expecting INCR segments.");
 			return Ok(ReadSelNotifyResult::EventNotRecognized);
 		}
-		// request the selection
-		let mut reply = reader
+		// Request the property with any type, as the owner may use a different one than the target.
+		// A property is only deleted if the requested type matches, and if it doesn't the value
+		// isn't returned either.
+		let reply = reader
 			.conn
-			.get_property(true, event.requestor, event.property, event.target, 0, u32::MAX / 4)
-			.map_err(into_unknown)?
+			.get_property(true, event.requestor, event.property, AtomEnum::ANY, 0, u32::MAX / 4)
+			.map_err(x11_error("get_property"))?
 			.reply()
-			.map_err(into_unknown)?;
+			.map_err(x11_error("get_property"))?;
 
 		// trace!("Property.type: {:?}", self.atom_name(reply.type_));
 
 		// we found something
-		if reply.type_ == target_format {
-			Ok(ReadSelNotifyResult::GotData(reply.value))
-		} else if reply.type_ == self.atoms.INCR {
-			// Note that we call the get_property again because we are
-			// indicating that we are ready to receive the data by deleting the
-			// property, however deleting only works if the type matches the
-			// property type. But the type didn't match in the previous call.
-			reply = reader
-				.conn
-				.get_property(
-					true,
-					event.requestor,
-					event.property,
-					self.atoms.INCR,
-					0,
-					u32::MAX / 4,
-				)
-				.map_err(into_unknown)?
-				.reply()
-				.map_err(into_unknown)?;
-			log::trace!("Receiving INCR segments");
+		if reply.type_ == self.atoms.INCR {
+			// Deleting the property above told the owner that we are ready to receive the
+			// segments. Its value is a lower bound of the size of the data.
+			trace!("Receiving INCR segments");
 			*using_incr = true;
 			if reply.value_len == 4 {
 				let min_data_len = reply.value32().and_then(|mut vals| vals.next()).unwrap_or(0);
 				incr_data.reserve(min_data_len as usize);
 			}
 			Ok(ReadSelNotifyResult::IncrStarted)
+		} else if self.is_expected_type(event.target, reply.type_) {
+			Ok(ReadSelNotifyResult::GotData(reply.value))
 		} else {
-			// this should never happen, we have sent a request only for supported types
+			// The owner converted the selection to another format than the one we asked for.
 			Err(Error::Unknown {
-				description: String::from("incorrect type received from clipboard"),
+				description: format!(
+					"incorrect type received from clipboard: {} for {}",
+					self.atom_name_dbg(reply.type_),
+					self.atom_name_dbg(event.target)
+				),
 			})
 		}
 	}
 
+	/// Returns whether the owner of a selection may reply to a request for `target` with a
+	/// property of type `type_`. The reply to a TARGETS request has the type ATOM, and text may
+	/// come back as any of the names of UTF-8 text.
+	fn is_expected_type(&self, target: Atom, type_: Atom) -> bool {
+		let utf8 = [self.atoms.UTF8_STRING, self.atoms.UTF8_MIME_0, self.atoms.UTF8_MIME_1];
+		type_ == target
+			|| (target == self.atoms.TARGETS && type_ == Atom::from(AtomEnum::ATOM))
+			|| (utf8.contains(&target) && utf8.contains(&type_))
+	}
+
 	/// Returns Ok(true) when the incr_data is ready
 	fn handle_read_property_notify(
 		&self,
 		reader: &XContext,
-		target_format: u32,
 		using_incr: &mut bool,
 		incr_data: &mut Vec<u8>,
 		timeout_end: &mut Instant,
@@ -500,14 +684,17 @@ impl ClipboardContext {
 			// now preparing the data
 			return Ok(false);
 		}
+		// Some owners send the chunks with a different type than the target, for example
+		// UTF8_STRING for a text/plain;charset=utf-8 request. A property is only deleted if the type
+		// matches, so any type is accepted.
 		let reply = reader
 			.conn
-			.get_property(true, event.window, event.atom, target_format, 0, u32::MAX / 4)
-			.map_err(into_unknown)?
+			.get_property(true, event.window, event.atom, AtomEnum::ANY, 0, u32::MAX / 4)
+			.map_err(x11_error("get_property"))?
 			.reply()
-			.map_err(into_unknown)?;
+			.map_err(x11_error("get_property"))?;
 
-		// log::trace!("Received segment. value_len {}", reply.value_len,);
+		// trace!("Received segment. value_len {}", reply.value_len,);
 		if reply.value_len == 0 {
 			// This indicates that all the data has been sent.
 			return Ok(true);
@@ -515,22 +702,29 @@ impl ClipboardContext {
 		incr_data.extend(reply.value);
 
 		// Let's reset our timeout, since we received a valid chunk.
-		*timeout_end = Instant::now() + SHORT_TIMEOUT_DUR;
+		*timeout_end = Instant::now() + INCR_CHUNK_TIMEOUT_DUR;
 
 		// Not yet complete
 		Ok(false)
 	}
 
-	fn handle_selection_request(&self, event: SelectionRequestEvent) -> Result<()> {
+	/// Answers a request for the contents of one of our selections. Returns the transfer that has
+	/// to be continued when the contents are too large to be sent at once.
+	fn handle_selection_request(
+		&self,
+		event: SelectionRequestEvent,
+	) -> Result<Option<IncrTransfer>> {
 		let selection = match self.kind_of(event.selection) {
 			Some(kind) => kind,
 			None => {
 				warn!("Received a selection request to a selection other than the CLIPBOARD, PRIMARY or SECONDARY. This is unexpected.");
-				return Ok(());
+				return Ok(None);
 			}
 		};
 
 		let success;
+		let mut incr = None;
+		let mut delivered = false;
 		// we are asked for a list of supported conversion targets
 		if event.target == self.atoms.TARGETS {
 			trace!("Handling TARGETS, dst property is {}", self.atom_name_dbg(event.property));
@@ -538,7 +732,7 @@ impl ClipboardContext {
 			targets.push(self.atoms.TARGETS);
 			targets.push(self.atoms.SAVE_TARGETS);
 			let data = self.data_of(selection).read();
-			if let Some(data) = &*data {
+			for data in data.iter().flatten() {
 				targets.push(data.format);
 				if data.format == self.atoms.UTF8_STRING {
 					// When we are storing a UTF8 string,
@@ -557,25 +751,62 @@ impl ClipboardContext {
 					self.atoms.ATOM,
 					&targets,
 				)
-				.map_err(into_unknown)?;
-			self.server.conn.flush().map_err(into_unknown)?;
+				.map_err(x11_error("change_property32"))?;
+			self.server.conn.flush().map_err(x11_error("flush"))?;
 			success = true;
 		} else {
 			trace!("Handling request for (probably) the clipboard contents.");
 			let data = self.data_of(selection).read();
 			if let Some(data) = &*data {
-				if data.format == event.target {
-					self.server
-						.conn
-						.change_property8(
-							PropMode::REPLACE,
-							event.requestor,
-							event.property,
-							event.target,
-							&data.bytes,
-						)
-						.map_err(into_unknown)?;
-					self.server.conn.flush().map_err(into_unknown)?;
+				if let Some(data) = self.find_data_for_target(data, event.target) {
+					if data.bytes.len() > self.incr_chunk_size() {
+						trace!("Starting an INCR transfer of {} bytes", data.bytes.len());
+						// We need to know when the requestor deletes the property to send the
+						// next chunk.
+						self.server
+							.conn
+							.change_window_attributes(
+								event.requestor,
+								&ChangeWindowAttributesAux::new()
+									.event_mask(EventMask::PROPERTY_CHANGE),
+							)
+							.map_err(x11_error("change_window_attributes"))?;
+						// The value of the INCR property is a lower bound of the size.
+						let size = u32::try_from(data.bytes.len()).unwrap_or(u32::MAX);
+						self.server
+							.conn
+							.change_property32(
+								PropMode::REPLACE,
+								event.requestor,
+								event.property,
+								self.atoms.INCR,
+								&[size],
+							)
+							.map_err(x11_error("change_property32"))?;
+						incr = Some(IncrTransfer {
+							selection,
+							requestor: event.requestor,
+							property: event.property,
+							target: event.target,
+							bytes: data.bytes.clone(),
+							offset: 0,
+							started: Instant::now(),
+							last_chunk: Instant::now(),
+						});
+					} else {
+						self.server
+							.conn
+							.change_property8(
+								PropMode::REPLACE,
+								event.requestor,
+								event.property,
+								event.target,
+								&data.bytes,
+							)
+							.map_err(x11_error("change_property8"))?;
+						delivered = true;
+					}
+					self.server.conn.flush().map_err(x11_error("flush"))?;
 					success = true;
 				} else {
 					success = false
@@ -606,12 +837,130 @@ impl ClipboardContext {
 					property,
 				},
 			)
-			.map_err(into_unknown)?;
+			.map_err(x11_error("send_event"))?;
 
-		self.server.conn.flush().map_err(into_unknown)
+		self.server.conn.flush().map_err(x11_error("flush"))?;
+		if delivered {
+			self.record_delivery(selection);
+		}
+		Ok(incr)
 	}
 
-	fn ask_clipboard_manager_to_request_our_data(&self) -> Result<()> {
+	/// Sends the next chunk of an INCR transfer after the requestor deleted the property. Returns
+	/// true once the transfer is complete.
+	fn send_incr_chunk(&self, transfer: &mut IncrTransfer) -> Result<bool> {
+		let end = (transfer.offset + self.incr_chunk_size()).min(transfer.bytes.len());
+		self.server
+			.conn
+			.change_property8(
+				PropMode::REPLACE,
+				transfer.requestor,
+				transfer.property,
+				transfer.target,
+				&transfer.bytes[transfer.offset..end],
+			)
+			.map_err(x11_error("change_property8"))?;
+		// The transfer ends with an empty chunk, which is sent once everything else was sent.
+		let finished = transfer.offset == end;
+		transfer.offset = end;
+		transfer.last_chunk = Instant::now();
+		if finished {
+			trace!("Finished the INCR transfer of {} bytes", transfer.bytes.len());
+			// Other events of the requestor window aren't interesting to us.
+			self.server
+				.conn
+				.change_window_attributes(
+					transfer.requestor,
+					&ChangeWindowAttributesAux::new().event_mask(EventMask::NO_EVENT),
+				)
+				.map_err(x11_error("change_window_attributes"))?;
+		}
+		self.server.conn.flush().map_err(x11_error("flush"))?;
+		if finished {
+			self.record_delivery(transfer.selection);
+		}
+		Ok(finished)
+	}
+
+	/// The size of the chunks of INCR transfers, which also is the size above which contents are
+	/// sent through INCR.
+	fn incr_chunk_size(&self) -> usize {
+		// Leave room for the header of the `ChangeProperty` request.
+		let max_request_bytes = self.server.conn.maximum_request_bytes().saturating_sub(32);
+		INCR_CHUNK_SIZE.min(max_request_bytes)
+	}
+
+	/// Must be called whenever the contents of `selection` were sent to another client.
+	fn record_delivery(&self, selection: LinuxClipboardKind) {
+		let mut deliveries = self.deliveries.lock();
+		deliveries[selection_index(selection)] += 1;
+		self.delivery_cv.notify_all();
+	}
+
+	/// Blocks until the contents of `selection` were sent to another client, another client
+	/// became the owner of the selection, or the deadline passed.
+	fn wait_for_paste(
+		&self,
+		selection: LinuxClipboardKind,
+		deadline: Option<Instant>,
+	) -> Result<()> {
+		let mut deliveries = self.deliveries.lock();
+		loop {
+			// `deliveries` is locked whenever the data is cleared, so we can't miss that here.
+			if deliveries[selection_index(selection)] > 0
+				|| self.data_of(selection).read().is_none()
+			{
+				return Ok(());
+			}
+			if self.serve_stopped.load(Ordering::Relaxed) {
+				return Err(Error::Unknown {
+					description: "The clipboard handler thread stopped while waiting for the contents to be pasted.".into(),
+				});
+			}
+			match deadline {
+				Some(deadline) => {
+					if self.delivery_cv.wait_until(&mut deliveries, deadline).timed_out() {
+						return Ok(());
+					}
+				}
+				None => self.delivery_cv.wait(&mut deliveries),
+			}
+		}
+	}
+
+	/// Returns the element of `data` that can be used to answer a request for `target`.
+	fn find_data_for_target<'a>(
+		&self,
+		data: &'a [ClipboardData],
+		target: Atom,
+	) -> Option<&'a ClipboardData> {
+		let utf8_aliases = [self.atoms.UTF8_MIME_0, self.atoms.UTF8_MIME_1];
+		data.iter().find(|d| d.format == target).or_else(|| {
+			if utf8_aliases.contains(&target) {
+				data.iter().find(|d| d.format == self.atoms.UTF8_STRING)
+			} else {
+				None
+			}
+		})
+	}
+
+	/// Returns the atom with the given name, creating it if necessary.
+	fn intern_atom(&self, name: &str) -> Result<Atom> {
+		Ok(self
+			.server
+			.conn
+			.intern_atom(false, name.as_bytes())
+			.map_err(x11_error("intern_atom"))?
+			.reply()
+			.map_err(x11_error("intern_atom"))?
+			.atom)
+	}
+
+	/// Hands the contents of the CLIPBOARD selection over to the clipboard manager, so that they
+	/// stay available after we stop serving them. Does nothing if we don't own the selection.
+	///
+	/// Returns `Error::NoClipboardManager` if no clipboard manager is running.
+	fn ask_clipboard_manager_to_request_our_data(&self, timeout: Duration) -> Result<()> {
 		if self.server.win_id == 0 {
 			// This shouldn't really ever happen but let's just check.
 			error!("The server's window id was 0. This is unexpected");
@@ -627,6 +976,18 @@ impl ClipboardContext {
 			return Ok(());
 		}
 
+		let manager = self
+			.server
+			.conn
+			.get_selection_owner(self.atoms.CLIPBOARD_MANAGER)
+			.map_err(x11_error("get_selection_owner"))?
+			.reply()
+			.map_err(x11_error("get_selection_owner"))?
+			.owner;
+		if manager == NONE {
+			return Err(Error::NoClipboardManager);
+		}
+
 		// It's important that we lock the state before sending the request
 		// because we don't want the request server thread to lock the state
 		// after the request but before we can lock it here.
@@ -642,22 +1003,24 @@ impl ClipboardContext {
 				self.atoms.ARBOARD_CLIPBOARD,
 				Time::CURRENT_TIME,
 			)
-			.map_err(into_unknown)?;
-		self.server.conn.flush().map_err(into_unknown)?;
+			.map_err(x11_error("convert_selection"))?;
+		self.server.conn.flush().map_err(x11_error("flush"))?;
 
 		*handover_state = ManagerHandoverState::InProgress;
-		let max_handover_duration = Duration::from_millis(100);
 
 		// Note that we are using a parking_lot condvar here, which doesn't wake up
 		// spouriously
-		let result = self.handover_cv.wait_for(&mut handover_state, max_handover_duration);
+		let result = self.handover_cv.wait_for(&mut handover_state, timeout);
 
 		if *handover_state == ManagerHandoverState::Finished {
 			return Ok(());
 		}
 		if result.timed_out() {
-			warn!("Could not hand the clipboard contents over to the clipboard manager. The request timed out.");
-			return Ok(());
+			// Whatever the clipboard manager sends later doesn't belong to a handover anymore.
+			*handover_state = ManagerHandoverState::Idle;
+			return Err(Error::Unknown {
+				description: "The clipboard manager didn't take the contents over in time.".into(),
+			});
 		}
 
 		Err(Error::Unknown {
@@ -666,6 +1029,14 @@ impl ClipboardContext {
 	}
 }
 
+fn selection_index(selection: LinuxClipboardKind) -> usize {
+	match selection {
+		LinuxClipboardKind::Clipboard => 0,
+		LinuxClipboardKind::Primary => 1,
+		LinuxClipboardKind::Secondary => 2,
+	}
+}
+
 struct ScopeGuard<F: FnOnce()> {
 	callback: Option<F>,
 }
@@ -687,7 +1058,7 @@ fn serve_requests(clipboard: Arc<ClipboardContext>) -> Result<(), Box<dyn std::e
 		clip: &Arc<ClipboardContext>,
 		mut handover_state: MutexGuard<ManagerHandoverState>,
 	) {
-		log::trace!("Finishing clipboard manager handover.");
+		trace!("Finishing clipboard manager handover.");
 		*handover_state = ManagerHandoverState::Finished;
 
 		// Not sure if unlocking the mutext is necessary here but better safe than sorry.
@@ -704,9 +1075,11 @@ fn serve_requests(clipboard: Arc<ClipboardContext>) -> Result<(), Box<dyn std::e
 
 	let mut written = false;
 	let mut notified = false;
+	// The INCR transfers in progress, by requestor window and property.
+	let mut transfers: HashMap<(Window, Atom), IncrTransfer> = HashMap::new();
 
 	loop {
-		match clipboard.server.conn.wait_for_event().map_err(into_unknown)? {
+		match clipboard.server.conn.wait_for_event().map_err(x11_error("wait_for_event"))? {
 			Event::DestroyNotify(_) => {
 				// This window is being destroyed.
 				trace!("Clipboard server window is being destroyed x_x");
@@ -719,18 +1092,32 @@ fn serve_requests(clipboard: Arc<ClipboardContext>) -> Result<(), Box<dyn std::e
 				trace!("Somebody else owns the clipboard now");
 
 				if let Some(selection) = clipboard.kind_of(event.selection) {
-					let mut data = clipboard.data_of(selection).write();
-					*data = None
+					*clipboard.data_of(selection).write() = None;
+					if let LinuxClipboardKind::Clipboard = selection {
+						clipboard.bump_generation_without_xfixes();
+					}
+					// Wake up everyone who waits for the contents to be pasted.
+					let _deliveries = clipboard.deliveries.lock();
+					clipboard.delivery_cv.notify_all();
 				}
 			}
+			Event::XfixesSelectionNotify(event) if event.selection == clipboard.atoms.CLIPBOARD => {
+				clipboard.generation.fetch_add(1, Ordering::Relaxed);
+			}
 			Event::SelectionRequest(event) => {
 				trace!(
 					"SelectionRequest - selection is: {}, target is {}",
 					clipboard.atom_name_dbg(event.selection),
 					clipboard.atom_name_dbg(event.target),
 				);
+				// Requestors that stopped accepting chunks (for example because they exited)
+				// would otherwise keep their transfers around forever.
+				transfers.retain(|_, transfer| !transfer.is_stalled());
+
 				// Someone is requesting the clipboard content from us.
-				clipboard.handle_selection_request(event).map_err(into_unknown)?;
+				if let Some(transfer) = clipboard.handle_selection_request(event)? {
+					transfers.insert((transfer.requestor, transfer.property), transfer);
+				}
 
 				// if we are in the progress of saving to the clipboard manager
 				// make sure we save that we have finished writing
@@ -744,8 +1131,14 @@ fn serve_requests(clipboard: Arc<ClipboardContext>) -> Result<(), Box<dyn std::e
 						// if we have written and notified, make sure to notify that we are done
 						if notified {
 							handover_finished(&clipboard, handover_state);
+							written = false;
+							notified = false;
 						}
 					}
+				} else {
+					// A previous handover has ended, the next one starts from scratch.
+					written = false;
+					notified = false;
 				}
 			}
 			Event::SelectionNotify(event) => {
@@ -773,6 +1166,19 @@ fn serve_requests(clipboard: Arc<ClipboardContext>) -> Result<(), Box<dyn std::e
 					// from the clipboard manager.
 					if written {
 						handover_finished(&clipboard, handover_state);
+						written = false;
+						notified = false;
+					}
+				} else {
+					written = false;
+					notified = false;
+				}
+			}
+			Event::PropertyNotify(event) if event.state == Property::DELETE => {
+				// The requestor of an INCR transfer is ready for the next chunk.
+				if let Entry::Occupied(mut transfer) = transfers.entry((event.window, event.atom)) {
+					if clipboard.send_incr_chunk(transfer.get_mut())? {
+						transfer.remove();
 					}
 				}
 			}
@@ -784,15 +1190,146 @@ fn serve_requests(clipboard: Arc<ClipboardContext>) -> Result<(), Box<dyn std::e
 	}
 }
 
+/// Sends an event to `events` whenever the owner of the CLIPBOARD or the PRIMARY selection changes,
+/// until the window of `context` is destroyed.
+fn watch_selections(
+	context: &XContext,
+	atoms: &Atoms,
+	xfixes_enabled: bool,
+	events: Sender<ClipboardEvent>,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let selections = [
+		(LinuxClipboardKind::Clipboard, atoms.CLIPBOARD),
+		(LinuxClipboardKind::Primary, atoms.PRIMARY),
+	];
+	let send = |selection, owner| {
+		let owner = if owner == NONE { None } else { Some(owner) };
+		// The receiver only goes away while the watcher is being dropped.
+		let _ = events.send(ClipboardEvent {
+			selection,
+			owner,
+			#[cfg(feature = "wayland-data-control")]
+			offer: None,
+		});
+	};
+
+	if xfixes_enabled {
+		loop {
+			match context.conn.wait_for_event()? {
+				Event::DestroyNotify(_) => return Ok(()),
+				Event::XfixesSelectionNotify(event) => {
+					if let Some(&(kind, _)) =
+						selections.iter().find(|(_, atom)| *atom == event.selection)
+					{
+						send(kind, event.owner);
+					}
+				}
+				_ => {}
+			}
+		}
+	}
+
+	let mut owners = [NONE; 2];
+	for (owner, &(_, atom)) in owners.iter_mut().zip(selections.iter()) {
+		*owner = context.conn.get_selection_owner(atom)?.reply()?.owner;
+	}
+	loop {
+		while let Some(event) = context.conn.poll_for_event()? {
+			if let Event::DestroyNotify(_) = event {
+				return Ok(());
+			}
+		}
+		for (owner, &(kind, atom)) in owners.iter_mut().zip(selections.iter()) {
+			let current = context.conn.get_selection_owner(atom)?.reply()?.owner;
+			if current != *owner {
+				*owner = current;
+				send(kind, current);
+			}
+		}
+		std::thread::sleep(WATCHER_POLL_INTERVAL);
+	}
+}
+
+/// Watches the selections through a connection of its own, so that its events never get mixed up
+/// with the ones handled by the clipboard server thread.
+pub(crate) struct X11ClipboardWatcher {
+	context: Arc<XContext>,
+	atoms: Atoms,
+	xfixes_enabled: bool,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl X11ClipboardWatcher {
+	pub(crate) fn new(events: Sender<ClipboardEvent>) -> Result<Self> {
+		let context = Arc::new(XContext::new()?);
+		let atoms = Atoms::new(&context.conn)
+			.map_err(x11_error("intern_atom"))?
+			.reply()
+			.map_err(x11_error("intern_atom"))?;
+		let selections = [atoms.CLIPBOARD, atoms.PRIMARY];
+		let xfixes_enabled =
+			match context.select_selection_input(&selections, selection_change_mask()) {
+				Ok(()) => true,
+				Err(e) => {
+					warn!(
+						"XFIXES is not available, falling back to polling the selection owners: {}",
+						e
+					);
+					false
+				}
+			};
+		let thread = {
+			let context = Arc::clone(&context);
+			std::thread::spawn(move || {
+				if let Err(error) = watch_selections(&context, &atoms, xfixes_enabled, events) {
+					error!("Clipboard watcher thread errored with: {}", error);
+				}
+			})
+		};
+		Ok(Self { context, atoms, xfixes_enabled, thread: Some(thread) })
+	}
+}
+
+impl Drop for X11ClipboardWatcher {
+	fn drop(&mut self) {
+		if self.xfixes_enabled {
+			let selections = [self.atoms.CLIPBOARD, self.atoms.PRIMARY];
+			if let Err(e) = self.context.select_selection_input(&selections, 0u8.into()) {
+				error!("Failed to stop the selection notifications of the watcher. Error: {}", e);
+			}
+		}
+		// Destroying the window stops the watcher thread.
+		if let Err(e) = self.context.conn.destroy_window(self.context.win_id) {
+			error!("Failed to destroy the clipboard watcher window. Error: {}", e);
+			return;
+		}
+		if let Err(e) = self.context.conn.flush() {
+			error!("Failed to flush the clipboard watcher window. Error: {}", e);
+			return;
+		}
+		if let Some(thread) = self.thread.take() {
+			if thread.join().is_err() {
+				error!("The clipboard watcher thread paniced.");
+			}
+		}
+	}
+}
+
+#[derive(Clone)]
 pub struct X11ClipboardContext {
 	inner: Arc<ClipboardContext>,
+	/// How long a read waits for the selection owner to start sending the data.
+	read_timeout: Duration,
 }
 
 impl X11ClipboardContext {
 	pub fn new() -> Result<Self> {
 		let mut global_cb = CLIPBOARD.lock();
 		if let Some(global_cb) = &*global_cb {
-			return Ok(Self { inner: Arc::clone(&global_cb.context) });
+			return Ok(Self {
+				inner: Arc::clone(&global_cb.context),
+				read_timeout: LONG_TIMEOUT_DUR,
+			});
 		}
 		// At this point we know that the clipboard does not exists.
 		let ctx = Arc::new(ClipboardContext::new()?);
@@ -807,7 +1344,32 @@ impl X11ClipboardContext {
 		}
 		*global_cb =
 			Some(GlobalClipboard { context: Arc::clone(&ctx), server_handle: join_handle });
-		Ok(Self { inner: ctx })
+		Ok(Self { inner: ctx, read_timeout: LONG_TIMEOUT_DUR })
+	}
+
+	pub(crate) fn set_read_timeout(&mut self, timeout: Duration) {
+		self.read_timeout = timeout;
+	}
+
+	pub fn generation(&self) -> u64 {
+		self.inner.generation.load(Ordering::Relaxed)
+	}
+
+	/// Returns how many times this process wrote the selection. Unlike `generation`, this is
+	/// updated before a write returns.
+	pub(crate) fn write_count(&self, selection: LinuxClipboardKind) -> u64 {
+		self.inner.writes[selection_index(selection)].load(Ordering::Relaxed)
+	}
+
+	/// Returns whether the contents of the selection are still the ones that this process wrote
+	/// when `write_count` returned `count`, as no other client took the selection over and this
+	/// process didn't write it again since.
+	pub(crate) fn is_written_by_us(
+		&self,
+		selection: LinuxClipboardKind,
+		count: u64,
+	) -> Result<bool> {
+		Ok(self.inner.is_owner(selection)? && self.write_count(selection) == count)
 	}
 
 	pub fn get_text(&self) -> Result<String> {
@@ -823,16 +1385,47 @@ impl X11ClipboardContext {
 			self.inner.atoms.TEXT,
 			self.inner.atoms.TEXT_MIME_UNKNOWN,
 		];
-		let result = self.inner.read(&formats, selection)?;
+		let result = self.inner.read(&formats, selection, self.read_timeout)?;
 		if result.format == self.inner.atoms.STRING {
 			// ISO Latin-1
 			// See: https://stackoverflow.com/questions/28169745/what-are-the-options-to-convert-iso-8859-1-latin-1-to-a-string-utf-8
 			Ok(result.bytes.into_iter().map(|c| c as char).collect())
 		} else {
-			String::from_utf8(result.bytes).map_err(|_| Error::ConversionFailure)
+			text_from_utf8(result.bytes)
 		}
 	}
 
+	pub fn swap_text(&self, message: String) -> Result<Option<String>> {
+		// There's no way to prevent others from taking ownership of the selection in between
+		// these two calls. Reading right before becoming the owner keeps the window small.
+		let previous = match self.get_text() {
+			Ok(text) => Some(text),
+			Err(Error::ContentNotAvailable) => None,
+			Err(e) => return Err(e),
+		};
+		self.set_text(message)?;
+		Ok(previous)
+	}
+
+	/// Reads the text through the X server even if we own the selection, which tests the way
+	/// other applications receive our contents.
+	#[cfg(test)]
+	pub(crate) fn get_text_from_server(&self) -> Result<String> {
+		let reader = XContext::new()?;
+		let bytes = self.inner.read_single(
+			&reader,
+			LinuxClipboardKind::Clipboard,
+			self.inner.atoms.UTF8_STRING,
+			self.read_timeout,
+		)?;
+		text_from_utf8(bytes)
+	}
+
+	pub fn get_text_bytes(&self, format: &str) -> Result<Vec<u8>> {
+		let format = self.inner.intern_atom(format)?;
+		Ok(self.inner.read(&[format], LinuxClipboardKind::Clipboard, self.read_timeout)?.bytes)
+	}
+
 	pub fn set_text(&self, message: String) -> Result<()> {
 		self.set_text_with_clipboard(message, LinuxClipboardKind::Clipboard)
 	}
@@ -844,31 +1437,134 @@ impl X11ClipboardContext {
 	) -> Result<()> {
 		let data =
 			ClipboardData { bytes: message.into_bytes(), format: self.inner.atoms.UTF8_STRING };
-		self.inner.write(data, selection)
+		self.inner.write(vec![data], selection)
 	}
 
 	#[cfg(feature = "image-data")]
-	pub fn get_image(&self) -> Result<ImageData> {
+	pub fn get_image(&self) -> Result<ImageData<'static>> {
 		let formats = [self.inner.atoms.PNG_MIME];
-		let bytes = self.inner.read(&formats, LinuxClipboardKind::Clipboard)?.bytes;
-
-		let cursor = std::io::Cursor::new(&bytes);
-		let mut reader = image::io::Reader::new(cursor);
-		reader.set_format(image::ImageFormat::Png);
-		let image = match reader.decode() {
-			Ok(img) => img.into_rgba8(),
-			Err(_e) => return Err(Error::ConversionFailure),
-		};
-		let (w, h) = image.dimensions();
-		let image_data =
-			ImageData { width: w as usize, height: h as usize, bytes: image.into_raw().into() };
-		Ok(image_data)
+		let bytes =
+			self.inner.read(&formats, LinuxClipboardKind::Clipboard, self.read_timeout)?.bytes;
+		decode_image(ImageFormatTag::Png, &bytes)
 	}
 
 	#[cfg(feature = "image-data")]
 	pub fn set_image(&self, image: ImageData) -> Result<()> {
 		let encoded = encode_as_png(&image)?;
 		let data = ClipboardData { bytes: encoded, format: self.inner.atoms.PNG_MIME };
+		self.inner.write(vec![data], LinuxClipboardKind::Clipboard)
+	}
+
+	pub(crate) fn write_all_with_clipboard(
+		&self,
+		representations: &[Representation],
+		selection: LinuxClipboardKind,
+	) -> Result<()> {
+		let mut data = Vec::with_capacity(representations.len());
+		for representation in representations {
+			data.push(match representation {
+				Representation::Text(text) => ClipboardData {
+					bytes: text.as_bytes().to_vec(),
+					format: self.inner.atoms.UTF8_STRING,
+				},
+				Representation::Html(html) => ClipboardData {
+					bytes: html.as_bytes().to_vec(),
+					format: self.inner.atoms.HTML_MIME,
+				},
+				Representation::Custom(name, bytes) => {
+					ClipboardData { bytes: bytes.clone(), format: self.inner.intern_atom(name)? }
+				}
+			});
+		}
+		self.inner.write(data, selection)
+	}
+
+	/// Returns the targets that the owner of the CLIPBOARD selection offers, other than the
+	/// special ones that ask the owner to do something.
+	fn targets(&self) -> Result<Vec<Atom>> {
+		let atoms = &self.inner.atoms;
+		let targets = match self.inner.read(
+			&[atoms.TARGETS],
+			LinuxClipboardKind::Clipboard,
+			self.read_timeout,
+		) {
+			Ok(targets) => targets.bytes,
+			Err(Error::ContentNotAvailable) => return Ok(Vec::new()),
+			Err(e) => return Err(e),
+		};
+		// These targets don't represent the contents, but rather ask the owner to do something.
+		let special_targets = [
+			atoms.TARGETS,
+			atoms.SAVE_TARGETS,
+			atoms.MULTIPLE,
+			atoms.TIMESTAMP,
+			atoms.DELETE,
+			atoms.INSERT_SELECTION,
+			atoms.INSERT_PROPERTY,
+		];
+		// The reply holds 32 bit atoms in the native byte order.
+		Ok(targets
+			.chunks_exact(4)
+			.map(|c| Atom::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+			.filter(|target| !special_targets.contains(target))
+			.collect())
+	}
+
+	/// Returns the names of the targets of the CLIPBOARD selection.
+	pub fn available_formats(&self) -> Result<Vec<String>> {
+		const SELECTION: LinuxClipboardKind = LinuxClipboardKind::Clipboard;
+		if self.inner.is_owner(SELECTION)? {
+			let data = self.inner.data_of(SELECTION).read();
+			return data.iter().flatten().map(|data| self.inner.atom_name(data.format)).collect();
+		}
+		self.targets()?.into_iter().map(|target| self.inner.atom_name(target)).collect()
+	}
+
+	pub fn snapshot(&self, max_bytes: Option<usize>) -> Result<ClipboardSnapshot> {
+		const SELECTION: LinuxClipboardKind = LinuxClipboardKind::Clipboard;
+		let mut snapshot = ClipboardSnapshot::default();
+		snapshot.new_item();
+
+		if self.inner.is_owner(SELECTION)? {
+			let data = self.inner.data_of(SELECTION).read();
+			for data in data.iter().flatten() {
+				let name = self.inner.atom_name(data.format)?;
+				snapshot.push(name, data.bytes.clone(), max_bytes);
+			}
+			return Ok(snapshot);
+		}
+
+		for target in self.targets()? {
+			let name = self.inner.atom_name(target)?;
+			match self.inner.read(&[target], SELECTION, self.read_timeout) {
+				Ok(data) => snapshot.push(name, data.bytes, max_bytes),
+				Err(e) => {
+					warn!("Could not read the `{}` target for the snapshot: {}", name, e);
+					snapshot.skip(&name, e);
+				}
+			}
+		}
+		Ok(snapshot)
+	}
+
+	pub(crate) fn wait_for_paste(
+		&self,
+		selection: LinuxClipboardKind,
+		deadline: Option<Instant>,
+	) -> Result<()> {
+		self.inner.wait_for_paste(selection, deadline)
+	}
+
+	pub fn persist(&self) -> Result<()> {
+		self.inner.ask_clipboard_manager_to_request_our_data(LONG_TIMEOUT_DUR)
+	}
+
+	pub fn restore(&self, snapshot: &ClipboardSnapshot) -> Result<()> {
+		let mut data = Vec::new();
+		for (name, bytes) in snapshot.items.iter().flatten() {
+			let format = self.inner.intern_atom(name)?;
+			data.push(ClipboardData { bytes: bytes.clone(), format });
+		}
 		self.inner.write(data, LinuxClipboardKind::Clipboard)
 	}
 }
@@ -887,8 +1583,11 @@ impl Drop for X11ClipboardContext {
 			// the global object, then we should destroy the global object,
 			// and send the data to the clipboard manager
 
-			if let Err(e) = self.inner.ask_clipboard_manager_to_request_our_data() {
-				error!("Could not hand the clipboard data over to the clipboard manager: {}", e);
+			match self.inner.ask_clipboard_manager_to_request_our_data(HANDOVER_TIMEOUT_DUR) {
+				Ok(()) | Err(Error::NoClipboardManager) => {}
+				Err(e) => {
+					warn!("Could not hand the clipboard data over to the clipboard manager: {}", e);
+				}
 			}
 			let global_cb = global_cb.take();
 			if let Err(e) = self.inner.server.conn.destroy_window(self.inner.server.win_id) {
@@ -0,0 +1,82 @@
+// Runs the `arboard` binary, which is only built with the `cli` feature:
+//
+//     cargo test --features cli --test cli
+//
+// The clipboard of the system isn't touched. Only the arguments are checked, and with the
+// `test-util` feature on Linux, the commands run against the mock clipboard, which every run of the
+// binary starts out empty.
+
+#![cfg(feature = "cli")]
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn arboard(args: &[&str], input: &[u8]) -> Output {
+	let mut child = Command::new(env!("CARGO_BIN_EXE_arboard"))
+		.args(args)
+		.env("ARBOARD_BACKEND", "mock")
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.unwrap();
+	child.stdin.take().unwrap().write_all(input).unwrap();
+	child.wait_with_output().unwrap()
+}
+
+#[test]
+fn help() {
+	for args in [&["help"][..], &["--help"], &["-h"]] {
+		let output = arboard(args, b"");
+		assert_eq!(output.status.code(), Some(0), "{:?}", args);
+		assert!(String::from_utf8_lossy(&output.stdout).starts_with("Usage: arboard"));
+	}
+}
+
+#[test]
+fn invalid_arguments() {
+	let invalid: &[&[&str]] = &[
+		&[],
+		&["cut"],
+		&["copy", "--out", "shot.png"],
+		&["paste", "--format"],
+		&["paste", "--verbose"],
+		&["formats", "text"],
+		&["watch", "--format", "png"],
+	];
+	for args in invalid {
+		let output = arboard(args, b"");
+		assert_eq!(output.status.code(), Some(2), "{:?}", args);
+		assert!(String::from_utf8_lossy(&output.stderr).contains("Usage: arboard"), "{:?}", args);
+	}
+}
+
+#[cfg(all(
+	feature = "test-util",
+	unix,
+	not(any(
+		target_os = "macos",
+		target_os = "ios",
+		target_os = "android",
+		target_os = "emscripten"
+	))
+))]
+#[test]
+fn empty_clipboard() {
+	let empty: &[&[&str]] = &[
+		&["formats"],
+		&["paste"],
+		&["paste", "--format", "png"],
+		&["paste", "-f", "image/x-test"],
+	];
+	for args in empty {
+		let output = arboard(args, b"");
+		assert_eq!(output.status.code(), Some(1), "{:?}", args);
+		assert!(output.stdout.is_empty(), "{:?}", args);
+	}
+
+	assert_eq!(arboard(&["copy"], b"copied").status.code(), Some(0));
+	let output = arboard(&["copy", "--format", "png"], b"not a PNG");
+	assert_eq!(output.status.code(), Some(2));
+	assert!(String::from_utf8_lossy(&output.stderr).contains("PNG"));
+}
@@ -0,0 +1,99 @@
+/*
+ * Exercises the C interface of the `ffi` feature. Run it with `tests/ffi/run.sh`, which uses the
+ * in-memory clipboard of the `test-util` feature, so that no display server is needed.
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "arboard.h"
+
+/* Seeds the in-memory clipboard. Only built with the `test-util` feature, so not in arboard.h. */
+ArboardStatus arboard_mock_set_image(const uint8_t *pixels, size_t width, size_t height);
+
+#define CHECK(condition)                                                                           \
+	do {                                                                                           \
+		if (!(condition)) {                                                                        \
+			fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #condition);         \
+			exit(1);                                                                               \
+		}                                                                                          \
+	} while (0)
+
+int main(void) {
+	ArboardClipboard *clipboard = arboard_clipboard_new();
+	CHECK(clipboard != NULL);
+	CHECK(arboard_last_error_message() == NULL);
+
+	/* The text is copied, so the caller's buffer can be reused right away. */
+	char text[] = "Hello from C, h\xc3\xa9 h\xc3\xa9";
+	CHECK(arboard_set_text(clipboard, text) == ARBOARD_STATUS_OK);
+	memset(text, 0, sizeof(text));
+
+	/* The text that's returned belongs to the caller until it's passed to arboard_string_free. */
+	char *read = (char *)"not overwritten";
+	CHECK(arboard_get_text(clipboard, &read) == ARBOARD_STATUS_OK);
+	CHECK(strcmp(read, "Hello from C, h\xc3\xa9 h\xc3\xa9") == 0);
+	arboard_string_free(read);
+	arboard_string_free(NULL);
+
+	/* Invalid arguments are reported instead of crashing. */
+	CHECK(arboard_set_text(clipboard, NULL) == ARBOARD_STATUS_INVALID_ARGUMENT);
+	CHECK(arboard_last_error_message() != NULL);
+	CHECK(arboard_set_text(clipboard, "\xff") == ARBOARD_STATUS_INVALID_ARGUMENT);
+	CHECK(arboard_set_text(NULL, "text") == ARBOARD_STATUS_INVALID_ARGUMENT);
+	CHECK(arboard_get_text(clipboard, NULL) == ARBOARD_STATUS_INVALID_ARGUMENT);
+
+	/* The message belongs to arboard and is replaced by the next call. */
+	size_t width = 0, height = 0, stride = 0;
+	ArboardStatus status = arboard_get_image(clipboard, NULL, 0, &width, &height, &stride);
+	CHECK(status == ARBOARD_STATUS_CONTENT_NOT_AVAILABLE ||
+	      status == ARBOARD_STATUS_CLIPBOARD_NOT_SUPPORTED);
+	const char *message = arboard_last_error_message();
+	CHECK(message != NULL && strlen(message) > 0);
+	CHECK(arboard_get_image(clipboard, NULL, 0, NULL, &height, &stride) ==
+	      ARBOARD_STATUS_INVALID_ARGUMENT);
+
+	CHECK(arboard_get_text(clipboard, &read) == ARBOARD_STATUS_OK);
+	CHECK(arboard_last_error_message() == NULL);
+	arboard_string_free(read);
+
+	/* An image is read into a buffer of the caller. The first call, without a buffer, only
+	 * reports the dimensions, so that the caller can allocate `stride * height` bytes. */
+	const uint8_t pixels[2 * 3 * 4] = {
+		255, 0,   0,   255, 0,   255, 0,   255, /* row 0 */
+		0,   0,   255, 255, 255, 255, 255, 128, /* row 1 */
+		1,   2,   3,   4,   5,   6,   7,   8,   /* row 2 */
+	};
+	CHECK(arboard_mock_set_image(pixels, 2, 3) == ARBOARD_STATUS_OK);
+	width = height = stride = 0;
+	status = arboard_get_image(clipboard, NULL, 0, &width, &height, &stride);
+	CHECK(status == ARBOARD_STATUS_BUFFER_TOO_SMALL);
+	CHECK(width == 2 && height == 3 && stride == 2 * 4);
+	CHECK(arboard_last_error_message() != NULL);
+
+	size_t buffer_len = stride * height;
+	uint8_t *buffer = malloc(buffer_len);
+	CHECK(buffer != NULL);
+	/* A buffer that's one byte short is left untouched. */
+	memset(buffer, 0xAB, buffer_len);
+	status = arboard_get_image(clipboard, buffer, buffer_len - 1, &width, &height, &stride);
+	CHECK(status == ARBOARD_STATUS_BUFFER_TOO_SMALL);
+	CHECK(buffer[0] == 0xAB && buffer[buffer_len - 1] == 0xAB);
+
+	width = height = stride = 0;
+	status = arboard_get_image(clipboard, buffer, buffer_len, &width, &height, &stride);
+	CHECK(status == ARBOARD_STATUS_OK);
+	CHECK(arboard_last_error_message() == NULL);
+	CHECK(width == 2 && height == 3 && stride == 2 * 4);
+	for (size_t row = 0; row < height; row++) {
+		CHECK(memcmp(buffer + row * stride, pixels + row * width * 4, width * 4) == 0);
+	}
+	/* The buffer belongs to the caller, so it's released with the free of the C library. */
+	free(buffer);
+
+	arboard_clipboard_free(clipboard);
+	arboard_clipboard_free(NULL);
+	printf("ffi test passed\n");
+	return 0;
+}
@@ -0,0 +1,10 @@
+#!/bin/sh
+# Builds arboard as a C library and runs tests/ffi/main.c against it, using the in-memory
+# clipboard of the `test-util` feature. Only works on Linux, where `ARBOARD_BACKEND` is supported.
+set -e
+cd "$(dirname "$0")/../.."
+
+cargo rustc --lib --features ffi,test-util --crate-type cdylib
+cc -std=c99 -Wall -Wextra -Werror -Iinclude tests/ffi/main.c -Ltarget/debug -larboard \
+	-o target/debug/ffi_test
+ARBOARD_BACKEND=mock LD_LIBRARY_PATH=target/debug target/debug/ffi_test
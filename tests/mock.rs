@@ -0,0 +1,504 @@
+// Exercises the methods of `Clipboard` on the in-memory mock backend, so they run everywhere,
+// including on CI machines without a display server.
+
+#![cfg(feature = "test-util")]
+
+use std::time::{Duration, Instant};
+
+use arboard::{Clipboard, Error, ImageData, LineEndings, MockClipboard, MOCK_TEXT_FORMAT};
+
+/// The name of the format that holds PDF documents on the platform.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const PDF_FORMAT: &str = "com.adobe.pdf";
+#[cfg(windows)]
+const PDF_FORMAT: &str = "Portable Document Format";
+#[cfg(not(any(target_os = "macos", target_os = "ios", windows)))]
+const PDF_FORMAT: &str = "application/pdf";
+
+/// The name of the format that holds HTML on the platform.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const HTML_FORMAT: &str = "public.html";
+#[cfg(windows)]
+const HTML_FORMAT: &str = "HTML Format";
+#[cfg(not(any(target_os = "macos", target_os = "ios", windows)))]
+const HTML_FORMAT: &str = "text/html";
+
+/// Returns a clipboard backed by a new mock, and a clone of the mock that shares its contents, to
+/// inspect what the test placed onto the clipboard or to make the next operation fail.
+fn mock_clipboard() -> (Clipboard, MockClipboard) {
+	let mock = MockClipboard::new();
+	(Clipboard::from_mock(mock.clone()), mock)
+}
+
+#[test]
+fn mock_backend() {
+	let (mut ctx, mock) = mock_clipboard();
+	assert_eq!(ctx.backend_name(), "Mock");
+
+	ctx.write()
+		.text("text")
+		.html("<b>html</b>")
+		.data("application/x-custom", [1, 2])
+		.commit()
+		.unwrap();
+	assert_eq!(ctx.get().text().unwrap(), "text");
+	assert_eq!(ctx.get_text_bytes("application/x-custom").unwrap(), [1, 2]);
+	let mut formats = mock.formats().collect::<Vec<_>>();
+	formats.sort();
+	assert_eq!(formats, ["application/x-custom", "text/html", MOCK_TEXT_FORMAT]);
+
+	let generation = ctx.generation().unwrap();
+	let snapshot = ctx.snapshot().unwrap();
+	assert_eq!(ctx.swap_text("swapped".into()).unwrap().as_deref(), Some("text"));
+	assert_ne!(ctx.generation().unwrap(), generation);
+	let previous = ctx.swap_content(&snapshot).unwrap();
+	assert_eq!(previous.formats().collect::<Vec<_>>(), [MOCK_TEXT_FORMAT]);
+	assert_eq!(ctx.get_text().unwrap(), "text");
+	assert_eq!(ctx.snapshot_with_limit(3).unwrap().warnings().len(), 2);
+
+	mock.fail_next_read(Error::ClipboardOccupied);
+	assert!(matches!(ctx.get_text(), Err(Error::ClipboardOccupied)));
+	assert_eq!(ctx.get_text().unwrap(), "text");
+	mock.fail_next_write(Error::ClipboardOccupied);
+	assert!(matches!(ctx.set_text("lost"), Err(Error::ClipboardOccupied)));
+	assert_eq!(ctx.get_text().unwrap(), "text");
+
+	ctx.set_text_multi("multi").unwrap();
+	assert_eq!(ctx.get_text().unwrap(), "multi");
+	assert_eq!(ctx.available_formats().unwrap(), [MOCK_TEXT_FORMAT]);
+	ctx.clear().unwrap();
+	assert!(ctx.available_formats().unwrap().is_empty());
+	ctx.set_text("text").unwrap();
+
+	#[cfg(feature = "image-data")]
+	{
+		let image = ImageData { width: 4, height: 2, bytes: vec![255; 32].into() };
+		ctx.set_image_scaled(image, 2).unwrap();
+		let image = ctx.get_image().unwrap();
+		assert_eq!((image.width, image.height), (2, 1));
+	}
+
+	#[cfg(all(
+		unix,
+		not(any(
+			target_os = "macos",
+			target_os = "ios",
+			target_os = "android",
+			target_os = "emscripten"
+		)),
+	))]
+	{
+		use arboard::{
+			ClipboardBuilderExtLinux, ClipboardExtLinux, LinuxBackend, LinuxClipboardKind,
+		};
+
+		assert_eq!(ClipboardExtLinux::backend(&ctx), LinuxBackend::Mock);
+		ctx.set_text_with_clipboard("selection".into(), LinuxClipboardKind::Clipboard).unwrap();
+		assert!(matches!(
+			ctx.get_text_with_clipboard(LinuxClipboardKind::Primary),
+			Err(Error::ClipboardNotSupported)
+		));
+		let built = Clipboard::builder().backend(LinuxBackend::Mock).build().unwrap();
+		assert_eq!(built.backend_name(), "Mock");
+	}
+}
+
+#[test]
+fn invalid_utf8_mock() {
+	let (mut ctx, _) = mock_clipboard();
+	ctx.write().data(MOCK_TEXT_FORMAT, [b'a', 0xFF]).commit().unwrap();
+	assert!(matches!(ctx.get_text(), Err(Error::InvalidUtf8 { bytes }) if bytes == [b'a', 0xFF]));
+	assert_eq!(ctx.get_text_lossy().unwrap(), "a\u{FFFD}");
+}
+
+#[test]
+fn empty_text_mock() {
+	let (mut ctx, _) = mock_clipboard();
+	ctx.set_text("previous").unwrap();
+	ctx.set_text(String::new()).unwrap();
+	assert_eq!(ctx.get_text().unwrap(), "");
+	assert_eq!(ctx.available_formats().unwrap(), [MOCK_TEXT_FORMAT]);
+	assert_eq!(ctx.swap_text("next".into()).unwrap().as_deref(), Some(""));
+}
+
+#[test]
+fn swap_text_mock() {
+	let (mut ctx, _) = mock_clipboard();
+	assert_eq!(ctx.swap_text("first".into()).unwrap(), None);
+	assert_eq!(ctx.swap_text("second".into()).unwrap().as_deref(), Some("first"));
+	assert_eq!(ctx.get_text().unwrap(), "second");
+
+	ctx.write().data(MOCK_TEXT_FORMAT, [0xff]).commit().unwrap();
+	assert!(matches!(ctx.swap_text("third".into()), Err(Error::InvalidUtf8 { .. })));
+	assert!(matches!(ctx.get_text(), Err(Error::InvalidUtf8 { .. })));
+}
+
+#[test]
+fn borrowed_text_mock() {
+	let (mut ctx, _) = mock_clipboard();
+	let owned = String::from("owned");
+	ctx.set_text(&owned).unwrap();
+	assert_eq!(ctx.get_text().unwrap(), "owned");
+	ctx.set_text(owned).unwrap();
+	assert_eq!(ctx.get_text().unwrap(), "owned");
+	ctx.set_text(std::borrow::Cow::Borrowed("borrowed")).unwrap();
+	assert_eq!(ctx.get_text().unwrap(), "borrowed");
+}
+
+#[test]
+fn line_endings_mock() {
+	let (mut ctx, _) = mock_clipboard();
+	ctx.write().text("a\r\nb\rc\n").commit().unwrap();
+	assert_eq!(ctx.get().text().unwrap(), "a\r\nb\rc\n");
+	assert_eq!(ctx.get().line_endings(LineEndings::Unix).text().unwrap(), "a\nb\nc\n");
+
+	ctx.write().text("one\ntwo").line_endings(LineEndings::Dos).commit().unwrap();
+	assert_eq!(ctx.get_text().unwrap(), "one\r\ntwo");
+	ctx.write().line_endings(LineEndings::Unix).text("one\r\ntwo\r").commit().unwrap();
+	assert_eq!(ctx.get_text().unwrap(), "one\ntwo\n");
+}
+
+#[test]
+fn items_mock() {
+	let (mut ctx, _) = mock_clipboard();
+	assert!(ctx.items().unwrap().is_empty());
+	assert_eq!(ctx.count_items(), 0);
+	ctx.write().text("text").data("custom", [1, 2]).commit().unwrap();
+	assert_eq!(ctx.count_items(), 1);
+	let items = ctx.items().unwrap();
+	assert_eq!(items.len(), 1);
+	assert_eq!(items[0].types().count(), 2);
+	assert_eq!(items[0].data_for_type("custom"), Some(&[1, 2][..]));
+	assert_eq!(items[0].data_for_type("missing"), None);
+}
+
+#[test]
+fn pdf_mock() {
+	let (mut ctx, _) = mock_clipboard();
+	ctx.set_text("not a document").unwrap();
+	assert!(matches!(ctx.get_pdf(), Err(Error::ContentNotAvailable)));
+	ctx.write().data(PDF_FORMAT, &b"%PDF-1.7"[..]).commit().unwrap();
+	assert_eq!(ctx.get_pdf().unwrap(), b"%PDF-1.7");
+}
+
+#[test]
+fn html_mock() {
+	let (mut ctx, _) = mock_clipboard();
+	assert!(matches!(ctx.get_html(), Err(Error::ContentNotAvailable)));
+	let raw = &b"\xEF\xBB\xBF<p>a\r\nb</p>"[..];
+	ctx.write().data(HTML_FORMAT, raw).commit().unwrap();
+	assert_eq!(ctx.get_html_raw().unwrap(), raw);
+	assert_eq!(ctx.get_html().unwrap(), "<p>a\nb</p>");
+}
+
+#[test]
+fn set_with_generation_mock() {
+	let (mut ctx, mock) = mock_clipboard();
+	let before = ctx.generation().unwrap();
+	let written = ctx.set_text_with_generation("text").unwrap();
+	assert_ne!(written, before);
+	assert_eq!(written, ctx.generation().unwrap());
+
+	mock.fail_next_write(Error::ClipboardOccupied);
+	assert!(matches!(ctx.set_text_with_generation("failed"), Err(Error::ClipboardOccupied)));
+	assert_eq!(ctx.generation().unwrap(), written);
+
+	#[cfg(feature = "image-data")]
+	{
+		let image = ImageData { width: 1, height: 1, bytes: vec![0; 4].into() };
+		let written = ctx.set_image_with_generation(image).unwrap();
+		assert_eq!(written, ctx.generation().unwrap());
+	}
+}
+
+#[test]
+fn clear_after_mock() {
+	let (mut ctx, mock) = mock_clipboard();
+	let clear = ctx.write().text("123456").clear_after(Duration::from_millis(10)).unwrap();
+	assert_eq!(ctx.get_text().unwrap(), "123456");
+	assert!(clear.wait().unwrap());
+	assert!(matches!(ctx.get_text(), Err(Error::ContentNotAvailable)));
+
+	// Contents that were copied later are left alone.
+	let clear = ctx.write().text("123456").clear_after(Duration::from_millis(50)).unwrap();
+	Clipboard::from_mock(mock).set_text("copied later").unwrap();
+	assert!(!clear.wait().unwrap());
+	assert_eq!(ctx.get_text().unwrap(), "copied later");
+
+	let clear = ctx.write().text("123456").clear_after(Duration::from_secs(60)).unwrap();
+	let cancelled_at = Instant::now();
+	clear.cancel();
+	assert!(cancelled_at.elapsed() < Duration::from_secs(60));
+	assert_eq!(ctx.get_text().unwrap(), "123456");
+
+	// Dropping the handle doesn't cancel the clear.
+	drop(ctx.write().text("654321").clear_after(Duration::from_millis(10)).unwrap());
+	let deadline = Instant::now() + Duration::from_secs(5);
+	while ctx.get_text().is_ok() && Instant::now() < deadline {
+		std::thread::sleep(Duration::from_millis(5));
+	}
+	assert!(matches!(ctx.get_text(), Err(Error::ContentNotAvailable)));
+
+	let mut custom = Clipboard::from_provider(Box::new(MockClipboard::new()));
+	let result = custom.write().text("123456").clear_after(Duration::from_millis(10));
+	assert!(matches!(result, Err(Error::ClipboardNotSupported)));
+	assert!(matches!(custom.get_text(), Err(Error::ContentNotAvailable)));
+}
+
+#[test]
+fn sensitive_mock() {
+	let (mut ctx, _) = mock_clipboard();
+	ctx.write().text("hunter2").sensitive().commit().unwrap();
+	assert_eq!(ctx.get_text().unwrap(), "hunter2");
+	#[cfg(all(
+		unix,
+		not(any(
+			target_os = "macos",
+			target_os = "ios",
+			target_os = "android",
+			target_os = "emscripten"
+		)),
+	))]
+	assert_eq!(ctx.get_text_bytes("x-kde-passwordManagerHint").unwrap(), b"secret");
+	#[cfg(target_os = "macos")]
+	assert!(ctx.is_concealed());
+	#[cfg(windows)]
+	{
+		let formats = ctx.available_formats().unwrap();
+		for format in [
+			"ExcludeClipboardContentFromMonitorProcessing",
+			"CanIncludeInClipboardHistory",
+			"CanUploadToCloudClipboard",
+		] {
+			assert!(formats.iter().any(|f| f == format), "{} is missing", format);
+		}
+	}
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing_spans_mock() {
+	use std::sync::Mutex;
+	use tracing::{field, span, Event, Metadata};
+
+	/// Collects the fields recorded on the spans as `name=value` strings.
+	#[derive(Default)]
+	struct Recorder(Mutex<Vec<String>>);
+
+	impl field::Visit for &Recorder {
+		fn record_debug(&mut self, field: &field::Field, value: &dyn std::fmt::Debug) {
+			self.0.lock().unwrap().push(format!("{}={:?}", field.name(), value));
+		}
+	}
+
+	impl tracing::Subscriber for &'static Recorder {
+		fn enabled(&self, _: &Metadata<'_>) -> bool {
+			true
+		}
+		fn new_span(&self, attributes: &span::Attributes<'_>) -> span::Id {
+			attributes.record(&mut &**self);
+			span::Id::from_u64(1)
+		}
+		fn record(&self, _: &span::Id, values: &span::Record<'_>) {
+			values.record(&mut &**self);
+		}
+		fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+		fn event(&self, _: &Event<'_>) {}
+		fn enter(&self, _: &span::Id) {}
+		fn exit(&self, _: &span::Id) {}
+	}
+
+	let recorder: &'static Recorder = Box::leak(Box::default());
+	let mock = MockClipboard::new();
+	tracing::subscriber::with_default(recorder, || {
+		let mut ctx = Clipboard::from_mock(mock.clone());
+		ctx.set_text("hello").unwrap();
+		mock.fail_next_read(Error::ClipboardOccupied);
+		assert!(ctx.get_text().is_err());
+		ctx.write().data("application/x-custom", [1, 2, 3]).commit().unwrap();
+	});
+	let fields = recorder.0.lock().unwrap();
+	for expected in [
+		"backend=\"Mock\"",
+		"operation=\"set_text\"",
+		"bytes=5",
+		"operation=\"get_text\"",
+		"operation=\"commit\"",
+		"formats=[\"application/x-custom\"]",
+		"bytes=3",
+	] {
+		assert!(fields.iter().any(|field| field == expected), "{} not in {:?}", expected, fields);
+	}
+	assert!(fields.contains(&format!("error={}", Error::ClipboardOccupied)));
+}
+
+#[cfg(feature = "image-data")]
+#[test]
+fn image_format_mock() {
+	let (mut ctx, _) = mock_clipboard();
+	assert!(matches!(ctx.get_image_with_format(), Err(Error::ContentNotAvailable)));
+	let image = ImageData { width: 1, height: 1, bytes: vec![1, 2, 3, 4].into() };
+	ctx.set_image_ref(&image).unwrap();
+	let (format, got) = ctx.get_image_with_format().unwrap();
+	assert_eq!(format, arboard::ImageFormatTag::Other);
+	assert_eq!(got.bytes, image.bytes);
+	let frames = ctx.get_image_frames().unwrap();
+	assert_eq!(frames.len(), 1);
+	assert_eq!(frames[0].bytes, image.bytes);
+}
+
+#[cfg(feature = "image-data")]
+#[test]
+fn pixel_order_mock() {
+	use arboard::{PixelFormat, PixelOrder};
+
+	let (mut ctx, _) = mock_clipboard();
+	let bgra = [30, 20, 10, 40, 3, 2, 1, 4];
+	let image = ImageData { width: 2, height: 1, bytes: bgra.as_ref().into() };
+	ctx.set_image_with_order(image.clone(), PixelOrder::Bgra).unwrap();
+	assert_eq!(ctx.get_image().unwrap().bytes, [10, 20, 30, 40, 1, 2, 3, 4].as_ref());
+	ctx.set_image_with_order(image, PixelOrder::Rgba).unwrap();
+	assert_eq!(ctx.get_image().unwrap().bytes, bgra.as_ref());
+	assert_eq!(PixelOrder::default(), PixelOrder::Rgba);
+
+	let rgba = [200, 100, 50, 128, 255, 255, 255, 0];
+	let image = ImageData { width: 2, height: 1, bytes: rgba.as_ref().into() };
+	ctx.set_image_ref(&image).unwrap();
+	assert_eq!(ctx.get_image_as(PixelFormat::Rgba8).unwrap(), image);
+	let got = ctx.get_image_as(PixelFormat::Bgra8).unwrap();
+	assert_eq!(got.bytes, [50, 100, 200, 128, 255, 255, 255, 0].as_ref());
+	let got = ctx.get_image_as(PixelFormat::PremultipliedRgba8).unwrap();
+	assert_eq!(got.bytes, [100, 50, 25, 128, 0, 0, 0, 0].as_ref());
+
+	let odd = ImageData { width: 2, height: 1, bytes: bgra[..7].as_ref().into() };
+	let result = ctx.set_image_with_order(odd, PixelOrder::Bgra);
+	assert!(matches!(result, Err(Error::ConversionFailure)));
+}
+
+#[cfg(feature = "image-data")]
+#[test]
+fn lazy_image_mock() {
+	let (mut ctx, _) = mock_clipboard();
+	assert!(matches!(ctx.get_image_lazy(), Err(Error::ContentNotAvailable)));
+	let image = ImageData { width: 2, height: 1, bytes: vec![1, 2, 3, 4, 5, 6, 7, 8].into() };
+	ctx.set_image_ref(&image).unwrap();
+	// The mock only has decoded pixels, which are returned as they are.
+	let lazy = ctx.get_image_lazy().unwrap();
+	assert_eq!(
+		(lazy.width(), lazy.height(), lazy.format()),
+		(2, 1, arboard::ImageFormatTag::Other)
+	);
+	assert_eq!(lazy.data(), None);
+	ctx.clear().unwrap();
+	assert_eq!(lazy.decode().unwrap(), image);
+}
+
+#[cfg(feature = "image-data")]
+#[test]
+fn image_timeout_mock() {
+	let (mut ctx, mock) = mock_clipboard();
+	let image = ImageData { width: 1, height: 1, bytes: vec![1, 2, 3, 4].into() };
+	ctx.set_image_ref(&image).unwrap();
+	let got = ctx.get_image_with_timeout(Duration::from_secs(10)).unwrap();
+	assert_eq!(got, image);
+
+	mock.delay_next_read(Duration::from_millis(500));
+	let start = Instant::now();
+	let result = ctx.get_image_with_timeout(Duration::from_millis(20));
+	assert!(matches!(result, Err(Error::Timeout)));
+	assert!(start.elapsed() < Duration::from_millis(500));
+}
+
+#[cfg(all(
+	feature = "image-data",
+	unix,
+	not(any(
+		target_os = "macos",
+		target_os = "ios",
+		target_os = "android",
+		target_os = "emscripten"
+	)),
+))]
+#[test]
+fn builder_options_mock() {
+	use arboard::{ClipboardBuilderExtLinux, LinuxBackend};
+
+	// The timeout and retry policy don't apply to the mock, so they're ignored.
+	let mut built = Clipboard::builder()
+		.backend(LinuxBackend::Mock)
+		.timeout(Duration::from_millis(1))
+		.retry(0, Duration::from_secs(0))
+		.max_image_bytes(16)
+		.build()
+		.unwrap();
+	assert_eq!(built.backend_name(), "Mock");
+	let large = ImageData { width: 3, height: 2, bytes: vec![0; 24].into() };
+	assert!(matches!(
+		built.set_image_ref(&large),
+		Err(Error::ImageTooLarge { size: 24, limit: 16 })
+	));
+}
+
+#[cfg(not(feature = "image-data"))]
+#[test]
+fn images_without_feature() {
+	use arboard::{PixelFormat, PixelOrder};
+
+	let (mut ctx, _) = mock_clipboard();
+	let image = ImageData { width: 1, height: 1, bytes: [0, 0, 0, 255].as_ref().into() };
+	assert!(matches!(ctx.set_image_ref(&image), Err(Error::UnsupportedFormat { .. })));
+	assert!(matches!(ctx.set_image_scaled(image.clone(), 1), Err(Error::UnsupportedFormat { .. })));
+	let result = ctx.set_image_with_order(image.clone(), PixelOrder::Bgra);
+	assert!(matches!(result, Err(Error::UnsupportedFormat { .. })));
+	assert!(matches!(ctx.get_image_as(PixelFormat::Bgra8), Err(Error::UnsupportedFormat { .. })));
+	assert!(matches!(ctx.set_image(image), Err(Error::UnsupportedFormat { .. })));
+	assert!(matches!(ctx.get_image(), Err(Error::UnsupportedFormat { .. })));
+	assert!(matches!(ctx.get_image_frames(), Err(Error::UnsupportedFormat { .. })));
+}
+
+#[cfg(target_os = "macos")]
+#[test]
+fn lazy_data_mock() {
+	use arboard::{ClipboardType, DataProvider, HTML, PNG};
+
+	let (mut ctx, _) = mock_clipboard();
+	let mut providers: std::collections::HashMap<String, DataProvider> = Default::default();
+	providers.insert(HTML.into(), Box::new(|| b"<b>lazy</b>".to_vec()));
+	providers.insert(PNG.into(), Box::new(|| vec![1, 2, 3]));
+	ctx.set_lazy_data(providers).unwrap();
+	assert_eq!(ctx.get_text_bytes(HTML).unwrap(), b"<b>lazy</b>");
+	assert_eq!(ctx.get_text_bytes(PNG).unwrap(), [1, 2, 3]);
+	assert!(ctx.types().contains(&ClipboardType::Image));
+}
+
+#[cfg(target_os = "macos")]
+#[test]
+fn color_mock() {
+	use arboard::COLOR;
+
+	let (mut ctx, _) = mock_clipboard();
+	assert!(matches!(ctx.get_color(), Err(Error::ContentNotAvailable)));
+	ctx.set_color([1.0, 0.5, 0.0, 0.25]).unwrap();
+	assert_eq!(ctx.get_color().unwrap(), [1.0, 0.5, 0.0, 0.25]);
+	ctx.write().data(COLOR, [0u8; 3]).commit().unwrap();
+	assert!(matches!(ctx.get_color(), Err(Error::ConversionFailure)));
+}
+
+#[cfg(windows)]
+#[test]
+fn delayed_rendering_mock() {
+	use arboard::{ClipboardWriteExtWindows, DelayedRenderingDrop};
+
+	let (mut ctx, _) = mock_clipboard();
+	let rendering = ctx
+		.write()
+		.text("text")
+		.with_provider("arboard delayed", || vec![1, 2, 3])
+		.commit_delayed(DelayedRenderingDrop::Empty)
+		.unwrap();
+	drop(rendering);
+	// Backends other than the system clipboard render everything right away.
+	assert_eq!(ctx.get_text_bytes("arboard delayed").unwrap(), [1, 2, 3]);
+	ctx.write().with_provider("arboard delayed", || vec![4]).commit().unwrap();
+	assert_eq!(ctx.get_text_bytes("arboard delayed").unwrap(), [4]);
+}
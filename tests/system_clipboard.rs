@@ -0,0 +1,185 @@
+// Exercises the clipboard of the system, so the tests are ignored by default. Run them on a
+// machine with a desktop session with
+//
+//     cargo test --test system_clipboard -- --ignored
+//
+// On Linux without a desktop, they run under Xvfb with `xvfb-run cargo test ...`. Every test puts
+// back the contents that were on the clipboard before it started, as far as they could be read.
+
+#![cfg(not(any(target_arch = "wasm32", target_os = "android", target_os = "ios")))]
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+
+use arboard::{Clipboard, ClipboardSnapshot, Error};
+
+/// The tests share the clipboard of the system, so they take turns.
+static CLIPBOARD_LOCK: Mutex<()> = Mutex::new(());
+
+/// The clipboard for a single test. It restores the contents that the clipboard held before the
+/// test when it's dropped, even if the test failed.
+struct Harness {
+	clipboard: Clipboard,
+	saved: Option<ClipboardSnapshot>,
+	_lock: MutexGuard<'static, ()>,
+}
+
+impl Harness {
+	fn new() -> Self {
+		// A test that failed while holding the lock shouldn't make the other tests fail.
+		let lock = CLIPBOARD_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+		let mut clipboard = Clipboard::new().expect(
+			"the system clipboard isn't available, the tests need a desktop session (or Xvfb)",
+		);
+		assert_ne!(
+			clipboard.backend_name(),
+			"Mock",
+			"unset ARBOARD_BACKEND, these tests are meant for the system clipboard"
+		);
+		let saved = match clipboard.snapshot() {
+			Ok(saved) => Some(saved),
+			Err(e) => {
+				eprintln!("Could not save the clipboard, it won't be restored: {}", e);
+				None
+			}
+		};
+		Harness { clipboard, saved, _lock: lock }
+	}
+}
+
+impl Drop for Harness {
+	fn drop(&mut self) {
+		if let Some(saved) = &self.saved {
+			if let Err(e) = self.clipboard.restore(saved) {
+				eprintln!("Could not restore the clipboard: {}", e);
+			}
+		}
+	}
+}
+
+impl Deref for Harness {
+	type Target = Clipboard;
+
+	fn deref(&self) -> &Clipboard {
+		&self.clipboard
+	}
+}
+
+impl DerefMut for Harness {
+	fn deref_mut(&mut self) -> &mut Clipboard {
+		&mut self.clipboard
+	}
+}
+
+#[test]
+#[ignore]
+fn text_round_trip() {
+	let mut clipboard = Harness::new();
+	let texts = ["plain ASCII text", "Some utf8: 🤓 ∑φ(n)<ε 🐔 👩‍👩‍👧‍👦", "line\nbreaks\tand tabs"];
+	for text in texts {
+		clipboard.set_text(text).unwrap();
+		assert_eq!(clipboard.get_text().unwrap(), text);
+	}
+
+	// Large enough to need incremental transfers on X11.
+	let large = "0123456789abcdef🦀\n".repeat(5 * 1024 * 1024 / 21);
+	clipboard.set_text(large.as_str()).unwrap();
+	let got = clipboard.get_text().unwrap();
+	assert_eq!(got.len(), large.len());
+	assert!(got == large, "the large text was altered");
+}
+
+#[cfg(feature = "image-data")]
+#[test]
+#[ignore]
+fn image_round_trip() {
+	use arboard::ImageData;
+
+	let mut clipboard = Harness::new();
+	// Opaque, translucent and fully transparent pixels, whose colors must all survive.
+	#[rustfmt::skip]
+	let bytes = [
+		255, 0, 0, 255,    0, 255, 0, 128,    0, 0, 255, 1,
+		10, 20, 30, 0,     255, 255, 255, 255, 0, 0, 0, 64,
+	];
+	let image = ImageData { width: 3, height: 2, bytes: bytes.as_ref().into() };
+	clipboard.set_image(image.clone()).unwrap();
+	assert_eq!(clipboard.get_image().unwrap(), image);
+}
+
+#[test]
+#[ignore]
+fn clear_empties_the_clipboard() {
+	let mut clipboard = Harness::new();
+	clipboard.set_text("about to be cleared").unwrap();
+	clipboard.clear().unwrap();
+	assert!(matches!(clipboard.get_text(), Err(Error::ContentNotAvailable)));
+	assert_eq!(clipboard.count_items(), 0);
+}
+
+#[test]
+#[ignore]
+fn generation_changes_with_every_write() {
+	let mut clipboard = Harness::new();
+	let mut previous = match clipboard.generation() {
+		Ok(generation) => generation,
+		Err(Error::ClipboardNotSupported) => {
+			eprintln!("The {} backend has no generation, skipping", clipboard.backend_name());
+			return;
+		}
+		Err(e) => panic!("{}", e),
+	};
+	for i in 0..5 {
+		clipboard.set_text(format!("write {}", i)).unwrap();
+		let generation = clipboard.generation().unwrap();
+		// The change count on macOS and the sequence number on Windows only ever increase.
+		if cfg!(any(target_os = "macos", windows)) {
+			assert!(generation > previous, "{} isn't after {}", generation, previous);
+		} else {
+			assert_ne!(generation, previous);
+		}
+		assert_eq!(clipboard.generation().unwrap(), generation, "changed without a write");
+		previous = generation;
+	}
+}
+
+#[test]
+#[ignore]
+fn two_instances() {
+	let mut clipboard = Harness::new();
+	let mut other = Clipboard::new().unwrap();
+	clipboard.set_text("written by the first").unwrap();
+	assert_eq!(other.get_text().unwrap(), "written by the first");
+	other.set_text("written by the second").unwrap();
+	assert_eq!(clipboard.get_text().unwrap(), "written by the second");
+
+	// Both write and read at the same time. Every read sees one of the written texts, and the
+	// clipboard ends up with one of them.
+	let texts: Vec<String> = (0..2).map(|i| format!("thread {}", i)).collect();
+	let threads: Vec<_> = texts
+		.iter()
+		.cloned()
+		.map(|text| {
+			let texts = texts.clone();
+			thread::spawn(move || {
+				let mut clipboard = Clipboard::new().unwrap();
+				for _ in 0..50 {
+					match clipboard.set_text(text.as_str()) {
+						Ok(()) | Err(Error::ClipboardOccupied) => {}
+						Err(e) => panic!("{}", e),
+					}
+					match clipboard.get_text() {
+						Ok(read) => assert!(texts.contains(&read), "read {:?}", read),
+						Err(Error::ClipboardOccupied) => {}
+						Err(e) => panic!("{}", e),
+					}
+				}
+			})
+		})
+		.collect();
+	for thread in threads {
+		thread.join().unwrap();
+	}
+	assert!(texts.contains(&clipboard.get_text().unwrap()));
+}
@@ -0,0 +1,50 @@
+// Runs in a browser with `wasm-pack test --chrome --features wasm`. Browsers only grant the
+// clipboard permission to pages that are focused, so keep the test page in the foreground.
+
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use arboard::{Clipboard, Error};
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+#[wasm_bindgen_test]
+async fn text_round_trip() {
+	let mut clipboard = Clipboard::new().unwrap();
+	assert!(matches!(clipboard.get_text(), Err(Error::ClipboardNotSupported)));
+
+	let text = "Some utf8: 🤓 ∑φ(n)<ε 🐔";
+	match clipboard.set_text_async(text.to_owned()).await {
+		Ok(()) => assert_eq!(clipboard.get_text_async().await.unwrap(), text),
+		// Headless browsers don't grant the permission.
+		Err(Error::PermissionDenied) => {}
+		Err(e) => panic!("{:?}", e),
+	}
+}
+
+#[cfg(feature = "image-data")]
+#[wasm_bindgen_test]
+async fn image_round_trip() {
+	use arboard::ImageData;
+
+	let mut clipboard = Clipboard::new().unwrap();
+	assert!(matches!(clipboard.get_image(), Err(Error::ClipboardNotSupported)));
+
+	#[rustfmt::skip]
+	let bytes = [
+		255, 100, 100, 255,
+		100, 255, 100, 100,
+		100, 100, 255, 100,
+		0, 0, 0, 255,
+	];
+	let image = ImageData { width: 2, height: 2, bytes: bytes.as_ref().into() };
+	match clipboard.set_image_async(image).await {
+		Ok(()) => {
+			let read = clipboard.get_image_async().await.unwrap();
+			assert_eq!((read.width, read.height), (2, 2));
+			assert_eq!(read.bytes.as_ref(), bytes.as_ref());
+		}
+		Err(Error::PermissionDenied) => {}
+		Err(e) => panic!("{:?}", e),
+	}
+}